@@ -8,19 +8,30 @@ use std::{
 use bit_field::BitField as _;
 use log::debug;
 use mbr::{MasterBootRecordPartitionBuilder, MasterBootRecordPartitionTableBuilder};
-use part::{build_layout, num_cast, start_end_to_size, PartitionLayout, PartitionLayoutHint};
+use part::{
+    build_layout_aligned, minimum_end_lba_aligned, num_cast, round_up, start_end_to_size,
+    try_num_cast, PartitionLayoutHint,
+};
+pub use part::{device_size, PartitionBuilder, PartitionLayout, PartitionTableWriter};
+use types::Architecture;
 use uuid::{uuid, Uuid};
 
 const BLOCK_SIZE: usize = 512;
 
+/// Alignment, in LBAs, of the partitions we place: 1 MiB, like most partitioning tools do
+pub const PARTITION_ALIGNMENT_LBA: usize = 2048;
+
 const MBR_HEADER_OFFSET_LBA: usize = 0;
 const MBR_SIZE_LBA: usize = 1;
+const PROTECTIVE_MBR_MAX_SIZE_LBA: usize = 0xffff_ffff;
+const HYBRID_MBR_MAX_PARTITIONS: usize = 3;
 
 const GPT_SIGNATURE_HEADER: u64 = 0x5452_4150_2049_4645;
 const GPT_VERSION_HEADER: u32 = 0x0001_0000;
 const GPT_HEADER_SIZE_LBA: usize = 1;
 const GPT_PARTITION_NUM: usize = 128;
 const GPT_PARTITION_ENTRY_SIZE: usize = 128;
+const GPT_PARTITION_NAME_MAX_LEN: usize = 36;
 const GPT_PARTITION_HEADER_SIZE_LBA: usize =
     (GPT_PARTITION_NUM * GPT_PARTITION_ENTRY_SIZE) / BLOCK_SIZE;
 
@@ -34,11 +45,142 @@ pub const EFI_SYSTEM_PART_GUID: Uuid = uuid!("c12a7328-f81f-11d2-ba4b-00a0c93ec9
 /// for further details.
 pub const EXTENDED_BOOTLOADER_PART_GUID: Uuid = uuid!("bc13c2ff-59e6-4262-a352-b275fd6f7172");
 
+/// Standard Root Partition GUID for the ARM 32-bit architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const ROOT_PART_GUID_ARM: Uuid = uuid!("69dad710-2ce4-4e3c-b16c-21a1d49abed3");
+
 /// Standard Root Partition GUID for the ARM64/AARCH64 architecture. See the
 /// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
 /// for further details.
 pub const ROOT_PART_GUID_ARM64: Uuid = uuid!("b921b045-1df0-41c3-af44-4c6f280d3fae");
 
+/// Standard Root Partition GUID for the x86 architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const ROOT_PART_GUID_X86: Uuid = uuid!("44479540-f297-41b2-9af7-d131d5f0458a");
+
+/// Standard Root Partition GUID for the x86-64/AMD64 architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const ROOT_PART_GUID_X86_64: Uuid = uuid!("4f68bce3-e8cd-4db1-96e7-fbcaf984b709");
+
+/// Standard Root Partition GUID for the RISC-V 64-bit architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const ROOT_PART_GUID_RISCV64: Uuid = uuid!("72ec70a6-cf74-40e6-bd49-4bda08e8f224");
+
+/// Standard Root Partition GUID for the POWER 64-bit Little-Endian architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const ROOT_PART_GUID_PPC64LE: Uuid = uuid!("c31c45e6-3f39-412e-80fb-4809c4980599");
+
+/// Standard Root Partition GUID for the s390x architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const ROOT_PART_GUID_S390X: Uuid = uuid!("5eead9a9-fe09-4a1e-a1d7-520d00531306");
+
+/// Standard /usr Partition GUID for the ARM 32-bit architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const USR_PART_GUID_ARM: Uuid = uuid!("7d0359a3-02b3-4f0a-865c-654403e70625");
+
+/// Standard /usr Partition GUID for the ARM64/AARCH64 architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const USR_PART_GUID_ARM64: Uuid = uuid!("b0e01050-ee5f-4390-949a-9101b17104e9");
+
+/// Standard /usr Partition GUID for the x86 architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const USR_PART_GUID_X86: Uuid = uuid!("75250d76-8cc6-458e-bd66-bd47cc81a812");
+
+/// Standard /usr Partition GUID for the x86-64/AMD64 architecture. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const USR_PART_GUID_X86_64: Uuid = uuid!("8484680c-9521-48c6-9c11-b0720656f69e");
+
+/// Standard Swap Partition GUID. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const SWAP_PART_GUID: Uuid = uuid!("0657fd6d-a4ab-43c4-84e5-0933c84b4f4f");
+
+/// Generic Linux Data Partition GUID. See the
+/// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+/// for further details.
+pub const LINUX_DATA_PART_GUID: Uuid = uuid!("0fc63daf-8483-4772-8e79-3d69d8477de4");
+
+/// Returns the standard Root Partition GUID for an architecture
+#[must_use]
+pub fn root_part_guid_for(arch: Architecture) -> Uuid {
+    match arch {
+        Architecture::Arm => ROOT_PART_GUID_ARM,
+        Architecture::Arm64 => ROOT_PART_GUID_ARM64,
+        Architecture::X86 => ROOT_PART_GUID_X86,
+        Architecture::X86_64 => ROOT_PART_GUID_X86_64,
+        Architecture::Riscv64 => ROOT_PART_GUID_RISCV64,
+        Architecture::Ppc64le => ROOT_PART_GUID_PPC64LE,
+        Architecture::S390x => ROOT_PART_GUID_S390X,
+    }
+}
+
+/// Returns the size, in bytes, available to the partitions once a GPT with the default partition
+/// entry size is written to a file
+///
+/// The space before the first aligned LBA can't be used by the partitions we place, and isn't
+/// accounted for.
+///
+/// # Errors
+///
+/// This function will return an [`std::io::Error`] if the [`File`] is too small to hold a GPT and
+/// an aligned partition, or if its metadata can't be accessed.
+///
+pub fn usable_size(file: &File) -> Result<usize, io::Error> {
+    let (first_lba, last_lba) = usable_lba_range(device_size(file)?)?;
+
+    let first_lba = round_up(first_lba, PARTITION_ALIGNMENT_LBA);
+    if first_lba > last_lba {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "File is too small",
+        ));
+    }
+
+    Ok((last_lba - first_lba + 1) * BLOCK_SIZE)
+}
+
+/// Returns the first and last LBAs available to the partitions once a GPT with the default
+/// partition entry size is written to a device of the given size, in bytes
+///
+/// # Errors
+///
+/// This function will return an [`std::io::Error`] if the device is too small to hold a GPT.
+pub fn usable_lba_range(device_size: u64) -> Result<(usize, usize), io::Error> {
+    let blocks = try_num_cast!(usize, device_size)? / BLOCK_SIZE;
+    let overhead_lba = MBR_SIZE_LBA + 2 * (GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA);
+
+    if blocks <= overhead_lba {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "File is too small",
+        ));
+    }
+
+    Ok((
+        MBR_HEADER_OFFSET_LBA + MBR_SIZE_LBA + GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA,
+        blocks - 1 - GPT_HEADER_SIZE_LBA - GPT_PARTITION_HEADER_SIZE_LBA,
+    ))
+}
+
+fn mbr_type_from_gpt_type(type_: &Uuid) -> u8 {
+    match *type_ {
+        EFI_SYSTEM_PART_GUID => 0xef,
+        EXTENDED_BOOTLOADER_PART_GUID => 0xea,
+        SWAP_PART_GUID => 0x82,
+        _ => 0x83,
+    }
+}
+
 fn guid_bytes(uuid: &Uuid) -> [u8; 16] {
     let uuid_fields = uuid.as_fields();
 
@@ -64,6 +206,32 @@ struct GuidPartitionTableLayout {
     backup_gpt_header_lba: usize,
 }
 
+/// A GUID Partition, as written to a file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GuidPartitionInfo {
+    /// Partition GUID
+    pub guid: Uuid,
+
+    /// Partition Type GUID
+    pub type_: Uuid,
+
+    /// Partition Start LBA
+    pub start_lba: usize,
+
+    /// Partition End LBA
+    pub end_lba: usize,
+}
+
+/// A GUID Partition Table, as written to a file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GuidPartitionTableInfo {
+    /// Disk GUID
+    pub disk_guid: Uuid,
+
+    /// Partitions, in the order they were added to the table
+    pub partitions: Vec<GuidPartitionInfo>,
+}
+
 /// GUID Partition Table Representation
 #[derive(Debug)]
 pub struct GuidPartitionTable {
@@ -71,17 +239,129 @@ pub struct GuidPartitionTable {
 }
 
 impl GuidPartitionTable {
-    #[allow(clippy::too_many_lines, clippy::unwrap_in_result)]
+    fn device_size(&self, file: &File) -> Result<u64, io::Error> {
+        Ok(match self.builder.device_size {
+            Some(size) => size,
+            None => device_size(file)?,
+        })
+    }
+
+    fn resize(&self, file: &File) -> Result<(), io::Error> {
+        let Some(size) = self.builder.device_size else {
+            return Ok(());
+        };
+
+        let current = device_size(file)?;
+        if current == size {
+            return Ok(());
+        }
+
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Device size is {current} bytes, but {size} bytes were requested"),
+            ));
+        }
+
+        if size < current && !self.builder.shrink {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "File is {current} bytes, shrinking it to the requested {size} bytes would truncate it"
+                ),
+            ));
+        }
+
+        debug!("Resizing file from {current} to {size} bytes");
+
+        file.set_len(size)
+    }
+
+    fn partition_entries_size_lba(&self) -> usize {
+        (GPT_PARTITION_NUM * self.builder.partition_entry_size).div_ceil(BLOCK_SIZE)
+    }
+
+    fn first_usable_lba(&self) -> usize {
+        usize::max(
+            MBR_HEADER_OFFSET_LBA
+                + MBR_SIZE_LBA
+                + GPT_HEADER_SIZE_LBA
+                + self.partition_entries_size_lba(),
+            self.builder.reserved_start.div_ceil(BLOCK_SIZE),
+        )
+    }
+
+    fn layout_hints(&self) -> Vec<PartitionLayoutHint> {
+        self.builder
+            .partitions
+            .iter()
+            .map(|p| PartitionLayoutHint {
+                offset_lba: p.builder.offset_lba,
+                size_lba: p.builder.size_lba,
+            })
+            .collect()
+    }
+
+    #[allow(clippy::too_many_lines)]
     fn build_gpt_layout(&self, file: &File) -> Result<GuidPartitionTableLayout, io::Error> {
-        let metadata = file.metadata()?;
+        let hybrid_mbr = &self.builder.hybrid_mbr;
+        if hybrid_mbr.len() > HYBRID_MBR_MAX_PARTITIONS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Hybrid MBR can only mirror {HYBRID_MBR_MAX_PARTITIONS} partitions, {} requested",
+                    hybrid_mbr.len()
+                ),
+            ));
+        }
 
-        let blocks = num_cast!(usize, metadata.len()) / BLOCK_SIZE;
+        for (pos, idx) in hybrid_mbr.iter().enumerate() {
+            if *idx >= self.builder.partitions.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Hybrid MBR partition {idx} doesn't exist"),
+                ));
+            }
 
-        debug!(
-            "File has len of {} bytes, {} blocks",
-            metadata.len(),
-            blocks
-        );
+            if hybrid_mbr[..pos].contains(idx) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Hybrid MBR partition {idx} is mirrored multiple times"),
+                ));
+            }
+        }
+
+        let entry_size = self.builder.partition_entry_size;
+        if !entry_size.is_multiple_of(GPT_PARTITION_ENTRY_SIZE)
+            || !(entry_size / GPT_PARTITION_ENTRY_SIZE).is_power_of_two()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid partition entry size {entry_size}: must be {GPT_PARTITION_ENTRY_SIZE} bytes multiplied by a power of two"
+                ),
+            ));
+        }
+
+        for (idx, part) in self.builder.partitions.iter().enumerate() {
+            if let Some(name) = &part.builder.name {
+                let len = name.encode_utf16().count();
+
+                if len > GPT_PARTITION_NAME_MAX_LEN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Partition {idx} name \"{name}\" is too long ({len} UTF-16 code units, {GPT_PARTITION_NAME_MAX_LEN} max)"
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let device_size = self.device_size(file)?;
+        let blocks = try_num_cast!(usize, device_size)? / BLOCK_SIZE;
+
+        debug!("Device has len of {device_size} bytes, {blocks} blocks");
 
         let mbr_lba = MBR_HEADER_OFFSET_LBA;
         debug!("Setting up Protective MBR at LBA {}", mbr_lba);
@@ -92,9 +372,10 @@ impl GuidPartitionTable {
         let primary_gpt_parts_lba = primary_gpt_lba + GPT_HEADER_SIZE_LBA;
         debug!("Primary GPT Partition table is located at LBA {primary_gpt_parts_lba}");
 
-        debug!("GPT Partition Table Size: {GPT_PARTITION_HEADER_SIZE_LBA} LBAs");
+        let parts_size_lba = self.partition_entries_size_lba();
+        debug!("GPT Partition Table Size: {parts_size_lba} LBAs");
 
-        let first_usable_lba = primary_gpt_parts_lba + GPT_PARTITION_HEADER_SIZE_LBA;
+        let first_usable_lba = self.first_usable_lba();
         debug!("First Usable LBA: {first_usable_lba}");
 
         if first_usable_lba >= blocks {
@@ -107,7 +388,7 @@ impl GuidPartitionTable {
         let backup_gpt_lba = blocks - GPT_HEADER_SIZE_LBA;
         debug!("Backup GPT Header is located at LBA {backup_gpt_lba}");
 
-        let backup_gpt_parts_lba = backup_gpt_lba - GPT_PARTITION_HEADER_SIZE_LBA;
+        let backup_gpt_parts_lba = backup_gpt_lba - parts_size_lba;
         debug!("Backup GPT Partition table is located at LBA {backup_gpt_parts_lba}");
 
         let last_usable_lba = backup_gpt_parts_lba - 1;
@@ -120,15 +401,7 @@ impl GuidPartitionTable {
             ));
         }
 
-        let parts_hints = self
-            .builder
-            .partitions
-            .iter()
-            .map(|p| PartitionLayoutHint {
-                offset_lba: p.builder.offset_lba,
-                size_lba: p.builder.size_lba,
-            })
-            .collect::<Vec<_>>();
+        let parts_hints = self.layout_hints();
 
         Ok(GuidPartitionTableLayout {
             block_size: BLOCK_SIZE,
@@ -136,15 +409,90 @@ impl GuidPartitionTable {
             primary_gpt_header_lba: primary_gpt_lba,
             primary_gpt_table_lba: primary_gpt_parts_lba,
             first_usable: first_usable_lba,
-            partitions_offset: build_layout(first_usable_lba, last_usable_lba, &parts_hints)?,
+            partitions_offset: build_layout_aligned(
+                first_usable_lba,
+                last_usable_lba,
+                &parts_hints,
+                Some(PARTITION_ALIGNMENT_LBA),
+            )?,
             last_usable: last_usable_lba,
             backup_gpt_table_lba: backup_gpt_parts_lba,
             backup_gpt_header_lba: backup_gpt_lba,
         })
     }
 
+    fn protective_mbr(
+        &self,
+        cfg: &GuidPartitionTableLayout,
+    ) -> MasterBootRecordPartitionTableBuilder {
+        // The UEFI Specification requires the MBR Disk Signature to be unused, and set to 0.
+        let builder = MasterBootRecordPartitionTableBuilder::new().disk_id(0);
+
+        if self.builder.hybrid_mbr.is_empty() {
+            // The protective partition covers the whole disk, or as much as an MBR can address if
+            // it's too large.
+            let protective_size_lba = usize::min(
+                start_end_to_size(cfg.primary_gpt_header_lba, cfg.backup_gpt_header_lba),
+                PROTECTIVE_MBR_MAX_SIZE_LBA,
+            );
+
+            return builder.add_partition(
+                MasterBootRecordPartitionBuilder::new(0xee)
+                    .offset(cfg.primary_gpt_header_lba)
+                    .size(protective_size_lba * cfg.block_size)
+                    .build(),
+            );
+        }
+
+        // In an hybrid MBR, the protective partition only covers the GPT header and partition
+        // entries, and the mirrored partitions follow in the order they are laid out on the disk.
+        let gpt_end_lba = cfg.primary_gpt_table_lba + self.partition_entries_size_lba();
+        let mut builder = builder.add_partition(
+            MasterBootRecordPartitionBuilder::new(0xee)
+                .offset(cfg.primary_gpt_header_lba)
+                .size((gpt_end_lba - cfg.primary_gpt_header_lba) * cfg.block_size)
+                .build(),
+        );
+
+        let mut mirrored: Vec<_> = self
+            .builder
+            .hybrid_mbr
+            .iter()
+            .map(|idx| (&self.builder.partitions[*idx], &cfg.partitions_offset[*idx]))
+            .collect();
+        mirrored.sort_by_key(|(_, layout)| layout.start_lba);
+
+        for (part, layout) in mirrored {
+            debug!(
+                "Mirroring partition at LBA {} in the hybrid MBR",
+                layout.start_lba
+            );
+
+            let type_ = part
+                .builder
+                .mbr_type
+                .unwrap_or_else(|| mbr_type_from_gpt_type(&part.builder.type_));
+
+            builder = builder.add_partition(
+                MasterBootRecordPartitionBuilder::new(type_)
+                    .offset(layout.start_lba)
+                    .size(start_end_to_size(layout.start_lba, layout.end_lba) * cfg.block_size)
+                    .bootable(part.builder.bits.get_bit(2))
+                    .build(),
+            );
+        }
+
+        builder
+    }
+
     /// Writes a GPT to a file
     ///
+    /// Returns the disk and partitions GUIDs, and the partitions layout, as written to the file.
+    ///
+    /// If a device size has been set with [`GuidPartitionTableBuilder::device_size_bytes`], and
+    /// the file is a regular file, it is resized beforehand. It is only shrunk if
+    /// [`GuidPartitionTableBuilder::shrink`] has been set.
+    ///
     /// # Errors
     ///
     /// This function will return an [`std::io::Error`] if there's an issue with the Partition Table
@@ -154,7 +502,9 @@ impl GuidPartitionTable {
     ///
     /// Panics if we have an integer overflow in one of the integer type conversions
     #[allow(clippy::too_many_lines, clippy::unwrap_in_result)]
-    pub fn write(self, mut file: &File) -> Result<(), io::Error> {
+    pub fn write(self, mut file: &File) -> Result<GuidPartitionTableInfo, io::Error> {
+        self.resize(file)?;
+
         let cfg = self.build_gpt_layout(file)?;
 
         let mut primary_gpt = [0u8; 92];
@@ -173,20 +523,20 @@ impl GuidPartitionTable {
         primary_gpt[48..56].copy_from_slice(&cfg.last_usable.to_le_bytes());
         primary_gpt[56..72].copy_from_slice(&guid_bytes(&self.builder.guid));
 
-        let first_part_entry_lba = 2u64;
-        primary_gpt[72..80].copy_from_slice(&first_part_entry_lba.to_le_bytes());
+        primary_gpt[72..80].copy_from_slice(&cfg.primary_gpt_table_lba.to_le_bytes());
 
         let num_parts = num_cast!(u32, GPT_PARTITION_NUM);
         primary_gpt[80..84].copy_from_slice(&num_parts.to_le_bytes());
 
-        let part_entry_size = num_cast!(u32, GPT_PARTITION_ENTRY_SIZE);
+        let entry_size = self.builder.partition_entry_size;
+        let part_entry_size = num_cast!(u32, entry_size);
         primary_gpt[84..88].copy_from_slice(&part_entry_size.to_le_bytes());
 
         let mut parts: Vec<u8> = Vec::new();
         for (part, layout) in
             Iterator::zip(self.builder.partitions.iter(), cfg.partitions_offset.iter())
         {
-            let mut entry = [0u8; GPT_PARTITION_ENTRY_SIZE];
+            let mut entry = vec![0u8; entry_size];
 
             entry[0..16].copy_from_slice(&guid_bytes(&part.builder.type_));
             entry[16..32].copy_from_slice(&guid_bytes(&part.builder.guid));
@@ -207,11 +557,14 @@ impl GuidPartitionTable {
             parts.extend_from_slice(&entry);
         }
 
-        let gpt_part_entries_size = GPT_PARTITION_NUM * GPT_PARTITION_ENTRY_SIZE;
+        // The CRC only covers the partition entries, but the whole array is written so that the
+        // padding up to the next LBA is cleared as well.
+        let gpt_part_entries_size = GPT_PARTITION_NUM * entry_size;
         parts.resize(gpt_part_entries_size, 0);
 
         let crc_alg = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
         let parts_crc = crc_alg.checksum(&parts);
+        parts.resize(self.partition_entries_size_lba() * BLOCK_SIZE, 0);
         primary_gpt[88..92].copy_from_slice(&parts_crc.to_le_bytes());
 
         let mut backup_gpt = primary_gpt;
@@ -225,52 +578,51 @@ impl GuidPartitionTable {
         let backup_gpt_crc = crc_alg.checksum(&backup_gpt);
         backup_gpt[16..20].copy_from_slice(&backup_gpt_crc.to_le_bytes());
 
-        file.seek(io::SeekFrom::Start(num_cast!(
+        file.seek(io::SeekFrom::Start(try_num_cast!(
             u64,
             cfg.mbr_header_lba * cfg.block_size
-        )))?;
+        )?))?;
 
-        MasterBootRecordPartitionTableBuilder::new()
-            .add_partition(
-                MasterBootRecordPartitionBuilder::new(0xee)
-                    .size(num_cast!(
-                        usize,
-                        start_end_to_size(cfg.primary_gpt_header_lba, cfg.backup_gpt_header_lba)
-                            * cfg.block_size
-                    ))
-                    .build(),
-            )
-            .build()
-            .write(file)?;
+        self.protective_mbr(&cfg).build().write(file)?;
 
-        file.seek(io::SeekFrom::Start(num_cast!(
+        file.seek(io::SeekFrom::Start(try_num_cast!(
             u64,
             cfg.primary_gpt_header_lba * cfg.block_size
-        )))?;
+        )?))?;
         file.write_all(&primary_gpt)?;
 
-        file.seek(io::SeekFrom::Start(num_cast!(
+        file.seek(io::SeekFrom::Start(try_num_cast!(
             u64,
             cfg.primary_gpt_table_lba * cfg.block_size
-        )))?;
+        )?))?;
         file.write_all(&parts)?;
 
-        file.seek(io::SeekFrom::Start(num_cast!(
+        file.seek(io::SeekFrom::Start(try_num_cast!(
             u64,
             cfg.backup_gpt_table_lba * cfg.block_size
-        )))?;
+        )?))?;
         file.write_all(&parts)?;
 
-        file.seek(io::SeekFrom::Start(num_cast!(
+        file.seek(io::SeekFrom::Start(try_num_cast!(
             u64,
             cfg.backup_gpt_header_lba * cfg.block_size
-        )))?;
+        )?))?;
         file.write_all(&backup_gpt)?;
 
         file.flush()?;
         file.sync_data()?;
 
-        Ok(())
+        Ok(GuidPartitionTableInfo {
+            disk_guid: self.builder.guid,
+            partitions: Iterator::zip(self.builder.partitions.iter(), cfg.partitions_offset.iter())
+                .map(|(part, layout)| GuidPartitionInfo {
+                    guid: part.builder.guid,
+                    type_: part.builder.type_,
+                    start_lba: layout.start_lba,
+                    end_lba: layout.end_lba,
+                })
+                .collect(),
+        })
     }
 }
 
@@ -278,7 +630,12 @@ impl GuidPartitionTable {
 #[derive(Debug)]
 pub struct GuidPartitionTableBuilder {
     guid: Uuid,
+    device_size: Option<u64>,
+    shrink: bool,
+    partition_entry_size: usize,
+    reserved_start: usize,
     partitions: Vec<GuidPartition>,
+    hybrid_mbr: Vec<usize>,
 }
 
 impl GuidPartitionTableBuilder {
@@ -287,7 +644,12 @@ impl GuidPartitionTableBuilder {
     pub fn new_with_uuid(guid: Uuid) -> Self {
         Self {
             guid,
+            device_size: None,
+            shrink: false,
+            partition_entry_size: GPT_PARTITION_ENTRY_SIZE,
+            reserved_start: 0,
             partitions: Vec::new(),
+            hybrid_mbr: Vec::new(),
         }
     }
 
@@ -298,6 +660,49 @@ impl GuidPartitionTableBuilder {
         Self::new_with_uuid(Uuid::new_v4())
     }
 
+    /// Sets the size of the device, in bytes, instead of using the file size
+    ///
+    /// This allows to create a partition table on a new, empty, file: it will be resized to that
+    /// size when the table is written.
+    #[must_use]
+    pub fn device_size_bytes(mut self, size: u64) -> Self {
+        self.device_size = Some(size);
+        self
+    }
+
+    /// Allows to shrink a regular file larger than the size set with
+    /// [`GuidPartitionTableBuilder::device_size_bytes`]
+    ///
+    /// Writing the table fails otherwise, since everything past that size would be lost.
+    #[must_use]
+    pub fn shrink(mut self, shrink: bool) -> Self {
+        self.shrink = shrink;
+        self
+    }
+
+    /// Sets the size, in bytes, of each partition entry
+    ///
+    /// Defaults to 128 bytes, which is what virtually every implementation uses. The UEFI
+    /// specification allows larger entries, padded with zeros, as long as the size is 128 bytes
+    /// multiplied by a power of two. Writing the [`GuidPartitionTable`] will fail otherwise.
+    #[must_use]
+    pub fn partition_entry_size(mut self, size: usize) -> Self {
+        self.partition_entry_size = size;
+        self
+    }
+
+    /// Leaves the given number of bytes at the start of the device out of the partitions
+    ///
+    /// The First Usable LBA of the GPT is moved after them, so that a firmware loaded from a
+    /// fixed offset by the boot ROM can be written there. The protective MBR, GPT header and
+    /// partition entries are still at the start of the device, so the firmware must start after
+    /// them.
+    #[must_use]
+    pub fn reserved_start_bytes(mut self, size: usize) -> Self {
+        self.reserved_start = size;
+        self
+    }
+
     /// Adds a [`GuidPartition`] to the Partition Table
     #[must_use]
     pub fn add_partition(mut self, part: GuidPartition) -> Self {
@@ -305,6 +710,19 @@ impl GuidPartitionTableBuilder {
         self
     }
 
+    /// Writes an hybrid MBR instead of a protective MBR
+    ///
+    /// The partitions at the given indices, in the order they were added to the table, are also
+    /// referenced in the MBR so that legacy BIOS implementations can boot from them. Their MBR
+    /// type is derived from their GPT type, unless set with [`GuidPartitionBuilder::mbr_type`].
+    ///
+    /// Up to 3 partitions can be mirrored, otherwise writing the [`GuidPartitionTable`] will fail.
+    #[must_use]
+    pub fn hybrid_mbr(mut self, partitions: &[usize]) -> Self {
+        self.hybrid_mbr = partitions.to_vec();
+        self
+    }
+
     /// Creates a [`GuidPartitionTable`] from our builder
     #[must_use]
     pub fn build(self) -> GuidPartitionTable {
@@ -312,6 +730,60 @@ impl GuidPartitionTableBuilder {
     }
 }
 
+impl PartitionTableWriter for GuidPartitionTable {
+    /// Computes the layout the partitions would have once the GPT is written to a file, without
+    /// modifying it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an [`std::io::Error`] if there's an issue with the Partition Table
+    /// layout, or when accessing the underlying [`File`] metadata.
+    fn partitions_layout(&self, file: &File) -> Result<Vec<PartitionLayout>, io::Error> {
+        Ok(self.build_gpt_layout(file)?.partitions_offset)
+    }
+
+    /// Returns the size, in bytes, of the smallest device the partitions fit in
+    ///
+    /// This accounts for the protective MBR, and both the primary and backup GPT headers and
+    /// partition entries. Returns `None` if a partition has no size, since it would extend up to
+    /// the end of the device.
+    fn minimum_size_bytes(&self) -> Option<usize> {
+        let parts_size_lba = self.partition_entries_size_lba();
+        let first_usable_lba = self.first_usable_lba();
+
+        let end_lba = minimum_end_lba_aligned(
+            first_usable_lba,
+            &self.layout_hints(),
+            Some(PARTITION_ALIGNMENT_LBA),
+        )?
+        // We need at least one usable LBA
+        .max(first_usable_lba + 1);
+
+        Some((end_lba + parts_size_lba + GPT_HEADER_SIZE_LBA) * BLOCK_SIZE)
+    }
+
+    /// Returns the size, in bytes, of the protective MBR and primary GPT at the start of the
+    /// device, and of the backup GPT at its end
+    fn table_size_bytes(&self) -> (usize, usize) {
+        let gpt_size_lba = GPT_HEADER_SIZE_LBA + self.partition_entries_size_lba();
+
+        (
+            (MBR_HEADER_OFFSET_LBA + MBR_SIZE_LBA + gpt_size_lba) * BLOCK_SIZE,
+            gpt_size_lba * BLOCK_SIZE,
+        )
+    }
+
+    fn write_table(self: Box<Self>, file: &File) -> Result<Vec<String>, io::Error> {
+        let info = self.write(file)?;
+
+        Ok(info
+            .partitions
+            .iter()
+            .map(|part| part.guid.hyphenated().to_string())
+            .collect())
+    }
+}
+
 impl Default for GuidPartitionTableBuilder {
     fn default() -> Self {
         Self::new()
@@ -333,6 +805,7 @@ pub struct GuidPartitionBuilder {
     offset_lba: Option<usize>,
     size_lba: Option<usize>,
     bits: u64,
+    mbr_type: Option<u8>,
 }
 
 impl GuidPartitionBuilder {
@@ -347,6 +820,7 @@ impl GuidPartitionBuilder {
             offset_lba: None,
             size_lba: None,
             bits: 0,
+            mbr_type: None,
         }
     }
 
@@ -357,31 +831,10 @@ impl GuidPartitionBuilder {
         Self::new_with_uuid(part_type, Uuid::new_v4())
     }
 
-    /// Sets the partition offset in LBAs from the start of the device.
-    /// Unlike the size, if provided, the offset will always be exactly
-    /// the one provided even if unaligned.
-    ///
-    /// If the offset isn't provided, the offset used is guaranteed to
-    /// be after the end of the previous partition, but isn't guaranteed
-    /// to start on the next LBA.
-    #[must_use]
-    pub fn offset(mut self, offset: usize) -> Self {
-        self.offset_lba = Some(offset);
-        self
-    }
-
-    /// Sets the partition size in bytes. Whenever building the GPT, this size might be increased to
-    /// be aligned to provide optimal device settings, but will never be decreased.
-    ///
-    /// If the size isn't provided, the partition will be made to fill any available space. Only one
-    /// size-less partition is allowed to be part of a [`GuidPartitionTable`].
-    #[must_use]
-    pub fn size(mut self, size: usize) -> Self {
-        self.size_lba = Some(size / BLOCK_SIZE);
-        self
-    }
-
     /// Sets the partition name
+    ///
+    /// The name must fit in 36 UTF-16 code units, otherwise writing the [`GuidPartitionTable`] will
+    /// fail.
     #[must_use]
     pub fn name(mut self, name: &str) -> Self {
         self.name = Some(name.to_owned());
@@ -404,40 +857,126 @@ impl GuidPartitionBuilder {
         self
     }
 
+    /// Marks the partition filesystem to be grown to the partition size when mounted. See the
+    /// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+    /// for further details.
+    #[must_use]
+    pub fn grow_fs(mut self, val: bool) -> Self {
+        self.bits.set_bit(59, val);
+        self
+    }
+
+    /// Marks the partition as read-only. See the
+    /// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+    /// for further details.
+    #[must_use]
+    pub fn read_only(mut self, val: bool) -> Self {
+        self.bits.set_bit(60, val);
+        self
+    }
+
+    /// Marks the partition as hidden, so it shouldn't be mounted even if discovered. See the
+    /// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+    /// for further details.
+    #[must_use]
+    pub fn hidden(mut self, val: bool) -> Self {
+        self.bits.set_bit(62, val);
+        self
+    }
+
+    /// Marks the partition as excluded from automatic discovery. See the
+    /// [UAPI discoverable partition specification](https://uapi-group.org/specifications/specs/discoverable_partitions_specification/)
+    /// for further details.
+    #[must_use]
+    pub fn no_auto(mut self, val: bool) -> Self {
+        self.bits.set_bit(63, val);
+        self
+    }
+
+    /// Sets the MBR partition type to use if the partition is mirrored in an hybrid MBR. See
+    /// [`GuidPartitionTableBuilder::hybrid_mbr`].
+    #[must_use]
+    pub fn mbr_type(mut self, type_: u8) -> Self {
+        self.mbr_type = Some(type_);
+        self
+    }
+}
+
+impl PartitionBuilder for GuidPartitionBuilder {
+    type Partition = GuidPartition;
+
+    /// Sets the partition offset in LBAs from the start of the device.
+    /// Unlike the size, if provided, the offset will always be exactly
+    /// the one provided even if unaligned.
+    ///
+    /// If the offset isn't provided, the offset used is guaranteed to
+    /// be after the end of the previous partition, but isn't guaranteed
+    /// to start on the next LBA.
+    fn offset(mut self, offset: usize) -> Self {
+        self.offset_lba = Some(offset);
+        self
+    }
+
+    /// Sets the partition size in bytes. Whenever building the GPT, this size might be increased to
+    /// be aligned to provide optimal device settings, but will never be decreased.
+    ///
+    /// If the size isn't provided, the partition will be made to fill any available space. Only one
+    /// size-less partition is allowed to be part of a [`GuidPartitionTable`].
+    fn size(mut self, size: usize) -> Self {
+        self.size_lba = Some(size / BLOCK_SIZE);
+        self
+    }
+
     /// Marks the partition as bootable for Legacy BIOS implementations. See Table 5.8 of the UEFI
     /// Specification for further explanations.
-    #[must_use]
-    pub fn bootable(mut self, val: bool) -> Self {
+    fn bootable(mut self, val: bool) -> Self {
         self.bits.set_bit(2, val);
         self
     }
 
     /// Creates a [`GuidPartition`] from our builder
-    #[must_use]
-    pub fn build(self) -> GuidPartition {
+    fn build(self) -> GuidPartition {
         GuidPartition { builder: self }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{path::PathBuf, process::Command};
+    use core::iter::zip;
+    use std::{
+        fs,
+        io::{Read as _, Seek as _, SeekFrom, Write as _},
+        path::{Path, PathBuf},
+        process::Command,
+    };
 
     use log::trace;
-    use part::{num_cast, round_up, start_end_to_size, start_size_to_end};
+    use mbr::{MasterBootRecordPartitionBuilder, MasterBootRecordPartitionTableBuilder};
+    use part::{
+        num_cast, round_down, round_up, start_end_to_size, start_size_to_end, PartitionBuilder,
+        PartitionTableWriter,
+    };
     use serde::Deserialize;
     use tempfile::NamedTempFile;
     use test_log::test;
+    use types::Architecture;
     use uuid::Uuid;
 
     use crate::{
-        GuidPartitionBuilder, GuidPartitionTableBuilder, BLOCK_SIZE, EFI_SYSTEM_PART_GUID,
-        EXTENDED_BOOTLOADER_PART_GUID, GPT_HEADER_SIZE_LBA, GPT_PARTITION_HEADER_SIZE_LBA,
-        MBR_SIZE_LBA,
+        root_part_guid_for, GuidPartitionBuilder, GuidPartitionTableBuilder, BLOCK_SIZE,
+        EFI_SYSTEM_PART_GUID, EXTENDED_BOOTLOADER_PART_GUID, GPT_HEADER_SIZE_LBA,
+        GPT_PARTITION_ENTRY_SIZE, GPT_PARTITION_HEADER_SIZE_LBA, LINUX_DATA_PART_GUID,
+        MBR_SIZE_LBA, PARTITION_ALIGNMENT_LBA, ROOT_PART_GUID_ARM, ROOT_PART_GUID_ARM64,
+        ROOT_PART_GUID_PPC64LE, ROOT_PART_GUID_RISCV64, ROOT_PART_GUID_S390X, ROOT_PART_GUID_X86,
+        ROOT_PART_GUID_X86_64, SWAP_PART_GUID, USR_PART_GUID_ARM, USR_PART_GUID_ARM64,
+        USR_PART_GUID_X86, USR_PART_GUID_X86_64,
     };
 
     const TEMP_FILE_SIZE: u64 = 2 << 30;
 
+    // Larger than the 2 TiB a 32-bit LBA can address
+    const LARGE_FILE_SIZE: u64 = 3 << 40;
+
     #[derive(Deserialize)]
     #[serde(deny_unknown_fields)]
     struct SfDiskGptPartition {
@@ -487,6 +1026,10 @@ mod tests {
         MBR_SIZE_LBA + GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA
     }
 
+    fn first_partition_lba() -> usize {
+        round_up(first_lba(), PARTITION_ALIGNMENT_LBA)
+    }
+
     fn last_lba(size_lba: usize) -> usize {
         size_lba - (GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA) - 1
     }
@@ -553,6 +1096,51 @@ mod tests {
         assert_eq!(gpt.id, uuid);
     }
 
+    #[test]
+    fn test_write_info() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let info = GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .size(64 << 20)
+                    .build(),
+            )
+            .add_partition(GuidPartitionBuilder::new(ROOT_PART_GUID_ARM64).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let output = Command::new("sfdisk")
+            .arg("-J")
+            .arg(temp_file.path())
+            .output()
+            .unwrap();
+
+        trace!("{}", String::from_utf8(output.stdout.clone()).unwrap());
+
+        let res: SfdiskOutput = serde_json::from_slice(&output.stdout).unwrap();
+
+        let gpt = match res.table {
+            SfDiskPartitionTable::Gpt(v) => v,
+            _ => panic!(),
+        };
+
+        assert_eq!(gpt.id, info.disk_guid);
+        assert_eq!(gpt.partitions.len(), info.partitions.len());
+
+        for (sfdisk_part, part) in Iterator::zip(gpt.partitions.iter(), info.partitions.iter()) {
+            assert_eq!(sfdisk_part.uuid, part.guid);
+            assert_eq!(sfdisk_part.kind, part.type_);
+            assert_eq!(sfdisk_part.start, part.start_lba);
+            assert_eq!(
+                sfdisk_part.size,
+                start_end_to_size(part.start_lba, part.end_lba)
+            );
+        }
+    }
+
     #[test]
     fn test_file_too_small() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -568,23 +1156,308 @@ mod tests {
             ))
             .unwrap();
 
-        GuidPartitionTableBuilder::new()
-            .build()
-            .write(temp_file.as_file())
-            .unwrap_err();
+        GuidPartitionTableBuilder::new()
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_well_known_guids() {
+        let guids = [
+            (EFI_SYSTEM_PART_GUID, "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"),
+            (
+                EXTENDED_BOOTLOADER_PART_GUID,
+                "BC13C2FF-59E6-4262-A352-B275FD6F7172",
+            ),
+            (ROOT_PART_GUID_ARM, "69DAD710-2CE4-4E3C-B16C-21A1D49ABED3"),
+            (ROOT_PART_GUID_ARM64, "B921B045-1DF0-41C3-AF44-4C6F280D3FAE"),
+            (ROOT_PART_GUID_X86, "44479540-F297-41B2-9AF7-D131D5F0458A"),
+            (
+                ROOT_PART_GUID_X86_64,
+                "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+            ),
+            (
+                ROOT_PART_GUID_RISCV64,
+                "72EC70A6-CF74-40E6-BD49-4BDA08E8F224",
+            ),
+            (
+                ROOT_PART_GUID_PPC64LE,
+                "C31C45E6-3F39-412E-80FB-4809C4980599",
+            ),
+            (ROOT_PART_GUID_S390X, "5EEAD9A9-FE09-4A1E-A1D7-520D00531306"),
+            (USR_PART_GUID_ARM, "7D0359A3-02B3-4F0A-865C-654403E70625"),
+            (USR_PART_GUID_ARM64, "B0E01050-EE5F-4390-949A-9101B17104E9"),
+            (USR_PART_GUID_X86, "75250D76-8CC6-458E-BD66-BD47CC81A812"),
+            (USR_PART_GUID_X86_64, "8484680C-9521-48C6-9C11-B0720656F69E"),
+            (SWAP_PART_GUID, "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F"),
+            (LINUX_DATA_PART_GUID, "0FC63DAF-8483-4772-8E79-3D69D8477DE4"),
+        ];
+
+        for (guid, expected) in guids {
+            assert_eq!(guid, Uuid::parse_str(expected).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_root_part_guid_for() {
+        let guids = [
+            (Architecture::Arm, "69DAD710-2CE4-4E3C-B16C-21A1D49ABED3"),
+            (Architecture::Arm64, "B921B045-1DF0-41C3-AF44-4C6F280D3FAE"),
+            (Architecture::X86, "44479540-F297-41B2-9AF7-D131D5F0458A"),
+            (Architecture::X86_64, "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709"),
+            (
+                Architecture::Riscv64,
+                "72EC70A6-CF74-40E6-BD49-4BDA08E8F224",
+            ),
+            (
+                Architecture::Ppc64le,
+                "C31C45E6-3F39-412E-80FB-4809C4980599",
+            ),
+            (Architecture::S390x, "5EEAD9A9-FE09-4A1E-A1D7-520D00531306"),
+        ];
+
+        for (arch, expected) in guids {
+            assert_eq!(
+                root_part_guid_for(arch),
+                Uuid::parse_str(expected).unwrap(),
+                "{arch}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_partition_name_too_long() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .name(&"a".repeat(40))
+                    .build(),
+            )
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_partition_name_max_len() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .name(&"a".repeat(36))
+                    .build(),
+            )
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        // Characters outside of the Basic Multilingual Plane take two UTF-16 code units
+        GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .name(&"\u{1f980}".repeat(19))
+                    .build(),
+            )
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_one_partition_no_size() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        GuidPartitionTableBuilder::new()
+            .add_partition(GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let output = Command::new("sfdisk")
+            .arg("-J")
+            .arg(temp_file.path())
+            .output()
+            .unwrap();
+
+        trace!("{}", String::from_utf8(output.stdout.clone()).unwrap());
+
+        let res: SfdiskOutput = serde_json::from_slice(&output.stdout).unwrap();
+
+        let gpt = match res.table {
+            SfDiskPartitionTable::Gpt(v) => v,
+            _ => panic!(),
+        };
+
+        assert_eq!(gpt.sector_size, BLOCK_SIZE);
+
+        let first_lba = first_lba();
+        assert_eq!(gpt.first_lba, first_lba);
+
+        let last_lba = last_lba(num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE);
+        assert_eq!(gpt.last_lba, last_lba);
+        assert_eq!(gpt.partitions.len(), 1);
+
+        let part = &gpt.partitions[0];
+        assert_eq!(part.kind, EFI_SYSTEM_PART_GUID);
+        assert_eq!(part.start, first_partition_lba());
+        assert_eq!(
+            part.size,
+            start_end_to_size(first_partition_lba(), last_lba)
+        );
+    }
+
+    #[test]
+    fn test_one_partition_no_size_offset() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let start_lba = first_lba() + 4;
+        GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .offset(start_lba)
+                    .build(),
+            )
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let output = Command::new("sfdisk")
+            .arg("-J")
+            .arg(temp_file.path())
+            .output()
+            .unwrap();
+
+        trace!("{}", String::from_utf8(output.stdout.clone()).unwrap());
+
+        let res: SfdiskOutput = serde_json::from_slice(&output.stdout).unwrap();
+
+        let gpt = match res.table {
+            SfDiskPartitionTable::Gpt(v) => v,
+            _ => panic!(),
+        };
+
+        assert_eq!(gpt.sector_size, BLOCK_SIZE);
+
+        let first_lba = first_lba();
+        assert_eq!(gpt.first_lba, first_lba);
+
+        let last_lba = last_lba(num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE);
+        assert_eq!(gpt.last_lba, last_lba);
+        assert_eq!(gpt.partitions.len(), 1);
+
+        let part = &gpt.partitions[0];
+        assert_eq!(part.kind, EFI_SYSTEM_PART_GUID);
+        assert_eq!(part.start, start_lba);
+        assert_eq!(part.size, start_end_to_size(start_lba, last_lba));
+    }
+
+    #[test]
+    fn test_partition_with_uuid() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let uuid = Uuid::new_v4();
+        GuidPartitionTableBuilder::new()
+            .add_partition(GuidPartitionBuilder::new_with_uuid(EFI_SYSTEM_PART_GUID, uuid).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let output = Command::new("sfdisk")
+            .arg("-J")
+            .arg(temp_file.path())
+            .output()
+            .unwrap();
+
+        trace!("{}", String::from_utf8(output.stdout.clone()).unwrap());
+
+        let res: SfdiskOutput = serde_json::from_slice(&output.stdout).unwrap();
+
+        let gpt = match res.table {
+            SfDiskPartitionTable::Gpt(v) => v,
+            _ => panic!(),
+        };
+
+        assert_eq!(gpt.partitions.len(), 1);
+        let part = &gpt.partitions[0];
+        assert_eq!(part.uuid, uuid);
+    }
+
+    #[test]
+    fn test_partition_attributes() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .size(16 << 20)
+                    .platform_required(true)
+                    .read_only(true)
+                    .build(),
+            )
+            .add_partition(
+                GuidPartitionBuilder::new(ROOT_PART_GUID_ARM64)
+                    .hidden(true)
+                    .no_auto(true)
+                    .grow_fs(true)
+                    .build(),
+            )
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let mut header = vec![0u8; BLOCK_SIZE * 2 + GPT_PARTITION_ENTRY_SIZE * 2];
+        temp_file.reopen().unwrap().read_exact(&mut header).unwrap();
+
+        let attributes = |idx: usize| {
+            let entry = &header[BLOCK_SIZE * 2 + GPT_PARTITION_ENTRY_SIZE * idx..];
+            u64::from_le_bytes(entry[48..56].try_into().unwrap())
+        };
+
+        assert_eq!(attributes(0), (1 << 60) | (1 << 0));
+        assert_eq!(attributes(1), (1 << 63) | (1 << 62) | (1 << 59));
     }
 
     #[test]
-    fn test_one_partition_no_size() {
+    fn test_partition_entry_size() {
         let temp_file = NamedTempFile::new().unwrap();
         temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
 
+        let entry_size = 256;
+        let part_guid = Uuid::new_v4();
         GuidPartitionTableBuilder::new()
-            .add_partition(GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID).build())
+            .partition_entry_size(entry_size)
+            .add_partition(
+                GuidPartitionBuilder::new_with_uuid(ROOT_PART_GUID_ARM64, part_guid)
+                    .name("root")
+                    .build(),
+            )
             .build()
             .write(temp_file.as_file())
             .unwrap();
 
+        let mut header = vec![0u8; BLOCK_SIZE * 2];
+        temp_file.reopen().unwrap().read_exact(&mut header).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(header[BLOCK_SIZE + 84..BLOCK_SIZE + 88].try_into().unwrap()),
+            num_cast!(u32, entry_size)
+        );
+
         let output = Command::new("sfdisk")
             .arg("-J")
             .arg(temp_file.path())
@@ -600,97 +1473,217 @@ mod tests {
             _ => panic!(),
         };
 
-        assert_eq!(gpt.sector_size, BLOCK_SIZE);
-
-        let first_lba = first_lba();
-        assert_eq!(gpt.first_lba, first_lba);
-
-        let last_lba = last_lba(num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE);
-        assert_eq!(gpt.last_lba, last_lba);
+        let parts_size_lba = (128 * entry_size) / BLOCK_SIZE;
+        let size_lba = num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE;
+        assert_eq!(
+            gpt.first_lba,
+            MBR_SIZE_LBA + GPT_HEADER_SIZE_LBA + parts_size_lba
+        );
+        assert_eq!(
+            gpt.last_lba,
+            size_lba - (GPT_HEADER_SIZE_LBA + parts_size_lba) - 1
+        );
         assert_eq!(gpt.partitions.len(), 1);
 
         let part = &gpt.partitions[0];
-        assert_eq!(part.kind, EFI_SYSTEM_PART_GUID);
-        assert_eq!(part.start, gpt.first_lba);
-        assert_eq!(part.size, start_end_to_size(first_lba, last_lba));
+        assert_eq!(part.start, round_up(gpt.first_lba, PARTITION_ALIGNMENT_LBA));
+        assert_eq!(part.kind, ROOT_PART_GUID_ARM64);
+        assert_eq!(part.uuid, part_guid);
     }
 
     #[test]
-    fn test_one_partition_no_size_offset() {
+    fn test_partition_entry_size_invalid() {
         let temp_file = NamedTempFile::new().unwrap();
         temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
 
-        let start_lba = first_lba() + 4;
-        GuidPartitionTableBuilder::new()
+        for size in [0, 64, 132, 136, 384] {
+            GuidPartitionTableBuilder::new()
+                .partition_entry_size(size)
+                .build()
+                .write(temp_file.as_file())
+                .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_reserved_start() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let reserved = 256 << 10;
+        let table = GuidPartitionTableBuilder::new()
+            .reserved_start_bytes(reserved)
             .add_partition(
                 GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
-                    .offset(start_lba)
+                    .size(16 << 20)
                     .build(),
             )
+            .build();
+
+        assert_eq!(
+            table.minimum_size_bytes(),
+            Some(
+                PARTITION_ALIGNMENT_LBA * BLOCK_SIZE
+                    + (16 << 20)
+                    + (GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA) * BLOCK_SIZE
+            )
+        );
+
+        let info = table.write(temp_file.as_file()).unwrap();
+        assert_eq!(info.partitions[0].start_lba, PARTITION_ALIGNMENT_LBA);
+
+        let mut header = vec![0u8; BLOCK_SIZE * 2];
+        temp_file.reopen().unwrap().read_exact(&mut header).unwrap();
+        assert_eq!(
+            u64::from_le_bytes(header[BLOCK_SIZE + 40..BLOCK_SIZE + 48].try_into().unwrap()),
+            num_cast!(u64, reserved / BLOCK_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_reserved_start_smaller_than_gpt() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let info = GuidPartitionTableBuilder::new()
+            .reserved_start_bytes(4 << 10)
+            .add_partition(GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID).build())
             .build()
             .write(temp_file.as_file())
             .unwrap();
 
-        let output = Command::new("sfdisk")
-            .arg("-J")
-            .arg(temp_file.path())
-            .output()
-            .unwrap();
+        assert_eq!(info.partitions[0].start_lba, PARTITION_ALIGNMENT_LBA);
+    }
+
+    fn read_u64(buf: &[u8], offset: usize) -> usize {
+        num_cast!(
+            usize,
+            u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+        )
+    }
+
+    fn read_u32(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn sfdisk_gpt(path: &Path) -> SfDiskGptPartitionTable {
+        let output = Command::new("sfdisk").arg("-J").arg(path).output().unwrap();
 
         trace!("{}", String::from_utf8(output.stdout.clone()).unwrap());
 
         let res: SfdiskOutput = serde_json::from_slice(&output.stdout).unwrap();
 
-        let gpt = match res.table {
+        match res.table {
             SfDiskPartitionTable::Gpt(v) => v,
             _ => panic!(),
-        };
-
-        assert_eq!(gpt.sector_size, BLOCK_SIZE);
+        }
+    }
 
-        let first_lba = first_lba();
-        assert_eq!(gpt.first_lba, first_lba);
+    fn two_partitions_table(entry_size: usize) -> GuidPartitionTableBuilder {
+        GuidPartitionTableBuilder::new()
+            .partition_entry_size(entry_size)
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .add_partition(GuidPartitionBuilder::new(ROOT_PART_GUID_ARM64).build())
+    }
 
-        let last_lba = last_lba(num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE);
-        assert_eq!(gpt.last_lba, last_lba);
-        assert_eq!(gpt.partitions.len(), 1);
+    #[test]
+    fn test_backup_header() {
+        let crc_alg = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 
-        let part = &gpt.partitions[0];
-        assert_eq!(part.kind, EFI_SYSTEM_PART_GUID);
-        assert_eq!(part.start, start_lba);
-        assert_eq!(part.size, start_end_to_size(start_lba, last_lba));
+        for entry_size in [GPT_PARTITION_ENTRY_SIZE, 256] {
+            let temp_file = NamedTempFile::new().unwrap();
+            temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+            two_partitions_table(entry_size)
+                .build()
+                .write(temp_file.as_file())
+                .unwrap();
+
+            let content = fs::read(temp_file.path()).unwrap();
+            let size_lba = content.len() / BLOCK_SIZE;
+            let parts_size_lba = (128 * entry_size).div_ceil(BLOCK_SIZE);
+
+            let primary = &content[BLOCK_SIZE..BLOCK_SIZE + 92];
+            let backup_lba = size_lba - 1;
+            let backup = &content[backup_lba * BLOCK_SIZE..backup_lba * BLOCK_SIZE + 92];
+
+            let mut unsigned_backup = backup.to_vec();
+            unsigned_backup[16..20].fill(0);
+            assert_eq!(read_u32(backup, 16), crc_alg.checksum(&unsigned_backup));
+
+            // My LBA and Alternate LBA
+            assert_eq!(read_u64(primary, 24), 1);
+            assert_eq!(read_u64(primary, 32), backup_lba);
+            assert_eq!(read_u64(backup, 24), backup_lba);
+            assert_eq!(read_u64(backup, 32), 1);
+
+            // Partition Entry LBA
+            let primary_entries_lba = read_u64(primary, 72);
+            let backup_entries_lba = read_u64(backup, 72);
+            assert_eq!(primary_entries_lba, 2);
+            assert_eq!(backup_entries_lba, backup_lba - parts_size_lba);
+            assert_eq!(
+                read_u64(backup, 48),
+                backup_entries_lba - 1,
+                "Last Usable LBA overlaps with the backup partition entries"
+            );
+
+            // The backup partition entries match the primary ones, and their CRC
+            let entries_size = 128 * entry_size;
+            let primary_entries = &content
+                [primary_entries_lba * BLOCK_SIZE..primary_entries_lba * BLOCK_SIZE + entries_size];
+            let backup_entries = &content
+                [backup_entries_lba * BLOCK_SIZE..backup_entries_lba * BLOCK_SIZE + entries_size];
+            assert_eq!(primary_entries, backup_entries);
+            assert_eq!(read_u32(backup, 88), crc_alg.checksum(backup_entries));
+
+            // Apart from the location fields and the CRC, both headers are identical
+            for range in [0..16, 20..24, 40..72, 80..92] {
+                assert_eq!(primary[range.clone()], backup[range]);
+            }
+        }
     }
 
     #[test]
-    fn test_partition_with_uuid() {
+    fn test_backup_header_recovery() {
         let temp_file = NamedTempFile::new().unwrap();
         temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
 
-        let uuid = Uuid::new_v4();
-        GuidPartitionTableBuilder::new()
-            .add_partition(GuidPartitionBuilder::new_with_uuid(EFI_SYSTEM_PART_GUID, uuid).build())
+        two_partitions_table(GPT_PARTITION_ENTRY_SIZE)
             .build()
             .write(temp_file.as_file())
             .unwrap();
 
-        let output = Command::new("sfdisk")
-            .arg("-J")
-            .arg(temp_file.path())
-            .output()
-            .unwrap();
-
-        trace!("{}", String::from_utf8(output.stdout.clone()).unwrap());
-
-        let res: SfdiskOutput = serde_json::from_slice(&output.stdout).unwrap();
-
-        let gpt = match res.table {
-            SfDiskPartitionTable::Gpt(v) => v,
-            _ => panic!(),
-        };
+        let expected = sfdisk_gpt(temp_file.path());
 
-        assert_eq!(gpt.partitions.len(), 1);
-        let part = &gpt.partitions[0];
-        assert_eq!(part.uuid, uuid);
+        // Wipe the primary header and partition entries, so only the backup ones are left.
+        let mut file = temp_file.reopen().unwrap();
+        file.seek(SeekFrom::Start(num_cast!(u64, BLOCK_SIZE)))
+            .unwrap();
+        file.write_all(&vec![
+            0;
+            (GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA)
+                * BLOCK_SIZE
+        ])
+        .unwrap();
+        file.sync_all().unwrap();
+
+        let recovered = sfdisk_gpt(temp_file.path());
+        assert_eq!(recovered.id, expected.id);
+        assert_eq!(recovered.first_lba, expected.first_lba);
+        assert_eq!(recovered.last_lba, expected.last_lba);
+        assert_eq!(recovered.partitions.len(), 2);
+
+        for (recovered, expected) in zip(&recovered.partitions, &expected.partitions) {
+            assert_eq!(recovered.start, expected.start);
+            assert_eq!(recovered.size, expected.size);
+            assert_eq!(recovered.kind, expected.kind);
+            assert_eq!(recovered.uuid, expected.uuid);
+        }
     }
 
     #[test]
@@ -704,7 +1697,7 @@ mod tests {
         GuidPartitionTableBuilder::new()
             .add_partition(
                 GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
-                    .size(start_end_to_size(first_lba, last_lba) * BLOCK_SIZE)
+                    .size(start_end_to_size(first_partition_lba(), last_lba) * BLOCK_SIZE)
                     .build(),
             )
             .build()
@@ -733,8 +1726,11 @@ mod tests {
 
         let part = &gpt.partitions[0];
         assert_eq!(part.kind, EFI_SYSTEM_PART_GUID);
-        assert_eq!(part.start, gpt.first_lba);
-        assert_eq!(part.size, start_end_to_size(first_lba, last_lba));
+        assert_eq!(part.start, first_partition_lba());
+        assert_eq!(
+            part.size,
+            start_end_to_size(first_partition_lba(), last_lba)
+        );
     }
 
     #[test]
@@ -795,10 +1791,10 @@ mod tests {
         let first_lba = first_lba();
         let last_lba = last_lba(num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE);
 
-        let available_lbas = start_end_to_size(first_lba, last_lba);
+        let available_lbas = start_end_to_size(first_partition_lba(), last_lba);
 
-        let first_part_start = first_lba;
-        let first_part_size = available_lbas / 2;
+        let first_part_start = first_partition_lba();
+        let first_part_size = round_down(available_lbas / 2, PARTITION_ALIGNMENT_LBA);
         let first_part_end = start_size_to_end(first_part_start, first_part_size);
 
         let second_part_start = first_part_end + 1;
@@ -860,13 +1856,12 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = first_lba();
         let last_lba = last_lba(num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE);
 
-        let available_lbas = start_end_to_size(first_lba, last_lba);
+        let available_lbas = start_end_to_size(first_partition_lba(), last_lba);
 
-        let first_part_start = first_lba;
-        let first_part_size = available_lbas / 2;
+        let first_part_start = first_partition_lba();
+        let first_part_size = round_down(available_lbas / 2, PARTITION_ALIGNMENT_LBA);
         let first_part_end = start_size_to_end(first_part_start, first_part_size);
 
         let second_part_start = first_part_end + 1;
@@ -1032,9 +2027,9 @@ mod tests {
         let first_lba = first_lba();
         let last_lba = last_lba(num_cast!(usize, TEMP_FILE_SIZE) / BLOCK_SIZE);
 
-        let available_lbas = start_end_to_size(first_lba, last_lba);
+        let available_lbas = start_end_to_size(first_partition_lba(), last_lba);
 
-        let first_offset_lba = first_lba;
+        let first_offset_lba = first_partition_lba();
         let first_size_lba = available_lbas / 2;
 
         let second_offset_lba = first_offset_lba + first_size_lba;
@@ -1094,4 +2089,305 @@ mod tests {
             .write(temp_file.as_file())
             .unwrap_err();
     }
+
+    #[test]
+    fn test_device_size() {
+        let temp_file = NamedTempFile::new().unwrap();
+
+        GuidPartitionTableBuilder::new()
+            .add_partition(GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+
+        let info = GuidPartitionTableBuilder::new()
+            .device_size_bytes(TEMP_FILE_SIZE)
+            .add_partition(GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        assert_eq!(
+            temp_file.as_file().metadata().unwrap().len(),
+            TEMP_FILE_SIZE
+        );
+
+        let part = info.partitions.first().unwrap();
+        assert!(
+            num_cast!(u64, (part.end_lba + 1) * BLOCK_SIZE)
+                <= TEMP_FILE_SIZE
+                    - num_cast!(
+                        u64,
+                        (GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA) * BLOCK_SIZE
+                    )
+        );
+    }
+
+    #[test]
+    fn test_device_size_shrink() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE * 2).unwrap();
+
+        let table = || {
+            GuidPartitionTableBuilder::new()
+                .device_size_bytes(TEMP_FILE_SIZE)
+                .add_partition(GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID).build())
+        };
+
+        table().build().write(temp_file.as_file()).unwrap_err();
+        assert_eq!(
+            temp_file.as_file().metadata().unwrap().len(),
+            TEMP_FILE_SIZE * 2
+        );
+
+        table()
+            .shrink(true)
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+        assert_eq!(
+            temp_file.as_file().metadata().unwrap().len(),
+            TEMP_FILE_SIZE
+        );
+    }
+
+    #[test]
+    fn test_minimum_size() {
+        let first_part_size = 16 << 20;
+        let second_part_size = 32 << 20;
+
+        let table = GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .size(first_part_size)
+                    .build(),
+            )
+            .add_partition(
+                GuidPartitionBuilder::new(ROOT_PART_GUID_ARM64)
+                    .size(second_part_size)
+                    .build(),
+            )
+            .build();
+
+        let size = table.minimum_size_bytes().unwrap();
+        assert_eq!(
+            size,
+            PARTITION_ALIGNMENT_LBA * BLOCK_SIZE
+                + first_part_size
+                + second_part_size
+                + (GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA) * BLOCK_SIZE
+        );
+
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, size - BLOCK_SIZE))
+            .unwrap();
+        table.partitions_layout(temp_file.as_file()).unwrap_err();
+
+        temp_file.as_file().set_len(num_cast!(u64, size)).unwrap();
+        let info = table.write(temp_file.as_file()).unwrap();
+
+        let part = info.partitions.last().unwrap();
+        assert_eq!(part.end_lba, last_lba(size / BLOCK_SIZE));
+    }
+
+    #[test]
+    fn test_minimum_size_fill_partition() {
+        let table = GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .add_partition(GuidPartitionBuilder::new(ROOT_PART_GUID_ARM64).build())
+            .build();
+
+        assert_eq!(table.minimum_size_bytes(), None);
+    }
+
+    #[test]
+    fn test_protective_mbr_large_device() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(LARGE_FILE_SIZE).unwrap();
+
+        let info = GuidPartitionTableBuilder::new()
+            .add_partition(GuidPartitionBuilder::new(LINUX_DATA_PART_GUID).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let part = info.partitions.first().unwrap();
+        assert_eq!(
+            part.end_lba,
+            last_lba(num_cast!(usize, LARGE_FILE_SIZE) / BLOCK_SIZE)
+        );
+
+        let mut mbr = [0u8; 512];
+        temp_file.reopen().unwrap().read_exact(&mut mbr).unwrap();
+
+        // The protective partition starts right after the MBR, and covers as much as it can
+        assert_eq!(mbr[450], 0xee);
+        assert_eq!(&mbr[454..458], &1u32.to_le_bytes());
+        assert_eq!(&mbr[458..462], &u32::MAX.to_le_bytes());
+    }
+
+    fn boot_partition<B>(builder: B) -> B::Partition
+    where
+        B: PartitionBuilder,
+    {
+        builder.offset(2048).size(16 << 20).bootable(true).build()
+    }
+
+    #[test]
+    fn test_partition_table_writer() {
+        let part_guid = Uuid::new_v4();
+
+        let tables: [Box<dyn PartitionTableWriter>; 2] = [
+            Box::new(
+                GuidPartitionTableBuilder::new()
+                    .add_partition(boot_partition(GuidPartitionBuilder::new_with_uuid(
+                        EFI_SYSTEM_PART_GUID,
+                        part_guid,
+                    )))
+                    .build(),
+            ),
+            Box::new(
+                MasterBootRecordPartitionTableBuilder::new()
+                    .disk_id(0x1234_5678)
+                    .add_partition(boot_partition(MasterBootRecordPartitionBuilder::new(0xef)))
+                    .build(),
+            ),
+        ];
+
+        let expected = [
+            vec![part_guid.hyphenated().to_string()],
+            vec![String::from("12345678-01")],
+        ];
+
+        for (table, expected) in zip(tables, expected) {
+            let temp_file = NamedTempFile::new().unwrap();
+            temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+            let layout = table.partitions_layout(temp_file.as_file()).unwrap();
+            assert_eq!(layout.len(), 1);
+            assert_eq!(layout[0].start_lba, 2048);
+            assert_eq!(
+                start_end_to_size(layout[0].start_lba, layout[0].end_lba),
+                (16 << 20) / BLOCK_SIZE
+            );
+
+            assert!(table.minimum_size_bytes().unwrap() > (2048 * BLOCK_SIZE) + (16 << 20) - 1);
+
+            assert_eq!(table.write_table(temp_file.as_file()).unwrap(), expected);
+
+            let mut mbr = [0u8; 512];
+            temp_file.reopen().unwrap().read_exact(&mut mbr).unwrap();
+            assert_eq!(&mbr[510..512], &[0x55, 0xaa]);
+        }
+    }
+
+    #[test]
+    fn test_hybrid_mbr() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let info = GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .size(16 << 20)
+                    .bootable(true)
+                    .build(),
+            )
+            .add_partition(
+                GuidPartitionBuilder::new(SWAP_PART_GUID)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .add_partition(GuidPartitionBuilder::new(ROOT_PART_GUID_ARM64).build())
+            .hybrid_mbr(&[2, 0])
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let mut mbr = [0u8; 512];
+        temp_file.reopen().unwrap().read_exact(&mut mbr).unwrap();
+
+        let entry = |idx: usize| &mbr[446 + idx * 16..][..16];
+        let start = |idx: usize| u32::from_le_bytes(entry(idx)[8..12].try_into().unwrap());
+        let size = |idx: usize| u32::from_le_bytes(entry(idx)[12..16].try_into().unwrap());
+
+        assert_eq!(&mbr[440..444], &[0; 4]);
+        assert_eq!(entry(0)[4], 0xee);
+        assert_eq!(start(0), 1);
+        assert_eq!(
+            num_cast!(usize, size(0)),
+            GPT_HEADER_SIZE_LBA + GPT_PARTITION_HEADER_SIZE_LBA
+        );
+
+        for (idx, (part, type_, bits)) in [
+            (info.partitions[0], 0xef, 0x80),
+            (info.partitions[2], 0x83, 0x00),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(entry(idx + 1)[0], bits);
+            assert_eq!(entry(idx + 1)[4], type_);
+            assert_eq!(num_cast!(usize, start(idx + 1)), part.start_lba);
+            assert_eq!(
+                num_cast!(usize, size(idx + 1)),
+                start_end_to_size(part.start_lba, part.end_lba)
+            );
+        }
+
+        assert_eq!(entry(3), &[0; 16]);
+    }
+
+    #[test]
+    fn test_hybrid_mbr_mbr_type() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(SWAP_PART_GUID)
+                    .size(16 << 20)
+                    .mbr_type(0x42)
+                    .build(),
+            )
+            .hybrid_mbr(&[0])
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+
+        let mut mbr = [0u8; 512];
+        temp_file.reopen().unwrap().read_exact(&mut mbr).unwrap();
+
+        assert_eq!(mbr[446 + 16 + 4], 0x42);
+    }
+
+    #[test]
+    fn test_hybrid_mbr_invalid() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let table = |partitions: &[usize]| {
+            (0..4)
+                .fold(GuidPartitionTableBuilder::new(), |builder, _| {
+                    builder.add_partition(
+                        GuidPartitionBuilder::new(LINUX_DATA_PART_GUID)
+                            .size(16 << 20)
+                            .build(),
+                    )
+                })
+                .hybrid_mbr(partitions)
+                .build()
+        };
+
+        table(&[0, 1, 2, 3]).write(temp_file.as_file()).unwrap_err();
+        table(&[4]).write(temp_file.as_file()).unwrap_err();
+        table(&[1, 1]).write(temp_file.as_file()).unwrap_err();
+        table(&[0, 1, 2]).write(temp_file.as_file()).unwrap();
+    }
 }
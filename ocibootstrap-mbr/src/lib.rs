@@ -3,22 +3,80 @@
 use core::iter::zip;
 use std::{
     fs::File,
-    io::{self, Seek as _, Write as _},
+    io::{self, Read as _, Seek as _, Write as _},
 };
 
 use bit_field::BitField as _;
 use log::debug;
-use num_traits::ToPrimitive as _;
 use part::{
-    build_layout, div_round_up, num_cast, start_end_to_size, PartitionLayout, PartitionLayoutHint,
+    build_layout_aligned, div_round_up, minimum_end_lba_aligned, num_cast, round_up,
+    start_end_to_size, try_num_cast, PartitionLayoutHint,
 };
+pub use part::{device_size, PartitionBuilder, PartitionLayout, PartitionTableWriter};
 
 const LBA_SIZE: usize = 512;
 
+/// Alignment, in LBAs, of the partitions we place: 1 MiB, like most partitioning tools do
+pub const PARTITION_ALIGNMENT_LBA: usize = 2048;
+
 const MBR_LBA_OFFSET: usize = 0;
 const MBR_LBA_SIZE: usize = 1;
 const MBR_PART_ENTRY_OFFSET_BYTES: usize = 446;
 const MBR_PART_ENTRY_SIZE_BYTES: usize = 16;
+const MBR_PART_ENTRY_NUM: usize = 4;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+/// Number of heads per cylinder used to compute the partitions CHS addresses, unless overridden
+pub const DEFAULT_HEADS_PER_CYLINDER: u8 = 16;
+
+/// Number of sectors per track used to compute the partitions CHS addresses, unless overridden
+pub const DEFAULT_SECTORS_PER_TRACK: u8 = 63;
+
+/// Maximum number of sectors per track a CHS address can encode
+const MAX_SECTORS_PER_TRACK: u8 = 63;
+
+/// Returns the size, in bytes, available to the partitions once an MBR is written to a file
+///
+/// The space before the first aligned LBA can't be used by the partitions we place, and isn't
+/// accounted for.
+///
+/// # Errors
+///
+/// This function will return an [`std::io::Error`] if the [`File`] is too small to hold an MBR
+/// and an aligned partition, or if its metadata can't be accessed.
+pub fn usable_size(file: &File) -> Result<usize, io::Error> {
+    let (first_lba, last_lba) = usable_lba_range(device_size(file)?)?;
+
+    let first_lba = round_up(first_lba, PARTITION_ALIGNMENT_LBA);
+    if first_lba > last_lba {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "File is too small",
+        ));
+    }
+
+    Ok((last_lba - first_lba + 1) * LBA_SIZE)
+}
+
+/// Returns the first and last LBAs available to the partitions once an MBR is written to a device
+/// of the given size, in bytes
+///
+/// # Errors
+///
+/// This function will return an [`std::io::Error`] if the device is too small to hold an MBR.
+pub fn usable_lba_range(device_size: u64) -> Result<(usize, usize), io::Error> {
+    let blocks = try_num_cast!(usize, device_size)? / LBA_SIZE;
+    let overhead_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+
+    if blocks <= overhead_lba {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "File is too small",
+        ));
+    }
+
+    Ok((overhead_lba, blocks - 1))
+}
 
 /// An MBR Partition Entry
 #[derive(Debug)]
@@ -46,6 +104,10 @@ impl MasterBootRecordPartitionBuilder {
             bits: 0,
         }
     }
+}
+
+impl PartitionBuilder for MasterBootRecordPartitionBuilder {
+    type Partition = MasterBootRecordPartition;
 
     /// Sets the partition offset in LBAs from the start of the device.
     /// Unlike the size, if provided, the offset will always be exactly
@@ -54,8 +116,7 @@ impl MasterBootRecordPartitionBuilder {
     /// If the offset isn't provided, the offset used is guaranteed to
     /// be after the end of the previous partition, but isn't guaranteed
     /// to start on the next LBA.
-    #[must_use]
-    pub fn offset(mut self, offset: usize) -> Self {
+    fn offset(mut self, offset: usize) -> Self {
         self.offset_lba = Some(offset);
         self
     }
@@ -65,22 +126,19 @@ impl MasterBootRecordPartitionBuilder {
     ///
     /// If the size isn't provided, the partition will be made to fill any available space. Only one
     /// size-less partition is allowed to be part of a [`MasterBootRecordPartitionTable`].
-    #[must_use]
-    pub fn size(mut self, size: usize) -> Self {
+    fn size(mut self, size: usize) -> Self {
         self.size_lba = Some(div_round_up(size, LBA_SIZE));
         self
     }
 
     /// Marks the partition as bootable.
-    #[must_use]
-    pub fn bootable(mut self, val: bool) -> Self {
+    fn bootable(mut self, val: bool) -> Self {
         self.bits.set_bit(7, val);
         self
     }
 
     /// Creates a [`MasterBootRecordPartition`] from our builder
-    #[must_use]
-    pub fn build(self) -> MasterBootRecordPartition {
+    fn build(self) -> MasterBootRecordPartition {
         MasterBootRecordPartition { builder: self }
     }
 }
@@ -91,6 +149,32 @@ struct MBRTableLayout {
     partitions_offset: Vec<PartitionLayout>,
 }
 
+/// An MBR Partition, as read from a file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MasterBootRecordPartitionInfo {
+    /// Partition Type
+    pub type_: u8,
+
+    /// Whether the partition is marked as bootable
+    pub bootable: bool,
+
+    /// Partition Start LBA
+    pub start_lba: usize,
+
+    /// Partition Size, in LBAs
+    pub size_lba: usize,
+}
+
+/// An MBR Partition Table, as read from a file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MasterBootRecordPartitionTableInfo {
+    /// Disk Identifier
+    pub disk_id: u32,
+
+    /// Primary Partitions, in the order of their entries. Unused entries are skipped.
+    pub partitions: Vec<MasterBootRecordPartitionInfo>,
+}
+
 /// an MBR Partition Table Representation
 #[derive(Debug)]
 pub struct MasterBootRecordPartitionTable {
@@ -98,11 +182,11 @@ pub struct MasterBootRecordPartitionTable {
 }
 
 impl MasterBootRecordPartitionTable {
-    fn lba_to_chs(&self, lba: usize) -> (u16, u8, u8) {
+    fn lba_to_chs(&self, lba: usize) -> (usize, u8, u8) {
         let hpc: usize = self.builder.heads_per_cylinder.into();
         let spt: usize = self.builder.sectors_per_track.into();
 
-        let c = num_cast!(u16, lba / (hpc * spt));
+        let c = lba / (hpc * spt);
         let h = num_cast!(u8, (lba / spt) % hpc);
         let s = num_cast!(u8, (lba % spt) + 1);
 
@@ -112,75 +196,138 @@ impl MasterBootRecordPartitionTable {
     fn lba_to_chs_bytes(&self, lba: usize) -> [u8; 3] {
         let (c, h, s) = self.lba_to_chs(lba);
         if c > ((1 << 10) - 1) {
+            [0xff, 0xff, 0xff]
+        } else {
             let c_lo = num_cast!(u8, c & 0xff);
             let c_hi = num_cast!(u8, (c >> 8) & 0x3);
 
             [h, c_hi << 6 | s & 0x3f, c_lo]
-        } else {
-            [0xff, 0xff, 0xff]
         }
     }
 
-    #[allow(clippy::unwrap_in_result)]
-    fn build_table_layout(&self, file: &File) -> Result<MBRTableLayout, io::Error> {
-        let metadata = file.metadata()?;
-
-        let blocks = num_cast!(usize, metadata.len()) / LBA_SIZE;
-        debug!(
-            "File has len of {} bytes, {} blocks",
-            metadata.len(),
-            blocks
-        );
+    fn device_size(&self, file: &File) -> Result<u64, io::Error> {
+        Ok(match self.builder.device_size {
+            Some(size) => size,
+            None => device_size(file)?,
+        })
+    }
 
-        debug!("Setting up MBR at LBA {MBR_LBA_OFFSET}");
+    fn resize(&self, file: &File) -> Result<(), io::Error> {
+        let Some(size) = self.builder.device_size else {
+            return Ok(());
+        };
 
-        let first_usable_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
-        debug!("First Usable LBA: {first_usable_lba}");
+        let current = device_size(file)?;
+        if current == size {
+            return Ok(());
+        }
 
-        let last_usable_lba = blocks - 1;
-        debug!("Last Usable LBA: {last_usable_lba}");
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Device size is {current} bytes, but {size} bytes were requested"),
+            ));
+        }
 
-        if first_usable_lba > last_usable_lba {
+        if size < current && !self.builder.shrink {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "File is too small",
+                format!(
+                    "File is {current} bytes, shrinking it to the requested {size} bytes would truncate it"
+                ),
             ));
         }
 
-        let parts_hints = self
-            .builder
+        debug!("Resizing file from {current} to {size} bytes");
+
+        file.set_len(size)
+    }
+
+    fn first_usable_lba(&self) -> usize {
+        usize::max(
+            MBR_LBA_OFFSET + MBR_LBA_SIZE,
+            self.builder.reserved_start.div_ceil(LBA_SIZE),
+        )
+    }
+
+    fn layout_hints(&self) -> Vec<PartitionLayoutHint> {
+        self.builder
             .partitions
             .iter()
             .map(|p| PartitionLayoutHint {
                 offset_lba: p.builder.offset_lba,
                 size_lba: p.builder.size_lba,
             })
-            .collect::<Vec<_>>();
+            .collect()
+    }
+
+    fn build_table_layout(&self, file: &File) -> Result<MBRTableLayout, io::Error> {
+        let hpc = self.builder.heads_per_cylinder;
+        let spt = self.builder.sectors_per_track;
+        if hpc == 0 || spt == 0 || spt > MAX_SECTORS_PER_TRACK {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid Geometry: {hpc} heads, {spt} sectors per track"),
+            ));
+        }
+
+        debug!("Using a geometry of {hpc} heads, {spt} sectors per track");
+
+        let device_size = self.device_size(file)?;
+        let blocks = try_num_cast!(usize, device_size)? / LBA_SIZE;
+        debug!("Device has len of {device_size} bytes, {blocks} blocks");
+
+        debug!("Setting up MBR at LBA {MBR_LBA_OFFSET}");
+
+        let first_usable_lba = self.first_usable_lba();
+        debug!("First Usable LBA: {first_usable_lba}");
+
+        if first_usable_lba >= blocks {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "File is too small",
+            ));
+        }
+
+        let last_usable_lba = blocks - 1;
+        debug!("Last Usable LBA: {last_usable_lba}");
+
+        let parts_hints = self.layout_hints();
 
         Ok(MBRTableLayout {
             block_size: LBA_SIZE,
             mbr_header_lba: MBR_LBA_OFFSET,
-            partitions_offset: build_layout(first_usable_lba, last_usable_lba, &parts_hints)?,
+            partitions_offset: build_layout_aligned(
+                first_usable_lba,
+                last_usable_lba,
+                &parts_hints,
+                Some(PARTITION_ALIGNMENT_LBA),
+            )?,
         })
     }
 
     /// Writes an MBR to a file
     ///
-    /// # Errors
+    /// Returns the Disk Identifier that has been generated for the partition table.
     ///
-    /// This function will return an [`std::io::Error`] if there's an issue with the Partition Table
-    /// layout, or when accessing the underlying [`File`].
+    /// If a device size has been set with
+    /// [`MasterBootRecordPartitionTableBuilder::device_size_bytes`], and the file is a regular
+    /// file, it is resized beforehand. It is only shrunk if
+    /// [`MasterBootRecordPartitionTableBuilder::shrink`] has been set.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if we have an integer overflow in one of the integer type conversions
-    #[allow(clippy::unwrap_in_result)]
-    pub fn write(self, mut file: &File) -> Result<(), io::Error> {
+    /// This function will return an [`std::io::Error`] if there's an issue with the Partition Table
+    /// layout, if the partitions don't fit in the 32-bit MBR fields, or when accessing the
+    /// underlying [`File`].
+    pub fn write(self, mut file: &File) -> Result<u32, io::Error> {
+        self.resize(file)?;
+
         let cfg = self.build_table_layout(file)?;
 
         let mut mbr = [0u8; 512];
 
-        let disk_id = rand::random::<u32>();
+        let disk_id = self.builder.disk_id.unwrap_or_else(rand::random::<u32>);
 
         debug!("Using Disk Identifier 0x{:x}", disk_id);
 
@@ -200,29 +347,90 @@ impl MasterBootRecordPartitionTable {
             let chs_bytes = self.lba_to_chs_bytes(layout.end_lba);
             mbr_part[5..8].copy_from_slice(&chs_bytes);
 
-            mbr_part[8..12]
-                .copy_from_slice(&layout.start_lba.to_u32().unwrap_or(u32::MAX).to_le_bytes());
-            mbr_part[12..16].copy_from_slice(
-                &start_end_to_size(layout.start_lba, layout.end_lba)
-                    .to_u32()
-                    .unwrap_or(u32::MAX)
-                    .to_le_bytes(),
-            );
+            let start_lba = try_num_cast!(u32, layout.start_lba)?;
+            mbr_part[8..12].copy_from_slice(&start_lba.to_le_bytes());
+
+            let size_lba = try_num_cast!(u32, start_end_to_size(layout.start_lba, layout.end_lba))?;
+            mbr_part[12..16].copy_from_slice(&size_lba.to_le_bytes());
 
             let part_idx = MBR_PART_ENTRY_OFFSET_BYTES + MBR_PART_ENTRY_SIZE_BYTES * idx;
             mbr[part_idx..(part_idx + MBR_PART_ENTRY_SIZE_BYTES)].copy_from_slice(&mbr_part);
         }
 
-        mbr[510] = 0x55;
-        mbr[511] = 0xaa;
+        mbr[510..512].copy_from_slice(&MBR_SIGNATURE);
 
-        let seek_offset = num_cast!(u64, cfg.mbr_header_lba * cfg.block_size);
+        let seek_offset = try_num_cast!(u64, cfg.mbr_header_lba * cfg.block_size)?;
         file.seek(io::SeekFrom::Start(seek_offset))?;
         file.write_all(&mbr)?;
         file.flush()?;
         file.sync_data()?;
 
-        Ok(())
+        Ok(disk_id)
+    }
+
+    /// Reads the MBR of a file
+    ///
+    /// Returns the Disk Identifier and the primary partitions found in the MBR.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an [`std::io::Error`] if the MBR signature is missing, or when
+    /// accessing the underlying [`File`].
+    pub fn read(mut file: &File) -> Result<MasterBootRecordPartitionTableInfo, io::Error> {
+        let mut mbr = [0u8; 512];
+
+        file.seek(io::SeekFrom::Start(try_num_cast!(
+            u64,
+            MBR_LBA_OFFSET * LBA_SIZE
+        )?))?;
+        file.read_exact(&mut mbr)?;
+
+        if mbr[510..512] != MBR_SIGNATURE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Missing MBR Signature",
+            ));
+        }
+
+        let mut disk_id = [0u8; 4];
+        disk_id.copy_from_slice(&mbr[440..444]);
+        let disk_id = u32::from_le_bytes(disk_id);
+
+        debug!("Found Disk Identifier 0x{disk_id:x}");
+
+        let (entries, _) =
+            mbr[MBR_PART_ENTRY_OFFSET_BYTES..].as_chunks::<MBR_PART_ENTRY_SIZE_BYTES>();
+
+        let mut partitions = Vec::with_capacity(MBR_PART_ENTRY_NUM);
+        for (idx, mbr_part) in entries.iter().take(MBR_PART_ENTRY_NUM).enumerate() {
+            let type_ = mbr_part[4];
+            if type_ == 0 {
+                debug!("Partition {idx}: Unused");
+                continue;
+            }
+
+            let mut start_lba = [0u8; 4];
+            start_lba.copy_from_slice(&mbr_part[8..12]);
+
+            let mut size_lba = [0u8; 4];
+            size_lba.copy_from_slice(&mbr_part[12..16]);
+
+            let part = MasterBootRecordPartitionInfo {
+                type_,
+                bootable: mbr_part[0].get_bit(7),
+                start_lba: try_num_cast!(usize, u32::from_le_bytes(start_lba))?,
+                size_lba: try_num_cast!(usize, u32::from_le_bytes(size_lba))?,
+            };
+
+            debug!("Partition {idx}: {part:?}");
+
+            partitions.push(part);
+        }
+
+        Ok(MasterBootRecordPartitionTableInfo {
+            disk_id,
+            partitions,
+        })
     }
 }
 
@@ -231,6 +439,10 @@ impl MasterBootRecordPartitionTable {
 pub struct MasterBootRecordPartitionTableBuilder {
     heads_per_cylinder: u8,
     sectors_per_track: u8,
+    device_size: Option<u64>,
+    shrink: bool,
+    disk_id: Option<u32>,
+    reserved_start: usize,
     partitions: Vec<MasterBootRecordPartition>,
 }
 
@@ -239,12 +451,72 @@ impl MasterBootRecordPartitionTableBuilder {
     #[must_use]
     pub fn new() -> Self {
         Self {
-            heads_per_cylinder: 16,
-            sectors_per_track: 63,
+            heads_per_cylinder: DEFAULT_HEADS_PER_CYLINDER,
+            sectors_per_track: DEFAULT_SECTORS_PER_TRACK,
+            device_size: None,
+            shrink: false,
+            disk_id: None,
+            reserved_start: 0,
             partitions: Vec::new(),
         }
     }
 
+    /// Sets the size of the device, in bytes, instead of using the file size
+    ///
+    /// This allows to create a partition table on a new, empty, file: it will be resized to that
+    /// size when the table is written.
+    #[must_use]
+    pub fn device_size_bytes(mut self, size: u64) -> Self {
+        self.device_size = Some(size);
+        self
+    }
+
+    /// Allows to shrink a regular file larger than the size set with
+    /// [`MasterBootRecordPartitionTableBuilder::device_size_bytes`]
+    ///
+    /// Writing the table fails otherwise, since everything past that size would be lost.
+    #[must_use]
+    pub fn shrink(mut self, shrink: bool) -> Self {
+        self.shrink = shrink;
+        self
+    }
+
+    /// Sets the Disk Identifier, instead of using a random one
+    #[must_use]
+    pub fn disk_id(mut self, id: u32) -> Self {
+        self.disk_id = Some(id);
+        self
+    }
+
+    /// Sets the number of heads per cylinder used to compute the partitions CHS addresses
+    ///
+    /// Defaults to [`DEFAULT_HEADS_PER_CYLINDER`]. It should match the geometry of the
+    /// filesystems stored in the partitions, FAT in particular.
+    #[must_use]
+    pub fn heads_per_cylinder(mut self, heads: u8) -> Self {
+        self.heads_per_cylinder = heads;
+        self
+    }
+
+    /// Sets the number of sectors per track used to compute the partitions CHS addresses
+    ///
+    /// Defaults to [`DEFAULT_SECTORS_PER_TRACK`], and can't be larger than 63.
+    #[must_use]
+    pub fn sectors_per_track(mut self, sectors: u8) -> Self {
+        self.sectors_per_track = sectors;
+        self
+    }
+
+    /// Leaves the given number of bytes at the start of the device out of the partitions
+    ///
+    /// This is meant for firmwares loaded from a fixed offset by the boot ROM. The MBR itself is
+    /// still written to the first LBA, so the firmware must start after it.
+    #[must_use]
+    pub fn reserved_start_bytes(mut self, size: usize) -> Self {
+        self.reserved_start = size;
+        self
+    }
+
     /// Adds a [`MasterBootRecordPartition`] to the Partition Table
     #[must_use]
     pub fn add_partition(mut self, part: MasterBootRecordPartition) -> Self {
@@ -259,6 +531,54 @@ impl MasterBootRecordPartitionTableBuilder {
     }
 }
 
+impl PartitionTableWriter for MasterBootRecordPartitionTable {
+    /// Computes the layout the partitions would have once the MBR is written to a file, without
+    /// modifying it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an [`std::io::Error`] if there's an issue with the Partition Table
+    /// layout, or when accessing the underlying [`File`] metadata.
+    fn partitions_layout(&self, file: &File) -> Result<Vec<PartitionLayout>, io::Error> {
+        Ok(self.build_table_layout(file)?.partitions_offset)
+    }
+
+    /// Returns the size, in bytes, of the smallest device the partitions fit in
+    ///
+    /// Returns `None` if a partition has no size, since it would extend up to the end of the
+    /// device.
+    fn minimum_size_bytes(&self) -> Option<usize> {
+        let first_usable_lba = self.first_usable_lba();
+
+        let end_lba = minimum_end_lba_aligned(
+            first_usable_lba,
+            &self.layout_hints(),
+            Some(PARTITION_ALIGNMENT_LBA),
+        )?
+        // We need at least one usable LBA
+        .max(first_usable_lba + 1);
+
+        Some(end_lba * LBA_SIZE)
+    }
+
+    /// Returns the size, in bytes, of the MBR at the start of the device. Nothing is stored at
+    /// its end.
+    fn table_size_bytes(&self) -> (usize, usize) {
+        ((MBR_LBA_OFFSET + MBR_LBA_SIZE) * LBA_SIZE, 0)
+    }
+
+    fn write_table(self: Box<Self>, file: &File) -> Result<Vec<String>, io::Error> {
+        let num_partitions = self.builder.partitions.len();
+        let disk_id = self.write(file)?;
+
+        // Linux builds the PARTUUID of MBR partitions from the disk identifier and the partition
+        // number.
+        Ok((1..=num_partitions)
+            .map(|idx| format!("{disk_id:08x}-{idx:02x}"))
+            .collect())
+    }
+}
+
 impl Default for MasterBootRecordPartitionTableBuilder {
     fn default() -> Self {
         Self::new()
@@ -267,18 +587,24 @@ impl Default for MasterBootRecordPartitionTableBuilder {
 
 #[cfg(test)]
 mod tests {
-    use std::{path::PathBuf, process::Command};
+    use std::{
+        io::{self, Read as _},
+        path::PathBuf,
+        process::Command,
+    };
 
     use log::{debug, trace};
     use num_traits::ToPrimitive;
-    use part::{num_cast, round_up};
+    use part::{num_cast, round_down, round_up};
     use serde::{de, Deserialize};
     use tempfile::NamedTempFile;
     use test_log::test;
 
     use crate::{
-        MasterBootRecordPartitionBuilder, MasterBootRecordPartitionTableBuilder, LBA_SIZE,
-        MBR_LBA_OFFSET, MBR_LBA_SIZE,
+        MasterBootRecordPartitionBuilder, MasterBootRecordPartitionInfo,
+        MasterBootRecordPartitionTable, MasterBootRecordPartitionTableBuilder,
+        PartitionBuilder as _, PartitionTableWriter as _, LBA_SIZE, MBR_LBA_OFFSET, MBR_LBA_SIZE,
+        MBR_PART_ENTRY_OFFSET_BYTES, MBR_PART_ENTRY_SIZE_BYTES, PARTITION_ALIGNMENT_LBA,
     };
 
     const TEST_PARTITION_TYPE: u8 = 42;
@@ -286,6 +612,12 @@ mod tests {
 
     const TEMP_FILE_SIZE: usize = 2 << 30;
 
+    // Larger than the 2 TiB a 32-bit LBA can address
+    const LARGE_FILE_SIZE: u64 = 3 << 40;
+
+    const TEST_HEADS_PER_CYLINDER: u8 = 255;
+    const TEST_SECTORS_PER_TRACK: u8 = 32;
+
     fn deserialize_hex_to_u32<'de, D>(deserializer: D) -> Result<u32, D::Error>
     where
         D: de::Deserializer<'de>,
@@ -435,7 +767,7 @@ mod tests {
         let part = &table.partitions[0];
         assert_eq!(part.kind, TEST_PARTITION_TYPE);
 
-        let start = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let start = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
         assert_eq!(part.start, start);
 
         let size = (TEMP_FILE_SIZE / LBA_SIZE) - start;
@@ -496,7 +828,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
         let last_lba = (TEMP_FILE_SIZE / LBA_SIZE) - MBR_LBA_SIZE;
         MasterBootRecordPartitionTableBuilder::new()
             .add_partition(
@@ -540,7 +872,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
         let last_lba = (TEMP_FILE_SIZE / LBA_SIZE) - MBR_LBA_SIZE;
 
         let start_lba = round_up(first_lba, 100);
@@ -588,7 +920,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
         let last_lba = (TEMP_FILE_SIZE / LBA_SIZE) - MBR_LBA_SIZE;
 
         let part_size_bytes = (last_lba - first_lba) * LBA_SIZE - 10;
@@ -634,7 +966,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
 
         debug!("First LBA is {first_lba}");
 
@@ -642,7 +974,7 @@ mod tests {
 
         debug!("Last LBA is {last_lba}");
 
-        let cutoff_lba = (last_lba - first_lba) / 2;
+        let cutoff_lba = round_down((last_lba - first_lba) / 2, PARTITION_ALIGNMENT_LBA);
 
         debug!("Cutoff LBA is {cutoff_lba}");
 
@@ -696,7 +1028,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
 
         debug!("First LBA is {first_lba}");
 
@@ -704,7 +1036,7 @@ mod tests {
 
         debug!("Last LBA is {last_lba}");
 
-        let cutoff_lba = (last_lba - first_lba) / 2;
+        let cutoff_lba = round_down((last_lba - first_lba) / 2, PARTITION_ALIGNMENT_LBA);
 
         debug!("Cutoff LBA is {cutoff_lba}");
 
@@ -760,7 +1092,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
 
         debug!("First LBA is {first_lba}");
 
@@ -768,7 +1100,7 @@ mod tests {
 
         debug!("Last LBA is {last_lba}");
 
-        let cutoff_lba = (last_lba - first_lba) / 2;
+        let cutoff_lba = round_down((last_lba - first_lba) / 2, PARTITION_ALIGNMENT_LBA);
 
         debug!("Cutoff LBA is {cutoff_lba}");
 
@@ -796,7 +1128,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
 
         debug!("First LBA is {first_lba}");
 
@@ -871,7 +1203,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
 
         debug!("First LBA is {first_lba}");
 
@@ -946,7 +1278,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
 
         debug!("First LBA is {first_lba}");
 
@@ -1016,7 +1348,7 @@ mod tests {
             .set_len(num_cast!(u64, TEMP_FILE_SIZE))
             .unwrap();
 
-        let first_lba = MBR_LBA_OFFSET + MBR_LBA_SIZE;
+        let first_lba = round_up(MBR_LBA_OFFSET + MBR_LBA_SIZE, PARTITION_ALIGNMENT_LBA);
 
         debug!("First LBA is {first_lba}");
 
@@ -1024,7 +1356,7 @@ mod tests {
 
         debug!("Last LBA is {last_lba}");
 
-        let first_size = ((last_lba - first_lba) + 1) / 3;
+        let first_size = round_down(((last_lba - first_lba) + 1) / 3, PARTITION_ALIGNMENT_LBA);
         let first_part_lba = first_lba;
 
         let second_size = first_size;
@@ -1104,4 +1436,282 @@ mod tests {
             .write(temp_file.as_file())
             .unwrap_err();
     }
+
+    #[test]
+    fn test_device_size() {
+        let temp_file = NamedTempFile::new().unwrap();
+
+        MasterBootRecordPartitionTableBuilder::new()
+            .add_partition(MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+
+        let table = MasterBootRecordPartitionTableBuilder::new()
+            .device_size_bytes(num_cast!(u64, TEMP_FILE_SIZE))
+            .add_partition(MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE).build())
+            .build();
+
+        let layout = table.partitions_layout(temp_file.as_file()).unwrap();
+        assert_eq!(
+            layout.first().unwrap().end_lba,
+            TEMP_FILE_SIZE / LBA_SIZE - 1
+        );
+        assert_eq!(temp_file.as_file().metadata().unwrap().len(), 0);
+
+        table.write(temp_file.as_file()).unwrap();
+        assert_eq!(
+            temp_file.as_file().metadata().unwrap().len(),
+            num_cast!(u64, TEMP_FILE_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_device_size_shrink() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE * 2))
+            .unwrap();
+
+        let table = || {
+            MasterBootRecordPartitionTableBuilder::new()
+                .device_size_bytes(num_cast!(u64, TEMP_FILE_SIZE))
+                .add_partition(MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE).build())
+        };
+
+        table().build().write(temp_file.as_file()).unwrap_err();
+        assert_eq!(
+            temp_file.as_file().metadata().unwrap().len(),
+            num_cast!(u64, TEMP_FILE_SIZE * 2)
+        );
+
+        table()
+            .shrink(true)
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+        assert_eq!(
+            temp_file.as_file().metadata().unwrap().len(),
+            num_cast!(u64, TEMP_FILE_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_geometry() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        let table = MasterBootRecordPartitionTableBuilder::new()
+            .heads_per_cylinder(TEST_HEADS_PER_CYLINDER)
+            .sectors_per_track(TEST_SECTORS_PER_TRACK)
+            .add_partition(
+                MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .build();
+
+        let layout = table.partitions_layout(temp_file.as_file()).unwrap();
+        let part = layout.first().unwrap();
+        table.write(temp_file.as_file()).unwrap();
+
+        let mut mbr = [0u8; 512];
+        temp_file.reopen().unwrap().read_exact(&mut mbr).unwrap();
+
+        let entry = &mbr[MBR_PART_ENTRY_OFFSET_BYTES..][..MBR_PART_ENTRY_SIZE_BYTES];
+        for (lba, chs_offset) in [(part.start_lba, 1), (part.end_lba, 5)] {
+            let chs: [u8; 3] = entry[chs_offset..chs_offset + 3].try_into().unwrap();
+            let hpc = usize::from(TEST_HEADS_PER_CYLINDER);
+            let spt = usize::from(TEST_SECTORS_PER_TRACK);
+            let c = lba / (hpc * spt);
+
+            assert_eq!(usize::from(chs[0]), (lba / spt) % hpc);
+            assert_eq!(usize::from(chs[1] & 0x3f), (lba % spt) + 1);
+            assert_eq!(usize::from(chs[1] >> 6) << 8 | usize::from(chs[2]), c);
+        }
+    }
+
+    #[test]
+    fn test_invalid_geometry() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        MasterBootRecordPartitionTableBuilder::new()
+            .sectors_per_track(64)
+            .add_partition(MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_minimum_size() {
+        let table = MasterBootRecordPartitionTableBuilder::new()
+            .add_partition(
+                MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE)
+                    .offset(2048)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .build();
+
+        assert_eq!(
+            table.minimum_size_bytes(),
+            Some((2048 * LBA_SIZE) + (16 << 20))
+        );
+
+        let table = MasterBootRecordPartitionTableBuilder::new()
+            .add_partition(MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE).build())
+            .build();
+
+        assert_eq!(table.minimum_size_bytes(), None);
+    }
+
+    #[test]
+    fn test_reserved_start() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        let reserved = 256 << 10;
+        let table = MasterBootRecordPartitionTableBuilder::new()
+            .reserved_start_bytes(reserved)
+            .add_partition(
+                MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .build();
+
+        assert_eq!(
+            table.minimum_size_bytes(),
+            Some(PARTITION_ALIGNMENT_LBA * LBA_SIZE + (16 << 20))
+        );
+
+        table.write(temp_file.as_file()).unwrap();
+
+        let info = MasterBootRecordPartitionTable::read(&temp_file.reopen().unwrap()).unwrap();
+        assert_eq!(info.partitions.len(), 1);
+        assert_eq!(info.partitions[0].start_lba, PARTITION_ALIGNMENT_LBA);
+    }
+
+    #[test]
+    fn test_reserved_start_offset_too_small() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        MasterBootRecordPartitionTableBuilder::new()
+            .reserved_start_bytes(256 << 10)
+            .add_partition(
+                MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE)
+                    .offset(8)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_partition_size_overflow() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(LARGE_FILE_SIZE).unwrap();
+
+        let err = MasterBootRecordPartitionTableBuilder::new()
+            .add_partition(MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_read() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        let table = MasterBootRecordPartitionTableBuilder::new()
+            .add_partition(
+                MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE)
+                    .size(16 << 20)
+                    .bootable(true)
+                    .build(),
+            )
+            .add_partition(
+                MasterBootRecordPartitionBuilder::new(TEST_PARTITION_SECONDARY_TYPE).build(),
+            )
+            .build();
+
+        let layout = table.partitions_layout(temp_file.as_file()).unwrap();
+        let disk_id = table.write(temp_file.as_file()).unwrap();
+
+        let info = MasterBootRecordPartitionTable::read(&temp_file.reopen().unwrap()).unwrap();
+        assert_eq!(info.disk_id, disk_id);
+        assert_eq!(
+            info.partitions,
+            [
+                MasterBootRecordPartitionInfo {
+                    type_: TEST_PARTITION_TYPE,
+                    bootable: true,
+                    start_lba: layout[0].start_lba,
+                    size_lba: (16 << 20) / LBA_SIZE,
+                },
+                MasterBootRecordPartitionInfo {
+                    type_: TEST_PARTITION_SECONDARY_TYPE,
+                    bootable: false,
+                    start_lba: layout[1].start_lba,
+                    size_lba: layout[1].end_lba - layout[1].start_lba + 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disk_id() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        let disk_id = MasterBootRecordPartitionTableBuilder::new()
+            .disk_id(0x1234_5678)
+            .add_partition(MasterBootRecordPartitionBuilder::new(TEST_PARTITION_TYPE).build())
+            .build()
+            .write(temp_file.as_file())
+            .unwrap();
+        assert_eq!(disk_id, 0x1234_5678);
+
+        let info = MasterBootRecordPartitionTable::read(&temp_file.reopen().unwrap()).unwrap();
+        assert_eq!(info.disk_id, 0x1234_5678);
+    }
+
+    #[test]
+    fn test_read_no_signature() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .set_len(num_cast!(u64, TEMP_FILE_SIZE))
+            .unwrap();
+
+        let err = MasterBootRecordPartitionTable::read(temp_file.as_file()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
 }
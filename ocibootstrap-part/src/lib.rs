@@ -1,7 +1,11 @@
 #![doc = include_str!("../README.md")]
 
 use core::ops::{Add, Div, Mul, Rem, Sub};
-use std::io;
+use std::{
+    fs::File,
+    io::{self, Seek as _},
+    os::unix::fs::FileTypeExt as _,
+};
 
 use log::debug;
 use num_traits::{ConstOne, ConstZero};
@@ -76,6 +80,29 @@ macro_rules! num_cast {
     };
 }
 
+/// Converts an integer to another integer type, returning an error if it doesn't fit
+///
+/// The macro evaluates to a `Result<$t, std::io::Error>`, with an
+/// [`std::io::ErrorKind::InvalidInput`] error if the conversion fails. It should be preferred
+/// over [`num_cast`] for values that are derived from user input or from the device.
+#[macro_export]
+macro_rules! try_num_cast {
+    ($t: ty, $v: expr) => {{
+        let value = $v;
+
+        <$t>::try_from(value).map_err(|_err| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Integer Overflow ({} to {})",
+                    $crate::type_name_of_expr(value),
+                    core::any::type_name::<$t>(),
+                ),
+            )
+        })
+    }};
+}
+
 /// Computes the size between a start and end indexes
 ///
 /// # Panics
@@ -124,6 +151,38 @@ where
     end - (size - T::ONE)
 }
 
+/// Aligns a range of LBAs, and checks that it still fits
+///
+/// The start is rounded up to the next multiple of `align`, and the size is rounded down to a
+/// multiple of `size_align` if there's one. Returns the aligned start and size, or `None` if an
+/// alignment is zero, if the size is zero or gets rounded down to zero, or if the aligned range
+/// doesn't end before or at `last_usable`.
+#[must_use]
+pub fn align_range(
+    start: usize,
+    size: usize,
+    align: usize,
+    size_align: Option<usize>,
+    last_usable: usize,
+) -> Option<(usize, usize)> {
+    if align == 0 || size_align == Some(0) {
+        return None;
+    }
+
+    let size = size_align.map_or(size, |size_align| round_down(size, size_align));
+    if size == 0 {
+        return None;
+    }
+
+    let aligned_start = start.checked_next_multiple_of(align)?;
+    let end = aligned_start.checked_add(size - 1)?;
+    if end > last_usable {
+        return None;
+    }
+
+    Some((aligned_start, size))
+}
+
 /// Size and Offset Partition Requirements for our layout
 #[derive(Debug)]
 pub struct PartitionLayoutHint {
@@ -144,6 +203,123 @@ pub struct PartitionLayout {
     pub end_lba: usize,
 }
 
+/// Setters shared by the partition builders of all the partition table formats
+pub trait PartitionBuilder: Sized {
+    /// The partition built
+    type Partition;
+
+    /// Sets the partition offset in LBAs from the start of the device
+    #[must_use]
+    fn offset(self, offset: usize) -> Self;
+
+    /// Sets the partition size in bytes
+    #[must_use]
+    fn size(self, size: usize) -> Self;
+
+    /// Marks the partition as bootable
+    #[must_use]
+    fn bootable(self, val: bool) -> Self;
+
+    /// Creates the partition from our builder
+    #[must_use]
+    fn build(self) -> Self::Partition;
+}
+
+/// Returns the size, in bytes, of a file or of a block device
+///
+/// The metadata of a block device report a size of 0, so its size is retrieved by seeking to its
+/// end instead. The file position is left untouched.
+///
+/// # Errors
+///
+/// This function will return an [`std::io::Error`] if the [`File`] metadata can't be accessed, or
+/// if the block device can't be seeked into.
+pub fn device_size(file: &File) -> Result<u64, io::Error> {
+    let metadata = file.metadata()?;
+    if !metadata.file_type().is_block_device() {
+        return Ok(metadata.len());
+    }
+
+    let mut file = file;
+    let position = file.stream_position()?;
+    let size = file.seek(io::SeekFrom::End(0))?;
+    file.seek(io::SeekFrom::Start(position))?;
+
+    Ok(size)
+}
+
+/// A partition table that can be written to a file, whatever its format
+pub trait PartitionTableWriter {
+    /// Computes the layout the partitions would have once the partition table is written to a
+    /// file, without modifying it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an [`std::io::Error`] if there's an issue with the Partition Table
+    /// layout, or when accessing the underlying [`File`] metadata.
+    fn partitions_layout(&self, file: &File) -> Result<Vec<PartitionLayout>, io::Error>;
+
+    /// Returns the size, in bytes, of the smallest device the partitions fit in, or `None` if a
+    /// partition has no size
+    fn minimum_size_bytes(&self) -> Option<usize>;
+
+    /// Returns the size, in bytes, the partition table itself occupies at the start and at the
+    /// end of the device
+    fn table_size_bytes(&self) -> (usize, usize);
+
+    /// Writes the partition table to a file
+    ///
+    /// Returns the unique identifier of each partition, formatted the way Linux reports it as the
+    /// partition PARTUUID.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an [`std::io::Error`] if there's an issue with the Partition Table
+    /// layout, or when accessing the underlying [`File`].
+    fn write_table(self: Box<Self>, file: &File) -> Result<Vec<String>, io::Error>;
+}
+
+/// Returns the first LBA past the last partition of a layout, if all its partitions have a size
+///
+/// Partitions without an offset are placed right after the previous one, like
+/// [`build_layout`] does. Returns `None` if any partition has no size, since it would extend up to
+/// the end of the device.
+#[must_use]
+pub fn minimum_end_lba(first_usable_lba: usize, parts: &[PartitionLayoutHint]) -> Option<usize> {
+    minimum_end_lba_aligned(first_usable_lba, parts, None)
+}
+
+/// Returns the first LBA past the last partition of a layout, if all its partitions have a size,
+/// once the offsets we derive are aligned like [`build_layout_aligned`] does
+///
+/// Returns `None` if any partition has no size, or if the alignment is zero.
+#[must_use]
+pub fn minimum_end_lba_aligned(
+    first_usable_lba: usize,
+    parts: &[PartitionLayoutHint],
+    alignment_lba: Option<usize>,
+) -> Option<usize> {
+    if alignment_lba == Some(0) {
+        return None;
+    }
+
+    let mut first_available_lba = first_usable_lba;
+    let mut end_lba = first_usable_lba;
+
+    for part in parts {
+        let offset_lba = part.offset_lba.unwrap_or_else(|| {
+            alignment_lba.map_or(first_available_lba, |align| {
+                round_up(first_available_lba, align)
+            })
+        });
+
+        first_available_lba = offset_lba + part.size_lba?;
+        end_lba = end_lba.max(first_available_lba);
+    }
+
+    Some(end_lba)
+}
+
 /// Builds the partition layout for partition table out of a set of constraints
 ///
 /// # Errors
@@ -153,13 +329,44 @@ pub struct PartitionLayout {
 /// # Panics
 ///
 /// If the code confused itself
+pub fn build_layout(
+    first_usable_lba: usize,
+    last_usable_lba: usize,
+    parts: &[PartitionLayoutHint],
+) -> Result<Vec<PartitionLayout>, io::Error> {
+    build_layout_aligned(first_usable_lba, last_usable_lba, parts, None)
+}
+
+/// Builds the partition layout for partition table out of a set of constraints, aligning the
+/// partitions offsets we derive to a multiple of `alignment_lba` LBAs.
+///
+/// The offsets explicitly provided in the hints are always used as is, even if they aren't
+/// aligned. The sizes we derive are shrunk to fit into the space left after alignment.
+///
+/// # Errors
+///
+/// Returns an [`std::io::Error`] if the constraints can't be met, or if the alignment is zero.
+///
+/// # Panics
+///
+/// If the code confused itself
 #[expect(clippy::too_many_lines)]
 #[expect(clippy::panic_in_result_fn)]
-pub fn build_layout(
+pub fn build_layout_aligned(
     first_usable_lba: usize,
     last_usable_lba: usize,
     parts: &[PartitionLayoutHint],
+    alignment_lba: Option<usize>,
 ) -> Result<Vec<PartitionLayout>, io::Error> {
+    if alignment_lba == Some(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Partition alignment can't be zero",
+        ));
+    }
+
+    let align_down = |lba: usize| alignment_lba.map_or(lba, |align| round_down(lba, align));
+
     let missing_size_count = parts.iter().filter(|p| p.size_lba.is_none()).count();
     if missing_size_count > 1 {
         return Err(io::Error::new(
@@ -178,10 +385,26 @@ pub fn build_layout(
     for idx in 0..parts.len() {
         let part = &parts[idx];
 
-        let part_offset_lba = if let Some(offset_lba) = part.offset_lba {
-            offset_lba
-        } else {
-            first_available_lba
+        let part_offset_lba = match (part.offset_lba, alignment_lba) {
+            (Some(offset_lba), _) => offset_lba,
+            (None, None) => first_available_lba,
+            (None, Some(align)) => {
+                let (offset_lba, _) = align_range(
+                    first_available_lba,
+                    part.size_lba.unwrap_or(1),
+                    align,
+                    None,
+                    last_usable_lba,
+                )
+                .ok_or(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Partition {idx} doesn't fit before LBA {last_usable_lba} once aligned to {align} LBAs."
+                    ),
+                ))?;
+
+                offset_lba
+            }
         };
 
         debug!("Partition {idx}: Offset is {:#?}", part_offset_lba);
@@ -223,7 +446,16 @@ pub fn build_layout(
             } else if let (Some(size_lba), None) = (part.size_lba, part.offset_lba) {
                 debug!("Partition {idx}: Fixed size ({size_lba} LBAs). Last Available LBA {last_available_lba}");
 
-                let offset_lba = last_available_lba - (size_lba - 1);
+                let offset_lba = align_down(
+                    last_available_lba
+                        .checked_sub(size_lba - 1)
+                        .ok_or(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "Partition {idx} ({size_lba} LBAs) doesn't fit before LBA {last_allocated_lba}."
+                            ),
+                        ))?,
+                );
 
                 debug!(
                         "Partition {idx}: Fixed size ({size_lba} LBAs). Offset derived at LBA {offset_lba}"
@@ -231,6 +463,16 @@ pub fn build_layout(
 
                 (offset_lba, size_lba)
             } else if let (None, Some(offset_lba)) = (part.size_lba, part.offset_lba) {
+                if offset_lba > last_available_lba {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Partition {idx} (no size, starting at LBA {offset_lba}) overlaps with partition {} starting at LBA {last_allocated_lba}.",
+                            idx + 1
+                        ),
+                    ));
+                }
+
                 let size_lba = (last_available_lba - offset_lba) + 1;
 
                 debug!(
@@ -240,6 +482,17 @@ pub fn build_layout(
                 (offset_lba, size_lba)
             } else {
                 let offset_lba = missing_part_offset_lba;
+
+                if offset_lba > last_available_lba {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Partition {idx} (no size, starting at LBA {offset_lba}) overlaps with partition {} starting at LBA {last_allocated_lba}.",
+                            idx + 1
+                        ),
+                    ));
+                }
+
                 let size_lba = (last_available_lba - missing_part_offset_lba) + 1;
 
                 debug!(
@@ -265,31 +518,47 @@ pub fn build_layout(
         "Our array must and should not have any None by now."
     );
 
-    let mut next_available_lba = first_usable_lba;
-    for (offset, size) in array.iter().flatten() {
-        if *offset < first_usable_lba {
+    let mut previous: Option<(usize, usize, usize)> = None;
+    for (idx, (offset, size)) in array.iter().flatten().enumerate() {
+        let offset = *offset;
+        let end = offset + (size - 1);
+
+        if offset < first_usable_lba {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "Partition starts before first usable LBA.",
+                format!(
+                    "Partition {idx} (LBAs {offset}-{end}) starts before first usable LBA {first_usable_lba}."
+                ),
             ));
         }
 
-        if *offset < next_available_lba {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Partition overlaps with previous partition.",
-            ));
+        if let Some((prev_idx, prev_offset, prev_end)) = previous {
+            if offset <= prev_end {
+                let msg = if parts[idx].offset_lba.is_some() && parts[prev_idx].offset_lba.is_some()
+                {
+                    format!(
+                        "Partition {idx} fixed offset (LBAs {offset}-{end}) starts before the end of partition {prev_idx} fixed offset (LBAs {prev_offset}-{prev_end})."
+                    )
+                } else {
+                    format!(
+                        "Partition {idx} (LBAs {offset}-{end}) overlaps with partition {prev_idx} (LBAs {prev_offset}-{prev_end})."
+                    )
+                };
+
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
         }
 
-        let end = offset + (size - 1);
         if end > last_usable_lba {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "Partition overflows the device",
+                format!(
+                    "Partition {idx} (LBAs {offset}-{end}) overflows the device (last usable LBA {last_usable_lba})"
+                ),
             ));
         }
 
-        next_available_lba = offset + size;
+        previous = Some((idx, offset, end));
     }
 
     Ok(array
@@ -305,3 +574,41 @@ pub fn build_layout(
         })
         .collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use crate::align_range;
+
+    #[test]
+    fn test_align_range_fits() {
+        assert_eq!(align_range(34, 2048, 2048, None, 8191), Some((2048, 2048)));
+        assert_eq!(
+            align_range(2048, 2048, 2048, None, 4095),
+            Some((2048, 2048))
+        );
+    }
+
+    #[test]
+    fn test_align_range_size() {
+        assert_eq!(
+            align_range(34, 5000, 2048, Some(2048), 8191),
+            Some((2048, 4096))
+        );
+        assert_eq!(align_range(34, 2000, 2048, Some(2048), 8191), None);
+    }
+
+    #[test]
+    fn test_align_range_doesnt_fit() {
+        assert_eq!(align_range(34, 2048, 2048, None, 4094), None);
+        assert_eq!(align_range(usize::MAX - 1, 1, 2048, None, usize::MAX), None);
+    }
+
+    #[test]
+    fn test_align_range_invalid() {
+        assert_eq!(align_range(34, 2048, 0, None, 8191), None);
+        assert_eq!(align_range(34, 0, 2048, None, 8191), None);
+        assert_eq!(align_range(34, 2048, 2048, Some(0), 8191), None);
+    }
+}
@@ -1,3 +1,5 @@
+use std::io;
+
 #[test]
 fn num_cast() {
     assert_eq!(ocibootstrap_part::num_cast!(u64, 42_usize), 42_u64);
@@ -14,3 +16,24 @@ fn num_cast_overflow() {
 fn num_cast_underflow() {
     ocibootstrap_part::num_cast!(u32, -1_i32);
 }
+
+#[test]
+fn try_num_cast() {
+    assert_eq!(
+        ocibootstrap_part::try_num_cast!(u64, 42_usize).unwrap(),
+        42_u64
+    );
+}
+
+#[test]
+fn try_num_cast_overflow() {
+    let err = ocibootstrap_part::try_num_cast!(u32, u64::from(u32::MAX) + 1).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(err.to_string(), "Integer Overflow (u64 to u32)");
+}
+
+#[test]
+fn try_num_cast_underflow() {
+    ocibootstrap_part::try_num_cast!(u32, -1_i32).unwrap_err();
+}
@@ -312,3 +312,309 @@ fn build_layout_three_partitions_one_missing_size_middle() {
         ]
     );
 }
+
+#[test]
+fn build_layout_aligned_one_partition_no_size() {
+    assert_eq!(
+        ocibootstrap_part::build_layout_aligned(
+            34,
+            100_000,
+            &[ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: None,
+                size_lba: None
+            }],
+            Some(2048)
+        )
+        .unwrap(),
+        &[ocibootstrap_part::PartitionLayout {
+            start_lba: 2048,
+            end_lba: 100_000,
+        }]
+    );
+}
+
+#[test]
+fn build_layout_aligned_one_partition_no_size_offset() {
+    assert_eq!(
+        ocibootstrap_part::build_layout_aligned(
+            34,
+            100_000,
+            &[ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: Some(100),
+                size_lba: None
+            }],
+            Some(2048)
+        )
+        .unwrap(),
+        &[ocibootstrap_part::PartitionLayout {
+            start_lba: 100,
+            end_lba: 100_000,
+        }]
+    );
+}
+
+#[test]
+fn build_layout_aligned_two_partitions_one_size_missing() {
+    assert_eq!(
+        ocibootstrap_part::build_layout_aligned(
+            34,
+            100_000,
+            &[
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: Some(1000),
+                },
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: None
+                }
+            ],
+            Some(2048)
+        )
+        .unwrap(),
+        &[
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 2048,
+                end_lba: 3047,
+            },
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 4096,
+                end_lba: 100_000,
+            }
+        ]
+    );
+}
+
+#[test]
+fn build_layout_aligned_three_partitions_one_missing_size_middle() {
+    assert_eq!(
+        ocibootstrap_part::build_layout_aligned(
+            34,
+            100_000,
+            &[
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: Some(4096),
+                },
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: None,
+                },
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: Some(4096)
+                }
+            ],
+            Some(2048)
+        )
+        .unwrap(),
+        &[
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 2048,
+                end_lba: 6143,
+            },
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 6144,
+                end_lba: 94_207,
+            },
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 94_208,
+                end_lba: 98_303,
+            }
+        ]
+    );
+}
+
+#[test]
+fn build_layout_aligned_null_alignment() {
+    ocibootstrap_part::build_layout_aligned(
+        0,
+        1000,
+        &[ocibootstrap_part::PartitionLayoutHint {
+            offset_lba: None,
+            size_lba: None,
+        }],
+        Some(0),
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn build_layout_aligned_doesnt_fit() {
+    ocibootstrap_part::build_layout_aligned(
+        34,
+        3000,
+        &[ocibootstrap_part::PartitionLayoutHint {
+            offset_lba: None,
+            size_lba: Some(1000),
+        }],
+        Some(2048),
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn minimum_end_lba_aligned_two_partitions() {
+    assert_eq!(
+        ocibootstrap_part::minimum_end_lba_aligned(
+            34,
+            &[
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: Some(1000),
+                },
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: Some(1000),
+                }
+            ],
+            Some(2048)
+        ),
+        Some(5096)
+    );
+}
+
+#[test]
+fn build_layout_two_partitions_fixed_offsets_overlap() {
+    let err = ocibootstrap_part::build_layout(
+        0,
+        1000,
+        &[
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: Some(100),
+                size_lba: Some(200),
+            },
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: Some(250),
+                size_lba: Some(100),
+            },
+        ],
+    )
+    .unwrap_err();
+
+    let msg = err.to_string();
+    assert!(
+        msg.contains("Partition 1 fixed offset (LBAs 250-349)"),
+        "{msg}"
+    );
+    assert!(
+        msg.contains("partition 0 fixed offset (LBAs 100-299)"),
+        "{msg}"
+    );
+}
+
+#[test]
+fn build_layout_two_partitions_overlap_message() {
+    let err = ocibootstrap_part::build_layout(
+        0,
+        1000,
+        &[
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: None,
+                size_lba: Some(500),
+            },
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: Some(499),
+                size_lba: None,
+            },
+        ],
+    )
+    .unwrap_err();
+
+    let msg = err.to_string();
+    assert!(
+        msg.contains("Partition 1 (LBAs 499-1000) overlaps with partition 0 (LBAs 0-499)"),
+        "{msg}"
+    );
+}
+
+#[test]
+fn build_layout_missing_size_before_gapped_fixed_offset() {
+    assert_eq!(
+        ocibootstrap_part::build_layout(
+            0,
+            1000,
+            &[
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: Some(300),
+                },
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: None,
+                    size_lba: None,
+                },
+                ocibootstrap_part::PartitionLayoutHint {
+                    offset_lba: Some(800),
+                    size_lba: Some(100),
+                },
+            ],
+        )
+        .unwrap(),
+        vec![
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 0,
+                end_lba: 299,
+            },
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 300,
+                end_lba: 799,
+            },
+            ocibootstrap_part::PartitionLayout {
+                start_lba: 800,
+                end_lba: 899,
+            },
+        ]
+    );
+}
+
+#[test]
+fn build_layout_missing_size_overlaps_next_fixed_offset() {
+    let err = ocibootstrap_part::build_layout(
+        0,
+        1000,
+        &[
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: None,
+                size_lba: Some(300),
+            },
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: None,
+                size_lba: None,
+            },
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: Some(200),
+                size_lba: Some(50),
+            },
+        ],
+    )
+    .unwrap_err();
+
+    let msg = err.to_string();
+    assert!(
+        msg.contains("Partition 1 (no size, starting at LBA 300) overlaps with partition 2"),
+        "{msg}"
+    );
+}
+
+#[test]
+fn build_layout_missing_size_followed_by_too_large_partition() {
+    ocibootstrap_part::build_layout(
+        0,
+        1000,
+        &[
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: None,
+                size_lba: Some(300),
+            },
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: None,
+                size_lba: None,
+            },
+            ocibootstrap_part::PartitionLayoutHint {
+                offset_lba: None,
+                size_lba: Some(2000),
+            },
+        ],
+    )
+    .unwrap_err();
+}
@@ -2,19 +2,19 @@
 
 use core::fmt;
 use std::{
-    ffi::OsString,
+    ffi::{OsStr, OsString},
     fs::File,
-    io::{self, BufReader},
+    io::{self, BufRead as _, BufReader, Seek as _},
     os::unix::ffi::OsStringExt as _,
     path::{Path, PathBuf},
 };
 
 use base64::Engine as _;
 use crc::{Crc, Digest as CrcDigest, CRC_64_GO_ISO};
-use flate2::bufread::GzDecoder;
 use log::debug;
 use serde::{de, Deserialize};
 use serde_json::{de::IoRead, StreamDeserializer, Value};
+use types::Compression;
 
 fn base64_decode<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
 where
@@ -158,7 +158,12 @@ impl<'de> Deserialize<'de> for Entry {
         Ok(match entry_kind {
             1 => Self::File(FileEntry::deserialize(value).map_err(de::Error::custom)?),
             2 => Self::Segment(SegmentEntry::deserialize(value).map_err(de::Error::custom)?),
-            _ => unimplemented!(),
+            _ => {
+                return Err(de::Error::invalid_value(
+                    de::Unexpected::Unsigned(entry_kind),
+                    &"a file (1) or segment (2) entry type",
+                ))
+            }
         })
     }
 }
@@ -194,7 +199,30 @@ enum TarSplitRemainerStatus {
     None,
 }
 
+fn check_integrity(digest: CrcDigest<'static, u64>, expected: Option<u64>) -> io::Result<()> {
+    if let Some(cksum) = expected {
+        let crc = digest.finalize();
+
+        if crc != cksum {
+            debug!(
+                "Remainder: CRC mismatch: actual {:x} vs expected {:x}",
+                crc, cksum
+            );
+
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("CRC mismatch: actual {crc:x} vs expected {cksum:x}"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 /// Reader over a checksum-reproducible tar archive
+///
+/// The reader can also seek forward, which allows to skip over the content of the files without
+/// reading them. Their checksum isn't verified then.
 pub struct TarSplitReader<'de, R>
 where
     R: io::Read,
@@ -202,12 +230,24 @@ where
     base: PathBuf,
     iter: StreamDeserializer<'de, IoRead<R>, Entry>,
     rem: Option<TarSplitRemainer>,
+    entry: Option<(OsString, u64)>,
+    position: u64,
 }
 
 impl<R> TarSplitReader<'_, R>
 where
     R: io::Read,
 {
+    /// Returns the name and size of the file whose content is being read
+    ///
+    /// It's `None` while the tar headers and padding are read, and stays set once the last byte
+    /// of a file has been read, until the reader moves past it.
+    pub fn current_entry(&self) -> Option<(&OsStr, u64)> {
+        self.entry
+            .as_ref()
+            .map(|(name, size)| (name.as_os_str(), *size))
+    }
+
     fn handle_remainder(&mut self, buf: &mut [u8]) -> io::Result<TarSplitRemainerStatus> {
         let buf_len = buf.len();
 
@@ -227,18 +267,7 @@ where
                     }
                     debug!("Remainder: Reached End-of-File, checking integrity");
 
-                    if let Some(cksum) = expected_checksum {
-                        let crc = digest.finalize();
-
-                        if crc != cksum {
-                            debug!(
-                                "Remainder: CRC mismatch: actual {:x} vs expected {:x}",
-                                crc, cksum
-                            );
-
-                            unimplemented!();
-                        }
-                    }
+                    check_integrity(digest, expected_checksum)?;
 
                     debug!("Remainder: Done. Going to the next entry.");
                     Ok(TarSplitRemainerStatus::Continue)
@@ -272,131 +301,184 @@ where
             Ok(TarSplitRemainerStatus::None)
         }
     }
-}
-
-impl<R> fmt::Debug for TarSplitReader<'_, R>
-where
-    R: io::Read,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("TarSplitReader")
-            .field("base", &self.base)
-            .field("remainder", &self.rem)
-            .finish_non_exhaustive()
-    }
-}
-
-impl<R> io::Read for TarSplitReader<'_, R>
-where
-    R: io::Read,
-{
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let buf_len = buf.len();
-
-        debug!("Reading next entry to a buffer of {buf_len} bytes");
-
-        match self.handle_remainder(buf)? {
-            TarSplitRemainerStatus::Handled(used) => return Ok(used),
-            TarSplitRemainerStatus::Continue | TarSplitRemainerStatus::None => {}
-        }
 
+    /// Moves to the next entry with some content, and returns whether there was one
+    ///
+    /// Files are opened, but nothing is read from them yet.
+    fn next_remainder(&mut self) -> io::Result<bool> {
         for o in self.iter.by_ref() {
             let entry = o?;
 
-            match &entry {
+            match entry {
                 Entry::File(f) => {
-                    if let Some(len) = f.size {
+                    let Some(size) = f.size else {
                         debug!(
-                            "Position {}: File Entry {}, size {len} bytes",
-                            f.position,
+                            "Found File Entry {} with no size. Skipping.",
                             f.name.to_string_lossy()
                         );
+                        continue;
+                    };
 
-                        let path = self.base.join(&f.name);
-                        debug!("Opening File {}", path.display());
+                    debug!(
+                        "Position {}: File Entry {}, size {size} bytes",
+                        f.position,
+                        f.name.to_string_lossy()
+                    );
 
-                        let mut file = File::open(&path)?;
-                        let metadata = file.metadata()?;
+                    let path = self.base.join(&f.name);
+                    debug!("Opening File {}", path.display());
 
-                        debug!(
-                            "Position {}: File opened, actual size {}",
-                            f.position,
-                            metadata.len()
-                        );
+                    let file = File::open(&path)?;
+                    let metadata = file.metadata()?;
 
-                        if let Some(size) = f.size {
-                            if metadata.len() != size {
-                                return Err(io::Error::from(io::ErrorKind::InvalidData));
-                            }
-                        }
+                    debug!(
+                        "Position {}: File opened, actual size {}",
+                        f.position,
+                        metadata.len()
+                    );
 
-                        let used = file.read(buf)?;
-                        debug!("Position {}: Read {used} bytes", f.position);
+                    if metadata.len() != size {
+                        return Err(io::Error::from(io::ErrorKind::InvalidData));
+                    }
 
-                        let mut digest = TAR_SPLIT_CRC.digest();
-                        digest.update(&buf[0..used]);
+                    self.rem = Some(TarSplitRemainer::File(
+                        file,
+                        size,
+                        TAR_SPLIT_CRC.digest(),
+                        f.checksum,
+                    ));
+                    self.entry = Some((f.name, size));
+                    return Ok(true);
+                }
+                Entry::Segment(f) => {
+                    debug!(
+                        "Position {}: Found Segment entry of {} bytes.",
+                        f.position,
+                        f.payload.len()
+                    );
 
-                        if used == buf_len {
-                            debug!("File is larger than buffer, postponing the rest");
-                            self.rem = Some(TarSplitRemainer::File(
-                                file,
-                                metadata.len() - used as u64,
-                                digest,
-                                f.checksum,
-                            ));
+                    if f.payload.is_empty() {
+                        continue;
+                    }
 
-                            return Ok(used);
-                        }
+                    self.rem = Some(TarSplitRemainer::Segment(f.payload));
+                    self.entry = None;
+                    return Ok(true);
+                }
+            }
+        }
 
-                        if let Some(checksum) = f.checksum {
-                            let crc = digest.finalize();
+        debug!("No entries left");
+        self.entry = None;
+        Ok(false)
+    }
 
-                            if crc != checksum {
-                                debug!(
-                                    "Position {}: CRC mismatch: actual {:x} vs expected {:x}",
-                                    f.position, crc, checksum
-                                );
+    fn skip(&mut self, mut amt: u64) -> io::Result<()> {
+        while amt > 0 {
+            match &mut self.rem {
+                Some(TarSplitRemainer::File(file, size, _digest, checksum)) if *size > 0 => {
+                    let skipped = amt.min(*size);
+                    debug!("Skipping {skipped} bytes of a file, without reading them");
+
+                    file.seek(io::SeekFrom::Current(
+                        i64::try_from(skipped)
+                            .map_err(|_err| io::Error::from(io::ErrorKind::InvalidInput))?,
+                    ))?;
+
+                    // We don't have the whole content anymore, so we can't check it.
+                    *size -= skipped;
+                    *checksum = None;
+                    amt -= skipped;
+                }
+                Some(TarSplitRemainer::Segment(payload)) if !payload.is_empty() => {
+                    let skipped =
+                        usize::try_from(amt).map_or(payload.len(), |amt| amt.min(payload.len()));
+                    debug!("Skipping {skipped} bytes of a segment");
 
-                                unimplemented!();
-                            }
-                        }
+                    payload.drain(0..skipped);
+                    amt -= skipped as u64;
 
-                        return Ok(used);
+                    if payload.is_empty() {
+                        self.rem = None;
                     }
-
-                    debug!(
-                        "Found File Entry {} with no size. Skipping.",
-                        f.name.to_string_lossy()
-                    );
-                    continue;
                 }
-                Entry::Segment(f) => {
-                    debug!("Position {}: Found Segment entry. Extracting.", f.position);
+                _ => {
+                    if let Some(TarSplitRemainer::File(_, _, digest, checksum)) = self.rem.take() {
+                        check_integrity(digest, checksum)?;
+                    }
 
-                    let payload_len = f.payload.len();
+                    if !self.next_remainder()? {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                    }
+                }
+            }
+        }
 
-                    if buf_len >= payload_len {
-                        debug!("Position {}: Buffer is large enough to hold the payload (buf {buf_len}, payload {payload_len})", f.position);
-                        buf[0..payload_len].copy_from_slice(&f.payload);
-                        debug!("Position {}: Returned length {payload_len}", f.position);
+        Ok(())
+    }
+}
 
-                        return Ok(payload_len);
-                    }
+impl<R> fmt::Debug for TarSplitReader<'_, R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TarSplitReader")
+            .field("base", &self.base)
+            .field("remainder", &self.rem)
+            .field("entry", &self.entry)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
 
-                    debug!("Position {}: Buffer is too small to hold the payload (buf {buf_len}, payload {payload_len}). Postponing the rest.", f.position);
+impl<R> io::Read for TarSplitReader<'_, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        debug!("Reading next entry to a buffer of {} bytes", buf.len());
 
-                    let mut payload = f.payload.clone();
-                    let bytes: Vec<_> = payload.drain(0..buf_len).collect();
-                    buf.copy_from_slice(&bytes);
-                    self.rem = Some(TarSplitRemainer::Segment(payload));
-                    debug!("Position {}: Returned length {buf_len}", f.position);
-                    return Ok(buf_len);
+        loop {
+            match self.handle_remainder(buf)? {
+                TarSplitRemainerStatus::Handled(used) => {
+                    self.position += used as u64;
+                    return Ok(used);
                 }
+                TarSplitRemainerStatus::Continue | TarSplitRemainerStatus::None => {}
+            }
+
+            if !self.next_remainder()? {
+                return Ok(0);
             }
         }
+    }
+}
 
-        debug!("No entries left");
-        Ok(0)
+impl<R> io::Seek for TarSplitReader<'_, R>
+where
+    R: io::Read,
+{
+    /// Seeks forward in the archive
+    ///
+    /// Seeking backward or relative to the end of the archive isn't supported, and returns an
+    /// error.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            io::SeekFrom::End(_) => None,
+        }
+        .filter(|target| *target >= self.position)
+        .ok_or(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "tar-split archives can only seek forward",
+        ))?;
+
+        self.skip(target - self.position)?;
+        self.position = target;
+
+        Ok(target)
     }
 }
 
@@ -409,6 +491,8 @@ where
         base: base.to_path_buf(),
         iter: StreamDeserializer::new(IoRead::new(reader)),
         rem: None,
+        entry: None,
+        position: 0,
     }
 }
 
@@ -421,14 +505,10 @@ pub fn from_path<'de>(
     base: &Path,
     path: &Path,
 ) -> Result<TarSplitReader<'de, Box<dyn io::Read>>, io::Error> {
-    let kind = infer::get_from_path(path)?.ok_or(io::Error::from(io::ErrorKind::Unsupported))?;
+    let mut bufread = BufReader::new(File::open(path)?);
+    let compression = Compression::from_magic(bufread.fill_buf()?);
 
-    let file = File::open(path)?;
-    let bufread = BufReader::new(file);
-    let reader: Box<dyn io::Read> = Box::new(match kind.mime_type() {
-        "application/gzip" => GzDecoder::new(bufread),
-        _ => return Err(io::Error::from(io::ErrorKind::Unsupported)),
-    });
+    debug!("Tar Split file is {compression:?}");
 
-    Ok(from_reader(base, reader))
+    Ok(from_reader(base, types::decoder(compression, bufread)?))
 }
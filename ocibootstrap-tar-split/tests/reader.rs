@@ -1,13 +1,14 @@
 use std::{
-    fs::File,
-    io::{self, Seek},
+    fs::{self, File},
+    io::{self, Read as _, Seek},
     path::{Path, PathBuf},
 };
 
 use flate2::read::GzDecoder;
 use log::debug;
-use ocibootstrap_tar_split::from_path;
-use tar::Archive;
+use ocibootstrap_tar_split::{from_path, from_reader};
+use sha2::{Digest as _, Sha256};
+use tar::{Archive, EntryType};
 use tempfile::{NamedTempFile, TempDir};
 use test_log::test;
 
@@ -31,9 +32,9 @@ fn test_archive_fn(archive_path: &Path) {
     io::copy(&mut archive_gz_reader, &mut archive_dec).unwrap();
 
     archive_dec.seek(io::SeekFrom::Start(0)).unwrap();
-    let expected_sha = sha256::try_digest(&archive_dec_path).unwrap();
+    let expected_sha = Sha256::digest(fs::read(&archive_dec_path).unwrap());
 
-    debug!("Expected Archive SHA-256 is {}", expected_sha);
+    debug!("Expected Archive SHA-256 is {expected_sha:x}");
 
     let base_dir = temp_dir.path().join("base");
 
@@ -48,7 +49,10 @@ fn test_archive_fn(archive_path: &Path) {
     let mut archive = NamedTempFile::new().unwrap();
     io::copy(&mut reader, &mut archive).unwrap();
 
-    assert_eq!(sha256::try_digest(&archive.path()).unwrap(), expected_sha);
+    assert_eq!(
+        Sha256::digest(fs::read(archive.path()).unwrap()),
+        expected_sha
+    );
 }
 
 #[test]
@@ -91,3 +95,131 @@ fn test_1c51fc286aa95d9413226599576bafa38490b1e292375c90de095855b64caea6() {
         "./tests/data/1c51fc286aa95d9413226599576bafa38490b1e292375c90de095855b64caea6/1c51fc286aa95d9413226599576bafa38490b1e292375c90de095855b64caea6",
     ));
 }
+
+#[test]
+fn test_seek() {
+    let archive_path = PathBuf::from("./tests/data/1c51fc286aa95d9413226599576bafa38490b1e292375c90de095855b64caea6/1c51fc286aa95d9413226599576bafa38490b1e292375c90de095855b64caea6");
+
+    let mut archive_dec = Vec::new();
+    io::copy(
+        &mut GzDecoder::new(File::open(&archive_path).unwrap()),
+        &mut archive_dec,
+    )
+    .unwrap();
+
+    let temp_dir = TempDir::new().unwrap();
+    let base_dir = temp_dir.path().join("base");
+    Archive::new(archive_dec.as_slice())
+        .unpack(&base_dir)
+        .unwrap();
+
+    let expected: Vec<_> = Archive::new(archive_dec.as_slice())
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.path().unwrap().into_owned(), entry.size())
+        })
+        .collect();
+
+    let json_path = archive_path.parent().unwrap().join("tar-data.json.gz");
+    let reader = from_path(&base_dir, &json_path).unwrap();
+
+    // Only the headers are read, the files content is skipped over
+    let mut archive = Archive::new(reader);
+    let found: Vec<_> = archive
+        .entries_with_seek()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.path().unwrap().into_owned(), entry.size())
+        })
+        .collect();
+
+    assert!(!found.is_empty());
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn test_seek_backward() {
+    let base_dir = TempDir::new().unwrap();
+    let mut reader = from_path(
+        base_dir.path(),
+        &PathBuf::from("./tests/data/t/tar-data.json.gz"),
+    )
+    .unwrap();
+
+    let mut header = [0; 512];
+    reader.read_exact(&mut header).unwrap();
+    assert_eq!(reader.stream_position().unwrap(), 512);
+
+    reader.seek(io::SeekFrom::Start(0)).unwrap_err();
+}
+
+#[test]
+fn test_current_entry() {
+    let archive_path = PathBuf::from("./tests/data/1c51fc286aa95d9413226599576bafa38490b1e292375c90de095855b64caea6/1c51fc286aa95d9413226599576bafa38490b1e292375c90de095855b64caea6");
+
+    let mut archive_dec = Vec::new();
+    io::copy(
+        &mut GzDecoder::new(File::open(&archive_path).unwrap()),
+        &mut archive_dec,
+    )
+    .unwrap();
+
+    let temp_dir = TempDir::new().unwrap();
+    let base_dir = temp_dir.path().join("base");
+    Archive::new(archive_dec.as_slice())
+        .unpack(&base_dir)
+        .unwrap();
+
+    // Empty files don't have any content to be read
+    let expected: Vec<_> = Archive::new(archive_dec.as_slice())
+        .entries()
+        .unwrap()
+        .map(Result::unwrap)
+        .filter(|entry| entry.header().entry_type() == EntryType::Regular && entry.size() > 0)
+        .map(|entry| (entry.path().unwrap().into_owned(), entry.size()))
+        .collect();
+
+    let json_path = archive_path.parent().unwrap().join("tar-data.json.gz");
+    let mut reader = from_path(&base_dir, &json_path).unwrap();
+    assert_eq!(reader.current_entry(), None);
+
+    let mut found = Vec::new();
+    let mut current = None;
+    let mut buf = [0; 4096];
+    while reader.read(&mut buf).unwrap() > 0 {
+        let entry = reader
+            .current_entry()
+            .map(|(name, size)| (PathBuf::from(name), size));
+
+        if entry != current {
+            found.extend(entry.clone());
+            current = entry;
+        }
+    }
+
+    assert_eq!(reader.current_entry(), None);
+    assert!(!found.is_empty());
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn test_malformed_entries() {
+    let base_dir = TempDir::new().unwrap();
+
+    for (entry, expected) in [
+        (
+            r#"{"type": 1, "size": 0, "position": 0}"#,
+            "name or name_raw",
+        ),
+        (r#"{"type": 3, "position": 0}"#, "entry type"),
+    ] {
+        let mut reader = from_reader(base_dir.path(), entry.as_bytes());
+
+        let err = reader.read(&mut [0; 512]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+}
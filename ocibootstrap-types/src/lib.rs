@@ -5,8 +5,12 @@ extern crate alloc;
 
 use alloc::fmt;
 use core::str::FromStr;
-use std::{env::consts, io};
+use std::{
+    env::consts,
+    io::{self, BufRead},
+};
 
+use flate2::bufread::GzDecoder;
 use serde::{de, Deserialize};
 
 /// Representation of an hardware architecture
@@ -25,6 +29,15 @@ pub enum Architecture {
     /// Intel's X86-64 Architecture
     #[clap(name = "amd64")]
     X86_64,
+
+    /// RISC-V 64-bit Architecture
+    Riscv64,
+
+    /// IBM's POWER 64-bit Little-Endian Architecture
+    Ppc64le,
+
+    /// IBM's z/Architecture
+    S390x,
 }
 
 impl Architecture {
@@ -40,6 +53,9 @@ impl Architecture {
             "arm64" => Self::Arm64,
             "x86" => Self::X86,
             "amd64" => Self::X86_64,
+            "riscv64" => Self::Riscv64,
+            "ppc64le" => Self::Ppc64le,
+            "s390x" => Self::S390x,
             _ => {
                 return Err(OciBootstrapError::Custom(format!(
                     "Unknown Architecture {s}"
@@ -59,6 +75,11 @@ impl Architecture {
             "arm" => Self::Arm,
             "x86_64" => Self::X86_64,
             "x86" => Self::X86,
+            "riscv64" => Self::Riscv64,
+            // Rust uses the same name for both endiannesses, but we only support the little-endian
+            // one
+            "powerpc64" if cfg!(target_endian = "little") => Self::Ppc64le,
+            "s390x" => Self::S390x,
             _ => {
                 return Err(OciBootstrapError::Custom(format!(
                     "Unknown architecture: {s}"
@@ -67,6 +88,15 @@ impl Architecture {
         })
     }
 
+    /// Returns the architecture we are running on
+    ///
+    /// # Errors
+    ///
+    /// If the host architecture is unknown
+    pub fn host() -> Result<Self, OciBootstrapError> {
+        Self::from_rust_str(consts::ARCH)
+    }
+
     /// Returns the OCI architecture name
     #[must_use]
     pub fn as_oci_str(self) -> &'static str {
@@ -76,31 +106,123 @@ impl Architecture {
             Self::Arm64 => "arm64",
             Self::X86 => "x86",
             Self::X86_64 => "amd64",
+            Self::Riscv64 => "riscv64",
+            Self::Ppc64le => "ppc64le",
+            Self::S390x => "s390x",
+        }
+    }
+
+    /// Returns the name of the UEFI removable media default boot file, if the architecture has one
+    ///
+    /// See the UEFI Specification, section 3.5 "Boot Mechanisms".
+    #[must_use]
+    pub fn efi_default_boot_file(self) -> Option<&'static str> {
+        match self {
+            Self::Arm => Some("BOOTARM.EFI"),
+            Self::Arm64 => Some("BOOTAA64.EFI"),
+            Self::X86 => Some("BOOTIA32.EFI"),
+            Self::X86_64 => Some("BOOTX64.EFI"),
+            Self::Riscv64 => Some("BOOTRISCV64.EFI"),
+            Self::Ppc64le | Self::S390x => None,
         }
     }
 }
 
-impl From<oci_spec::image::Arch> for Architecture {
-    fn from(value: oci_spec::image::Arch) -> Self {
+impl TryFrom<oci_spec::image::Arch> for Architecture {
+    type Error = OciBootstrapError;
+
+    fn try_from(value: oci_spec::image::Arch) -> Result<Self, Self::Error> {
         #[allow(clippy::wildcard_enum_match_arm)]
-        match value {
+        Ok(match value {
             oci_spec::image::Arch::ARM => Self::Arm,
             oci_spec::image::Arch::ARM64 => Self::Arm64,
             oci_spec::image::Arch::i386 => Self::X86,
             oci_spec::image::Arch::Amd64 => Self::X86_64,
-            _ => unimplemented!(),
-        }
+            oci_spec::image::Arch::RISCV64 => Self::Riscv64,
+            oci_spec::image::Arch::PowerPC64le => Self::Ppc64le,
+            oci_spec::image::Arch::s390x => Self::S390x,
+            arch => {
+                return Err(OciBootstrapError::Custom(format!(
+                    "Unknown Architecture {arch}"
+                )))
+            }
+        })
     }
 }
 
-impl Default for Architecture {
-    fn default() -> Self {
-        Architecture::from_rust_str(consts::ARCH)
-            .unwrap_or_else(|_| panic!("Running on unknown architecture: {}", consts::ARCH))
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_oci_str())
     }
 }
 
-impl fmt::Display for Architecture {
+/// Representation of an architecture variant
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+#[clap(rename_all = "lower")]
+pub enum Variant {
+    /// ARM Architecture Version 5
+    V5,
+
+    /// ARM Architecture Version 6
+    V6,
+
+    /// ARM Architecture Version 7
+    V7,
+
+    /// ARM Architecture Version 8
+    V8,
+}
+
+impl Variant {
+    /// Returns a `Variant` enum from the OCI string representation
+    ///
+    /// # Errors
+    ///
+    /// If the given variant is unknown
+    pub fn from_oci_str(s: &str) -> Result<Self, OciBootstrapError> {
+        // See <https://github.com/opencontainers/image-spec/blob/main/image-index.md#platform-variants>
+        Ok(match s {
+            "v5" => Self::V5,
+            "v6" => Self::V6,
+            "v7" => Self::V7,
+            "v8" => Self::V8,
+            _ => return Err(OciBootstrapError::Custom(format!("Unknown Variant {s}"))),
+        })
+    }
+
+    /// Returns the OCI variant name
+    #[must_use]
+    pub fn as_oci_str(self) -> &'static str {
+        match self {
+            Self::V5 => "v5",
+            Self::V6 => "v6",
+            Self::V7 => "v7",
+            Self::V8 => "v8",
+        }
+    }
+
+    /// Returns the index of the most suitable candidate for the requested variant.
+    ///
+    /// A candidate is compatible if it doesn't have any variant, or if its variant is lower than or
+    /// equal to the requested one. If no variant is requested, all candidates are compatible. The
+    /// highest compatible variant is then selected.
+    pub fn select<I>(requested: Option<Self>, candidates: I) -> Option<usize>
+    where
+        I: IntoIterator<Item = Option<Self>>,
+    {
+        candidates
+            .into_iter()
+            .enumerate()
+            .filter(|(_, candidate)| match (requested, candidate) {
+                (Some(requested), Some(candidate)) => *candidate <= requested,
+                (None, _) | (_, None) => true,
+            })
+            .max_by_key(|(_, candidate)| *candidate)
+            .map(|(idx, _)| idx)
+    }
+}
+
+impl fmt::Display for Variant {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.as_oci_str())
     }
@@ -111,9 +233,30 @@ impl fmt::Display for Architecture {
 pub enum OperatingSystem {
     /// Linux
     Linux,
+
+    /// Microsoft Windows
+    Windows,
+
+    /// Apple's Darwin
+    Darwin,
 }
 
 impl OperatingSystem {
+    /// Returns an `OperatingSystem` enum from the OCI string representation
+    ///
+    /// # Errors
+    ///
+    /// If the given OS is unknown
+    pub fn from_oci_str(s: &str) -> Result<Self, OciBootstrapError> {
+        // See GOOS <https://go.dev/doc/install/source#environment>
+        Ok(match s {
+            "linux" => Self::Linux,
+            "windows" => Self::Windows,
+            "darwin" => Self::Darwin,
+            _ => return Err(OciBootstrapError::Custom(format!("Unknown OS: {s}"))),
+        })
+    }
+
     /// Creates our OS enum from the Rust OS name
     ///
     /// # Errors
@@ -123,40 +266,156 @@ impl OperatingSystem {
         // See <https://github.com/rust-lang/rust/blob/master/library/std/build.rs#L21>
         Ok(match s {
             "linux" => Self::Linux,
+            "windows" => Self::Windows,
+            "macos" => Self::Darwin,
             _ => return Err(OciBootstrapError::Custom(format!("Unknown OS: {s}"))),
         })
     }
 
+    /// Returns the OS we are running on
+    ///
+    /// # Errors
+    ///
+    /// If the host OS is unknown
+    pub fn host() -> Result<Self, OciBootstrapError> {
+        Self::from_rust_str(consts::OS)
+    }
+
     /// Returns the OCI Operating System name
     #[must_use]
     pub fn as_oci_str(self) -> &'static str {
         // See GOOS <https://go.dev/doc/install/source#environment>
         match self {
             Self::Linux => "linux",
+            Self::Windows => "windows",
+            Self::Darwin => "darwin",
         }
     }
 }
 
-impl From<oci_spec::image::Os> for OperatingSystem {
-    fn from(value: oci_spec::image::Os) -> Self {
+impl TryFrom<oci_spec::image::Os> for OperatingSystem {
+    type Error = OciBootstrapError;
+
+    fn try_from(value: oci_spec::image::Os) -> Result<Self, Self::Error> {
         #[allow(clippy::wildcard_enum_match_arm)]
-        match value {
+        Ok(match value {
             oci_spec::image::Os::Linux => Self::Linux,
-            _ => unimplemented!(),
-        }
+            oci_spec::image::Os::Windows => Self::Windows,
+            oci_spec::image::Os::Darwin => Self::Darwin,
+            os => return Err(OciBootstrapError::Custom(format!("Unknown OS: {os}"))),
+        })
     }
 }
 
-impl Default for OperatingSystem {
-    fn default() -> Self {
-        OperatingSystem::from_rust_str(consts::OS)
-            .unwrap_or_else(|_| panic!("Running on unknown OS: {}", consts::OS))
+impl fmt::Display for OperatingSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_oci_str())
     }
 }
 
-impl fmt::Display for OperatingSystem {
+/// A platform, as given to `docker pull --platform`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Platform {
+    /// Operating System
+    pub os: OperatingSystem,
+
+    /// Hardware Architecture
+    pub arch: Architecture,
+
+    /// Architecture Variant, if any
+    pub variant: Option<Variant>,
+}
+
+impl FromStr for Platform {
+    type Err = OciBootstrapError;
+
+    /// Parses a platform in the `os/arch[/variant]` form
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+
+        let (Some(os), Some(arch)) = (parts.next(), parts.next()) else {
+            return Err(OciBootstrapError::Custom(format!(
+                "Invalid platform {s}, expected os/arch[/variant]"
+            )));
+        };
+
+        let variant = parts.next().map(Variant::from_oci_str).transpose()?;
+
+        if parts.next().is_some() {
+            return Err(OciBootstrapError::Custom(format!(
+                "Invalid platform {s}, expected os/arch[/variant]"
+            )));
+        }
+
+        Ok(Self {
+            os: OperatingSystem::from_oci_str(os)?,
+            arch: Architecture::from_oci_str(arch)?,
+            variant,
+        })
+    }
+}
+
+impl fmt::Display for Platform {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.as_oci_str())
+        write!(f, "{}/{}", self.os, self.arch)?;
+
+        if let Some(variant) = self.variant {
+            write!(f, "/{variant}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A range of user or group IDs in an image, and the IDs they are mapped to on the host
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct IdMapping {
+    /// First ID of the range, in the image
+    pub container_id: u32,
+
+    /// First ID of the range, on the host
+    pub host_id: u32,
+
+    /// Number of IDs in the range
+    pub size: u32,
+}
+
+impl IdMapping {
+    /// Returns the host ID an image ID is mapped to, if it's in the range
+    #[must_use]
+    pub fn map(&self, id: u32) -> Option<u32> {
+        let offset = id.checked_sub(self.container_id)?;
+        if offset >= self.size {
+            return None;
+        }
+
+        self.host_id.checked_add(offset)
+    }
+}
+
+impl FromStr for IdMapping {
+    type Err = OciBootstrapError;
+
+    /// Parses a mapping in the `container_id:host_id:size` form
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ids = s
+            .split(':')
+            .map(u32::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| OciBootstrapError::Custom(format!("Invalid ID mapping {s}: {e}")))?;
+
+        let [container_id, host_id, size] = ids[..] else {
+            return Err(OciBootstrapError::Custom(format!(
+                "Invalid ID mapping {s}, expected container_id:host_id:size"
+            )));
+        };
+
+        Ok(Self {
+            container_id,
+            host_id,
+            size,
+        })
     }
 }
 
@@ -179,6 +438,37 @@ pub enum OciBootstrapError {
     #[error("Invalid URL")]
     Url(#[from] url::ParseError),
 
+    /// No image matching the given name could be found
+    #[error("Couldn't find image {0} in registry")]
+    ImageNotFound(String),
+
+    /// The image doesn't provide a manifest for the requested platform
+    #[error("Couldn't find manifest for platform {}", Platform { os: *os, arch: *arch, variant: *variant })]
+    NoManifestForPlatform {
+        /// Requested Hardware Architecture
+        arch: Architecture,
+
+        /// Requested Operating System
+        os: OperatingSystem,
+
+        /// Requested Architecture Variant, if any
+        variant: Option<Variant>,
+    },
+
+    /// The domain part of a container name isn't a valid domain
+    #[error("Invalid domain name {0}")]
+    InvalidDomain(String),
+
+    /// A blob doesn't have the size its descriptor expects
+    #[error("Blob size mismatch: expected {expected} bytes, found {actual} bytes")]
+    BlobSizeMismatch {
+        /// Size given by the descriptor, in bytes
+        expected: u64,
+
+        /// Actual size of the blob, in bytes
+        actual: u64,
+    },
+
     /// An unknown error occurred
     #[error("Error: {0}")]
     Custom(String),
@@ -198,13 +488,11 @@ impl FromStr for DigestAlgorithm {
     type Err = OciBootstrapError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let alg = match s {
+        Ok(match s {
             "sha256" => DigestAlgorithm::Sha256,
             "sha512" => DigestAlgorithm::Sha512,
-            _ => unimplemented!(),
-        };
-
-        Ok(alg)
+            _ => return Err(OciBootstrapError::Custom(format!("Unknown algorithm {s}"))),
+        })
     }
 }
 
@@ -250,17 +538,13 @@ impl Digest {
                 "Malformed Digest Representation",
             )))?;
 
-        let alg = match alg {
-            "sha256" => DigestAlgorithm::Sha256,
-            "sha512" => DigestAlgorithm::Sha512,
-            _ => {
-                return Err(OciBootstrapError::Custom(format!(
-                    "Unknown algorithm {alg}"
-                )))
-            }
-        };
+        Self::new(DigestAlgorithm::from_str(alg)?, dig)
+    }
 
-        Self::new(alg, dig)
+    /// Returns the algorithm used to compute the digest
+    #[must_use]
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.digest
     }
 
     /// Returns the raw digest as a hex String
@@ -291,3 +575,370 @@ impl<'de> Deserialize<'de> for Digest {
         Self::from_oci_str(&s).map_err(de::Error::custom)
     }
 }
+
+/// Compression of a blob, either as recorded by containers/storage, or guessed from its content
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// Uncompressed Content
+    Uncompressed,
+
+    /// bzip2 Compression
+    Bzip2,
+
+    /// gzip Compression
+    Gzip,
+
+    /// xz Compression
+    Xz,
+
+    /// zstd Compression
+    Zstd,
+}
+
+impl Compression {
+    /// Guesses the compression of a blob from its first bytes
+    #[must_use]
+    pub fn from_magic(buf: &[u8]) -> Self {
+        if buf.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if buf.starts_with(b"BZh") {
+            Self::Bzip2
+        } else if buf.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Xz
+        } else if buf.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else {
+            Self::Uncompressed
+        }
+    }
+}
+
+/// Parses the compression recorded by containers/storage for a layer
+impl TryFrom<u8> for Compression {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Uncompressed,
+            1 => Self::Bzip2,
+            2 => Self::Gzip,
+            3 => Self::Xz,
+            4 => Self::Zstd,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown Layer Compression {value}"),
+                ))
+            }
+        })
+    }
+}
+
+/// Returns a reader decompressing the content of another one
+///
+/// # Errors
+///
+/// If the compression isn't supported.
+pub fn decoder<'a, R>(compression: Compression, reader: R) -> io::Result<Box<dyn io::Read + 'a>>
+where
+    R: BufRead + 'a,
+{
+    Ok(match compression {
+        Compression::Uncompressed => Box::new(reader),
+        Compression::Gzip => Box::new(GzDecoder::new(reader)),
+        Compression::Bzip2 | Compression::Xz | Compression::Zstd => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported {compression:?} Compression"),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr as _;
+    use std::io::{self, Read as _, Write as _};
+
+    use flate2::write::GzEncoder;
+    use oci_spec::image::ImageIndex;
+
+    use crate::{
+        decoder, Architecture, Compression, Digest, DigestAlgorithm, IdMapping, OperatingSystem,
+        Platform, Variant,
+    };
+
+    const ARM_VARIANTS_INDEX: &str = r#"{
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.index.v1+json",
+        "manifests": [
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+                "size": 7143,
+                "platform": { "architecture": "arm", "os": "linux", "variant": "v5" }
+            },
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+                "size": 7682,
+                "platform": { "architecture": "arm", "os": "linux", "variant": "v7" }
+            },
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7",
+                "size": 7682,
+                "platform": { "architecture": "arm", "os": "linux", "variant": "v6" }
+            }
+        ]
+    }"#;
+
+    fn index_variants() -> Vec<Option<Variant>> {
+        let index: ImageIndex = serde_json::from_str(ARM_VARIANTS_INDEX).unwrap();
+
+        index
+            .manifests()
+            .iter()
+            .map(|m| {
+                m.platform()
+                    .as_ref()
+                    .unwrap()
+                    .variant()
+                    .as_ref()
+                    .map(|v| Variant::from_oci_str(v).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_variant_select_exact() {
+        assert_eq!(
+            Variant::select(Some(Variant::V6), index_variants()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_variant_select_highest_compatible() {
+        assert_eq!(
+            Variant::select(Some(Variant::V8), index_variants()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_variant_select_default_highest() {
+        assert_eq!(Variant::select(None, index_variants()), Some(1));
+    }
+
+    #[test]
+    fn test_variant_select_none_compatible() {
+        assert_eq!(
+            Variant::select(Some(Variant::V5), [Some(Variant::V6), Some(Variant::V7)]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_variant_select_no_variant() {
+        assert_eq!(Variant::select(Some(Variant::V7), [None]), Some(0));
+    }
+
+    #[test]
+    fn test_arch_riscv64_round_trip() {
+        let arch = Architecture::from_oci_str("riscv64").unwrap();
+
+        assert_eq!(arch, Architecture::Riscv64);
+        assert_eq!(arch.as_oci_str(), "riscv64");
+        assert_eq!(Architecture::from_rust_str("riscv64").unwrap(), arch);
+    }
+
+    #[test]
+    fn test_arch_ppc64le_round_trip() {
+        let arch = Architecture::from_oci_str("ppc64le").unwrap();
+
+        assert_eq!(arch, Architecture::Ppc64le);
+        assert_eq!(arch.as_oci_str(), "ppc64le");
+
+        if cfg!(target_endian = "little") {
+            assert_eq!(Architecture::from_rust_str("powerpc64").unwrap(), arch);
+        } else {
+            Architecture::from_rust_str("powerpc64").unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_arch_s390x_round_trip() {
+        let arch = Architecture::from_oci_str("s390x").unwrap();
+
+        assert_eq!(arch, Architecture::S390x);
+        assert_eq!(arch.as_oci_str(), "s390x");
+        assert_eq!(Architecture::from_rust_str("s390x").unwrap(), arch);
+    }
+
+    #[test]
+    fn test_arch_from_oci_spec() {
+        assert_eq!(
+            Architecture::try_from(oci_spec::image::Arch::RISCV64).unwrap(),
+            Architecture::Riscv64
+        );
+        assert_eq!(
+            Architecture::try_from(oci_spec::image::Arch::PowerPC64le).unwrap(),
+            Architecture::Ppc64le
+        );
+        assert_eq!(
+            Architecture::try_from(oci_spec::image::Arch::s390x).unwrap(),
+            Architecture::S390x
+        );
+        Architecture::try_from(oci_spec::image::Arch::Mips64).unwrap_err();
+    }
+
+    #[test]
+    fn test_digest_algorithm_unknown() {
+        assert_eq!(
+            DigestAlgorithm::from_str("sha512").unwrap(),
+            DigestAlgorithm::Sha512
+        );
+        DigestAlgorithm::from_str("md5").unwrap_err();
+        Digest::from_oci_str("md5:d41d8cd98f00b204e9800998ecf8427e").unwrap_err();
+    }
+
+    #[test]
+    fn test_arch_unknown_host() {
+        Architecture::from_rust_str("loongarch64").unwrap_err();
+        OperatingSystem::from_rust_str("haiku").unwrap_err();
+    }
+
+    #[test]
+    fn test_host() {
+        Architecture::host().unwrap();
+        OperatingSystem::host().unwrap();
+    }
+
+    #[test]
+    fn test_os_round_trip() {
+        for (oci, rust, os) in [
+            ("linux", "linux", OperatingSystem::Linux),
+            ("windows", "windows", OperatingSystem::Windows),
+            ("darwin", "macos", OperatingSystem::Darwin),
+        ] {
+            assert_eq!(OperatingSystem::from_oci_str(oci).unwrap(), os);
+            assert_eq!(OperatingSystem::from_rust_str(rust).unwrap(), os);
+            assert_eq!(os.as_oci_str(), oci);
+            assert_eq!(
+                OperatingSystem::try_from(oci_spec::image::Os::from(oci)).unwrap(),
+                os
+            );
+        }
+    }
+
+    #[test]
+    fn test_os_from_oci_spec_unknown() {
+        OperatingSystem::try_from(oci_spec::image::Os::FreeBSD).unwrap_err();
+        OperatingSystem::from_oci_str("freebsd").unwrap_err();
+    }
+
+    #[test]
+    fn test_platform_with_variant() {
+        let platform: Platform = "linux/arm64/v8".parse().unwrap();
+
+        assert_eq!(
+            platform,
+            Platform {
+                os: OperatingSystem::Linux,
+                arch: Architecture::Arm64,
+                variant: Some(Variant::V8),
+            }
+        );
+        assert_eq!(platform.to_string(), "linux/arm64/v8");
+    }
+
+    #[test]
+    fn test_platform_without_variant() {
+        let platform: Platform = "linux/amd64".parse().unwrap();
+
+        assert_eq!(
+            platform,
+            Platform {
+                os: OperatingSystem::Linux,
+                arch: Architecture::X86_64,
+                variant: None,
+            }
+        );
+        assert_eq!(platform.to_string(), "linux/amd64");
+    }
+
+    #[test]
+    fn test_platform_invalid() {
+        for platform in [
+            "linux",
+            "linux/arm64/v8/extra",
+            "linux/arm64/v9",
+            "plan9/amd64",
+        ] {
+            platform.parse::<Platform>().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_id_mapping() {
+        let mapping: IdMapping = "1:100000:1000".parse().unwrap();
+
+        assert_eq!(
+            mapping,
+            IdMapping {
+                container_id: 1,
+                host_id: 100_000,
+                size: 1000,
+            }
+        );
+        assert_eq!(mapping.map(0), None);
+        assert_eq!(mapping.map(1), Some(100_000));
+        assert_eq!(mapping.map(1000), Some(100_999));
+        assert_eq!(mapping.map(1001), None);
+
+        for mapping in ["0:1000", "0:1000:1:1", "0:-1:1", "root:1000:1"] {
+            mapping.parse::<IdMapping>().unwrap_err();
+        }
+    }
+
+    const CONTENT: &[u8] = b"ocibootstrap";
+
+    fn decode(compression: Compression, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        decoder(compression, buf)?.read_to_end(&mut content)?;
+
+        Ok(content)
+    }
+
+    #[test]
+    fn test_decoder_uncompressed() {
+        assert_eq!(Compression::from_magic(CONTENT), Compression::Uncompressed);
+        assert_eq!(decode(Compression::Uncompressed, CONTENT).unwrap(), CONTENT);
+    }
+
+    #[test]
+    fn test_decoder_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(CONTENT).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(Compression::from_magic(&compressed), Compression::Gzip);
+        assert_eq!(decode(Compression::Gzip, &compressed).unwrap(), CONTENT);
+    }
+
+    #[test]
+    fn test_decoder_unsupported() {
+        for (compression, magic) in [
+            (Compression::Bzip2, &b"BZh91AY"[..]),
+            (Compression::Xz, &[0xfd, b'7', b'z', b'X', b'Z', 0x00][..]),
+            (Compression::Zstd, &[0x28, 0xb5, 0x2f, 0xfd][..]),
+        ] {
+            assert_eq!(Compression::from_magic(magic), compression);
+            assert_eq!(
+                decode(compression, magic).unwrap_err().kind(),
+                io::ErrorKind::Unsupported
+            );
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use core::time::Duration;
+use std::{
+    io,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Instant,
+};
+
+use log::{debug, trace};
+use types::OciBootstrapError;
+
+/// How long the external tools are allowed to run before being killed
+const COMMAND_TIMEOUT: Duration = Duration::from_mins(5);
+
+/// How long to wait between two checks of whether a command has exited
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn command_line(command: &Command) -> String {
+    [command.get_program()]
+        .into_iter()
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn read_pipe<R>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>>
+where
+    R: io::Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut content = Vec::new();
+
+        if let Some(mut pipe) = pipe {
+            if let Err(e) = pipe.read_to_end(&mut content) {
+                debug!("Couldn't read the command output: {e}");
+            }
+        }
+
+        content
+    })
+}
+
+fn kill(child: &mut Child) {
+    if let Err(e) = child.kill() {
+        debug!("Couldn't kill the command: {e}");
+    }
+
+    if let Err(e) = child.wait() {
+        debug!("Couldn't wait for the command: {e}");
+    }
+}
+
+/// Runs a command to completion, and returns its standard output
+///
+/// # Errors
+///
+/// Returns an error with the command line and its standard error if the command can't be
+/// started, exits with a non-zero status, or is still running after a timeout. It's then killed.
+pub(crate) fn run_command(command: &mut Command) -> Result<Vec<u8>, OciBootstrapError> {
+    run_command_with_timeout(command, COMMAND_TIMEOUT)
+}
+
+/// Runs a command to completion like [`run_command`], with a custom timeout
+pub(crate) fn run_command_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+) -> Result<Vec<u8>, OciBootstrapError> {
+    let cmdline = command_line(command);
+
+    debug!("Running {cmdline}");
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| OciBootstrapError::Custom(format!("Couldn't run `{cmdline}`: {e}")))?;
+
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() < timeout => thread::sleep(COMMAND_POLL_INTERVAL),
+            Ok(None) => {
+                kill(&mut child);
+
+                return Err(OciBootstrapError::Custom(format!(
+                    "`{cmdline}` timed out after {timeout:?}"
+                )));
+            }
+            Err(e) => {
+                kill(&mut child);
+
+                return Err(OciBootstrapError::Custom(format!(
+                    "Couldn't wait for `{cmdline}`: {e}"
+                )));
+            }
+        }
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    trace!(
+        "`{cmdline}` exited with {status} after {:?}",
+        start.elapsed()
+    );
+
+    if !status.success() {
+        return Err(OciBootstrapError::Custom(format!(
+            "`{cmdline}` failed ({status}): {}",
+            String::from_utf8_lossy(&stderr).trim()
+        )));
+    }
+
+    Ok(stdout)
+}
+
+#[cfg(test)]
+mod command_tests {
+    use core::time::Duration;
+    use std::{fs, process::Command};
+
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::command::{run_command, run_command_with_timeout};
+
+    #[test]
+    fn test_run_command() {
+        let stdout = run_command(Command::new("sh").args(["-c", "echo ocibootstrap"])).unwrap();
+
+        assert_eq!(stdout, b"ocibootstrap\n");
+    }
+
+    #[test]
+    fn test_run_command_failure() {
+        let dir = TempDir::new().unwrap();
+        let script = dir.path().join("mkfs.fake");
+        fs::write(
+            &script,
+            "#!/bin/sh\necho \"mkfs.fake: device is busy\" >&2\nexit 3\n",
+        )
+        .unwrap();
+
+        let err = run_command(Command::new("sh").arg(&script).arg("/dev/loop42p1")).unwrap_err();
+
+        let msg = err.to_string();
+        assert!(msg.contains("mkfs.fake: device is busy"), "{msg}");
+        assert!(msg.contains("/dev/loop42p1"), "{msg}");
+    }
+
+    #[test]
+    fn test_run_command_timeout() {
+        let err =
+            run_command_with_timeout(Command::new("sleep").arg("10"), Duration::from_millis(100))
+                .unwrap_err();
+
+        assert!(err.to_string().contains("timed out"), "{err}");
+    }
+
+    #[test]
+    fn test_run_command_missing() {
+        run_command(&mut Command::new("/nonexistent/mkfs.fake")).unwrap_err();
+    }
+}
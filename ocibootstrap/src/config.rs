@@ -6,6 +6,8 @@ use toml::{map::Map, Table, Value};
 use crate::OciBootstrapError;
 
 pub(crate) const CONTAINERS_CFG_ALIASES_KEY: &str = "aliases";
+pub(crate) const CONTAINERS_CFG_UNQUALIFIED_SEARCH_REGISTRIES_KEY: &str =
+    "unqualified-search-registries";
 
 pub(crate) static CONTAINERS_CFG: Lazy<Result<Map<String, Value>, OciBootstrapError>> =
     Lazy::new(|| {
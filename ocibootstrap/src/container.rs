@@ -1,14 +1,18 @@
 use core::fmt;
 
 use log::debug;
+use toml::{map::Map, Value};
 use types::Digest;
 
 use crate::{
-    config::{CONTAINERS_CFG, CONTAINERS_CFG_ALIASES_KEY},
+    config::{
+        CONTAINERS_CFG, CONTAINERS_CFG_ALIASES_KEY,
+        CONTAINERS_CFG_UNQUALIFIED_SEARCH_REGISTRIES_KEY,
+    },
     OciBootstrapError,
 };
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) enum ContainerReference {
     Tag(String),
     Digest(Digest),
@@ -23,60 +27,70 @@ impl fmt::Display for ContainerReference {
     }
 }
 
+/// A container image name, split into its components
 #[derive(Debug, Eq, PartialEq)]
-pub(crate) struct ContainerSpec {
+pub struct ContainerSpec {
     pub(crate) domain: String,
     pub(crate) name: String,
     pub(crate) reference: ContainerReference,
 }
 
+/// Docker Hub stores the images without a namespace in the library one
+const DOCKER_HUB_DOMAIN: &str = "docker.io";
+const DOCKER_HUB_LIBRARY: &str = "library";
+
+fn split_reference(name: &str) -> Result<(&str, ContainerReference), OciBootstrapError> {
+    if let Some((name, digest)) = name.rsplit_once('@') {
+        let digest = Digest::from_oci_str(digest)?;
+        Ok((name, ContainerReference::Digest(digest)))
+    } else if let Some((name, tag)) = name.rsplit_once(':') {
+        Ok((name, ContainerReference::Tag(tag.to_owned())))
+    } else {
+        Ok((name, ContainerReference::Tag("latest".to_owned())))
+    }
+}
+
+fn expand_alias(name: &str, cfg: Option<&Map<String, Value>>) -> Result<String, OciBootstrapError> {
+    if let Some(v) = cfg
+        .and_then(|cfg| cfg.get(CONTAINERS_CFG_ALIASES_KEY))
+        .and_then(|aliases| aliases.get(name))
+    {
+        return Ok(Value::try_into(v.clone())?);
+    }
+
+    Ok(String::from(name))
+}
+
+fn search_registries(cfg: Option<&Map<String, Value>>) -> Result<Vec<String>, OciBootstrapError> {
+    let Some(registries) =
+        cfg.and_then(|cfg| cfg.get(CONTAINERS_CFG_UNQUALIFIED_SEARCH_REGISTRIES_KEY))
+    else {
+        return Ok(Vec::new());
+    };
+
+    Ok(Value::try_into(registries.clone())?)
+}
+
 impl ContainerSpec {
     pub(crate) fn from_container_name(name: &str) -> Result<Self, OciBootstrapError> {
         debug!("Parsing container {name}");
 
-        let (name, reference) = if let Some((name, digest)) = name.rsplit_once('@') {
-            let digest = Digest::from_oci_str(digest)?;
-            (name, ContainerReference::Digest(digest))
-        } else if let Some((name, tag)) = name.rsplit_once(':') {
-            (name, ContainerReference::Tag(tag.to_owned()))
-        } else {
-            (name, ContainerReference::Tag("latest".to_owned()))
-        };
+        let (name, reference) = split_reference(name)?;
 
         debug!("Container name is {name}, reference is {reference}");
 
-        let expanded_name = if let Ok(cfg) = CONTAINERS_CFG.as_ref() {
-            if let Some(aliases) = cfg.get(CONTAINERS_CFG_ALIASES_KEY) {
-                if let Some(v) = aliases.get(name) {
-                    toml::Value::try_into(v.clone())?
-                } else {
-                    String::from(name)
-                }
-            } else {
-                String::from(name)
-            }
-        } else {
-            String::from(name)
-        };
+        let expanded_name = expand_alias(name, CONTAINERS_CFG.as_ref().ok())?;
 
         debug!("Expanded container name is {expanded_name}");
 
-        let (domain_name, container_name) =
-            expanded_name
-                .split_once('/')
-                .ok_or(OciBootstrapError::Custom(String::from(
-                    "Domain doesn't have the right format",
-                )))?;
+        let (domain_name, container_name) = expanded_name
+            .split_once('/')
+            .ok_or_else(|| OciBootstrapError::InvalidDomain(expanded_name.clone()))?;
 
         if domain_name != "localhost" && psl::domain(domain_name.as_bytes()).is_none() {
             debug!("The domain {domain_name} isn't valid, bailing out.");
 
-            // TODO: We should probably try handle it by looking at the registries.conf
-            // unqualified-search-registries key. This would however require to either ask the user
-            // for which registry to use, or try all of them.
-            return Err(OciBootstrapError::Custom(String::from(
-                "Invalid domain name",
-            )));
+            return Err(OciBootstrapError::InvalidDomain(domain_name.to_owned()));
         }
 
         debug!("Container domain name is {domain_name}");
@@ -92,6 +106,61 @@ impl ContainerSpec {
         Ok(spec)
     }
 
+    /// Returns the list of containers a name can refer to, in the order they should be looked up
+    ///
+    /// Fully qualified and aliased names refer to a single container, but short names are
+    /// expanded to one container for each of the `unqualified-search-registries`.
+    pub(crate) fn search_from_container_name(name: &str) -> Result<Vec<Self>, OciBootstrapError> {
+        match Self::from_container_name(name) {
+            Ok(spec) => Ok(vec![spec]),
+            Err(e) => {
+                let specs = Self::from_search_registries(name, CONTAINERS_CFG.as_ref().ok())?;
+                if specs.is_empty() {
+                    return Err(e);
+                }
+
+                Ok(specs)
+            }
+        }
+    }
+
+    /// Expands a short name to one container for each of the `unqualified-search-registries`
+    pub(crate) fn from_search_registries(
+        name: &str,
+        cfg: Option<&Map<String, Value>>,
+    ) -> Result<Vec<Self>, OciBootstrapError> {
+        let (name, reference) = split_reference(name)?;
+        let expanded_name = expand_alias(name, cfg)?;
+
+        let specs = search_registries(cfg)?
+            .into_iter()
+            .map(|domain| {
+                let name = if domain == DOCKER_HUB_DOMAIN && !expanded_name.contains('/') {
+                    format!("{DOCKER_HUB_LIBRARY}/{expanded_name}")
+                } else {
+                    expanded_name.clone()
+                };
+
+                ContainerSpec {
+                    domain,
+                    name,
+                    reference: reference.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        debug!(
+            "Short container name {expanded_name} will be looked up as {}",
+            specs
+                .iter()
+                .map(ContainerSpec::to_oci_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(specs)
+    }
+
     pub(crate) fn to_oci_string(&self) -> String {
         match &self.reference {
             ContainerReference::Tag(t) => format!("{}/{}:{}", self.domain, self.name, t),
@@ -111,7 +180,8 @@ impl fmt::Display for ContainerSpec {
 #[cfg(test)]
 mod registry_url_tests {
     use test_log::test;
-    use types::Digest;
+    use toml::{map::Map, Value};
+    use types::{Digest, OciBootstrapError};
 
     use crate::{
         config::{CONTAINERS_CFG, CONTAINERS_CFG_ALIASES_KEY},
@@ -192,6 +262,16 @@ mod registry_url_tests {
         assert!(ContainerSpec::from_container_name("pytorch/pytorch").is_err());
     }
 
+    #[test]
+    fn test_invalid_domain() {
+        let err = ContainerSpec::from_container_name("notadomain/image:latest").unwrap_err();
+
+        assert!(
+            matches!(&err, OciBootstrapError::InvalidDomain(domain) if domain == "notadomain"),
+            "{err}"
+        );
+    }
+
     #[test]
     fn test_full_name() {
         let container_name = "registry.access.redhat.com/ubi9";
@@ -238,4 +318,62 @@ mod registry_url_tests {
             }
         );
     }
+
+    fn search_config() -> Map<String, Value> {
+        toml::from_str(
+            r#"
+            unqualified-search-registries = ["registry.fedoraproject.org", "docker.io"]
+
+            [aliases]
+            "fedora" = "registry.fedoraproject.org/fedora"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_short_name_search_registries() {
+        assert_eq!(
+            ContainerSpec::from_search_registries("debian:stable", Some(&search_config())).unwrap(),
+            vec![
+                ContainerSpec {
+                    domain: String::from("registry.fedoraproject.org"),
+                    name: String::from("debian"),
+                    reference: ContainerReference::Tag(String::from("stable"))
+                },
+                ContainerSpec {
+                    domain: String::from("docker.io"),
+                    name: String::from("library/debian"),
+                    reference: ContainerReference::Tag(String::from("stable"))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_name_search_registries() {
+        assert_eq!(
+            ContainerSpec::from_search_registries("pytorch/pytorch", Some(&search_config()))
+                .unwrap(),
+            vec![
+                ContainerSpec {
+                    domain: String::from("registry.fedoraproject.org"),
+                    name: String::from("pytorch/pytorch"),
+                    reference: ContainerReference::Tag(String::from("latest"))
+                },
+                ContainerSpec {
+                    domain: String::from("docker.io"),
+                    name: String::from("pytorch/pytorch"),
+                    reference: ContainerReference::Tag(String::from("latest"))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_short_name_without_search_registries() {
+        assert!(ContainerSpec::from_search_registries("nginx", None)
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -0,0 +1,253 @@
+use alloc::collections::BTreeMap;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, trace};
+use tar::{Archive, Builder, EntryType};
+use types::OciBootstrapError;
+
+use crate::normalize_entry_path;
+
+/// Position of an entry in the layers of an image
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct EntryPosition {
+    layer: usize,
+    index: usize,
+}
+
+/// Entries left once all the layers of an image are applied on top of each other
+#[derive(Debug, Default)]
+struct SquashedTree {
+    entries: BTreeMap<PathBuf, EntryPosition>,
+    layers: usize,
+}
+
+impl SquashedTree {
+    /// Applies a layer on top of the ones previously added
+    fn add_layer<R>(&mut self, reader: R) -> Result<(), OciBootstrapError>
+    where
+        R: io::Read,
+    {
+        let layer = self.layers;
+        self.layers += 1;
+
+        let mut archive = Archive::new(reader);
+        for (index, entry) in archive.entries()?.enumerate() {
+            let entry = entry?;
+
+            let entry_path = normalize_entry_path(&entry.path()?);
+
+            let Some(file_name) = entry_path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            let parent_dir = entry_path.parent().unwrap_or(Path::new(""));
+
+            if file_name == ".wh..wh..opq" {
+                debug!("Directory {} is opaque", parent_dir.display());
+
+                self.entries.retain(|path, position| {
+                    path == parent_dir || !path.starts_with(parent_dir) || position.layer == layer
+                });
+                continue;
+            }
+
+            if let Some(remove_file_name) = file_name.strip_prefix(".wh.") {
+                let removed = parent_dir.join(remove_file_name);
+
+                debug!("File {} has been removed", removed.display());
+
+                self.entries.retain(|path, _| !path.starts_with(&removed));
+                continue;
+            }
+
+            self.entries
+                .insert(entry_path, EntryPosition { layer, index });
+        }
+
+        Ok(())
+    }
+
+    /// Appends the entries of a layer that are still part of the image to an archive
+    fn write_layer<R, W>(
+        &self,
+        layer: usize,
+        reader: R,
+        builder: &mut Builder<W>,
+    ) -> Result<(), OciBootstrapError>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        let mut archive = Archive::new(reader);
+        for (index, entry) in archive.entries()?.enumerate() {
+            let mut entry = entry?;
+
+            let entry_path = normalize_entry_path(&entry.path()?);
+            if self.entries.get(&entry_path) != Some(&EntryPosition { layer, index }) {
+                continue;
+            }
+
+            trace!("Adding {} to the squashed archive", entry_path.display());
+
+            let mut xattrs = Vec::new();
+            if let Some(extensions) = entry.pax_extensions()? {
+                for extension in extensions {
+                    let extension = extension?;
+                    if let Ok(key) = extension.key() {
+                        if key.starts_with("SCHILY.xattr.") {
+                            xattrs.push((String::from(key), extension.value_bytes().to_vec()));
+                        }
+                    }
+                }
+            }
+
+            if !xattrs.is_empty() {
+                builder.append_pax_extensions(
+                    xattrs
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_slice())),
+                )?;
+            }
+
+            let mut header = entry.header().clone();
+
+            #[allow(clippy::wildcard_enum_match_arm)]
+            match header.entry_type() {
+                EntryType::Link | EntryType::Symlink => {
+                    let link_name = entry.link_name()?.ok_or(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Link without a target",
+                    ))?;
+
+                    builder.append_link(&mut header, &entry_path, link_name)?;
+                }
+                _ => builder.append_data(&mut header, &entry_path, &mut entry)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Merges the layers of an image into a single archive, with the whiteouts applied
+///
+/// Each layer is read twice: once to find out which of its entries are still part of the image,
+/// and once to copy them. `open_layer` is called with the index of the layer to read.
+pub(crate) fn squash_layers<F, R, W>(
+    layers: usize,
+    mut open_layer: F,
+    writer: W,
+) -> Result<W, OciBootstrapError>
+where
+    F: FnMut(usize) -> io::Result<R>,
+    R: io::Read,
+    W: io::Write,
+{
+    let mut tree = SquashedTree::default();
+    for layer in 0..layers {
+        debug!("Listing layer {layer}");
+
+        tree.add_layer(open_layer(layer)?)?;
+    }
+
+    debug!("Squashed image has {} entries", tree.entries.len());
+
+    let mut builder = Builder::new(writer);
+
+    for layer in 0..layers {
+        debug!("Copying layer {layer}");
+
+        tree.write_layer(layer, open_layer(layer)?, &mut builder)?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+#[cfg(test)]
+mod export_tests {
+    use std::io::Read as _;
+
+    use tar::{Archive, Builder, EntryType, Header};
+    use test_log::test;
+
+    use crate::export::squash_layers;
+
+    fn layer(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for (path, content) in entries {
+            let mut header = Header::new_gnu();
+
+            if path.ends_with('/') {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o755);
+            } else {
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(0o644);
+            }
+
+            header.set_size(content.len() as u64);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_squash_layers() {
+        let layers = [
+            layer(&[
+                ("etc/", ""),
+                ("etc/hostname", "lower"),
+                ("etc/removed", "removed"),
+                ("etc/ssh/", ""),
+                ("etc/ssh/sshd_config", "lower"),
+                ("usr/", ""),
+                ("usr/bin/", ""),
+                ("usr/bin/tool", "#!/bin/sh"),
+            ]),
+            layer(&[
+                ("etc/.wh.removed", ""),
+                ("etc/hostname", "upper"),
+                ("etc/ssh/", ""),
+                ("etc/ssh/.wh..wh..opq", ""),
+                ("etc/ssh/ssh_config", "upper"),
+            ]),
+            layer(&[("usr/.wh.bin", ""), ("usr/lib/", "")]),
+        ];
+
+        let squashed =
+            squash_layers(layers.len(), |idx| Ok(layers[idx].as_slice()), Vec::new()).unwrap();
+
+        let mut archive = Archive::new(squashed.as_slice());
+        let mut files = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().display().to_string();
+
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+
+            files.push((path, content));
+        }
+        files.sort();
+
+        assert_eq!(
+            files,
+            [
+                ("etc", ""),
+                ("etc/hostname", "upper"),
+                ("etc/ssh", ""),
+                ("etc/ssh/ssh_config", "upper"),
+                ("usr", ""),
+                ("usr/lib", ""),
+            ]
+            .map(|(path, content)| (String::from(path), String::from(content)))
+        );
+    }
+}
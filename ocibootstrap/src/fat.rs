@@ -0,0 +1,446 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read as _, Seek as _, Write as _},
+    os::unix::fs::{FileTypeExt as _, MetadataExt as _},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::debug;
+use nix::sys::stat::{major, minor};
+use types::OciBootstrapError;
+
+use crate::{layout::FatParameters, reproducible::FilesystemIds};
+
+const SECTOR_SIZE: u16 = 512;
+const RESERVED_SECTORS: u16 = 32;
+const NUM_FATS: u8 = 2;
+const FSINFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u16 = 6;
+const ROOT_CLUSTER: u32 = 2;
+
+/// A FAT32 needs at least that many clusters, or it would be detected as a FAT16
+const MIN_CLUSTERS: u32 = 0xfff5;
+
+/// Returns the cluster size, in sectors, for a volume size, as recommended by Microsoft
+fn sectors_per_cluster(sectors: u32) -> u8 {
+    let size = u64::from(sectors) * u64::from(SECTOR_SIZE);
+
+    if size <= 260 << 20 {
+        1
+    } else if size <= 8 << 30 {
+        8
+    } else if size <= 16 << 30 {
+        16
+    } else if size <= 32 << 30 {
+        32
+    } else {
+        64
+    }
+}
+
+/// Returns the size of each FAT, in sectors
+fn fat_sectors(sectors: u32, sectors_per_cluster: u8) -> u32 {
+    let data = sectors - u32::from(RESERVED_SECTORS);
+    let per_sector = u32::midpoint(256 * u32::from(sectors_per_cluster), u32::from(NUM_FATS));
+
+    data.div_ceil(per_sector)
+}
+
+/// Pads a label to the 11 characters FAT uses
+fn fat_label(label: Option<&str>) -> Result<[u8; 11], OciBootstrapError> {
+    let mut buf = [b' '; 11];
+    let label = label.unwrap_or("NO NAME");
+
+    if label.len() > buf.len() || !label.is_ascii() {
+        return Err(OciBootstrapError::Custom(format!(
+            "Invalid FAT Label {label}"
+        )));
+    }
+
+    buf[..label.len()].copy_from_slice(label.to_ascii_uppercase().as_bytes());
+
+    Ok(buf)
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Creates the boot sector, and its BIOS Parameter Block
+fn boot_sector(
+    params: &FatParameters,
+    hidden_sectors: u32,
+    sectors: u32,
+    cluster_sectors: u8,
+    fat_size: u32,
+    volume_id: u32,
+    label: &[u8; 11],
+) -> Result<[u8; 512], OciBootstrapError> {
+    let mut boot = [0u8; 512];
+    boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    put_u16(&mut boot, 11, SECTOR_SIZE);
+    boot[13] = cluster_sectors;
+    put_u16(&mut boot, 14, RESERVED_SECTORS);
+    boot[16] = NUM_FATS;
+    boot[21] = 0xf8;
+    put_u16(
+        &mut boot,
+        24,
+        u16::try_from(params.sectors_per_track.unwrap_or(63))
+            .map_err(|_err| OciBootstrapError::Custom(String::from("Invalid FAT32 Geometry")))?,
+    );
+    put_u16(
+        &mut boot,
+        26,
+        u16::try_from(params.heads.unwrap_or(255))
+            .map_err(|_err| OciBootstrapError::Custom(String::from("Invalid FAT32 Geometry")))?,
+    );
+    put_u32(&mut boot, 28, hidden_sectors);
+    put_u32(&mut boot, 32, sectors);
+    put_u32(&mut boot, 36, fat_size);
+    put_u32(&mut boot, 44, ROOT_CLUSTER);
+    put_u16(&mut boot, 48, FSINFO_SECTOR);
+    put_u16(&mut boot, 50, BACKUP_BOOT_SECTOR);
+    boot[64] = 0x80;
+    boot[66] = 0x29;
+    put_u32(&mut boot, 67, volume_id);
+    boot[71..82].copy_from_slice(label);
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    Ok(boot)
+}
+
+/// Returns the number of sectors of a device or file
+fn volume_sectors(file: &mut File) -> Result<u32, OciBootstrapError> {
+    u32::try_from(file.seek(io::SeekFrom::End(0))? / u64::from(SECTOR_SIZE))
+        .map_err(|_err| OciBootstrapError::Custom(String::from("FAT32 Volume is too large")))
+}
+
+/// Returns the cluster size, the size of each FAT, the first data sector and the number of
+/// clusters of a volume
+fn volume_layout(sectors: u32) -> (u8, u32, u32, u32) {
+    let cluster_sectors = sectors_per_cluster(sectors);
+    let fat_size = fat_sectors(sectors, cluster_sectors);
+    let data_start = u32::from(RESERVED_SECTORS) + u32::from(NUM_FATS) * fat_size;
+    let clusters = sectors.saturating_sub(data_start) / u32::from(cluster_sectors);
+
+    (cluster_sectors, fat_size, data_start, clusters)
+}
+
+/// Returns whether a FAT32 can be created on a device or file with the given parameters
+///
+/// FAT12 and FAT16, and volumes too small to hold a FAT32, need mkfs.vfat.
+pub(crate) fn fits_fat32(dev: &Path, params: &FatParameters) -> Result<bool, OciBootstrapError> {
+    if params.fat_bits.is_some_and(|bits| bits != 32) {
+        return Ok(false);
+    }
+
+    let sectors = volume_sectors(&mut File::open(dev)?)?;
+    let (_, _, _, clusters) = volume_layout(sectors);
+
+    Ok(clusters >= MIN_CLUSTERS)
+}
+
+/// Returns the first sector of a partition device on its parent disk, or 0 if it isn't one
+///
+/// This is what the BIOS Parameter Block calls the hidden sectors, and some firmwares rely on
+/// it to find the filesystem.
+pub(crate) fn partition_start(dev: &Path) -> Result<u32, OciBootstrapError> {
+    let metadata = dev.metadata()?;
+    if !metadata.file_type().is_block_device() {
+        return Ok(0);
+    }
+
+    let rdev = metadata.rdev();
+    let start_path = PathBuf::from(format!(
+        "/sys/dev/block/{}:{}/start",
+        major(rdev),
+        minor(rdev)
+    ));
+
+    let start = match fs::read_to_string(&start_path) {
+        Ok(start) => start,
+        // Only partitions have a start
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    start.trim().parse().map_err(|_err| {
+        OciBootstrapError::Custom(format!(
+            "Invalid partition start {} for {}",
+            start.trim(),
+            dev.display()
+        ))
+    })
+}
+
+/// Creates a FAT32 filesystem on a device or file, without relying on mkfs.vfat
+///
+/// `hidden_sectors` is the first sector of the partition on its disk.
+pub(crate) fn format_fat32(
+    dev: &Path,
+    params: &FatParameters,
+    ids: Option<&FilesystemIds>,
+    hidden_sectors: u32,
+) -> Result<(), OciBootstrapError> {
+    if params.fat_bits.is_some_and(|bits| bits != 32) {
+        return Err(OciBootstrapError::Custom(String::from(
+            "Only FAT32 can be created without mkfs.vfat",
+        )));
+    }
+
+    let mut file = OpenOptions::new().write(true).open(dev)?;
+    let sector_size = u64::from(SECTOR_SIZE);
+    let sectors = volume_sectors(&mut file)?;
+    let (cluster_sectors, fat_size, data_start, clusters) = volume_layout(sectors);
+
+    debug!(
+        "Creating FAT32 on {} with {clusters} clusters of {cluster_sectors} sectors, {fat_size} sectors per FAT",
+        dev.display()
+    );
+
+    if clusters < MIN_CLUSTERS {
+        return Err(OciBootstrapError::Custom(format!(
+            "Partition {} is too small for FAT32",
+            dev.display()
+        )));
+    }
+
+    let volume_id = params
+        .volume_id
+        .or_else(|| ids.map(|ids| ids.uuid.as_fields().0))
+        .unwrap_or_else(|| {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+
+            #[allow(clippy::cast_possible_truncation)]
+            let secs = now.as_secs() as u32;
+
+            secs ^ now.subsec_nanos()
+        });
+
+    debug!("FAT32 Volume ID is {volume_id:x}");
+
+    let label = fat_label(params.label.as_deref())?;
+
+    let boot = boot_sector(
+        params,
+        hidden_sectors,
+        sectors,
+        cluster_sectors,
+        fat_size,
+        volume_id,
+        &label,
+    )?;
+
+    let mut fsinfo = [0u8; 512];
+    put_u32(&mut fsinfo, 0, 0x4161_5252);
+    put_u32(&mut fsinfo, 484, 0x6141_7272);
+    put_u32(&mut fsinfo, 488, clusters - 1);
+    put_u32(&mut fsinfo, 492, ROOT_CLUSTER + 1);
+    put_u32(&mut fsinfo, 508, 0xaa55_0000);
+
+    // Clear the reserved sectors, the FATs and the root directory cluster
+    let cleared = u64::from(data_start + u32::from(cluster_sectors)) * sector_size;
+    file.seek(io::SeekFrom::Start(0))?;
+    io::copy(&mut io::repeat(0).take(cleared), &mut file)?;
+
+    for base in [0, BACKUP_BOOT_SECTOR] {
+        file.seek(io::SeekFrom::Start(u64::from(base) * sector_size))?;
+        file.write_all(&boot)?;
+
+        file.seek(io::SeekFrom::Start(
+            u64::from(base + FSINFO_SECTOR) * sector_size,
+        ))?;
+        file.write_all(&fsinfo)?;
+    }
+
+    // The first two entries hold the media type and the clean shutdown flags, the third one is
+    // the end of the root directory cluster chain
+    let mut fat_start = [0u8; 12];
+    put_u32(&mut fat_start, 0, 0x0fff_fff8);
+    put_u32(&mut fat_start, 4, 0x0fff_ffff);
+    put_u32(&mut fat_start, 8, 0x0fff_ffff);
+
+    for idx in 0..NUM_FATS {
+        let offset =
+            u64::from(u32::from(RESERVED_SECTORS) + u32::from(idx) * fat_size) * sector_size;
+
+        file.seek(io::SeekFrom::Start(offset))?;
+        file.write_all(&fat_start)?;
+    }
+
+    if params.label.is_some() {
+        let mut entry = [0u8; 32];
+        entry[0..11].copy_from_slice(&label);
+        entry[11] = 0x08;
+
+        file.seek(io::SeekFrom::Start(u64::from(data_start) * sector_size))?;
+        file.write_all(&entry)?;
+    }
+
+    file.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod fat_tests {
+    use std::{fs, process::Command};
+
+    use tempfile::{NamedTempFile, TempDir};
+    use test_log::test;
+
+    use crate::{
+        fat::{fits_fat32, format_fat32, partition_start},
+        layout::FatParameters,
+    };
+
+    fn read_u16(buf: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn params() -> FatParameters {
+        FatParameters {
+            volume_id: Some(0x1234_abcd),
+            label: Some(String::from("esp")),
+            fat_bits: None,
+            heads: None,
+            sectors_per_track: None,
+            efi_boot: None,
+        }
+    }
+
+    #[test]
+    fn test_format_fat32() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 << 20).unwrap();
+
+        format_fat32(image.path(), &params(), None, 0).unwrap();
+
+        let content = fs::read(image.path()).unwrap();
+        let boot = &content[..512];
+        assert_eq!(boot.len(), 512);
+        assert_eq!(&boot[510..], &[0x55, 0xaa]);
+        assert_eq!(read_u16(boot, 11), 512);
+        assert_eq!(read_u32(boot, 28), 0);
+        assert_eq!(read_u32(boot, 32), (64 << 20) / 512);
+        assert_eq!(read_u32(boot, 67), 0x1234_abcd);
+        assert_eq!(&boot[71..82], b"ESP        ");
+        assert_eq!(&boot[82..90], b"FAT32   ");
+
+        // The backup boot sector
+        assert_eq!(&content[6 * 512..7 * 512], boot);
+
+        let reserved = usize::from(read_u16(boot, 14));
+        let fat_size = usize::try_from(read_u32(boot, 36)).unwrap();
+        let sectors_per_cluster = u32::from(boot[13]);
+        let data_start = reserved + 2 * fat_size;
+        let clusters =
+            (read_u32(boot, 32) - u32::try_from(data_start).unwrap()) / sectors_per_cluster;
+        assert!(clusters >= 0xfff5, "{clusters}");
+
+        // Both FATs need an entry per cluster
+        assert!(fat_size * 512 / 4 >= usize::try_from(clusters).unwrap() + 2);
+
+        for fat in 0..2 {
+            let fat = &content[(reserved + fat * fat_size) * 512..];
+            assert_eq!(read_u32(fat, 0), 0x0fff_fff8);
+            assert_eq!(read_u32(fat, 8), 0x0fff_ffff);
+            assert_eq!(read_u32(fat, 12), 0);
+        }
+
+        let root = &content[data_start * 512..(data_start + 1) * 512];
+        assert_eq!(root.len(), 512);
+        assert_eq!(&root[..11], b"ESP        ");
+        assert_eq!(root[11], 0x08);
+    }
+
+    #[test]
+    fn test_format_fat32_too_small() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(16 << 20).unwrap();
+
+        format_fat32(image.path(), &params(), None, 0).unwrap_err();
+        assert!(!fits_fat32(image.path(), &params()).unwrap());
+    }
+
+    #[test]
+    fn test_format_fat32_hidden_sectors() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 << 20).unwrap();
+        assert!(fits_fat32(image.path(), &params()).unwrap());
+
+        // Regular files aren't partitions
+        assert_eq!(partition_start(image.path()).unwrap(), 0);
+
+        format_fat32(image.path(), &params(), None, 2048).unwrap();
+
+        let content = fs::read(image.path()).unwrap();
+        assert_eq!(read_u32(&content[..512], 28), 2048);
+        assert_eq!(read_u32(&content[6 * 512..7 * 512], 28), 2048);
+    }
+
+    #[test]
+    fn test_fits_fat32_fat_bits() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 << 20).unwrap();
+
+        let params = FatParameters {
+            fat_bits: Some(16),
+            ..params()
+        };
+        assert!(!fits_fat32(image.path(), &params).unwrap());
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and loop devices"]
+    fn test_format_fat32_mount() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 << 20).unwrap();
+
+        format_fat32(image.path(), &params(), None, 0).unwrap();
+
+        let mnt = TempDir::new().unwrap();
+        let status = Command::new("mount")
+            .args(["-o", "loop", "-t", "vfat"])
+            .arg(image.path())
+            .arg(mnt.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        fs::create_dir_all(mnt.path().join("EFI/BOOT")).unwrap();
+        fs::write(mnt.path().join("EFI/BOOT/BOOTAA64.EFI"), "efi").unwrap();
+
+        let status = Command::new("umount").arg(mnt.path()).status().unwrap();
+        assert!(status.success());
+
+        let status = Command::new("mount")
+            .args(["-o", "loop,ro", "-t", "vfat"])
+            .arg(image.path())
+            .arg(mnt.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let content = fs::read_to_string(mnt.path().join("EFI/BOOT/BOOTAA64.EFI"));
+
+        let status = Command::new("umount").arg(mnt.path()).status().unwrap();
+        assert!(status.success());
+
+        assert_eq!(content.unwrap(), "efi");
+    }
+}
@@ -0,0 +1,281 @@
+use core::time::Duration;
+use std::{
+    fs::{self, File, Permissions},
+    io,
+    os::unix::fs::PermissionsExt as _,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::{debug, error, info};
+use sys_mount::{FilesystemType, Mount, MountFlags, PropagationType, Unmount as _, UnmountFlags};
+use types::OciBootstrapError;
+
+use crate::{
+    command::run_command_with_timeout, create_file_in_root, path_in_root, preflight::is_in_path,
+};
+
+/// Where the post-extraction script is copied to, relative to the root filesystem
+const POST_EXTRACT_SCRIPT_PATH: &str = ".ocibootstrap/post-extract";
+
+/// How long the post-extraction script is allowed to run, generating an initramfs can take a
+/// while
+const POST_EXTRACT_TIMEOUT: Duration = Duration::from_hours(1);
+
+/// Host filesystems made available to the post-extraction script when run through chroot
+const BIND_MOUNTS: [&str; 3] = ["/proc", "/sys", "/dev"];
+
+/// A host directory bind-mounted in the root filesystem, unmounted once dropped
+#[derive(Debug)]
+struct BindMount {
+    mount: Mount,
+}
+
+impl BindMount {
+    fn new(source: &Path, root: &Path) -> Result<Self, OciBootstrapError> {
+        let target = path_in_root(root, source)?;
+
+        debug!("Bind-mounting {} on {}", source.display(), target.display());
+
+        if target.symlink_metadata().is_err() {
+            fs::create_dir(&target)?;
+        }
+
+        // The image could make the target a symlink to a directory of the host
+        if !target.symlink_metadata()?.is_dir() {
+            return Err(OciBootstrapError::Custom(format!(
+                "Can't bind-mount {} on {}: Not a directory",
+                source.display(),
+                target.display()
+            )));
+        }
+
+        let mount = Mount::builder()
+            .fstype(FilesystemType::Manual("none"))
+            .flags(MountFlags::BIND | MountFlags::REC)
+            .mount(source, &target)
+            .map_err(|e| {
+                OciBootstrapError::Custom(format!(
+                    "Couldn't bind-mount {} on {}: {e}",
+                    source.display(),
+                    target.display()
+                ))
+            })?;
+
+        // Dropped, and thus unmounted, if we fail to change its propagation type
+        let mut bind = Self { mount };
+
+        // Without this, unmounting the bind mount would unmount the host submounts too
+        let propagation =
+            PropagationType::SLAVE | PropagationType::from_bits_retain(MountFlags::REC.bits());
+        bind.mount.set_propagation_type(propagation).map_err(|e| {
+            OciBootstrapError::Custom(format!(
+                "Couldn't make {} a slave mount: {e}",
+                target.display()
+            ))
+        })?;
+
+        Ok(bind)
+    }
+}
+
+impl Drop for BindMount {
+    fn drop(&mut self) {
+        debug!("Unmounting {}", self.mount.target_path().display());
+
+        if let Err(e) = self.mount.unmount(UnmountFlags::DETACH) {
+            error!(
+                "Couldn't unmount {}: {e}",
+                self.mount.target_path().display()
+            );
+        }
+    }
+}
+
+/// The post-extraction script, copied in the root filesystem, and removed once dropped
+#[derive(Debug)]
+struct InstalledScript {
+    path: PathBuf,
+}
+
+impl InstalledScript {
+    fn new(script: &Path, root: &Path) -> Result<Self, OciBootstrapError> {
+        let mut source = File::open(script).map_err(|e| {
+            OciBootstrapError::Custom(format!(
+                "Couldn't open the post-extraction script {}: {e}",
+                script.display()
+            ))
+        })?;
+
+        // The image isn't supposed to ship anything there, and we'll remove it afterwards
+        let (path, mut file) = create_file_in_root(root, Path::new(POST_EXTRACT_SCRIPT_PATH), true)
+            .map_err(|e| {
+                OciBootstrapError::Custom(format!(
+                    "Couldn't create {POST_EXTRACT_SCRIPT_PATH} in {}: {e}",
+                    root.display()
+                ))
+            })?;
+
+        let script = Self { path };
+
+        io::copy(&mut source, &mut file)?;
+        file.set_permissions(Permissions::from_mode(0o755))?;
+
+        Ok(script)
+    }
+}
+
+impl Drop for InstalledScript {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("Couldn't remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Runs a script of the host in the root filesystem, through systemd-nspawn if it's available or
+/// through chroot with /proc, /sys and /dev bind-mounted
+///
+/// The mounts are undone, and the script removed, whether it succeeds or not.
+///
+/// # Errors
+///
+/// If the filesystems can't be mounted, or if the script fails.
+pub(crate) fn run_post_extract(root: &Path, script: &Path) -> Result<(), OciBootstrapError> {
+    info!("Running {} in {}", script.display(), root.display());
+
+    let _script = InstalledScript::new(script, root)?;
+    let script_path = Path::new("/").join(POST_EXTRACT_SCRIPT_PATH);
+
+    if is_in_path("systemd-nspawn") {
+        run_command_with_timeout(
+            Command::new("systemd-nspawn")
+                .arg("--quiet")
+                .arg("--directory")
+                .arg(root)
+                .arg(&script_path),
+            POST_EXTRACT_TIMEOUT,
+        )?;
+
+        return Ok(());
+    }
+
+    let mut mounts = Vec::with_capacity(BIND_MOUNTS.len());
+    for source in BIND_MOUNTS {
+        mounts.push(BindMount::new(Path::new(source), root)?);
+    }
+
+    let res = run_command_with_timeout(
+        Command::new("chroot").arg(root).arg(&script_path),
+        POST_EXTRACT_TIMEOUT,
+    );
+
+    while let Some(mount) = mounts.pop() {
+        drop(mount);
+    }
+
+    res?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod hook_tests {
+    use std::{fs, os::unix::fs as unix_fs, path::Path, process::Command};
+
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::hook::{
+        run_post_extract, BindMount, InstalledScript, BIND_MOUNTS, POST_EXTRACT_SCRIPT_PATH,
+    };
+
+    /// Copies a host binary and the libraries it needs to the same paths in a root filesystem
+    fn install_binary(root: &Path, binary: &Path) {
+        let output = Command::new("ldd").arg(binary).output().unwrap();
+        let libraries = String::from_utf8(output.stdout).unwrap();
+
+        let paths = libraries
+            .split_whitespace()
+            .filter(|word| word.starts_with('/'))
+            .map(Path::new)
+            .chain([binary]);
+
+        for path in paths {
+            let dest = root.join(path.strip_prefix("/").unwrap());
+            fs::create_dir_all(dest.parent().unwrap()).unwrap();
+            fs::copy(path, dest).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_installed_script_symlink() {
+        let root = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        let script = outside.path().join("script");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+
+        // The image directory points to the host
+        unix_fs::symlink(outside.path(), root.path().join(".ocibootstrap")).unwrap();
+        InstalledScript::new(&script, root.path()).unwrap_err();
+        assert!(!outside.path().join("post-extract").exists());
+
+        // The image script points to a file of the host
+        let host_file = outside.path().join("host-file");
+        fs::write(&host_file, "host").unwrap();
+        fs::remove_file(root.path().join(".ocibootstrap")).unwrap();
+        fs::create_dir(root.path().join(".ocibootstrap")).unwrap();
+        unix_fs::symlink(&host_file, root.path().join(POST_EXTRACT_SCRIPT_PATH)).unwrap();
+        InstalledScript::new(&script, root.path()).unwrap_err();
+        assert_eq!(fs::read_to_string(&host_file).unwrap(), "host");
+        assert!(root
+            .path()
+            .join(POST_EXTRACT_SCRIPT_PATH)
+            .symlink_metadata()
+            .unwrap()
+            .is_symlink());
+    }
+
+    #[test]
+    fn test_bind_mount_symlink() {
+        let root = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+
+        unix_fs::symlink(outside.path(), root.path().join("dev")).unwrap();
+
+        let err = BindMount::new(Path::new("/dev"), root.path()).unwrap_err();
+        assert!(err.to_string().contains("Not a directory"), "{err}");
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and chroot"]
+    fn test_run_post_extract() {
+        let root = TempDir::new().unwrap();
+        let binary = fs::canonicalize("/bin/true").unwrap();
+        install_binary(root.path(), &binary);
+
+        run_post_extract(root.path(), &binary).unwrap();
+
+        assert!(!root.path().join(POST_EXTRACT_SCRIPT_PATH).exists());
+        for mount in BIND_MOUNTS {
+            let target = root.path().join(mount.trim_start_matches('/'));
+            assert_eq!(
+                fs::read_dir(target).map_or(0, Iterator::count),
+                0,
+                "{mount}"
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and chroot"]
+    fn test_run_post_extract_failure() {
+        let root = TempDir::new().unwrap();
+        let binary = fs::canonicalize("/bin/false").unwrap();
+        install_binary(root.path(), &binary);
+
+        run_post_extract(root.path(), &binary).unwrap_err();
+
+        assert!(!root.path().join(POST_EXTRACT_SCRIPT_PATH).exists());
+    }
+}
@@ -1,12 +1,20 @@
-use core::{fmt, str::FromStr as _};
-use std::{collections::HashMap, path::PathBuf};
+use core::{fmt, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+};
 
+use bitflags::bitflags;
 use log::debug;
 use num_traits::Num;
 use oci_spec::image::ImageConfiguration;
-use types::OciBootstrapError;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use types::{Architecture, OciBootstrapError};
 use uuid::Uuid;
 
+use crate::LBA_SIZE;
+
 fn parse_int_repr<T>(s: &str) -> Result<T, T::FromStrRadixErr>
 where
     T: Num,
@@ -22,16 +30,686 @@ where
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+fn parse_size_percent(
+    labels: &HashMap<String, String>,
+    part_name: &str,
+    idx: usize,
+) -> Result<Option<u8>, OciBootstrapError> {
+    labels
+        .get(&format!(
+            "com.github.mripard.ocibootstrap.partition.{part_name}.size_percent",
+        ))
+        .map(|s| {
+            let percent = u8::from_str(s).map_err(|_err| {
+                OciBootstrapError::Custom(format!("Partition {idx}: Invalid integer value"))
+            })?;
+
+            if percent == 0 || percent > 100 {
+                return Err(OciBootstrapError::Custom(format!(
+                    "Partition {idx}: Size Percentage must be between 1 and 100"
+                )));
+            }
+
+            Ok(percent)
+        })
+        .transpose()
+}
+
+/// A partition size, either in bytes or as a percentage of the usable space
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[expect(variant_size_differences)]
+pub(crate) enum Size {
+    Bytes(usize),
+    Percent(u8),
+}
+
+impl Size {
+    const UNITS: [(&'static str, usize); 9] = [
+        ("B", 1),
+        ("kB", 1000),
+        ("MB", 1000 * 1000),
+        ("GB", 1000 * 1000 * 1000),
+        ("TB", 1000 * 1000 * 1000 * 1000),
+        ("KiB", 1 << 10),
+        ("MiB", 1 << 20),
+        ("GiB", 1 << 30),
+        ("TiB", 1 << 40),
+    ];
+}
+
+impl FromStr for Size {
+    type Err = OciBootstrapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent = u8::from_str(percent.trim_end())
+                .ok()
+                .filter(|percent| (1..=100).contains(percent))
+                .ok_or(OciBootstrapError::Custom(format!(
+                    "Invalid Size \"{s}\": Percentage must be between 1 and 100"
+                )))?;
+
+            return Ok(Self::Percent(percent));
+        }
+
+        let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(unit_start);
+
+        let multiplier = match unit.trim_start() {
+            "" => 1,
+            unit => Self::UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .map(|(_, multiplier)| *multiplier)
+                .ok_or(OciBootstrapError::Custom(format!(
+                    "Invalid Size \"{s}\": Expected a number of bytes, a percentage, or one of the {} units",
+                    Self::UNITS.map(|(name, _)| name).join(", ")
+                )))?,
+        };
+
+        usize::from_str(value)
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .map(Self::Bytes)
+            .ok_or(OciBootstrapError::Custom(format!(
+                "Invalid Size \"{s}\": Invalid integer value"
+            )))
+    }
+}
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SizeVisitor;
+
+        impl de::Visitor<'_> for SizeVisitor {
+            type Value = Size;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a number of bytes, or a string with a unit or a percentage")
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                usize::try_from(v)
+                    .map(Size::Bytes)
+                    .map_err(|_err| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Size::from_str(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(SizeVisitor)
+    }
+}
+
+/// Returns the size of a partition, in bytes or as a percentage of the usable space, from one of
+/// its `size`, `size_mb` or `size_percent` labels
+fn parse_size(
+    labels: &HashMap<String, String>,
+    part_name: &str,
+    idx: usize,
+) -> Result<(Option<usize>, Option<u8>), OciBootstrapError> {
+    let size = labels
+        .get(&format!(
+            "com.github.mripard.ocibootstrap.partition.{part_name}.size",
+        ))
+        .map(|s| {
+            Size::from_str(s)
+                .map_err(|e| OciBootstrapError::Custom(format!("Partition {idx}: {e}")))
+        })
+        .transpose()?;
+
+    let size_mb = labels
+        .get(&format!(
+            "com.github.mripard.ocibootstrap.partition.{part_name}.size_mb",
+        ))
+        .map(|size_str| {
+            usize::from_str(size_str)
+                .map(|size_mb| size_mb << 20)
+                .map_err(|_err| {
+                    OciBootstrapError::Custom(format!("Partition {idx}: Invalid integer value"))
+                })
+        })
+        .transpose()?;
+
+    let size_percent = parse_size_percent(labels, part_name, idx)?;
+
+    let (size_bytes, size_percent) = match (size, size_mb, size_percent) {
+        (None, size_bytes, None) => (size_bytes, None),
+        (Some(Size::Bytes(size_bytes)), None, None) => (Some(size_bytes), None),
+        (None, None, Some(size_percent)) | (Some(Size::Percent(size_percent)), None, None) => {
+            (None, Some(size_percent))
+        }
+        (None, Some(_), Some(_)) => {
+            return Err(OciBootstrapError::Custom(format!(
+                "Partition {idx}: Size and Size Percentage are mutually exclusive"
+            )));
+        }
+        (Some(_), _, _) => {
+            return Err(OciBootstrapError::Custom(format!(
+                "Partition {idx}: Size is mutually exclusive with Size in MB and Size Percentage"
+            )));
+        }
+    };
+
+    if let Some(size_bytes) = size_bytes {
+        debug!("Partition {idx}: Size {size_bytes} bytes");
+    }
+
+    if let Some(size_percent) = size_percent {
+        debug!("Partition {idx}: Size {size_percent}% of the usable space");
+    }
+
+    Ok((size_bytes, size_percent))
+}
+
+fn parse_mount_options(labels: &HashMap<String, String>, part_name: &str) -> Vec<String> {
+    labels
+        .get(&format!(
+            "com.github.mripard.ocibootstrap.partition.{part_name}.mount_options",
+        ))
+        .map(|s| {
+            s.split(',')
+                .map(str::trim)
+                .filter(|opt| !opt.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_flag(
+    labels: &HashMap<String, String>,
+    part_name: &str,
+    idx: usize,
+    flag: &str,
+) -> Result<bool, OciBootstrapError> {
+    labels
+        .get(&format!(
+            "com.github.mripard.ocibootstrap.partition.{part_name}.flags.{flag}",
+        ))
+        .map_or(Ok(false), |s| {
+            bool::from_str(s).map_err(|_err| {
+                OciBootstrapError::Custom(format!("Partition {idx}: Invalid bool value"))
+            })
+        })
+}
+
+/// Parses the flag marking a partition to grow on first boot
+///
+/// Only the partition filling the rest of the device can grow, since the others would overlap
+/// with the next partition.
+fn parse_grow_flag(
+    labels: &HashMap<String, String>,
+    part_name: &str,
+    idx: usize,
+    size_bytes: Option<usize>,
+    size_percent: Option<u8>,
+) -> Result<bool, OciBootstrapError> {
+    let grow = parse_flag(labels, part_name, idx, "grow")?;
+
+    if grow && (size_bytes.is_some() || size_percent.is_some()) {
+        return Err(OciBootstrapError::Custom(format!(
+            "Partition {idx}: Only a partition without a size can grow"
+        )));
+    }
+
+    Ok(grow)
+}
+
+/// A file of the root filesystem to copy into a partition once its filesystem has been created
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PartitionFile {
+    pub(crate) source: PathBuf,
+    pub(crate) dest: PathBuf,
+}
+
+fn parse_files(
+    labels: &HashMap<String, String>,
+    part_name: &str,
+    idx: usize,
+) -> Result<Vec<PartitionFile>, OciBootstrapError> {
+    let Some(files) = labels.get(&format!(
+        "com.github.mripard.ocibootstrap.partition.{part_name}.files",
+    )) else {
+        return Ok(Vec::new());
+    };
+
+    serde_json::from_str(files)
+        .map_err(|_err| OciBootstrapError::Custom(format!("Partition {idx}: Invalid files list")))
+}
+
+/// A file of the root filesystem to write to the device at a given offset, outside of the
+/// partitions
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Firmware {
+    pub(crate) source: PathBuf,
+    pub(crate) offset_bytes: u64,
+}
+
+/// Parses the space to leave out of the partitions at the start of the device, and the firmwares
+/// to write there
+fn parse_firmware(
+    labels: &HashMap<String, String>,
+) -> Result<(Option<usize>, Vec<Firmware>), OciBootstrapError> {
+    let reserved_start_bytes = labels
+        .get("com.github.mripard.ocibootstrap.table.reserved_start_bytes")
+        .map(|s| {
+            parse_int_repr(s).map_err(|_err| {
+                OciBootstrapError::Custom(String::from("Invalid reserved start size"))
+            })
+        })
+        .transpose()?;
+
+    if let Some(size) = reserved_start_bytes {
+        debug!("Reserving {size} bytes at the start of the device");
+    }
+
+    let firmware: Vec<Firmware> = labels
+        .get("com.github.mripard.ocibootstrap.table.firmware")
+        .map(|firmware| {
+            serde_json::from_str(firmware)
+                .map_err(|_err| OciBootstrapError::Custom(String::from("Invalid firmware list")))
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    Ok((reserved_start_bytes, firmware))
+}
+
+fn check_size_percent_total<I>(percents: I) -> Result<(), OciBootstrapError>
+where
+    I: IntoIterator<Item = Option<u8>>,
+{
+    let total: usize = percents.into_iter().flatten().map(usize::from).sum();
+
+    if total > 100 {
+        return Err(OciBootstrapError::Custom(format!(
+            "Partitions Size Percentages add up to {total}%"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the mount points of a partition, including the ones of its btrfs subvolumes
+fn partition_mount_points<'a>(
+    mnt: Option<&'a PathBuf>,
+    fs: &'a Filesystem,
+) -> impl Iterator<Item = &'a Path> {
+    let subvolumes = match fs {
+        Filesystem::Btrfs(params) => params.subvolumes.as_slice(),
+        Filesystem::Fat32(_)
+        | Filesystem::Ext4(_)
+        | Filesystem::Raw(_)
+        | Filesystem::Squashfs(_)
+        | Filesystem::Swap => &[],
+    };
+
+    mnt.into_iter()
+        .chain(
+            subvolumes
+                .iter()
+                .filter_map(|subvolume| subvolume.mnt.as_ref()),
+        )
+        .map(PathBuf::as_path)
+}
+
+fn check_mount_points<'a, I>(mount_points: I) -> Result<(), OciBootstrapError>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let mut found = HashSet::new();
+
+    for mnt in mount_points {
+        if !mnt.is_absolute() {
+            return Err(OciBootstrapError::Custom(format!(
+                "Mount Point {} isn't an absolute path",
+                mnt.display()
+            )));
+        }
+
+        if !found.insert(mnt) {
+            return Err(OciBootstrapError::Custom(format!(
+                "Mount Point {} is used more than once",
+                mnt.display()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves the partitions sizes into bytes, converting the percentages of the usable space into
+/// a number of bytes rounded down to a multiple of `block_size`.
+///
+/// Each item is a partition size in bytes and size in percents, that are mutually exclusive.
+pub(crate) fn resolve_size_bytes<I>(
+    sizes: I,
+    usable_bytes: usize,
+    block_size: usize,
+) -> Result<Vec<Option<usize>>, OciBootstrapError>
+where
+    I: IntoIterator<Item = (Option<usize>, Option<u8>)>,
+{
+    let resolved = sizes
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (size_bytes, size_percent))| {
+            let Some(percent) = size_percent else {
+                return Ok(size_bytes);
+            };
+
+            let size = (usable_bytes * usize::from(percent)) / 100;
+            let size = size - (size % block_size);
+            if size == 0 {
+                return Err(OciBootstrapError::Custom(format!(
+                    "Partition {idx}: {percent}% of the device ({usable_bytes} bytes) is smaller than a block ({block_size} bytes)"
+                )));
+            }
+
+            Ok(Some(size))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let total: usize = resolved.iter().flatten().sum();
+    if total > usable_bytes {
+        return Err(OciBootstrapError::Custom(format!(
+            "Partitions total size ({total} bytes) exceeds the device capacity ({usable_bytes} bytes)"
+        )));
+    }
+
+    Ok(resolved)
+}
+
+/// FAT sizes, in bits, mkfs.vfat accepts
+const FAT_BITS: [u8; 3] = [12, 16, 32];
+
+/// Maximum length of a FAT volume label
+const FAT_LABEL_MAX_LEN: usize = 11;
+
+#[derive(Clone, Debug)]
 pub(crate) struct FatParameters {
     pub(crate) volume_id: Option<u32>,
+    pub(crate) label: Option<String>,
+    pub(crate) fat_bits: Option<u8>,
     pub(crate) heads: Option<u32>,
     pub(crate) sectors_per_track: Option<u32>,
+
+    /// EFI binary, in the root filesystem, to install at the removable media default boot path
+    pub(crate) efi_boot: Option<PathBuf>,
+}
+
+impl FatParameters {
+    fn from_labels(
+        labels: &HashMap<String, String>,
+        part_name: &str,
+    ) -> Result<Self, OciBootstrapError> {
+        let vol_id = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.fat.vol_id",
+            ))
+            .map(|s| u32::from_str_radix(s, 16))
+            .transpose()
+            .map_err(|_err| {
+                OciBootstrapError::Custom(format!("Partition {part_name}: Invalid Id Format"))
+            })?;
+
+        let label = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.fat.label",
+            ))
+            .map(|s| {
+                if s.len() > FAT_LABEL_MAX_LEN {
+                    return Err(OciBootstrapError::Custom(format!(
+                        "Partition {part_name}: Label must be at most {FAT_LABEL_MAX_LEN} characters"
+                    )));
+                }
+
+                Ok(s.clone())
+            })
+            .transpose()?;
+
+        let fat_bits = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.fat.fat_bits",
+            ))
+            .map(|s| {
+                let bits = u8::from_str(s).map_err(|_err| {
+                    OciBootstrapError::Custom(format!("Partition {part_name}: Invalid value"))
+                })?;
+
+                if !FAT_BITS.contains(&bits) {
+                    return Err(OciBootstrapError::Custom(format!(
+                        "Partition {part_name}: FAT Size must be one of {FAT_BITS:?}"
+                    )));
+                }
+
+                Ok(bits)
+            })
+            .transpose()?;
+
+        let heads = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.fat.heads",
+            ))
+            .map(|s| {
+                u32::from_str(s).map_err(|_err| {
+                    OciBootstrapError::Custom(format!("Partition {part_name}: Invalid value"))
+                })
+            })
+            .transpose()?;
+
+        let sectors_per_track = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.fat.sectors_per_track",
+            ))
+            .map(|s| {
+                u32::from_str(s).map_err(|_err| {
+                    OciBootstrapError::Custom(format!("Partition {part_name}: Invalid value"))
+                })
+            })
+            .transpose()?;
+
+        let efi_boot = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.fat.efi_boot",
+            ))
+            .map(PathBuf::from);
+
+        Ok(Self {
+            volume_id: vol_id,
+            label,
+            fat_bits,
+            heads,
+            sectors_per_track,
+            efi_boot,
+        })
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Block sizes, in bytes, mkfs.ext4 accepts
+const EXT4_BLOCK_SIZES: [u32; 3] = [1024, 2048, 4096];
+
+/// Largest percentage of the filesystem mkfs.ext4 can reserve for the super-user
+const EXT4_MAX_RESERVED_PERCENT: u8 = 50;
+
+/// Maximum length, in bytes, of an ext4 volume label
+const EXT4_LABEL_MAX_LEN: usize = 16;
+
+#[derive(Clone, Debug, Default)]
 pub(crate) struct ExtParameters {
     pub(crate) uuid: Option<Uuid>,
+    pub(crate) label: Option<String>,
+    pub(crate) block_size: Option<u32>,
+    pub(crate) reserved_percent: Option<u8>,
+}
+
+impl ExtParameters {
+    fn from_labels(
+        labels: &HashMap<String, String>,
+        part_name: &str,
+    ) -> Result<Self, OciBootstrapError> {
+        let uuid = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.ext4.uuid",
+            ))
+            .map(|s| Uuid::from_str(s))
+            .transpose()
+            .map_err(|_err| {
+                OciBootstrapError::Custom(format!("Partition {part_name}: Invalid UUID Format"))
+            })?;
+
+        let label = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.ext4.label",
+            ))
+            .map(|s| {
+                if s.len() > EXT4_LABEL_MAX_LEN {
+                    return Err(OciBootstrapError::Custom(format!(
+                        "Partition {part_name}: Label must be at most {EXT4_LABEL_MAX_LEN} bytes"
+                    )));
+                }
+
+                Ok(s.clone())
+            })
+            .transpose()?;
+
+        let block_size = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.ext4.block_size",
+            ))
+            .map(|s| {
+                let size = u32::from_str(s).map_err(|_err| {
+                    OciBootstrapError::Custom(format!("Partition {part_name}: Invalid value"))
+                })?;
+
+                if !EXT4_BLOCK_SIZES.contains(&size) {
+                    return Err(OciBootstrapError::Custom(format!(
+                        "Partition {part_name}: Block Size must be one of {EXT4_BLOCK_SIZES:?}"
+                    )));
+                }
+
+                Ok(size)
+            })
+            .transpose()?;
+
+        let reserved_percent = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.ext4.reserved_percent",
+            ))
+            .map(|s| {
+                let percent = u8::from_str(s).map_err(|_err| {
+                    OciBootstrapError::Custom(format!("Partition {part_name}: Invalid value"))
+                })?;
+
+                if percent > EXT4_MAX_RESERVED_PERCENT {
+                    return Err(OciBootstrapError::Custom(format!(
+                        "Partition {part_name}: Reserved Blocks Percentage must be at most {EXT4_MAX_RESERVED_PERCENT}"
+                    )));
+                }
+
+                Ok(percent)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            uuid,
+            label,
+            block_size,
+            reserved_percent,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct BtrfsSubvolume {
+    pub(crate) name: String,
+    pub(crate) mnt: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct BtrfsParameters {
+    pub(crate) label: Option<String>,
+    pub(crate) uuid: Option<Uuid>,
+    pub(crate) subvolumes: Vec<BtrfsSubvolume>,
+}
+
+impl BtrfsParameters {
+    fn from_labels(
+        labels: &HashMap<String, String>,
+        part_name: &str,
+    ) -> Result<Self, OciBootstrapError> {
+        let label = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.btrfs.label",
+            ))
+            .cloned();
+
+        let uuid = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.btrfs.uuid",
+            ))
+            .map(|s| Uuid::from_str(s))
+            .transpose()
+            .map_err(|_err| {
+                OciBootstrapError::Custom(format!("Partition {part_name}: Invalid UUID Format"))
+            })?;
+
+        let subvolume_names: Vec<String> = labels
+            .get(&format!(
+                "com.github.mripard.ocibootstrap.partition.{part_name}.btrfs.subvolumes",
+            ))
+            .map(|s| serde_json::from_str(s))
+            .transpose()?
+            .unwrap_or_default();
+
+        let subvolumes = subvolume_names
+            .into_iter()
+            .map(|name| {
+                // Subvolumes are created in the root of the filesystem, and the name ends up in
+                // the mount options.
+                let mut components = Path::new(&name).components();
+                if !matches!(
+                    (components.next(), components.next()),
+                    (Some(Component::Normal(_)), None)
+                ) || name.contains(',')
+                {
+                    return Err(OciBootstrapError::Custom(format!(
+                        "Partition {part_name}: Invalid Subvolume Name"
+                    )));
+                }
+
+                let mnt = labels
+                    .get(&format!(
+                        "com.github.mripard.ocibootstrap.partition.{part_name}.btrfs.subvolume.{name}.mount_point",
+                    ))
+                    .map(PathBuf::from);
+
+                Ok(BtrfsSubvolume { name, mnt })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            label,
+            uuid,
+            subvolumes,
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -39,14 +717,51 @@ pub(crate) struct RawParameters {
     pub(crate) content: PathBuf,
 }
 
+/// A prebuilt squashfs image, copied to the partition like a raw partition content
+#[derive(Clone, Debug)]
+pub(crate) struct SquashfsParameters {
+    pub(crate) source: PathBuf,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) enum Filesystem {
     Fat32(FatParameters),
     Ext4(ExtParameters),
+    Btrfs(BtrfsParameters),
     Raw(RawParameters),
+    Squashfs(SquashfsParameters),
+    Swap,
 }
 
 impl Filesystem {
+    /// Returns the filesystem type to pass to mount(2), if the filesystem can be mounted at all.
+    pub(crate) fn mount_type(&self) -> Option<&'static str> {
+        match self {
+            Filesystem::Fat32(_) => Some("vfat"),
+            Filesystem::Ext4(_) => Some("ext4"),
+            Filesystem::Btrfs(_) => Some("btrfs"),
+            Filesystem::Raw(_) | Filesystem::Squashfs(_) | Filesystem::Swap => None,
+        }
+    }
+
+    /// Returns the file in the image to copy to the partition, if its content isn't created by
+    /// ocibootstrap
+    pub(crate) fn content(&self) -> Option<&Path> {
+        match self {
+            Filesystem::Raw(p) => Some(&p.content),
+            Filesystem::Squashfs(p) => Some(&p.source),
+            Filesystem::Fat32(_)
+            | Filesystem::Ext4(_)
+            | Filesystem::Btrfs(_)
+            | Filesystem::Swap => None,
+        }
+    }
+
+    /// Returns whether the partition content can't be modified once written
+    pub(crate) fn is_read_only(&self) -> bool {
+        matches!(self, Filesystem::Squashfs(_))
+    }
+
     fn from_labels(
         labels: &HashMap<String, String>,
         part_name: &str,
@@ -60,66 +775,12 @@ impl Filesystem {
             )))?
             .as_str()
         {
-            "ext4" => {
-                let uuid = labels
-                    .get(&format!(
-                        "com.github.mripard.ocibootstrap.partition.{part_name}.ext4.uuid",
-                    ))
-                    .map(|s| Uuid::from_str(s))
-                    .transpose()
-                    .map_err(|_err| {
-                        OciBootstrapError::Custom(format!(
-                            "Partition {part_name}: Invalid UUID Format",
-                        ))
-                    })?;
-
-                Ok(Filesystem::Ext4(ExtParameters { uuid }))
-            }
-            "fat" => {
-                let vol_id = labels
-                    .get(&format!(
-                        "com.github.mripard.ocibootstrap.partition.{part_name}.fat.vol_id",
-                    ))
-                    .map(|s| u32::from_str_radix(s, 16))
-                    .transpose()
-                    .map_err(|_err| {
-                        OciBootstrapError::Custom(format!(
-                            "Partition {part_name}: Invalid Id Format",
-                        ))
-                    })?;
-
-                let heads = labels
-                    .get(&format!(
-                        "com.github.mripard.ocibootstrap.partition.{part_name}.fat.heads",
-                    ))
-                    .map(|s| {
-                        u32::from_str(s).map_err(|_err| {
-                            OciBootstrapError::Custom(format!(
-                                "Partition {part_name}: Invalid value"
-                            ))
-                        })
-                    })
-                    .transpose()?;
-
-                let sectors_per_track = labels
-                    .get(&format!(
-                        "com.github.mripard.ocibootstrap.partition.{part_name}.fat.sectors_per_track",
-                    ))
-                    .map(|s| {
-                        u32::from_str(s).map_err(|_err| {
-                            OciBootstrapError::Custom(format!(
-                                "Partition {part_name}: Invalid value"
-                            ))
-                        })
-                    })
-                    .transpose()?;
-
-                Ok(Filesystem::Fat32(FatParameters {
-                    volume_id: vol_id,
-                    heads,
-                    sectors_per_track,
-                }))
-            }
+            "ext4" => Ok(Filesystem::Ext4(ExtParameters::from_labels(
+                labels, part_name,
+            )?)),
+            "fat" => Ok(Filesystem::Fat32(FatParameters::from_labels(
+                labels, part_name,
+            )?)),
             "raw" => {
                 let content = labels
                     .get(&format!(
@@ -132,6 +793,22 @@ impl Filesystem {
 
                 Ok(Filesystem::Raw(RawParameters { content }))
             }
+            "squashfs" => {
+                let source = labels
+                    .get(&format!(
+                        "com.github.mripard.ocibootstrap.partition.{part_name}.squashfs.source",
+                    ))
+                    .map(PathBuf::from)
+                    .ok_or(OciBootstrapError::Custom(format!(
+                        "Partition {part_name}: Missing Squashfs Image Source",
+                    )))?;
+
+                Ok(Filesystem::Squashfs(SquashfsParameters { source }))
+            }
+            "btrfs" => Ok(Filesystem::Btrfs(BtrfsParameters::from_labels(
+                labels, part_name,
+            )?)),
+            "swap" => Ok(Filesystem::Swap),
             _ => unimplemented!(),
         }
     }
@@ -142,64 +819,659 @@ impl fmt::Display for Filesystem {
         match self {
             Filesystem::Fat32(_) => f.write_str("fat"),
             Filesystem::Ext4(_) => f.write_str("ext4"),
+            Filesystem::Btrfs(_) => f.write_str("btrfs"),
             Filesystem::Raw(_) => f.write_str("raw"),
+            Filesystem::Squashfs(_) => f.write_str("squashfs"),
+            Filesystem::Swap => f.write_str("swap"),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct GptPartition {
-    pub(crate) uuid: Uuid,
-    pub(crate) name: Option<String>,
-    pub(crate) mnt: Option<PathBuf>,
-    pub(crate) offset_lba: Option<usize>,
-    pub(crate) size_bytes: Option<usize>,
-    pub(crate) fs: Filesystem,
-    pub(crate) bootable: bool,
-    pub(crate) platform_required: bool,
+/// A GPT Partition Type, either referred to by its name in the UAPI Discoverable Partitions
+/// Specification, or by its raw GUID
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum PartitionType {
+    Esp,
+    Xbootldr,
+    Swap,
+    LinuxData,
+    LinuxRoot,
+    LinuxRootArm,
+    LinuxRootArm64,
+    LinuxRootX86,
+    LinuxRootX86_64,
+    LinuxUsrArm,
+    LinuxUsrArm64,
+    LinuxUsrX86,
+    LinuxUsrX86_64,
+    Guid(Uuid),
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct GptPartitionTable {
-    partitions: Vec<GptPartition>,
-}
+impl PartitionType {
+    const NAMED: [(&'static str, Self); 13] = [
+        ("esp", Self::Esp),
+        ("xbootldr", Self::Xbootldr),
+        ("swap", Self::Swap),
+        ("linux-data", Self::LinuxData),
+        ("linux-root", Self::LinuxRoot),
+        ("linux-root-arm", Self::LinuxRootArm),
+        ("linux-root-arm64", Self::LinuxRootArm64),
+        ("linux-root-x86", Self::LinuxRootX86),
+        ("linux-root-x86-64", Self::LinuxRootX86_64),
+        ("linux-usr-arm", Self::LinuxUsrArm),
+        ("linux-usr-arm64", Self::LinuxUsrArm64),
+        ("linux-usr-x86", Self::LinuxUsrX86),
+        ("linux-usr-x86-64", Self::LinuxUsrX86_64),
+    ];
+
+    /// Returns the type GUID, `arch` being the architecture of the image
+    pub(crate) fn guid(self, arch: Architecture) -> Uuid {
+        match self {
+            Self::Esp => gpt::EFI_SYSTEM_PART_GUID,
+            Self::Xbootldr => gpt::EXTENDED_BOOTLOADER_PART_GUID,
+            Self::Swap => gpt::SWAP_PART_GUID,
+            Self::LinuxData => gpt::LINUX_DATA_PART_GUID,
+            Self::LinuxRoot => gpt::root_part_guid_for(arch),
+            Self::LinuxRootArm => gpt::ROOT_PART_GUID_ARM,
+            Self::LinuxRootArm64 => gpt::ROOT_PART_GUID_ARM64,
+            Self::LinuxRootX86 => gpt::ROOT_PART_GUID_X86,
+            Self::LinuxRootX86_64 => gpt::ROOT_PART_GUID_X86_64,
+            Self::LinuxUsrArm => gpt::USR_PART_GUID_ARM,
+            Self::LinuxUsrArm64 => gpt::USR_PART_GUID_ARM64,
+            Self::LinuxUsrX86 => gpt::USR_PART_GUID_X86,
+            Self::LinuxUsrX86_64 => gpt::USR_PART_GUID_X86_64,
+            Self::Guid(guid) => guid,
+        }
+    }
+}
+
+impl FromStr for PartitionType {
+    type Err = OciBootstrapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, kind)) = Self::NAMED.iter().find(|(name, _)| *name == s) {
+            return Ok(*kind);
+        }
+
+        Uuid::from_str(s).map(Self::Guid).map_err(|_err| {
+            OciBootstrapError::Custom(format!(
+                "Unknown Partition Type \"{s}\": Expected a UUID or one of {}",
+                Self::NAMED.map(|(name, _)| name).join(", ")
+            ))
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for PartitionType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+
+        Self::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+bitflags! {
+    /// The attributes of a GPT partition
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    pub(crate) struct GptAttributes: u8 {
+        /// The legacy BIOS can boot from the partition
+        const BOOTABLE = 1 << 0;
+
+        /// The partition is required for the platform to function
+        const PLATFORM_REQUIRED = 1 << 1;
+
+        /// The partition must be mounted read-only
+        const READ_ONLY = 1 << 2;
+
+        /// The partition shouldn't be mounted, even if discovered
+        const HIDDEN = 1 << 3;
+
+        /// The partition is excluded from automatic discovery
+        const NO_AUTO = 1 << 4;
+
+        /// The first boot tooling should grow the partition and its filesystem
+        const GROW = 1 << 5;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct GptPartition {
+    pub(crate) uuid: Uuid,
+    pub(crate) name: Option<String>,
+    pub(crate) mnt: Option<PathBuf>,
+    pub(crate) mount_options: Vec<String>,
+    pub(crate) files: Vec<PartitionFile>,
+    pub(crate) offset_lba: Option<usize>,
+    pub(crate) size_bytes: Option<usize>,
+    pub(crate) size_percent: Option<u8>,
+    pub(crate) fs: Filesystem,
+    pub(crate) attributes: GptAttributes,
+}
+
+/// A GUID Partition Table layout
+#[derive(Debug, Clone)]
+pub struct GptPartitionTable {
+    partitions: Vec<GptPartition>,
+    reserved_start_bytes: Option<usize>,
+    firmware: Vec<Firmware>,
+}
 
 impl GptPartitionTable {
     pub(crate) fn partitions(&self) -> &[GptPartition] {
         &self.partitions
     }
+
+    /// Returns the space to leave out of the partitions at the start of the device, if any
+    pub(crate) fn reserved_start_bytes(&self) -> Option<usize> {
+        self.reserved_start_bytes
+    }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct MbrPartition {
     pub(crate) kind: u8,
     pub(crate) mnt: Option<PathBuf>,
+    pub(crate) mount_options: Vec<String>,
+    pub(crate) files: Vec<PartitionFile>,
     pub(crate) offset_lba: Option<usize>,
     pub(crate) size_bytes: Option<usize>,
+    pub(crate) size_percent: Option<u8>,
     pub(crate) fs: Filesystem,
     pub(crate) bootable: bool,
+
+    /// Whether the first boot tooling should grow the partition and its filesystem
+    pub(crate) grow: bool,
 }
 
+/// A Master Boot Record partition table layout
 #[derive(Debug, Clone)]
-pub(crate) struct MbrPartitionTable {
+pub struct MbrPartitionTable {
     partitions: Vec<MbrPartition>,
+    heads_per_cylinder: u8,
+    sectors_per_track: u8,
+    reserved_start_bytes: Option<usize>,
+    firmware: Vec<Firmware>,
 }
 
 impl MbrPartitionTable {
     pub(crate) fn partitions(&self) -> &[MbrPartition] {
         &self.partitions
     }
+
+    /// Returns the space to leave out of the partitions at the start of the device, if any
+    pub(crate) fn reserved_start_bytes(&self) -> Option<usize> {
+        self.reserved_start_bytes
+    }
+
+    /// Returns the heads per cylinder and sectors per track the table and its FAT partitions use
+    pub(crate) fn geometry(&self) -> (u8, u8) {
+        (self.heads_per_cylinder, self.sectors_per_track)
+    }
 }
 
+/// Figures out the geometry shared by the MBR and its FAT partitions
+///
+/// The CHS addresses of the partitions and the FAT BPB must agree, so the geometry set on any FAT
+/// partition is used for the whole table, and applied to all the FAT partitions.
+fn shared_fat_geometry(partitions: &mut [MbrPartition]) -> Result<(u8, u8), OciBootstrapError> {
+    let mut geometry = None;
+
+    for (idx, part) in partitions.iter().enumerate() {
+        let Filesystem::Fat32(params) = &part.fs else {
+            continue;
+        };
+
+        if params.heads.is_none() && params.sectors_per_track.is_none() {
+            continue;
+        }
+
+        let heads = params
+            .heads
+            .map_or(Ok(mbr::DEFAULT_HEADS_PER_CYLINDER), u8::try_from)
+            .map_err(|_err| {
+                OciBootstrapError::Custom(format!("Partition {idx}: Invalid number of heads"))
+            })?;
+
+        let sectors_per_track = params
+            .sectors_per_track
+            .map_or(Ok(mbr::DEFAULT_SECTORS_PER_TRACK), u8::try_from)
+            .map_err(|_err| {
+                OciBootstrapError::Custom(format!(
+                    "Partition {idx}: Invalid number of sectors per track"
+                ))
+            })?;
+
+        match geometry {
+            Some(geometry) if geometry != (heads, sectors_per_track) => {
+                return Err(OciBootstrapError::Custom(format!(
+                    "Partition {idx}: FAT Geometry conflicts with another partition"
+                )));
+            }
+            _ => geometry = Some((heads, sectors_per_track)),
+        }
+    }
+
+    let (heads, sectors_per_track) = geometry.unwrap_or((
+        mbr::DEFAULT_HEADS_PER_CYLINDER,
+        mbr::DEFAULT_SECTORS_PER_TRACK,
+    ));
+
+    debug!("Table Geometry uses {heads} heads, {sectors_per_track} sectors per track");
+
+    for part in partitions {
+        if let Filesystem::Fat32(params) = &mut part.fs {
+            params.heads = Some(heads.into());
+            params.sectors_per_track = Some(sectors_per_track.into());
+        }
+    }
+
+    Ok((heads, sectors_per_track))
+}
+
+/// The partition layout of a device, as described by the labels of an image
 #[derive(Debug, Clone)]
-pub(crate) enum PartitionTable {
+pub enum PartitionTable {
+    /// A GUID Partition Table
     Gpt(GptPartitionTable),
+
+    /// A Master Boot Record
     Mbr(MbrPartitionTable),
 }
 
+impl fmt::Display for PartitionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionTable::Gpt(_) => f.write_str("gpt"),
+            PartitionTable::Mbr(_) => f.write_str("mbr"),
+        }
+    }
+}
+
+/// A problem found in a partition layout by [`PartitionTable::validate`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LayoutError {
+    /// The device can't even hold the partition table
+    DeviceTooSmall(usize),
+
+    /// The partitions with a size need more space than the device has
+    TotalSizeTooLarge {
+        /// Space needed by the partitions with a size, in bytes
+        total: usize,
+
+        /// Space available to the partitions, in bytes
+        usable: usize,
+    },
+
+    /// Several partitions don't have a size, and would each take the rest of the device
+    SeveralFillPartitions(Vec<usize>),
+
+    /// A partition, given as a range of LBAs, isn't within the space available to the partitions
+    OutOfBounds {
+        /// Index of the partition
+        partition: usize,
+
+        /// First LBA of the partition
+        start_lba: usize,
+
+        /// LBA right after the end of the partition
+        end_lba: usize,
+    },
+
+    /// Two partitions overlap
+    Overlap(usize, usize),
+
+    /// A partition has a mount point, but its filesystem can't be mounted
+    NotMountable(usize),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::DeviceTooSmall(size) => {
+                write!(
+                    f,
+                    "Device ({size} bytes) is too small for the partition table"
+                )
+            }
+            LayoutError::TotalSizeTooLarge { total, usable } => write!(
+                f,
+                "Partitions total size ({total} bytes) exceeds the device capacity ({usable} bytes)"
+            ),
+            LayoutError::SeveralFillPartitions(partitions) => write!(
+                f,
+                "Partitions {} don't have a size, only one partition can fill the device",
+                partitions
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            LayoutError::OutOfBounds {
+                partition,
+                start_lba,
+                end_lba,
+            } => write!(
+                f,
+                "Partition {partition} (LBA {start_lba} to {end_lba}) doesn't fit in the device"
+            ),
+            LayoutError::Overlap(partition, other) => {
+                write!(f, "Partition {partition} overlaps with partition {other}")
+            }
+            LayoutError::NotMountable(partition) => write!(
+                f,
+                "Partition {partition} has a mount point, but its filesystem can't be mounted"
+            ),
+        }
+    }
+}
+
+/// What [`PartitionTable::validate`] needs to know about a partition
+struct PartitionBounds<'a> {
+    offset_lba: Option<usize>,
+    size_bytes: Option<usize>,
+    size_percent: Option<u8>,
+    mounted: bool,
+    fs: &'a Filesystem,
+}
+
+/// Places the partitions the way the partition table builders do, and returns their range of
+/// LBAs, the end being excluded
+///
+/// The partitions before the one without a size are placed from the start of the usable space,
+/// and the ones after it from its end. The offsets we derive are aligned to `alignment_lba`.
+///
+/// # Errors
+///
+/// If the end of a partition doesn't fit in a `usize`, in which case it's reported as out of
+/// bounds with the largest LBA.
+fn place_partitions(
+    first_lba: usize,
+    last_lba: usize,
+    alignment_lba: usize,
+    parts: &[(Option<usize>, Option<usize>)],
+) -> Result<Vec<(usize, usize)>, LayoutError> {
+    let end_lba = |idx, start_lba: usize, size_lba| {
+        start_lba
+            .checked_add(size_lba)
+            .ok_or(LayoutError::OutOfBounds {
+                partition: idx,
+                start_lba,
+                end_lba: usize::MAX,
+            })
+    };
+
+    let mut ranges = Vec::with_capacity(parts.len());
+
+    let mut next_lba = first_lba;
+    let mut fill = None;
+    for (idx, (offset_lba, size_bytes)) in parts.iter().enumerate() {
+        let start_lba = match offset_lba {
+            Some(offset_lba) => *offset_lba,
+            None => next_lba.checked_next_multiple_of(alignment_lba).ok_or(
+                LayoutError::OutOfBounds {
+                    partition: idx,
+                    start_lba: next_lba,
+                    end_lba: usize::MAX,
+                },
+            )?,
+        };
+
+        let Some(size_bytes) = size_bytes else {
+            fill = Some((idx, start_lba));
+            break;
+        };
+
+        next_lba = end_lba(idx, start_lba, size_bytes.div_ceil(LBA_SIZE))?;
+        ranges.push((start_lba, next_lba));
+    }
+
+    if let Some((fill_idx, fill_start_lba)) = fill {
+        let mut end_ranges = Vec::new();
+
+        let mut prev_lba = last_lba + 1;
+        for (idx, (offset_lba, size_bytes)) in parts.iter().enumerate().skip(fill_idx + 1).rev() {
+            let size_lba = size_bytes.unwrap_or_default().div_ceil(LBA_SIZE);
+            let start_lba = offset_lba.unwrap_or_else(|| {
+                let start_lba = prev_lba.saturating_sub(size_lba);
+
+                start_lba - (start_lba % alignment_lba)
+            });
+
+            prev_lba = start_lba;
+            end_ranges.push((start_lba, end_lba(idx, start_lba, size_lba)?));
+        }
+
+        ranges.push((fill_start_lba, prev_lba));
+        ranges.extend(end_ranges.into_iter().rev());
+    }
+
+    Ok(ranges)
+}
+
 impl PartitionTable {
+    /// Returns the files of the root filesystem to write to the device outside of the partitions
+    pub(crate) fn firmware(&self) -> &[Firmware] {
+        match self {
+            PartitionTable::Gpt(table) => &table.firmware,
+            PartitionTable::Mbr(table) => &table.firmware,
+        }
+    }
+
+    /// Checks that the layout fits a device of the given size, without accessing any device
+    ///
+    /// All the fixed offsets and sizes must fit in the device without overlapping, at most one
+    /// partition can fill the rest of the device, and the partitions with a mount point must be
+    /// mountable. The mount points and filesystems themselves are checked when the layout is
+    /// parsed.
+    ///
+    /// # Errors
+    ///
+    /// All the problems found, at once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ocibootstrap::{LayoutError, PartitionTable};
+    /// use types::Architecture;
+    ///
+    /// let table = PartitionTable::from_layout(
+    ///     r#"{
+    ///         "type": "gpt",
+    ///         "partitions": [
+    ///             { "name": "esp", "partition_uuid": "esp", "fs": "fat", "size_mb": 64 },
+    ///             { "name": "root", "partition_uuid": "linux-root", "fs": "ext4", "size_mb": 1024 }
+    ///         ]
+    ///     }"#,
+    ///     Architecture::Arm64,
+    /// )?;
+    ///
+    /// assert!(table.validate(2 << 30).is_ok());
+    ///
+    /// let errors = table.validate(512 << 20).unwrap_err();
+    /// assert!(matches!(errors[0], LayoutError::TotalSizeTooLarge { .. }));
+    /// # Ok::<(), types::OciBootstrapError>(())
+    /// ```
+    #[expect(clippy::too_many_lines)]
+    pub fn validate(&self, device_size_bytes: usize) -> Result<(), Vec<LayoutError>> {
+        let (usable_range, alignment_lba, reserved_start_bytes, parts) = match self {
+            PartitionTable::Gpt(table) => (
+                gpt::usable_lba_range(device_size_bytes as u64),
+                gpt::PARTITION_ALIGNMENT_LBA,
+                table.reserved_start_bytes,
+                table
+                    .partitions
+                    .iter()
+                    .map(|p| PartitionBounds {
+                        offset_lba: p.offset_lba,
+                        size_bytes: p.size_bytes,
+                        size_percent: p.size_percent,
+                        mounted: p.mnt.is_some(),
+                        fs: &p.fs,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            PartitionTable::Mbr(table) => (
+                mbr::usable_lba_range(device_size_bytes as u64),
+                mbr::PARTITION_ALIGNMENT_LBA,
+                table.reserved_start_bytes,
+                table
+                    .partitions
+                    .iter()
+                    .map(|p| PartitionBounds {
+                        offset_lba: p.offset_lba,
+                        size_bytes: p.size_bytes,
+                        size_percent: p.size_percent,
+                        mounted: p.mnt.is_some(),
+                        fs: &p.fs,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        };
+
+        let Ok((table_first_lba, last_lba)) = usable_range else {
+            return Err(vec![LayoutError::DeviceTooSmall(device_size_bytes)]);
+        };
+
+        let mut errors = Vec::new();
+
+        for (idx, part) in parts.iter().enumerate() {
+            if part.mounted && part.fs.mount_type().is_none() {
+                errors.push(LayoutError::NotMountable(idx));
+            }
+        }
+
+        // Percentages are relative to the aligned space after the partition table, and rounded to
+        // the alignment, like when building it
+        let alignment_bytes = alignment_lba * LBA_SIZE;
+        let usable = (last_lba + 1).saturating_sub(table_first_lba.next_multiple_of(alignment_lba))
+            * LBA_SIZE;
+        let sizes = parts
+            .iter()
+            .map(|p| {
+                let size_bytes = p.size_percent.map_or(p.size_bytes, |percent| {
+                    let size = (usable * usize::from(percent)) / 100;
+
+                    Some(size - (size % alignment_bytes))
+                });
+
+                (p.offset_lba, size_bytes)
+            })
+            .collect::<Vec<_>>();
+
+        // A total that doesn't fit in a usize is obviously too large
+        let total = sizes
+            .iter()
+            .filter_map(|(_, size)| *size)
+            .try_fold(0usize, usize::checked_add)
+            .unwrap_or(usize::MAX);
+        if total > usable {
+            errors.push(LayoutError::TotalSizeTooLarge { total, usable });
+        }
+
+        let fill = sizes
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, size))| size.is_none())
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+
+        // We can't tell where the partitions would be placed then
+        if fill.len() > 1 {
+            errors.push(LayoutError::SeveralFillPartitions(fill));
+            return Err(errors);
+        }
+
+        let first_lba =
+            table_first_lba.max(reserved_start_bytes.unwrap_or_default().div_ceil(LBA_SIZE));
+        let ranges = match place_partitions(first_lba, last_lba, alignment_lba, &sizes) {
+            Ok(ranges) => ranges,
+            Err(e) => {
+                errors.push(e);
+                return Err(errors);
+            }
+        };
+
+        for (idx, &(start_lba, end_lba)) in ranges.iter().enumerate() {
+            if start_lba < first_lba || end_lba > last_lba + 1 || start_lba >= end_lba {
+                errors.push(LayoutError::OutOfBounds {
+                    partition: idx,
+                    start_lba,
+                    end_lba,
+                });
+            }
+
+            for (other, &(other_start_lba, other_end_lba)) in
+                ranges.iter().enumerate().skip(idx + 1)
+            {
+                if start_lba < other_end_lba && other_start_lba < end_lba {
+                    errors.push(LayoutError::Overlap(idx, other));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses a JSON partition layout, as found in the `com.github.mripard.ocibootstrap.table.layout`
+    /// image label
+    ///
+    /// `arch` is the architecture of the image, that some partition types depend on.
+    ///
+    /// # Errors
+    ///
+    /// If the layout isn't valid JSON, or doesn't describe a valid partition table.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ocibootstrap::PartitionTable;
+    /// use types::Architecture;
+    ///
+    /// let table = PartitionTable::from_layout(
+    ///     r#"{ "type": "mbr", "partitions": [{ "name": "root", "type": "0x83", "fs": "ext4" }] }"#,
+    ///     Architecture::Arm64,
+    /// )?;
+    ///
+    /// assert_eq!(table.to_string(), "mbr");
+    /// # Ok::<(), types::OciBootstrapError>(())
+    /// ```
+    pub fn from_layout(layout: &str, arch: Architecture) -> Result<Self, OciBootstrapError> {
+        Self::from_labels(&labels_from_layout(layout)?, arch)
+    }
+
+    fn from_labels(
+        labels: &HashMap<String, String>,
+        arch: Architecture,
+    ) -> Result<Self, OciBootstrapError> {
+        let layout_type = labels
+            .get("com.github.mripard.ocibootstrap.table.type")
+            .ok_or(OciBootstrapError::Custom(
+                "Missing partition layout".to_owned(),
+            ))?;
+
+        debug!("Found {layout_type} partition layout type.");
+
+        Ok(match layout_type.as_str() {
+            "gpt" => Self::Gpt(PartitionTable::gpt_from_config(labels, arch)?),
+            "mbr" => Self::Mbr(PartitionTable::mbr_from_config(labels)?),
+            _ => {
+                return Err(OciBootstrapError::Custom(format!(
+                    "Invalid Layout Type: {layout_type}"
+                )))
+            }
+        })
+    }
+
     fn gpt_from_config(
         labels: &HashMap<String, String>,
+        arch: Architecture,
     ) -> Result<GptPartitionTable, OciBootstrapError> {
         let part_names: Vec<String> = serde_json::from_str(
             labels
@@ -215,7 +1487,7 @@ impl PartitionTable {
         for (idx, part_name) in part_names.iter().enumerate() {
             debug!("Partition {idx}: Name {part_name}");
 
-            let part_uuid = Uuid::from_str(
+            let part_type = PartitionType::from_str(
                 labels
                     .get(&format!(
                         "com.github.mripard.ocibootstrap.partition.{part_name}.partition_uuid",
@@ -223,8 +1495,8 @@ impl PartitionTable {
                     .ok_or(OciBootstrapError::Custom(format!(
                         "Partition {idx}: Missing Partition UUID",
                     )))?,
-            )
-            .map_err(|_err| OciBootstrapError::Custom(format!("Partition {idx}: Invalid UUID")))?;
+            )?;
+            let part_uuid = part_type.guid(arch);
 
             debug!("Partition {idx}: Partition UUID {part_uuid}");
 
@@ -238,6 +1510,23 @@ impl PartitionTable {
                 debug!("Partition {idx}: Mount Point {}", mnt.display());
             }
 
+            let part_mount_options = parse_mount_options(labels, part_name);
+            if !part_mount_options.is_empty() {
+                debug!(
+                    "Partition {idx}: Mount Options {}",
+                    part_mount_options.join(",")
+                );
+            }
+
+            let part_files = parse_files(labels, part_name, idx)?;
+            for file in &part_files {
+                debug!(
+                    "Partition {idx}: Copying {} to {}",
+                    file.source.display(),
+                    file.dest.display()
+                );
+            }
+
             let part_offset_lba = labels
                 .get(&format!(
                     "com.github.mripard.ocibootstrap.partition.{part_name}.offset_lba",
@@ -253,61 +1542,55 @@ impl PartitionTable {
                 debug!("Partition {idx}: Offset {offset_lba}");
             }
 
-            let part_size_bytes = labels
-                .get(&format!(
-                    "com.github.mripard.ocibootstrap.partition.{part_name}.size_mb",
-                ))
-                .map(|size_str| {
-                    usize::from_str(size_str)
-                        .map(|size_mb| size_mb << 20)
-                        .map_err(|_err| {
-                            OciBootstrapError::Custom(format!(
-                                "Partition {idx}: Invalid bool value"
-                            ))
-                        })
-                })
-                .transpose()?;
-
-            if let Some(size_bytes) = part_size_bytes {
-                debug!("Partition {idx}: Size {size_bytes} bytes");
-            }
+            let (part_size_bytes, part_size_percent) = parse_size(labels, part_name, idx)?;
 
             let part_fs = Filesystem::from_labels(labels, part_name)?;
             debug!("Partition {idx}: Filesystem {part_fs}");
 
-            let part_bootable = if let Some(bootable) = labels.get(&format!(
-                "com.github.mripard.ocibootstrap.partition.{part_name}.flags.bootable",
-            )) {
-                bool::from_str(bootable).map_err(|_err| {
-                    OciBootstrapError::Custom(format!("Partition {idx}: Invalid bool value"))
-                })?
-            } else {
-                false
-            };
+            let mut part_attributes = GptAttributes::empty();
+            for (flag, attribute) in [
+                ("bootable", GptAttributes::BOOTABLE),
+                ("required", GptAttributes::PLATFORM_REQUIRED),
+                ("read-only", GptAttributes::READ_ONLY),
+                ("hidden", GptAttributes::HIDDEN),
+                ("no-auto", GptAttributes::NO_AUTO),
+            ] {
+                part_attributes.set(attribute, parse_flag(labels, part_name, idx, flag)?);
+            }
 
-            let part_required = if let Some(bootable) = labels.get(&format!(
-                "com.github.mripard.ocibootstrap.partition.{part_name}.flags.required",
-            )) {
-                bool::from_str(bootable).map_err(|_err| {
-                    OciBootstrapError::Custom(format!("Partition {idx}: Invalid bool value"))
-                })?
-            } else {
-                false
-            };
+            part_attributes.set(
+                GptAttributes::GROW,
+                parse_grow_flag(labels, part_name, idx, part_size_bytes, part_size_percent)?,
+            );
 
             partitions.push(GptPartition {
                 uuid: part_uuid,
                 name: Some(part_name.clone()),
                 mnt: part_mnt,
+                mount_options: part_mount_options,
+                files: part_files,
                 offset_lba: part_offset_lba,
                 size_bytes: part_size_bytes,
+                size_percent: part_size_percent,
                 fs: part_fs,
-                bootable: part_bootable,
-                platform_required: part_required,
+                attributes: part_attributes,
             });
         }
 
-        Ok(GptPartitionTable { partitions })
+        check_size_percent_total(partitions.iter().map(|p| p.size_percent))?;
+        check_mount_points(
+            partitions
+                .iter()
+                .flat_map(|p| partition_mount_points(p.mnt.as_ref(), &p.fs)),
+        )?;
+
+        let (reserved_start_bytes, firmware) = parse_firmware(labels)?;
+
+        Ok(GptPartitionTable {
+            partitions,
+            reserved_start_bytes,
+            firmware,
+        })
     }
 
     fn mbr_from_config(
@@ -354,6 +1637,23 @@ impl PartitionTable {
                 debug!("Partition Mount Point {}", mnt.display());
             }
 
+            let part_mount_options = parse_mount_options(labels, part_name);
+            if !part_mount_options.is_empty() {
+                debug!(
+                    "Partition {idx}: Mount Options {}",
+                    part_mount_options.join(",")
+                );
+            }
+
+            let part_files = parse_files(labels, part_name, idx)?;
+            for file in &part_files {
+                debug!(
+                    "Partition {idx}: Copying {} to {}",
+                    file.source.display(),
+                    file.dest.display()
+                );
+            }
+
             let part_offset_lba = labels
                 .get(&format!(
                     "com.github.mripard.ocibootstrap.partition.{part_name}.offset_lba",
@@ -369,50 +1669,142 @@ impl PartitionTable {
                 debug!("Partition {idx}: Offset LBA {offset_lba}");
             }
 
-            let part_size_bytes = labels
-                .get(&format!(
-                    "com.github.mripard.ocibootstrap.partition.{part_name}.size_mb",
-                ))
-                .map(|size_str| {
-                    usize::from_str(size_str)
-                        .map(|size_mb| size_mb << 20)
-                        .map_err(|_err| {
-                            OciBootstrapError::Custom(format!(
-                                "Partition {idx}: Invalid integer value"
-                            ))
-                        })
-                })
-                .transpose()?;
-
-            if let Some(size_bytes) = part_size_bytes {
-                debug!("Partition {idx}: Size {size_bytes} bytes");
-            }
+            let (part_size_bytes, part_size_percent) = parse_size(labels, part_name, idx)?;
 
             let part_fs = Filesystem::from_labels(labels, part_name)?;
             debug!("Partition {idx}: Filesystem {part_fs}");
 
-            let part_bootable = if let Some(bootable) = labels.get(&format!(
-                "com.github.mripard.ocibootstrap.partition.{part_name}.flags.bootable",
-            )) {
-                bool::from_str(bootable).map_err(|_err| {
-                    OciBootstrapError::Custom(format!("Partition {idx}: Invalid bool value"))
-                })?
-            } else {
-                false
-            };
+            let part_bootable = parse_flag(labels, part_name, idx, "bootable")?;
+            let part_grow =
+                parse_grow_flag(labels, part_name, idx, part_size_bytes, part_size_percent)?;
 
             partitions.push(MbrPartition {
                 kind: part_type,
                 mnt: part_mnt,
+                mount_options: part_mount_options,
+                files: part_files,
                 offset_lba: part_offset_lba,
                 size_bytes: part_size_bytes,
+                size_percent: part_size_percent,
                 fs: part_fs,
                 bootable: part_bootable,
+                grow: part_grow,
             });
         }
 
-        Ok(MbrPartitionTable { partitions })
+        check_size_percent_total(partitions.iter().map(|p| p.size_percent))?;
+        check_mount_points(
+            partitions
+                .iter()
+                .flat_map(|p| partition_mount_points(p.mnt.as_ref(), &p.fs)),
+        )?;
+
+        let (heads_per_cylinder, sectors_per_track) = shared_fat_geometry(&mut partitions)?;
+        let (reserved_start_bytes, firmware) = parse_firmware(labels)?;
+
+        Ok(MbrPartitionTable {
+            partitions,
+            heads_per_cylinder,
+            sectors_per_track,
+            reserved_start_bytes,
+            firmware,
+        })
+    }
+}
+
+/// Prefix of all the labels describing the partition layout
+const LABEL_PREFIX: &str = "com.github.mripard.ocibootstrap";
+
+/// Label holding the whole partition layout as a single JSON document
+const LAYOUT_LABEL: &str = "com.github.mripard.ocibootstrap.table.layout";
+
+fn flatten_layout_value(
+    labels: &mut HashMap<String, String>,
+    key: String,
+    value: &Value,
+) -> Result<(), OciBootstrapError> {
+    match value {
+        Value::Null => {}
+        Value::Object(map) => {
+            for (name, value) in map {
+                flatten_layout_value(labels, format!("{key}.{name}"), value)?;
+            }
+        }
+        Value::String(s) => {
+            labels.insert(key, s.clone());
+        }
+        Value::Bool(_) | Value::Number(_) | Value::Array(_) => {
+            labels.insert(key, value.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a JSON partition layout into the labels describing the same layout
+///
+/// The layout is an object with the table settings, and a list of partitions that have a name and
+/// their own settings. Nested objects map to the dotted label names, and every value is what the
+/// equivalent label would hold, for example:
+///
+/// ```json
+/// {
+///     "type": "gpt",
+///     "partitions": [
+///         { "name": "root", "partition_uuid": "linux-root", "fs": "ext4", "ext4": { "label": "root" } }
+///     ]
+/// }
+/// ```
+///
+/// The settings aren't typed here, they are only checked once converted, by the labels parsing.
+/// There's thus no JSON schema for the layout: it would have to duplicate every label.
+fn labels_from_layout(layout: &str) -> Result<HashMap<String, String>, OciBootstrapError> {
+    #[derive(Deserialize)]
+    struct LayoutPartition {
+        name: String,
+
+        #[serde(flatten)]
+        settings: Map<String, Value>,
+    }
+
+    #[derive(Deserialize)]
+    struct Layout {
+        partitions: Vec<LayoutPartition>,
+
+        #[serde(flatten)]
+        settings: Map<String, Value>,
+    }
+
+    let layout: Layout = serde_json::from_str(layout)
+        .map_err(|e| OciBootstrapError::Custom(format!("Invalid partition layout: {e}")))?;
+
+    let mut labels = HashMap::new();
+    flatten_layout_value(
+        &mut labels,
+        format!("{LABEL_PREFIX}.table"),
+        &Value::Object(layout.settings),
+    )?;
+
+    let part_names = layout
+        .partitions
+        .iter()
+        .map(|part| part.name.as_str())
+        .collect::<Vec<_>>();
+
+    labels.insert(
+        format!("{LABEL_PREFIX}.table.partitions"),
+        serde_json::to_string(&part_names)?,
+    );
+
+    for part in layout.partitions {
+        flatten_layout_value(
+            &mut labels,
+            format!("{LABEL_PREFIX}.partition.{}", part.name),
+            &Value::Object(part.settings),
+        )?;
     }
+
+    Ok(labels)
 }
 
 impl TryFrom<&ImageConfiguration> for PartitionTable {
@@ -423,22 +1815,1078 @@ impl TryFrom<&ImageConfiguration> for PartitionTable {
             "Container Configuration has no labels.".to_owned(),
         ))?;
 
-        let layout_type = labels
-            .get("com.github.mripard.ocibootstrap.table.type")
-            .ok_or(OciBootstrapError::Custom(
-                "Missing partition layout".to_owned(),
-            ))?;
+        let arch = Architecture::from_oci_str(&config.architecture().to_string())?;
 
-        debug!("Found {layout_type} partition layout type.");
+        if let Some(layout) = labels.get(LAYOUT_LABEL) {
+            debug!("Found a JSON partition layout, ignoring the other layout labels.");
 
-        Ok(match layout_type.as_str() {
-            "gpt" => Self::Gpt(PartitionTable::gpt_from_config(labels)?),
-            "mbr" => Self::Mbr(PartitionTable::mbr_from_config(labels)?),
-            _ => {
-                return Err(OciBootstrapError::Custom(format!(
-                    "Invalid Layout Type: {layout_type}"
-                )))
-            }
+            return Self::from_layout(layout, arch);
+        }
+
+        Self::from_labels(labels, arch)
+    }
+}
+
+#[cfg(test)]
+mod layout_tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use oci_spec::image::ImageConfiguration;
+    use serde_json::Value;
+    use test_log::test;
+    use types::Architecture;
+    use uuid::Uuid;
+
+    use crate::layout::{
+        resolve_size_bytes, Filesystem, Firmware, GptAttributes, LayoutError, PartitionFile,
+        PartitionTable, PartitionType, Size,
+    };
+
+    fn labels(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| {
+                (
+                    format!("com.github.mripard.ocibootstrap.{k}"),
+                    (*v).to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    fn gpt_labels(
+        boot_size: (&str, &str),
+        root_size: Option<(&str, &str)>,
+    ) -> HashMap<String, String> {
+        let boot_size_key = format!("partition.boot.{}", boot_size.0);
+        let mut entries = vec![
+            ("table.partitions", r#"["boot", "root"]"#),
+            (
+                "partition.boot.partition_uuid",
+                "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+            ),
+            ("partition.boot.fs", "fat"),
+            (boot_size_key.as_str(), boot_size.1),
+            (
+                "partition.root.partition_uuid",
+                "b921b045-1df0-41c3-af44-4c6f280d3fae",
+            ),
+            ("partition.root.fs", "ext4"),
+        ];
+
+        let root_size_key = root_size.map(|(k, _)| format!("partition.root.{k}"));
+        if let (Some(key), Some((_, val))) = (&root_size_key, root_size) {
+            entries.push((key.as_str(), val));
+        }
+
+        labels(&entries)
+    }
+
+    #[test]
+    fn test_gpt_size_percent() {
+        let table = PartitionTable::gpt_from_config(
+            &gpt_labels(("size_percent", "25"), None),
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        let parts = table.partitions();
+        assert_eq!(parts[0].size_percent, Some(25));
+        assert_eq!(parts[0].size_bytes, None);
+        assert_eq!(parts[1].size_percent, None);
+    }
+
+    #[test]
+    fn test_gpt_size_percent_invalid() {
+        PartitionTable::gpt_from_config(
+            &gpt_labels(("size_percent", "101"), None),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+        PartitionTable::gpt_from_config(
+            &gpt_labels(("size_percent", "0"), None),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_gpt_size_percent_total_too_large() {
+        PartitionTable::gpt_from_config(
+            &gpt_labels(("size_percent", "60"), Some(("size_percent", "50"))),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_gpt_size_percent_and_size() {
+        let mut labels = gpt_labels(("size_percent", "50"), None);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.boot.size_mb"),
+            String::from("64"),
+        );
+
+        PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+    }
+
+    #[test]
+    fn test_size_deserialize() {
+        let size = |v: Value| serde_json::from_value::<Size>(v);
+
+        assert_eq!(size(Value::from("512MiB")).unwrap(), Size::Bytes(512 << 20));
+        assert_eq!(
+            size(Value::from("2GB")).unwrap(),
+            Size::Bytes(2_000_000_000)
+        );
+        assert_eq!(size(Value::from("100%")).unwrap(), Size::Percent(100));
+        assert_eq!(size(Value::from(1 << 20)).unwrap(), Size::Bytes(1 << 20));
+        assert_eq!(size(Value::from("1048576")).unwrap(), Size::Bytes(1 << 20));
+
+        size(Value::from("512M")).unwrap_err();
+        size(Value::from("512mib")).unwrap_err();
+        size(Value::from("2XB")).unwrap_err();
+        size(Value::from("0%")).unwrap_err();
+        size(Value::from(-1)).unwrap_err();
+    }
+
+    #[test]
+    fn test_gpt_size() {
+        let table = PartitionTable::gpt_from_config(
+            &gpt_labels(("size", "512MiB"), Some(("size", "50%"))),
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        let parts = table.partitions();
+        assert_eq!(parts[0].size_bytes, Some(512 << 20));
+        assert_eq!(parts[0].size_percent, None);
+        assert_eq!(parts[1].size_bytes, None);
+        assert_eq!(parts[1].size_percent, Some(50));
+
+        let mut labels = gpt_labels(("size", "512MiB"), None);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.boot.size_mb"),
+            String::from("64"),
+        );
+
+        PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate() {
+        let table = PartitionTable::Gpt(
+            PartitionTable::gpt_from_config(
+                &gpt_labels(("size_mb", "64"), None),
+                Architecture::Arm64,
+            )
+            .unwrap(),
+        );
+
+        table.validate(128 << 20).unwrap();
+        assert_eq!(
+            table.validate(16 << 10).unwrap_err(),
+            [LayoutError::DeviceTooSmall(16 << 10)]
+        );
+    }
+
+    #[test]
+    fn test_validate_extreme_sizes() {
+        let max = usize::MAX.to_string();
+
+        let validate = |labels: &HashMap<String, String>| {
+            PartitionTable::Gpt(
+                PartitionTable::gpt_from_config(labels, Architecture::Arm64).unwrap(),
+            )
+            .validate(1 << 30)
+            .unwrap_err()
+        };
+
+        let mut labels = gpt_labels(("size_mb", "1"), None);
+        labels.extend(self::labels(&[("partition.boot.offset_lba", &max)]));
+        let errors = validate(&labels);
+        assert!(
+            errors
+                .iter()
+                .any(|e| matches!(e, LayoutError::OutOfBounds { partition: 0, .. })),
+            "{errors:?}"
+        );
+
+        // The partitions after the one filling the device are placed from its end
+        let mut labels = gpt_labels(("offset_lba", "2048"), Some(("size_mb", "1")));
+        labels.extend(self::labels(&[("partition.root.offset_lba", &max)]));
+        let errors = validate(&labels);
+        assert!(
+            errors
+                .iter()
+                .any(|e| matches!(e, LayoutError::OutOfBounds { partition: 1, .. })),
+            "{errors:?}"
+        );
+
+        let errors = validate(&gpt_labels(("size", &max), Some(("size", &max))));
+        assert!(
+            errors.iter().any(|e| matches!(
+                e,
+                LayoutError::TotalSizeTooLarge {
+                    total: usize::MAX,
+                    ..
+                }
+            )),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let mut labels = gpt_labels(("size_mb", "64"), Some(("size_mb", "64")));
+        labels.extend(self::labels(&[
+            ("partition.boot.offset_lba", "2048"),
+            ("partition.root.offset_lba", "100000"),
+            ("partition.root.mount_point", "/"),
+            ("partition.root.fs", "swap"),
+        ]));
+
+        let table = PartitionTable::Gpt(
+            PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap(),
+        );
+        let errors = table.validate(96 << 20).unwrap_err();
+
+        assert!(errors.contains(&LayoutError::Overlap(0, 1)), "{errors:?}");
+        assert!(errors.contains(&LayoutError::NotMountable(1)), "{errors:?}");
+        assert!(
+            errors
+                .iter()
+                .any(|e| matches!(e, LayoutError::TotalSizeTooLarge { .. })),
+            "{errors:?}"
+        );
+        assert!(
+            errors
+                .iter()
+                .any(|e| matches!(e, LayoutError::OutOfBounds { partition: 1, .. })),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_several_fill_partitions() {
+        let table = PartitionTable::Gpt(
+            PartitionTable::gpt_from_config(
+                &labels(&[
+                    ("table.partitions", r#"["boot", "root"]"#),
+                    ("partition.boot.partition_uuid", "esp"),
+                    ("partition.boot.fs", "fat"),
+                    ("partition.root.partition_uuid", "linux-root"),
+                    ("partition.root.fs", "ext4"),
+                ]),
+                Architecture::Arm64,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(
+            table.validate(128 << 20).unwrap_err(),
+            [LayoutError::SeveralFillPartitions(vec![0, 1])]
+        );
+    }
+
+    #[test]
+    fn test_resolve_size_percent() {
+        assert_eq!(
+            resolve_size_bytes([(None, Some(25)), (None, None)], 1024 * 512, 512).unwrap(),
+            vec![Some(256 * 512), None]
+        );
+    }
+
+    #[test]
+    fn test_resolve_size_percent_rounded_down() {
+        assert_eq!(
+            resolve_size_bytes([(None, Some(33)), (None, Some(67))], 1001 * 512, 512).unwrap(),
+            vec![Some(330 * 512), Some(670 * 512)]
+        );
+    }
+
+    #[test]
+    fn test_resolve_size_percent_and_fixed_size() {
+        assert_eq!(
+            resolve_size_bytes([(Some(512 * 512), None), (None, Some(50))], 1024 * 512, 512)
+                .unwrap(),
+            vec![Some(512 * 512), Some(512 * 512)]
+        );
+    }
+
+    #[test]
+    fn test_resolve_size_percent_exceeds_capacity() {
+        resolve_size_bytes([(Some(600 * 512), None), (None, Some(50))], 1024 * 512, 512)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_resolve_size_percent_empty() {
+        resolve_size_bytes([(None, Some(1)), (None, None)], 64 * 512, 1 << 20).unwrap_err();
+    }
+
+    #[test]
+    fn test_btrfs_subvolumes() {
+        let table = PartitionTable::gpt_from_config(
+            &labels(&[
+                ("table.partitions", r#"["root"]"#),
+                (
+                    "partition.root.partition_uuid",
+                    "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                ),
+                ("partition.root.fs", "btrfs"),
+                ("partition.root.btrfs.label", "rootfs"),
+                (
+                    "partition.root.btrfs.uuid",
+                    "ad3d3a0a-0e47-4e0c-9d10-6f4a0a3d0d75",
+                ),
+                (
+                    "partition.root.btrfs.subvolumes",
+                    r#"["@", "@home", "@snapshots"]"#,
+                ),
+                ("partition.root.btrfs.subvolume.@.mount_point", "/"),
+                ("partition.root.btrfs.subvolume.@home.mount_point", "/home"),
+            ]),
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        let Filesystem::Btrfs(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't a btrfs partition");
+        };
+
+        assert_eq!(params.label.as_deref(), Some("rootfs"));
+        assert_eq!(
+            params.uuid.unwrap().to_string(),
+            "ad3d3a0a-0e47-4e0c-9d10-6f4a0a3d0d75"
+        );
+
+        let subvolumes = params
+            .subvolumes
+            .iter()
+            .map(|s| (s.name.as_str(), s.mnt.as_deref().and_then(|m| m.to_str())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subvolumes,
+            vec![
+                ("@", Some("/")),
+                ("@home", Some("/home")),
+                ("@snapshots", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_btrfs_invalid_subvolume() {
+        for name in ["/srv", "..", ".", "", "home/user", "home,subvolid=5"] {
+            PartitionTable::gpt_from_config(
+                &labels(&[
+                    ("table.partitions", r#"["root"]"#),
+                    (
+                        "partition.root.partition_uuid",
+                        "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                    ),
+                    ("partition.root.fs", "btrfs"),
+                    (
+                        "partition.root.btrfs.subvolumes",
+                        &serde_json::to_string(&[name]).unwrap(),
+                    ),
+                ]),
+                Architecture::Arm64,
+            )
+            .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_btrfs_no_subvolume() {
+        let table = PartitionTable::gpt_from_config(
+            &labels(&[
+                ("table.partitions", r#"["root"]"#),
+                (
+                    "partition.root.partition_uuid",
+                    "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                ),
+                ("partition.root.fs", "btrfs"),
+            ]),
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        let Filesystem::Btrfs(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't a btrfs partition");
+        };
+
+        assert!(params.label.is_none(), "Unexpected label");
+        assert!(params.uuid.is_none(), "Unexpected UUID");
+        assert!(params.subvolumes.is_empty(), "Unexpected subvolumes");
+    }
+
+    fn ext4_labels(extra: &[(&str, &str)]) -> HashMap<String, String> {
+        let mut entries = vec![
+            ("table.partitions", r#"["root"]"#),
+            (
+                "partition.root.partition_uuid",
+                "b921b045-1df0-41c3-af44-4c6f280d3fae",
+            ),
+            ("partition.root.fs", "ext4"),
+        ];
+        entries.extend_from_slice(extra);
+
+        labels(&entries)
+    }
+
+    #[test]
+    fn test_ext4_parameters() {
+        let table = PartitionTable::gpt_from_config(
+            &ext4_labels(&[
+                ("partition.root.ext4.label", "rootfs"),
+                ("partition.root.ext4.block_size", "4096"),
+                ("partition.root.ext4.reserved_percent", "1"),
+            ]),
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        let Filesystem::Ext4(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't an ext4 partition");
+        };
+
+        assert!(params.uuid.is_none(), "Unexpected UUID");
+        assert_eq!(params.label.as_deref(), Some("rootfs"));
+        assert_eq!(params.block_size, Some(4096));
+        assert_eq!(params.reserved_percent, Some(1));
+    }
+
+    #[test]
+    fn test_ext4_no_parameters() {
+        let table =
+            PartitionTable::gpt_from_config(&ext4_labels(&[]), Architecture::Arm64).unwrap();
+
+        let Filesystem::Ext4(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't an ext4 partition");
+        };
+
+        assert!(params.label.is_none(), "Unexpected label");
+        assert!(params.block_size.is_none(), "Unexpected block size");
+        assert!(
+            params.reserved_percent.is_none(),
+            "Unexpected reserved blocks"
+        );
+    }
+
+    #[test]
+    fn test_ext4_invalid_block_size() {
+        PartitionTable::gpt_from_config(
+            &ext4_labels(&[("partition.root.ext4.block_size", "8192")]),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+
+        PartitionTable::gpt_from_config(
+            &ext4_labels(&[("partition.root.ext4.block_size", "4k")]),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_ext4_label_too_long() {
+        PartitionTable::gpt_from_config(
+            &ext4_labels(&[("partition.root.ext4.label", "0123456789abcdef")]),
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        PartitionTable::gpt_from_config(
+            &ext4_labels(&[("partition.root.ext4.label", "0123456789abcdefg")]),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+
+        // The limit is in bytes, not characters
+        PartitionTable::gpt_from_config(
+            &ext4_labels(&[("partition.root.ext4.label", "syst\u{e8}me-racines1")]),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_ext4_invalid_reserved_percent() {
+        PartitionTable::gpt_from_config(
+            &ext4_labels(&[("partition.root.ext4.reserved_percent", "51")]),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_mount_options() {
+        let mut labels = gpt_labels(("size_mb", "64"), None);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.root.mount_options"),
+            String::from("ro, noatime,,"),
+        );
+
+        let table = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap();
+
+        let parts = table.partitions();
+        assert!(parts[0].mount_options.is_empty());
+        assert_eq!(parts[1].mount_options, vec!["ro", "noatime"]);
+    }
+
+    #[test]
+    fn test_partition_files() {
+        let mut labels = gpt_labels(("size_mb", "64"), None);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.boot.files"),
+            String::from(r#"[{"source": "firmware/config.txt", "dest": "/config.txt"}]"#),
+        );
+
+        let table = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap();
+
+        let parts = table.partitions();
+        assert_eq!(
+            parts[0].files,
+            vec![PartitionFile {
+                source: PathBuf::from("firmware/config.txt"),
+                dest: PathBuf::from("/config.txt"),
+            }]
+        );
+        assert!(parts[1].files.is_empty());
+    }
+
+    #[test]
+    fn test_partition_files_invalid() {
+        let mut labels = gpt_labels(("size_mb", "64"), None);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.boot.files"),
+            String::from(r#"[{"source": "firmware/config.txt"}]"#),
+        );
+
+        PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+    }
+
+    #[test]
+    fn test_firmware() {
+        let mut labels = gpt_labels(("size_mb", "64"), None);
+        labels.extend([
+            (
+                String::from("com.github.mripard.ocibootstrap.table.reserved_start_bytes"),
+                String::from("0x100000"),
+            ),
+            (
+                String::from("com.github.mripard.ocibootstrap.table.firmware"),
+                String::from(r#"[{"source": "firmware/idbloader.img", "offset_bytes": 32768}]"#),
+            ),
+        ]);
+
+        let table = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap();
+
+        assert_eq!(table.reserved_start_bytes(), Some(1 << 20));
+        assert_eq!(
+            table.firmware,
+            vec![Firmware {
+                source: PathBuf::from("firmware/idbloader.img"),
+                offset_bytes: 0x8000,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_firmware_invalid() {
+        let mut labels = gpt_labels(("size_mb", "64"), None);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.table.firmware"),
+            String::from(r#"[{"source": "firmware/idbloader.img"}]"#),
+        );
+
+        PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+    }
+
+    fn mbr_labels(boot_geometry: &[(&str, &str)]) -> HashMap<String, String> {
+        let keys = boot_geometry
+            .iter()
+            .map(|(k, v)| (format!("partition.boot.fat.{k}"), *v))
+            .collect::<Vec<_>>();
+
+        let mut entries = vec![
+            ("table.partitions", r#"["boot", "root"]"#),
+            ("partition.boot.type", "0x0c"),
+            ("partition.boot.fs", "fat"),
+            ("partition.boot.size_mb", "64"),
+            ("partition.root.type", "0x83"),
+            ("partition.root.fs", "ext4"),
+        ];
+        entries.extend(keys.iter().map(|(k, v)| (k.as_str(), *v)));
+
+        labels(&entries)
+    }
+
+    #[test]
+    fn test_mount_point_duplicate() {
+        let mut labels = gpt_labels(("size_mb", "64"), None);
+        labels.extend([
+            (
+                String::from("com.github.mripard.ocibootstrap.partition.boot.mount_point"),
+                String::from("/boot"),
+            ),
+            (
+                String::from("com.github.mripard.ocibootstrap.partition.root.mount_point"),
+                String::from("/boot/"),
+            ),
+        ]);
+
+        let err = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+        assert!(err.to_string().contains("/boot"), "{err}");
+    }
+
+    #[test]
+    fn test_mount_point_relative() {
+        let mut labels = mbr_labels(&[]);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.boot.mount_point"),
+            String::from("boot"),
+        );
+
+        let err = PartitionTable::mbr_from_config(&labels).unwrap_err();
+        assert!(err.to_string().contains("absolute"), "{err}");
+    }
+
+    #[test]
+    fn test_mount_point_btrfs_subvolume_duplicate() {
+        PartitionTable::gpt_from_config(
+            &labels(&[
+                ("table.partitions", r#"["root"]"#),
+                (
+                    "partition.root.partition_uuid",
+                    "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                ),
+                ("partition.root.fs", "btrfs"),
+                ("partition.root.mount_point", "/"),
+                ("partition.root.btrfs.subvolumes", r#"["@"]"#),
+                ("partition.root.btrfs.subvolume.@.mount_point", "/"),
+            ]),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_mbr_offset() {
+        let mut labels = mbr_labels(&[]);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.boot.offset_lba"),
+            String::from("2048"),
+        );
+
+        let table = PartitionTable::mbr_from_config(&labels).unwrap();
+        assert_eq!(table.partitions()[0].offset_lba, Some(2048));
+        assert_eq!(table.partitions()[1].offset_lba, None);
+    }
+
+    #[test]
+    fn test_mbr_offset_invalid() {
+        let mut labels = mbr_labels(&[]);
+        labels.insert(
+            String::from("com.github.mripard.ocibootstrap.partition.boot.offset_lba"),
+            String::from("-1"),
+        );
+
+        PartitionTable::mbr_from_config(&labels).unwrap_err();
+    }
+
+    #[test]
+    fn test_mbr_default_geometry() {
+        let table = PartitionTable::mbr_from_config(&mbr_labels(&[])).unwrap();
+
+        let Filesystem::Fat32(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't a FAT partition");
+        };
+
+        assert_eq!(table.geometry(), (16, 63));
+        assert_eq!(params.heads, Some(16));
+        assert_eq!(params.sectors_per_track, Some(63));
+        assert!(params.label.is_none(), "Unexpected label");
+        assert!(params.fat_bits.is_none(), "Unexpected FAT size");
+    }
+
+    #[test]
+    fn test_mbr_fat_label_and_size() {
+        let table =
+            PartitionTable::mbr_from_config(&mbr_labels(&[("label", "EFI"), ("fat_bits", "16")]))
+                .unwrap();
+
+        let Filesystem::Fat32(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't a FAT partition");
+        };
+
+        assert_eq!(params.label.as_deref(), Some("EFI"));
+        assert_eq!(params.fat_bits, Some(16));
+    }
+
+    #[test]
+    fn test_mbr_fat_invalid_size() {
+        PartitionTable::mbr_from_config(&mbr_labels(&[("fat_bits", "24")])).unwrap_err();
+        PartitionTable::mbr_from_config(&mbr_labels(&[("fat_bits", "FAT16")])).unwrap_err();
+    }
+
+    #[test]
+    fn test_mbr_fat_label_too_long() {
+        PartitionTable::mbr_from_config(&mbr_labels(&[("label", "EFI SYSTEM PART")])).unwrap_err();
+    }
+
+    #[test]
+    fn test_mbr_fat_geometry() {
+        let table = PartitionTable::mbr_from_config(&mbr_labels(&[
+            ("heads", "255"),
+            ("sectors_per_track", "32"),
+        ]))
+        .unwrap();
+
+        let Filesystem::Fat32(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't a FAT partition");
+        };
+
+        assert_eq!(table.geometry(), (255, 32));
+        assert_eq!(params.heads, Some(255));
+        assert_eq!(params.sectors_per_track, Some(32));
+    }
+
+    #[test]
+    fn test_mbr_fat_geometry_invalid() {
+        PartitionTable::mbr_from_config(&mbr_labels(&[("heads", "256")])).unwrap_err();
+    }
+
+    #[test]
+    fn test_partition_type_names() {
+        for (name, guid) in [
+            ("esp", gpt::EFI_SYSTEM_PART_GUID),
+            ("xbootldr", gpt::EXTENDED_BOOTLOADER_PART_GUID),
+            ("swap", gpt::SWAP_PART_GUID),
+            ("linux-data", gpt::LINUX_DATA_PART_GUID),
+            ("linux-root-arm", gpt::ROOT_PART_GUID_ARM),
+            ("linux-root-arm64", gpt::ROOT_PART_GUID_ARM64),
+            ("linux-root-x86", gpt::ROOT_PART_GUID_X86),
+            ("linux-root-x86-64", gpt::ROOT_PART_GUID_X86_64),
+            ("linux-usr-arm", gpt::USR_PART_GUID_ARM),
+            ("linux-usr-arm64", gpt::USR_PART_GUID_ARM64),
+            ("linux-usr-x86", gpt::USR_PART_GUID_X86),
+            ("linux-usr-x86-64", gpt::USR_PART_GUID_X86_64),
+        ] {
+            let kind: PartitionType = serde_json::from_value(serde_json::json!(name)).unwrap();
+            assert_eq!(kind.guid(Architecture::Arm64), guid, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_partition_type_linux_root() {
+        let kind: PartitionType = serde_json::from_value(serde_json::json!("linux-root")).unwrap();
+
+        for (arch, guid) in [
+            (Architecture::Arm64, gpt::ROOT_PART_GUID_ARM64),
+            (Architecture::X86_64, gpt::ROOT_PART_GUID_X86_64),
+            (Architecture::Riscv64, gpt::ROOT_PART_GUID_RISCV64),
+        ] {
+            assert_eq!(kind.guid(arch), guid, "{arch}");
+        }
+    }
+
+    #[test]
+    fn test_partition_type_guid() {
+        let guid = "0fc63daf-8483-4772-8e79-3d69d8477de4";
+        let kind: PartitionType = serde_json::from_value(serde_json::json!(guid)).unwrap();
+
+        assert_eq!(kind, PartitionType::Guid(Uuid::parse_str(guid).unwrap()));
+    }
+
+    #[test]
+    fn test_partition_type_unknown() {
+        let err = serde_json::from_value::<PartitionType>(serde_json::json!("linux-root-m68k"))
+            .unwrap_err();
+        assert!(err.to_string().contains("linux-root-m68k"), "{err}");
+    }
+
+    #[test]
+    fn test_gpt_partition_type_name() {
+        let mut labels = gpt_labels(("size_mb", "16"), None);
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.boot.partition_uuid".to_owned(),
+            "esp".to_owned(),
+        );
+
+        let table = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap();
+        assert_eq!(table.partitions()[0].uuid, gpt::EFI_SYSTEM_PART_GUID);
+
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.boot.partition_uuid".to_owned(),
+            "efi".to_owned(),
+        );
+        PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+    }
+
+    #[test]
+    fn test_gpt_partition_flags() {
+        let mut labels = gpt_labels(("size_mb", "16"), None);
+        for flag in ["required", "read-only", "hidden", "no-auto"] {
+            labels.insert(
+                format!("com.github.mripard.ocibootstrap.partition.root.flags.{flag}"),
+                "true".to_owned(),
+            );
+        }
+
+        let gpt = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap();
+
+        assert!(gpt.partitions()[0].attributes.is_empty());
+        assert_eq!(
+            gpt.partitions()[1].attributes,
+            GptAttributes::PLATFORM_REQUIRED
+                | GptAttributes::READ_ONLY
+                | GptAttributes::HIDDEN
+                | GptAttributes::NO_AUTO
+        );
+
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.root.flags.no-auto".to_owned(),
+            "maybe".to_owned(),
+        );
+        PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+    }
+
+    #[test]
+    fn test_squashfs() {
+        let mut labels = gpt_labels(("size_mb", "16"), None);
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.root.fs".to_owned(),
+            "squashfs".to_owned(),
+        );
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.root.squashfs.source".to_owned(),
+            "/usr/share/rootfs.squashfs".to_owned(),
+        );
+
+        let gpt = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap();
+
+        let boot = &gpt.partitions()[0];
+        assert!(!boot.fs.is_read_only());
+        assert_eq!(boot.fs.content(), None);
+
+        let root = &gpt.partitions()[1];
+        let Filesystem::Squashfs(params) = &root.fs else {
+            panic!("Partition isn't a squashfs partition");
+        };
+        assert_eq!(params.source, PathBuf::from("/usr/share/rootfs.squashfs"));
+        assert_eq!(root.fs.to_string(), "squashfs");
+        assert_eq!(root.fs.mount_type(), None);
+        assert!(root.fs.is_read_only());
+        assert_eq!(
+            root.fs.content(),
+            Some(PathBuf::from("/usr/share/rootfs.squashfs").as_path())
+        );
+    }
+
+    #[test]
+    fn test_squashfs_missing_source() {
+        let mut labels = gpt_labels(("size_mb", "16"), None);
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.root.fs".to_owned(),
+            "squashfs".to_owned(),
+        );
+
+        let err = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap_err();
+        assert!(err.to_string().contains("Squashfs Image Source"), "{err}");
+    }
+
+    #[test]
+    fn test_partition_grow() {
+        let mut labels = gpt_labels(("size_mb", "16"), None);
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.root.flags.grow".to_owned(),
+            "true".to_owned(),
+        );
+
+        let gpt = PartitionTable::gpt_from_config(&labels, Architecture::Arm64).unwrap();
+        assert!(!gpt.partitions()[0].attributes.contains(GptAttributes::GROW));
+        assert!(gpt.partitions()[1].attributes.contains(GptAttributes::GROW));
+
+        let mut labels = mbr_labels(&[]);
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.root.flags.grow".to_owned(),
+            "true".to_owned(),
+        );
+
+        let mbr = PartitionTable::mbr_from_config(&labels).unwrap();
+        assert!(!mbr.partitions()[0].grow);
+        assert!(mbr.partitions()[1].grow);
+
+        // Only the partition filling the rest of the device can grow
+        labels.insert(
+            "com.github.mripard.ocibootstrap.partition.boot.flags.grow".to_owned(),
+            "true".to_owned(),
+        );
+        let err = PartitionTable::mbr_from_config(&labels).unwrap_err();
+        assert!(err.to_string().contains("without a size"), "{err}");
+    }
+
+    fn configuration(labels: &Value) -> ImageConfiguration {
+        serde_json::from_value(serde_json::json!({
+            "architecture": "arm64",
+            "os": "linux",
+            "config": {
+                "Labels": labels,
+            },
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        }))
+        .unwrap()
+    }
+
+    fn layout() -> String {
+        serde_json::json!({
+            "type": "gpt",
+            "partitions": [
+                {
+                    "name": "boot",
+                    "partition_uuid": "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+                    "fs": "fat",
+                    "size_mb": 64,
+                    "mount_point": "/boot",
+                    "flags": { "bootable": true },
+                    "fat": { "label": "EFI" },
+                },
+                {
+                    "name": "root",
+                    "partition_uuid": "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                    "fs": "btrfs",
+                    "mount_options": "noatime",
+                    "btrfs": {
+                        "subvolumes": ["@", "@home"],
+                        "subvolume": {
+                            "@": { "mount_point": "/" },
+                            "@home": { "mount_point": "/home" },
+                        },
+                    },
+                },
+            ],
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_layout_json_example() {
+        // The example of the labels_from_layout documentation
+        let table = PartitionTable::from_layout(
+            r#"{
+                "type": "gpt",
+                "partitions": [
+                    { "name": "root", "partition_uuid": "linux-root", "fs": "ext4", "ext4": { "label": "root" } }
+                ]
+            }"#,
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        let PartitionTable::Gpt(table) = table else {
+            panic!("Partition Table isn't a GPT");
+        };
+
+        let Filesystem::Ext4(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't ext4");
+        };
+
+        assert_eq!(params.label.as_deref(), Some("root"));
+    }
+
+    #[test]
+    fn test_layout_json() {
+        let table = PartitionTable::try_from(&configuration(&serde_json::json!({
+            "com.github.mripard.ocibootstrap.table.layout": layout(),
+        })))
+        .unwrap();
+
+        let PartitionTable::Gpt(table) = table else {
+            panic!("Partition Table isn't a GPT");
+        };
+
+        let parts = table.partitions();
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].size_bytes, Some(64 << 20));
+        assert_eq!(parts[0].mnt, Some(PathBuf::from("/boot")));
+        assert!(parts[0].attributes.contains(GptAttributes::BOOTABLE));
+        let Filesystem::Fat32(params) = &parts[0].fs else {
+            panic!("Partition isn't a FAT partition");
+        };
+        assert_eq!(params.label.as_deref(), Some("EFI"));
+
+        assert_eq!(parts[1].mount_options, vec![String::from("noatime")]);
+        let Filesystem::Btrfs(params) = &parts[1].fs else {
+            panic!("Partition isn't a btrfs partition");
+        };
+        assert_eq!(
+            params
+                .subvolumes
+                .iter()
+                .map(|s| (s.name.as_str(), s.mnt.as_deref().and_then(|m| m.to_str())))
+                .collect::<Vec<_>>(),
+            vec![("@", Some("/")), ("@home", Some("/home"))]
+        );
+    }
+
+    #[test]
+    fn test_layout_json_squashfs() {
+        let layout = serde_json::json!({
+            "type": "gpt",
+            "partitions": [
+                {
+                    "name": "root",
+                    "partition_uuid": "linux-root",
+                    "fs": "squashfs",
+                    "size_mb": 512,
+                    "squashfs": { "source": "/usr/share/rootfs.squashfs" },
+                },
+            ],
         })
+        .to_string();
+
+        let table = PartitionTable::try_from(&configuration(&serde_json::json!({
+            "com.github.mripard.ocibootstrap.table.layout": layout,
+        })))
+        .unwrap();
+
+        let PartitionTable::Gpt(table) = table else {
+            panic!("Partition Table isn't a GPT");
+        };
+
+        let Filesystem::Squashfs(params) = &table.partitions()[0].fs else {
+            panic!("Partition isn't a squashfs partition");
+        };
+        assert_eq!(params.source, PathBuf::from("/usr/share/rootfs.squashfs"));
+    }
+
+    #[test]
+    fn test_layout_json_overrides_labels() {
+        let table = PartitionTable::try_from(&configuration(&serde_json::json!({
+            "com.github.mripard.ocibootstrap.table.type": "mbr",
+            "com.github.mripard.ocibootstrap.table.partitions": "[\"root\"]",
+            "com.github.mripard.ocibootstrap.table.layout": layout(),
+        })))
+        .unwrap();
+
+        assert!(matches!(table, PartitionTable::Gpt(_)));
+    }
+
+    #[test]
+    fn test_layout_json_invalid() {
+        let err = PartitionTable::try_from(&configuration(&serde_json::json!({
+            "com.github.mripard.ocibootstrap.table.layout": r#"{"type": "gpt"}"#,
+        })))
+        .unwrap_err();
+
+        assert!(err.to_string().contains("partitions"), "{err}");
     }
 }
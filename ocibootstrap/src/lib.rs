@@ -0,0 +1,5621 @@
+#![allow(clippy::multiple_crate_versions)]
+#![doc = include_str!("../../README.md")]
+
+extern crate alloc;
+
+use core::{iter::zip, time::Duration};
+use std::{
+    collections::HashSet,
+    fs::{self, File, FileTimes, Permissions},
+    io::{self, Read as _, Seek as _, Write as _},
+    os::{
+        fd::{AsFd as _, AsRawFd as _},
+        unix::fs::{
+            self as unix_fs, FileTypeExt as _, MetadataExt as _, OpenOptionsExt as _,
+            PermissionsExt as _,
+        },
+    },
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Instant, SystemTime},
+};
+
+// Only used by the command line interface
+use anyhow as _;
+use env_logger as _;
+use flate2::write::GzEncoder;
+use gpt::{
+    GuidPartitionBuilder, GuidPartitionTable, GuidPartitionTableBuilder, PartitionBuilder,
+    PartitionLayout, PartitionTableWriter,
+};
+use layout::{
+    resolve_size_bytes, BtrfsParameters, BtrfsSubvolume, ExtParameters, FatParameters, Filesystem,
+    Firmware, GptAttributes, PartitionFile,
+};
+use local::{LocalManifest, LocalRegistry};
+use log::{debug, error, info, log_enabled, trace, Level};
+use loopdev::LoopControl;
+use mbr::{
+    MasterBootRecordPartitionBuilder, MasterBootRecordPartitionTable,
+    MasterBootRecordPartitionTableBuilder,
+};
+use nix::fcntl::{posix_fallocate, OFlag};
+use serde::Deserialize;
+use sys_mount::{FilesystemType, Mount, Unmount as _, UnmountFlags};
+use tar::{Archive, EntryType};
+use tempfile::TempDir;
+use types::{Architecture, Digest, IdMapping, OciBootstrapError, OperatingSystem, Platform};
+
+mod command;
+mod config;
+mod container;
+mod export;
+#[cfg(feature = "native-fat")]
+mod fat;
+mod hook;
+mod layout;
+mod local;
+mod preflight;
+mod report;
+mod reproducible;
+mod runtime;
+mod selinux;
+mod store;
+mod verify;
+
+use crate::{
+    command::run_command,
+    export::squash_layers,
+    hook::run_post_extract,
+    preflight::{check_device_requirements, check_dir_requirements, is_in_path, required_tools},
+    report::{ImageSidecar, PartitionReport, SidecarPartition},
+    reproducible::{FilesystemIds, Reproducible, SOURCE_DATE_EPOCH},
+    runtime::RuntimeConfig,
+    selinux::relabel,
+    store::ContentStore,
+    verify::ExpectedTree,
+};
+pub use crate::{
+    container::ContainerSpec,
+    layout::{GptPartitionTable, LayoutError, MbrPartitionTable, PartitionTable},
+    report::{OutputFormat, Report},
+};
+
+pub(crate) const LBA_SIZE: usize = 512;
+
+/// Mount option asking systemd to grow a filesystem to the size of its partition
+const SYSTEMD_GROWFS_OPTION: &str = "x-systemd.growfs";
+
+/// Environment variable mkfs.ext4 reads the current time from, if set
+const E2FSPROGS_FAKE_TIME: &str = "E2FSPROGS_FAKE_TIME";
+
+const LOOP_DEVICE_ATTACH_ATTEMPTS: usize = 5;
+const LOOP_DEVICE_ATTACH_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+const PARTITION_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+const PARTITION_SCAN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+struct LoopDevice {
+    loopdev: loopdev::LoopDevice,
+    _file: File,
+    detach: bool,
+}
+
+impl LoopDevice {
+    pub(crate) fn create(ctrl: &LoopControl, file: File) -> Result<Self, io::Error> {
+        let mut attempt = 1;
+
+        // Another process can grab the free loop device before we attach our file to it. If
+        // that happens, we just look for another one.
+        let loop_device = loop {
+            let loop_device = ctrl.next_free()?;
+
+            if log_enabled!(Level::Debug) {
+                debug!(
+                    "Using loop device {}",
+                    loop_device
+                        .path()
+                        .ok_or(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            "Loop Device File Not Found"
+                        ))?
+                        .display()
+                );
+            }
+
+            match loop_device.with().part_scan(true).attach_fd(file.as_fd()) {
+                Ok(()) => break loop_device,
+                Err(e)
+                    if attempt < LOOP_DEVICE_ATTACH_ATTEMPTS
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::ResourceBusy | io::ErrorKind::AlreadyExists
+                        ) =>
+                {
+                    debug!("Loop device already in use ({e}), retrying.");
+
+                    attempt += 1;
+                    thread::sleep(LOOP_DEVICE_ATTACH_RETRY_DELAY);
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        debug!("Attached the loop device to our file");
+
+        Ok(Self {
+            loopdev: loop_device,
+            _file: file,
+            detach: true,
+        })
+    }
+
+    pub(crate) fn path(&self) -> PathBuf {
+        self.loopdev
+            .path()
+            .expect("Couldn't retrieve the loop device path")
+    }
+}
+
+impl Drop for LoopDevice {
+    fn drop(&mut self) {
+        if !self.detach {
+            debug!("Keeping our loop device attached");
+            return;
+        }
+
+        debug!("Destroying our loop device");
+
+        let res = self.loopdev.detach();
+        if let Err(e) = res {
+            error!("Couldn't detach the Loop Device: {}", e);
+        }
+
+        debug!("Loop device detached");
+    }
+}
+
+#[derive(Debug)]
+struct DevicePartition {
+    fs: Filesystem,
+    dev: PathBuf,
+    host_mnt: Option<Mount>,
+}
+
+impl DevicePartition {
+    fn new(
+        dev: &Path,
+        fs: Filesystem,
+        mnt: Option<&Path>,
+        data: Option<&str>,
+    ) -> Result<Self, io::Error> {
+        let mount = if let Some(mnt) = mnt {
+            debug!("Mounting {} on {}", dev.display(), mnt.display());
+
+            fs::create_dir_all(mnt)?;
+
+            let fstype = fs.mount_type().ok_or(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Partition {} can't be mounted", dev.display()),
+            ))?;
+
+            let mut builder = Mount::builder().fstype(FilesystemType::Manual(fstype));
+            if let Some(data) = data {
+                debug!("Using mount options {data}");
+                builder = builder.data(data);
+            }
+
+            let mount = builder.mount(dev, mnt)?;
+
+            trace!("Mount Successful");
+            Some(mount)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            dev: dev.to_path_buf(),
+            fs,
+            host_mnt: mount,
+        })
+    }
+}
+
+impl Drop for DevicePartition {
+    fn drop(&mut self) {
+        if let Some(mnt) = &self.host_mnt {
+            debug!(
+                "Unmounting {} from {}",
+                self.dev.display(),
+                mnt.target_path().display()
+            );
+
+            let res = mnt.unmount(UnmountFlags::DETACH);
+            if let Err(e) = res {
+                error!("Couldn't unmount {}: {e}", self.dev.display());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Device {
+    parts: Vec<DevicePartition>,
+
+    dir: TempDir,
+    path: PathBuf,
+
+    /// Loop device the partitions are on, if the output isn't a block device already
+    loopdev: Option<LoopDevice>,
+}
+
+impl Device {
+    /// Leaves the loop device attached and its partitions mounted once dropped, and returns the
+    /// path of the device and of the directory they're mounted on
+    fn keep(mut self) -> (PathBuf, PathBuf) {
+        for part in &mut self.parts {
+            // The mounts are only undone by DevicePartition::drop
+            part.host_mnt = None;
+        }
+
+        self.dir.disable_cleanup(true);
+        if let Some(loopdev) = &mut self.loopdev {
+            loopdev.detach = false;
+        }
+
+        (self.path.clone(), self.dir.path().to_path_buf())
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        while let Some(item) = self.parts.pop() {
+            drop(item);
+        }
+    }
+}
+
+/// A partition of a block device, as reported by lsblk
+#[derive(Debug, Deserialize)]
+struct LsblkPartition {
+    path: PathBuf,
+    partn: Option<u32>,
+    partlabel: Option<String>,
+    partuuid: Option<String>,
+}
+
+/// Parses the JSON output of lsblk for a device, and returns its partitions, sorted by partition
+/// number
+fn parse_lsblk_partitions(
+    file: &Path,
+    output: &[u8],
+) -> Result<Vec<LsblkPartition>, OciBootstrapError> {
+    #[derive(Debug, Deserialize)]
+    struct LsblkDevice {
+        #[serde(rename = "children")]
+        parts: Option<Vec<LsblkPartition>>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct LsblkOutput {
+        #[serde(rename = "blockdevices")]
+        devices: Vec<LsblkDevice>,
+    }
+
+    let res: LsblkOutput = serde_json::from_slice(output)?;
+
+    let device = res
+        .devices
+        .into_iter()
+        .next()
+        .ok_or(OciBootstrapError::Custom(format!(
+            "lsblk didn't report any block device for {}",
+            file.display()
+        )))?;
+
+    let parts = device.parts.ok_or(OciBootstrapError::Custom(format!(
+        "lsblk didn't report any partition for {}",
+        file.display()
+    )))?;
+
+    // lsblk sorts the partitions by name, so p10 might come before p2
+    let mut parts = parts
+        .into_iter()
+        .map(|p| {
+            let number =
+                p.partn
+                    .or_else(|| partition_number(&p.path))
+                    .ok_or(OciBootstrapError::Custom(format!(
+                        "Couldn't find the partition number of {}",
+                        p.path.display()
+                    )))?;
+
+            Ok((number, p))
+        })
+        .collect::<Result<Vec<_>, OciBootstrapError>>()?;
+
+    parts.sort_unstable_by_key(|(number, _)| *number);
+
+    Ok(parts.into_iter().map(|(_, part)| part).collect())
+}
+
+/// Parses the JSON output of lsblk for a device, and returns the paths of its partitions, sorted
+/// by partition number
+fn parse_lsblk_parts(file: &Path, output: &[u8]) -> Result<Vec<PathBuf>, OciBootstrapError> {
+    Ok(parse_lsblk_partitions(file, output)?
+        .into_iter()
+        .map(|p| p.path)
+        .collect())
+}
+
+/// Finds the device file of each partition of the layout, through its PARTUUID or, if the device
+/// doesn't report it, its unique PARTLABEL, regardless of the order the device enumerates them in
+fn match_device_parts(
+    device_parts: &[LsblkPartition],
+    part_uuids: &[String],
+    part_labels: &[Option<String>],
+) -> Result<Vec<PathBuf>, OciBootstrapError> {
+    part_uuids
+        .iter()
+        .enumerate()
+        .map(|(idx, uuid)| {
+            if let Some(part) = device_parts.iter().find(|p| {
+                p.partuuid
+                    .as_ref()
+                    .is_some_and(|partuuid| partuuid.eq_ignore_ascii_case(uuid))
+            }) {
+                return Ok(part.path.clone());
+            }
+
+            let label = part_labels.get(idx).and_then(Option::as_ref);
+            let mut candidates = device_parts
+                .iter()
+                .filter(|p| label.is_some() && p.partlabel.as_ref() == label);
+
+            match (candidates.next(), candidates.next()) {
+                (Some(part), None) => Ok(part.path.clone()),
+                (Some(_), Some(_)) => Err(OciBootstrapError::Custom(format!(
+                    "Several partitions are labelled {}, and none has the PARTUUID {uuid}",
+                    label.map_or("", String::as_str)
+                ))),
+                (None, _) => Err(OciBootstrapError::Custom(format!(
+                    "Couldn't find partition {} (PARTUUID {uuid}) on the device",
+                    idx + 1
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Returns the partition number of a partition device file, from the digits its name ends with
+fn partition_number(part: &Path) -> Option<u32> {
+    let name = part.file_name()?.to_str()?;
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    name[prefix.len()..].parse().ok()
+}
+
+fn lsblk(file: &Path) -> Result<Vec<u8>, OciBootstrapError> {
+    run_command(
+        Command::new("lsblk")
+            .args(["--bytes", "--json", "--paths", "--output-all"])
+            .arg(file.as_os_str()),
+    )
+}
+
+fn find_device_parts(file: &Path) -> Result<Vec<PathBuf>, OciBootstrapError> {
+    parse_lsblk_parts(file, &lsblk(file)?)
+}
+
+/// Returns the partitions of a device, alongside their PARTLABEL and PARTUUID
+fn find_device_partitions(file: &Path) -> Result<Vec<LsblkPartition>, OciBootstrapError> {
+    parse_lsblk_partitions(file, &lsblk(file)?)
+}
+
+/// Returns the filesystem UUID of a partition, if it has one
+fn filesystem_uuid(part: &Path) -> Option<String> {
+    let output = run_command(
+        Command::new("blkid")
+            .args(["--probe", "--match-tag", "UUID", "--output", "value"])
+            .arg(part.as_os_str()),
+    );
+
+    // blkid exits with 2 if it couldn't find the tag, eg. for raw partitions
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("No filesystem UUID found on {}: {e}", part.display());
+            return None;
+        }
+    };
+
+    let uuid = String::from_utf8_lossy(&output).trim().to_owned();
+    (!uuid.is_empty()).then_some(uuid)
+}
+
+/// Collects the identifiers of the partitions of a device, to record them in the image sidecar
+fn sidecar_partitions(
+    device: &Device,
+    partition_table: &PartitionTable,
+    part_uuids: &[String],
+) -> Result<Vec<SidecarPartition>, OciBootstrapError> {
+    let partitions = partition_descriptions(partition_table);
+    let device_parts = match_device_parts(
+        &find_device_partitions(&device.path)?,
+        part_uuids,
+        &partition_labels(partition_table),
+    )?;
+
+    let mut res = Vec::with_capacity(partitions.len());
+    for (idx, (device_part, (fs, _, _))) in zip(&device_parts, &partitions).enumerate() {
+        res.push(SidecarPartition {
+            partuuid: part_uuids.get(idx).cloned(),
+            filesystem: fs.to_string(),
+            filesystem_uuid: filesystem_uuid(device_part),
+        });
+    }
+
+    Ok(res)
+}
+
+/// Waits for the kernel to scan the partitions of a device, and for their device files to show up
+fn wait_for_device_parts(file: &Path, count: usize) -> Result<Vec<PathBuf>, OciBootstrapError> {
+    let start = Instant::now();
+
+    loop {
+        let found = match find_device_parts(file) {
+            Ok(parts) if parts.len() >= count && parts.iter().all(|p| p.exists()) => {
+                debug!(
+                    "Found {} partitions on {} after {:?}",
+                    parts.len(),
+                    file.display(),
+                    start.elapsed()
+                );
+
+                return Ok(parts);
+            }
+            Ok(parts) => parts.len(),
+            // The kernel might not have scanned the partition table yet
+            Err(e) if start.elapsed() < PARTITION_SCAN_TIMEOUT => {
+                trace!("Couldn't find the partitions of {}: {e}", file.display());
+                0
+            }
+            Err(e) => return Err(e),
+        };
+
+        if start.elapsed() >= PARTITION_SCAN_TIMEOUT {
+            return Err(OciBootstrapError::Custom(format!(
+                "Timed out waiting for the partitions of {}: found {found}, expected {count}",
+                file.display(),
+            )));
+        }
+
+        trace!(
+            "Found {found} partitions out of {count} on {}, waiting...",
+            file.display()
+        );
+
+        thread::sleep(PARTITION_SCAN_POLL_INTERVAL);
+    }
+}
+
+fn is_dir_in_root(root: &Path, path: &Path) -> bool {
+    debug!("Checking if {} is in {}", path.display(), root.display());
+
+    if let Ok(p) = path.canonicalize() {
+        debug!("File can be canonicalized: {}", p.display());
+
+        return p.starts_with(root);
+    }
+
+    if let Some(p) = path.parent() {
+        is_dir_in_root(root, p)
+    } else {
+        false
+    }
+}
+
+fn join_path(root: &Path, path: &Path) -> Result<PathBuf, io::Error> {
+    let joined = if path.is_absolute() {
+        let mut joined = root.to_path_buf();
+
+        for part in path.components() {
+            match part {
+                std::path::Component::Prefix(_) => unreachable!(),
+                std::path::Component::RootDir | std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => joined.push(".."),
+                std::path::Component::Normal(c) => joined.push(c),
+            }
+        }
+
+        joined
+    } else {
+        root.join(path)
+    };
+
+    debug!("Joined Path {}", joined.display());
+
+    let canonical = match joined.canonicalize() {
+        Ok(p) => p,
+        Err(e) => {
+            if e.kind() == io::ErrorKind::NotFound {
+                debug!(
+                    "File {} doesn't exist... Checking if its parent exists in the root dir",
+                    joined.display()
+                );
+
+                if is_dir_in_root(root, &joined) {
+                    debug!("File ancestors in chroot.. Returning");
+                    return Ok(joined);
+                }
+            }
+
+            return Err(e);
+        }
+    };
+
+    debug!("Canonicalized Path {}", canonical.display());
+
+    if !canonical.starts_with(root) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path isn't contained in root",
+        ));
+    }
+
+    Ok(canonical)
+}
+
+/// Returns the path of a file within a root directory, without following it if it's a symlink
+///
+/// Only the parent directory is resolved, so that the file can be replaced or created without
+/// writing outside of the root.
+///
+/// # Errors
+///
+/// If the path has no file name, or if its parent directory isn't within the root
+fn path_in_root(root: &Path, path: &Path) -> Result<PathBuf, io::Error> {
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid path {}", path.display()),
+        ));
+    };
+
+    Ok(join_path(&root.canonicalize()?, parent)?.join(file_name))
+}
+
+/// Creates a file, and its missing parent directories, within a root directory
+///
+/// Neither the file nor its parent directories can be symlinks leading outside of the root, and
+/// the file itself can't be a symlink at all. An existing file is truncated, unless `create_new`
+/// is set in which case it's an error.
+///
+/// # Errors
+///
+/// If the file or its parent directory isn't within the root, or if the file can't be created
+fn create_file_in_root(
+    root: &Path,
+    path: &Path,
+    create_new: bool,
+) -> Result<(PathBuf, File), io::Error> {
+    let path = path_in_root(root, path)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_symlink())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is a symlink, refusing to write through it",
+                path.display()
+            ),
+        ));
+    }
+
+    let mut options = File::options();
+    options.write(true).custom_flags(OFlag::O_NOFOLLOW.bits());
+    if create_new {
+        options.create_new(true);
+    } else {
+        options.create(true).truncate(true);
+    }
+
+    let file = options.open(&path)?;
+
+    Ok((path, file))
+}
+
+/// Applies the offset, size and bootable flag shared by all the partition table formats
+fn partition_builder<B>(
+    mut builder: B,
+    offset_lba: Option<usize>,
+    size_bytes: Option<usize>,
+    bootable: bool,
+) -> B
+where
+    B: PartitionBuilder,
+{
+    if let Some(offset_lba) = offset_lba {
+        builder = builder.offset(offset_lba);
+    }
+
+    if let Some(size_bytes) = size_bytes {
+        builder = builder.size(size_bytes);
+    }
+
+    builder.bootable(bootable)
+}
+
+fn build_gpt(
+    table: &GptPartitionTable,
+    file: &File,
+    reproducible: Option<&Reproducible>,
+) -> Result<GuidPartitionTable, OciBootstrapError> {
+    let sizes = resolve_size_bytes(
+        table
+            .partitions()
+            .iter()
+            .map(|p| (p.size_bytes, p.size_percent)),
+        gpt::usable_size(file)?,
+        // The partitions are aligned, so a smaller size would leave a gap before the next one
+        gpt::PARTITION_ALIGNMENT_LBA * LBA_SIZE,
+    )?;
+
+    let mut builder = reproducible.map_or_else(GuidPartitionTableBuilder::new, |r| {
+        GuidPartitionTableBuilder::new_with_uuid(r.disk_guid())
+    });
+
+    if let Some(reserved) = table.reserved_start_bytes() {
+        builder = builder.reserved_start_bytes(reserved);
+    }
+
+    for (idx, (partition, size_bytes)) in zip(table.partitions(), sizes).enumerate() {
+        let mut part_builder = reproducible.map_or_else(
+            || GuidPartitionBuilder::new(partition.uuid),
+            |r| GuidPartitionBuilder::new_with_uuid(partition.uuid, r.partition_guid(idx)),
+        );
+
+        if let Some(name) = &partition.name {
+            part_builder = part_builder.name(name);
+        }
+
+        let part = partition_builder(
+            part_builder,
+            partition.offset_lba,
+            size_bytes,
+            partition.attributes.contains(GptAttributes::BOOTABLE),
+        )
+        .platform_required(
+            partition
+                .attributes
+                .contains(GptAttributes::PLATFORM_REQUIRED),
+        )
+        .read_only(
+            partition.attributes.contains(GptAttributes::READ_ONLY) || partition.fs.is_read_only(),
+        )
+        .hidden(partition.attributes.contains(GptAttributes::HIDDEN))
+        .no_auto(partition.attributes.contains(GptAttributes::NO_AUTO))
+        .grow_fs(partition.attributes.contains(GptAttributes::GROW))
+        .build();
+
+        builder = builder.add_partition(part);
+    }
+
+    Ok(builder.build())
+}
+
+fn build_mbr(
+    table: &MbrPartitionTable,
+    file: &File,
+    reproducible: Option<&Reproducible>,
+) -> Result<MasterBootRecordPartitionTable, OciBootstrapError> {
+    let sizes = resolve_size_bytes(
+        table
+            .partitions()
+            .iter()
+            .map(|p| (p.size_bytes, p.size_percent)),
+        mbr::usable_size(file)?,
+        // The partitions are aligned, so a smaller size would leave a gap before the next one
+        mbr::PARTITION_ALIGNMENT_LBA * LBA_SIZE,
+    )?;
+
+    let (heads_per_cylinder, sectors_per_track) = table.geometry();
+
+    let mut builder = MasterBootRecordPartitionTableBuilder::new()
+        .heads_per_cylinder(heads_per_cylinder)
+        .sectors_per_track(sectors_per_track);
+
+    if let Some(reproducible) = reproducible {
+        builder = builder.disk_id(reproducible.disk_id());
+    }
+
+    if let Some(reserved) = table.reserved_start_bytes() {
+        builder = builder.reserved_start_bytes(reserved);
+    }
+
+    for (partition, size_bytes) in zip(table.partitions(), sizes) {
+        let part = partition_builder(
+            MasterBootRecordPartitionBuilder::new(partition.kind),
+            partition.offset_lba,
+            size_bytes,
+            partition.bootable,
+        )
+        .build();
+
+        builder = builder.add_partition(part);
+    }
+
+    Ok(builder.build())
+}
+
+fn build_partition_table(
+    partition_table: &PartitionTable,
+    file: &File,
+    reproducible: Option<&Reproducible>,
+) -> Result<Box<dyn PartitionTableWriter>, OciBootstrapError> {
+    Ok(match partition_table {
+        PartitionTable::Gpt(table) => Box::new(build_gpt(table, file, reproducible)?),
+        PartitionTable::Mbr(table) => Box::new(build_mbr(table, file, reproducible)?),
+    })
+}
+
+/// Checks that the firmwares of the root filesystem fit in the device, and don't overlap with each
+/// other, the partition table or the partitions
+fn check_firmware(
+    root: &Path,
+    firmware: &[Firmware],
+    table: &dyn PartitionTableWriter,
+    file: &File,
+) -> Result<(), OciBootstrapError> {
+    let device_size = gpt::device_size(file)?;
+    let (table_start_size, table_end_size) = table.table_size_bytes();
+
+    let mut used = vec![
+        (
+            String::from("the partition table"),
+            0,
+            table_start_size as u64,
+        ),
+        (
+            String::from("the backup partition table"),
+            device_size.saturating_sub(table_end_size as u64),
+            device_size,
+        ),
+    ];
+
+    for (idx, part) in table.partitions_layout(file)?.iter().enumerate() {
+        used.push((
+            format!("partition {idx}"),
+            (part.start_lba * LBA_SIZE) as u64,
+            ((part.end_lba + 1) * LBA_SIZE) as u64,
+        ));
+    }
+
+    for fw in firmware {
+        let size = join_path(root, &fw.source)
+            .and_then(fs::metadata)
+            .map_err(|e| {
+                OciBootstrapError::Custom(format!(
+                    "Couldn't access firmware {}: {e}",
+                    fw.source.display()
+                ))
+            })?
+            .len();
+
+        let name = format!("firmware {}", fw.source.display());
+        let start = fw.offset_bytes;
+        let end = start
+            .checked_add(size)
+            .ok_or(OciBootstrapError::Custom(format!(
+                "Invalid offset for {name}"
+            )))?;
+
+        if end > device_size {
+            return Err(OciBootstrapError::Custom(format!(
+                "{name} ({start:#x}-{end:#x}) doesn't fit in the device ({device_size:#x} bytes)"
+            )));
+        }
+
+        if let Some((other, _, _)) = used
+            .iter()
+            .find(|(_, other_start, other_end)| start < *other_end && *other_start < end)
+        {
+            return Err(OciBootstrapError::Custom(format!(
+                "{name} ({start:#x}-{end:#x}) overlaps with {other}"
+            )));
+        }
+
+        used.push((name, start, end));
+    }
+
+    Ok(())
+}
+
+/// Writes the firmwares of the root filesystem to the file at their offsets
+fn write_firmware(
+    root: &Path,
+    file: &mut File,
+    firmware: &[Firmware],
+) -> Result<(), OciBootstrapError> {
+    for fw in firmware {
+        debug!(
+            "Writing firmware {} at offset {:#x}",
+            fw.source.display(),
+            fw.offset_bytes
+        );
+
+        let mut source = File::open(join_path(root, &fw.source)?)?;
+        file.seek(io::SeekFrom::Start(fw.offset_bytes))?;
+        io::copy(&mut source, file)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the partition table to the file, and returns the PARTUUID of each partition
+fn create_partition_table(
+    partition_table: &PartitionTable,
+    file: &mut File,
+    reproducible: Option<&Reproducible>,
+) -> Result<Vec<String>, OciBootstrapError> {
+    let table = build_partition_table(partition_table, file, reproducible)?;
+
+    let part_uuids = table.write_table(file)?;
+    file.flush()?;
+    file.sync_all()?;
+
+    Ok(part_uuids)
+}
+
+/// Writes the firmwares to the file, once the image has been extracted to the root filesystem
+/// they are taken from
+///
+/// The firmwares are looked up in the root filesystem only, so that an image can't copy files of
+/// the host.
+fn install_firmware(
+    root: &Path,
+    partition_table: &PartitionTable,
+    file: &mut File,
+    reproducible: Option<&Reproducible>,
+) -> Result<(), OciBootstrapError> {
+    let firmware = partition_table.firmware();
+    if firmware.is_empty() {
+        return Ok(());
+    }
+
+    let table = build_partition_table(partition_table, file, reproducible)?;
+    check_firmware(root, firmware, table.as_ref(), file)?;
+
+    write_firmware(root, file, firmware)?;
+    file.flush()?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Returns the size of the smallest device the partitions fit in, or `None` if a partition fills
+/// the remaining space
+fn minimum_device_size(
+    file: &File,
+    partition_table: &PartitionTable,
+) -> Result<Option<usize>, OciBootstrapError> {
+    Ok(build_partition_table(partition_table, file, None)?.minimum_size_bytes())
+}
+
+/// Checks the partition layout against the size of the device, before touching it
+fn check_layout(
+    partition_table: &PartitionTable,
+    device_size: u64,
+) -> Result<(), OciBootstrapError> {
+    let device_size = device_size.try_into().map_err(|_err| {
+        OciBootstrapError::Custom(format!("Device size {device_size} is too large"))
+    })?;
+
+    partition_table.validate(device_size).map_err(|errors| {
+        OciBootstrapError::Custom(format!(
+            "Invalid partition layout: {}",
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ")
+        ))
+    })
+}
+
+fn resize_output_file(
+    file: &File,
+    size: u64,
+    preallocate: bool,
+    partition_table: &PartitionTable,
+) -> Result<(), OciBootstrapError> {
+    file.set_len(size)?;
+
+    if let Some(minimum) = minimum_device_size(file, partition_table)? {
+        if u64::try_from(minimum).is_ok_and(|minimum| size < minimum) {
+            return Err(OciBootstrapError::Custom(format!(
+                "Output file size ({size} bytes) is smaller than what the partitions need ({minimum} bytes)"
+            )));
+        }
+    }
+
+    // Partitions without a size still need some room
+    partition_plan(file, partition_table)?;
+
+    if preallocate {
+        debug!("Allocating the {size} bytes of the output file");
+
+        let len = size.try_into().map_err(|_err| {
+            OciBootstrapError::Custom(format!("Output file size {size} is too large"))
+        })?;
+
+        posix_fallocate(file.as_raw_fd(), 0, len).map_err(io::Error::from)?;
+    }
+
+    Ok(())
+}
+
+/// Creates the output device file, with a size large enough for the partitions
+///
+/// The file is sparse unless `preallocate` is set, in which case all its blocks are allocated
+/// upfront so that it's as contiguous as possible. The file is removed if it can't hold the
+/// partitions.
+fn create_output_file(
+    path: &Path,
+    size: u64,
+    preallocate: bool,
+    partition_table: &PartitionTable,
+) -> Result<File, OciBootstrapError> {
+    debug!("Creating output file {} of {size} bytes", path.display());
+
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+
+    if let Err(e) = resize_output_file(&file, size, preallocate, partition_table) {
+        drop(file);
+
+        if let Err(err) = fs::remove_file(path) {
+            error!("Couldn't remove {}: {err}", path.display());
+        }
+
+        return Err(e);
+    }
+
+    Ok(file)
+}
+
+type PartitionPlan = (String, PartitionLayout, Filesystem, Option<PathBuf>);
+
+/// Computes the layout of the partitions, without writing anything to the file
+fn partition_plan(
+    file: &File,
+    partition_table: &PartitionTable,
+) -> Result<Vec<PartitionPlan>, OciBootstrapError> {
+    let layout = build_partition_table(partition_table, file, None)?.partitions_layout(file)?;
+
+    Ok(match partition_table {
+        PartitionTable::Gpt(table) => zip(table.partitions(), layout)
+            .map(|(p, l)| (p.uuid.to_string(), l, p.fs.clone(), p.mnt.clone()))
+            .collect::<Vec<_>>(),
+        PartitionTable::Mbr(table) => zip(table.partitions(), layout)
+            .map(|(p, l)| (format!("0x{:02x}", p.kind), l, p.fs.clone(), p.mnt.clone()))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn partition_reports(
+    plan: Vec<PartitionPlan>,
+    part_uuids: Option<&[String]>,
+) -> Vec<PartitionReport> {
+    plan.into_iter()
+        .enumerate()
+        .map(|(idx, (kind, layout, fs, mnt))| PartitionReport {
+            guid: part_uuids.and_then(|uuids| uuids.get(idx)).cloned(),
+            kind,
+            offset_lba: layout.start_lba,
+            size_bytes: (layout.end_lba - layout.start_lba + 1) * LBA_SIZE,
+            filesystem: fs.to_string(),
+            mount_point: mnt.map(|mnt| mnt.display().to_string()),
+        })
+        .collect()
+}
+
+/// Logs the platform of the image we selected, since several can match a request without a
+/// variant
+fn log_platform(manifest: &LocalManifest<'_>) -> Result<(), OciBootstrapError> {
+    let (arch, os, variant) = manifest.platform()?;
+
+    match variant {
+        Some(variant) => info!("Using image for platform {os}/{arch}/{variant}"),
+        None => info!("Using image for platform {os}/{arch}"),
+    }
+
+    Ok(())
+}
+
+fn report(
+    container_spec: &ContainerSpec,
+    manifest: &LocalManifest<'_>,
+    output: &Path,
+    partitions: Vec<PartitionReport>,
+) -> Report {
+    Report {
+        container: container_spec.to_oci_string(),
+        manifest_digest: manifest.digest().map(Digest::to_oci_string),
+        image_created: manifest.created().map(|created| created.to_string()),
+        platform: manifest.platform_string(),
+        output: output.display().to_string(),
+        partitions,
+    }
+}
+
+type PartitionDescription = (Filesystem, Option<PathBuf>, Vec<String>);
+
+type PartitionMount = (PathBuf, Filesystem, Option<PathBuf>, Option<String>);
+
+fn partition_descriptions(partition_table: &PartitionTable) -> Vec<PartitionDescription> {
+    match partition_table {
+        PartitionTable::Gpt(table) => table
+            .partitions()
+            .iter()
+            .map(|p| (p.fs.clone(), p.mnt.clone(), p.mount_options.clone()))
+            .collect(),
+        PartitionTable::Mbr(table) => table
+            .partitions()
+            .iter()
+            .map(|p| (p.fs.clone(), p.mnt.clone(), p.mount_options.clone()))
+            .collect(),
+    }
+}
+
+/// Returns the PARTLABEL of each partition, if the partition table supports them
+fn partition_labels(partition_table: &PartitionTable) -> Vec<Option<String>> {
+    match partition_table {
+        PartitionTable::Gpt(table) => table.partitions().iter().map(|p| p.name.clone()).collect(),
+        PartitionTable::Mbr(table) => vec![None; table.partitions().len()],
+    }
+}
+
+/// Returns whether each partition should grow on first boot
+fn partition_grow(partition_table: &PartitionTable) -> Vec<bool> {
+    match partition_table {
+        PartitionTable::Gpt(table) => table
+            .partitions()
+            .iter()
+            .map(|p| p.attributes.contains(GptAttributes::GROW))
+            .collect(),
+        PartitionTable::Mbr(table) => table.partitions().iter().map(|p| p.grow).collect(),
+    }
+}
+
+/// Returns the mount point and the files to copy of each partition
+fn partition_files(partition_table: &PartitionTable) -> Vec<(Option<&Path>, &[PartitionFile])> {
+    match partition_table {
+        PartitionTable::Gpt(table) => table
+            .partitions()
+            .iter()
+            .map(|p| (p.mnt.as_deref(), p.files.as_slice()))
+            .collect(),
+        PartitionTable::Mbr(table) => table
+            .partitions()
+            .iter()
+            .map(|p| (p.mnt.as_deref(), p.files.as_slice()))
+            .collect(),
+    }
+}
+
+fn partition_mounts(
+    dev: &Path,
+    desc: &PartitionDescription,
+    read_only: bool,
+) -> Vec<PartitionMount> {
+    let (fs, mnt, options) = desc;
+
+    let options = read_only
+        .then(|| String::from("ro"))
+        .into_iter()
+        .chain(options.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let mut mounts = vec![(
+        dev.to_path_buf(),
+        fs.clone(),
+        mnt.clone(),
+        mount_data(None, &options),
+    )];
+
+    if let Filesystem::Btrfs(p) = fs {
+        mounts.extend(p.subvolumes.iter().filter_map(|subvolume| {
+            subvolume.mnt.as_ref().map(|mnt| {
+                (
+                    dev.to_path_buf(),
+                    fs.clone(),
+                    Some(mnt.clone()),
+                    mount_data(Some(format!("subvol={}", subvolume.name)), &options),
+                )
+            })
+        }));
+    }
+
+    mounts
+}
+
+/// Sorts the mounts so that parents are mounted before their children
+///
+/// Mounts are sorted by the depth of their mount point, and the partitions that aren't mounted
+/// come last. Mounts at the same depth keep their order in the partition table.
+fn sort_partition_mounts(mounts: &mut [PartitionMount]) {
+    mounts.sort_by_key(|(_, _, mnt, _)| {
+        (
+            mnt.is_none(),
+            mnt.as_ref().map_or(0, |mnt| mnt.components().count()),
+        )
+    });
+}
+
+fn mount_data(extra: Option<String>, options: &[String]) -> Option<String> {
+    let data = extra
+        .into_iter()
+        .chain(options.iter().cloned())
+        .collect::<Vec<_>>();
+
+    if data.is_empty() {
+        None
+    } else {
+        Some(data.join(","))
+    }
+}
+
+fn create_btrfs_subvolumes(
+    dev: &Path,
+    subvolumes: &[BtrfsSubvolume],
+) -> Result<(), OciBootstrapError> {
+    let temp_dir = TempDir::new()?;
+
+    debug!(
+        "Mounting {} on {} to create subvolumes",
+        dev.display(),
+        temp_dir.path().display()
+    );
+
+    let mount = Mount::builder()
+        .fstype(FilesystemType::Manual("btrfs"))
+        .mount(dev, temp_dir.path())?;
+
+    for subvolume in subvolumes {
+        debug!("Creating BTRFS subvolume {}", subvolume.name);
+
+        run_command(
+            Command::new("btrfs")
+                .args(["subvolume", "create"])
+                .arg(temp_dir.path().join(&subvolume.name)),
+        )?;
+    }
+
+    mount.unmount(UnmountFlags::empty())?;
+
+    Ok(())
+}
+
+fn create_fat(
+    dev: &Path,
+    params: &FatParameters,
+    ids: Option<&FilesystemIds>,
+) -> Result<(), OciBootstrapError> {
+    #[cfg(feature = "native-fat")]
+    if fat::fits_fat32(dev, params)? {
+        debug!("Creating FAT32 partition on {} natively", dev.display());
+
+        return fat::format_fat32(dev, params, ids, fat::partition_start(dev)?);
+    }
+
+    let mut command = Command::new("mkfs.vfat");
+    let mut command_ref = &mut command;
+
+    debug!("Creating FAT32 partition on {}", dev.display());
+
+    if let (Some(heads), Some(spt)) = (params.heads, params.sectors_per_track) {
+        let geometry = format!("{heads}/{spt}");
+
+        debug!("FAT32 Geometry uses {heads} heads, {spt} sectors per track");
+
+        command_ref = command_ref.args(["-g", &geometry]);
+    }
+
+    if let Some(vol_id) = params
+        .volume_id
+        .or_else(|| ids.map(|ids| ids.uuid.as_fields().0))
+    {
+        let id = format!("{vol_id:x}");
+
+        debug!("FAT32 Volume ID is {id}");
+
+        command_ref = command_ref.args(["-i", &id]);
+    }
+
+    if let Some(label) = &params.label {
+        debug!("FAT32 Label is {label}");
+
+        command_ref = command_ref.args(["-n", label]);
+    }
+
+    if let Some(bits) = params.fat_bits {
+        debug!("Using a FAT{bits}");
+
+        command_ref = command_ref.args(["-F", &bits.to_string()]);
+    }
+
+    if let Some(ids) = ids {
+        command_ref = command_ref.env(SOURCE_DATE_EPOCH, ids.timestamp.to_string());
+    }
+
+    run_command(command_ref.arg(dev.as_os_str()))?;
+
+    Ok(())
+}
+
+fn create_ext4(
+    dev: &Path,
+    params: &ExtParameters,
+    ids: Option<&FilesystemIds>,
+) -> Result<(), OciBootstrapError> {
+    let mut command = Command::new("mkfs.ext4");
+    let mut command_ref = &mut command;
+
+    debug!("Creating EXT4 partition on {}", dev.display());
+
+    if let Some(uuid) = params.uuid.or_else(|| ids.map(|ids| ids.uuid)) {
+        let uuid = uuid.to_string();
+
+        debug!("EXT4 UUID is {uuid}");
+
+        command_ref = command_ref.args(["-U", &uuid]);
+    }
+
+    if let Some(ids) = ids {
+        // The directory hash seed is random, and the timestamps are the current time otherwise
+        command_ref = command_ref
+            .args(["-E", &format!("hash_seed={}", ids.hash_seed)])
+            .env(E2FSPROGS_FAKE_TIME, ids.timestamp.to_string());
+    }
+
+    if let Some(label) = &params.label {
+        debug!("EXT4 Label is {label}");
+
+        command_ref = command_ref.args(["-L", label]);
+    }
+
+    if let Some(block_size) = params.block_size {
+        debug!("EXT4 Block Size is {block_size}");
+
+        command_ref = command_ref.args(["-b", &block_size.to_string()]);
+    }
+
+    if let Some(percent) = params.reserved_percent {
+        debug!("EXT4 Reserved Blocks Percentage is {percent}");
+
+        command_ref = command_ref.args(["-m", &percent.to_string()]);
+    }
+
+    run_command(command_ref.arg(dev.as_os_str()))?;
+
+    Ok(())
+}
+
+fn create_btrfs(
+    dev: &Path,
+    params: &BtrfsParameters,
+    ids: Option<&FilesystemIds>,
+) -> Result<(), OciBootstrapError> {
+    let mut command = Command::new("mkfs.btrfs");
+    let mut command_ref = &mut command;
+
+    debug!("Creating BTRFS partition on {}", dev.display());
+
+    if let Some(label) = &params.label {
+        debug!("BTRFS Label is {label}");
+
+        command_ref = command_ref.args(["-L", label]);
+    }
+
+    if let Some(uuid) = params.uuid.or_else(|| ids.map(|ids| ids.uuid)) {
+        let uuid = uuid.to_string();
+
+        debug!("BTRFS UUID is {uuid}");
+
+        command_ref = command_ref.args(["-U", &uuid]);
+    }
+
+    if let Some(ids) = ids {
+        command_ref = command_ref.env(SOURCE_DATE_EPOCH, ids.timestamp.to_string());
+    }
+
+    run_command(command_ref.arg(dev.as_os_str()))?;
+
+    if !params.subvolumes.is_empty() {
+        create_btrfs_subvolumes(dev, &params.subvolumes)?;
+    }
+
+    Ok(())
+}
+
+fn create_swap(dev: &Path, ids: Option<&FilesystemIds>) -> Result<(), OciBootstrapError> {
+    let mut command = Command::new("mkswap");
+    let mut command_ref = &mut command;
+
+    debug!("Creating swap partition on {}", dev.display());
+
+    if let Some(ids) = ids {
+        command_ref = command_ref.args(["-U", &ids.uuid.to_string()]);
+    }
+
+    run_command(command_ref.arg(dev.as_os_str()))?;
+
+    Ok(())
+}
+
+/// Creates the filesystems of each partition of a device
+fn create_filesystems(
+    device_parts: &[PathBuf],
+    partitions: &[PartitionDescription],
+    reproducible: Option<&Reproducible>,
+) -> Result<(), OciBootstrapError> {
+    for (idx, (device_part, part_desc)) in zip(device_parts, partitions).enumerate() {
+        let ids = reproducible.map(|r| r.filesystem(idx));
+
+        match &part_desc.0 {
+            Filesystem::Fat32(p) => create_fat(device_part, p, ids.as_ref())?,
+            Filesystem::Ext4(p) => create_ext4(device_part, p, ids.as_ref())?,
+            Filesystem::Btrfs(p) => create_btrfs(device_part, p, ids.as_ref())?,
+            Filesystem::Swap => create_swap(device_part, ids.as_ref())?,
+            Filesystem::Raw(_) | Filesystem::Squashfs(_) => {
+                debug!("Partition content copied from the image, Skipping.");
+            }
+        };
+    }
+
+    Ok(())
+}
+
+/// Creates the partition table and filesystems, mounts them, and returns the device alongside the
+/// PARTUUID of each partition
+fn create_and_mount_loop_device(
+    mut file: File,
+    partition_table: &PartitionTable,
+    reproducible: Option<&Reproducible>,
+) -> Result<(Device, Vec<String>), OciBootstrapError> {
+    let part_uuids = create_partition_table(partition_table, &mut file, reproducible)?;
+
+    let partitions = partition_descriptions(partition_table);
+
+    let loop_control = LoopControl::open()?;
+    let loop_device = LoopDevice::create(&loop_control, file)?;
+
+    let device_parts = wait_for_device_parts(&loop_device.path(), partitions.len())?;
+    create_filesystems(&device_parts, &partitions, reproducible)?;
+
+    let device = mount_device_partitions(
+        &loop_device.path(),
+        Some(loop_device),
+        &device_parts,
+        &partitions,
+        false,
+    )?;
+
+    Ok((device, part_uuids))
+}
+
+/// Creates the partition table and filesystems directly on a block device, mounts them, and
+/// returns the device alongside the PARTUUID of each partition
+fn create_and_mount_block_device(
+    mut file: File,
+    path: &Path,
+    partition_table: &PartitionTable,
+    reproducible: Option<&Reproducible>,
+) -> Result<(Device, Vec<String>), OciBootstrapError> {
+    let part_uuids = create_partition_table(partition_table, &mut file, reproducible)?;
+
+    // Unlike loop devices, the kernel doesn't scan the new partition table on its own
+    run_command(Command::new("blockdev").arg("--rereadpt").arg(path))?;
+
+    let partitions = partition_descriptions(partition_table);
+
+    let device_parts = wait_for_device_parts(path, partitions.len())?;
+    create_filesystems(&device_parts, &partitions, reproducible)?;
+
+    let device = mount_device_partitions(path, None, &device_parts, &partitions, false)?;
+
+    Ok((device, part_uuids))
+}
+
+/// Generates an fstab mounting the partitions through their PARTUUID
+///
+/// The ext4 and btrfs filesystems of the partitions marked to grow are grown by systemd when
+/// mounted.
+fn fstab(partitions: &[PartitionDescription], part_uuids: &[String], grow: &[bool]) -> String {
+    let mut entries = Vec::new();
+
+    for (idx, (part_desc, part_uuid)) in zip(partitions, part_uuids).enumerate() {
+        let source = format!("PARTUUID={part_uuid}");
+        let grow = grow.get(idx).copied().unwrap_or_default();
+
+        if matches!(part_desc.0, Filesystem::Swap) {
+            entries.push((None, format!("{source}\tnone\tswap\tsw\t0\t0")));
+            continue;
+        }
+
+        for (mount_idx, (_, fs, mnt, data)) in partition_mounts(Path::new(""), part_desc, false)
+            .into_iter()
+            .enumerate()
+        {
+            let (Some(mnt), Some(fstype)) = (mnt, fs.mount_type()) else {
+                continue;
+            };
+
+            // A filesystem only needs to be grown once, even if it has several mount points, and
+            // systemd-growfs only knows how to grow ext4 and btrfs
+            let growfs = matches!(fs, Filesystem::Ext4(_) | Filesystem::Btrfs(_));
+            let data = if grow && growfs && mount_idx == 0 {
+                Some(data.map_or_else(
+                    || String::from(SYSTEMD_GROWFS_OPTION),
+                    |data| format!("{data},{SYSTEMD_GROWFS_OPTION}"),
+                ))
+            } else {
+                data
+            };
+
+            // fsck.btrfs doesn't do anything, so there's no point in checking it at boot.
+            let pass = if matches!(fs, Filesystem::Btrfs(_)) {
+                0
+            } else if mnt == Path::new("/") {
+                1
+            } else {
+                2
+            };
+
+            let line = format!(
+                "{source}\t{}\t{fstype}\t{}\t0\t{pass}",
+                mnt.display(),
+                data.as_deref().unwrap_or("defaults")
+            );
+
+            entries.push((Some(mnt), line));
+        }
+    }
+
+    // Parents need to be mounted before their children, and swap comes last.
+    entries.sort_by(|a, b| Ord::cmp(&(a.0.is_none(), &a.0), &(b.0.is_none(), &b.0)));
+
+    let mut content = String::new();
+    for (_, line) in entries {
+        content.push_str(&line);
+        content.push('\n');
+    }
+
+    content
+}
+
+fn write_fstab(root: &Path, content: &str) -> Result<(), io::Error> {
+    let path = Path::new("etc/fstab");
+    if root.join(path).symlink_metadata().is_ok() {
+        info!("Overwriting the image fstab");
+    }
+
+    debug!("Writing fstab:\n{content}");
+
+    let (_, mut file) = create_file_in_root(root, path, false)?;
+    file.write_all(content.as_bytes())
+}
+
+fn mount_device_partitions(
+    path: &Path,
+    loop_device: Option<LoopDevice>,
+    device_parts: &[PathBuf],
+    partitions: &[PartitionDescription],
+    read_only: bool,
+) -> Result<Device, io::Error> {
+    let temp_dir = TempDir::new()?;
+    let output_dir = temp_dir.path().to_path_buf();
+    debug!("Temp output dir is {}", output_dir.display());
+
+    let mut device_partitions = zip(device_parts, partitions)
+        .flat_map(|(device_part, part_desc)| {
+            if let Some(mnt) = &part_desc.1 {
+                debug!(
+                    "Partition {} Mounted on {}",
+                    device_part.display(),
+                    mnt.display()
+                );
+            }
+
+            partition_mounts(device_part, part_desc, read_only)
+        })
+        .collect::<Vec<_>>();
+
+    sort_partition_mounts(&mut device_partitions);
+
+    let device_partitions = device_partitions
+        .into_iter()
+        .map(|(part, fs, target_mnt, data)| {
+            if let Some(mnt) = &target_mnt {
+                let mount_dir = join_path(&output_dir, mnt)?;
+
+                DevicePartition::new(&part, fs, Some(&mount_dir), data.as_deref())
+            } else {
+                DevicePartition::new(&part, fs, None, None)
+            }
+        })
+        .collect::<Result<Vec<_>, io::Error>>()?;
+
+    Ok(Device {
+        path: path.to_path_buf(),
+        loopdev: loop_device,
+        dir: temp_dir,
+        parts: device_partitions,
+    })
+}
+
+fn remove_file_or_dir(path: &Path) -> Result<(), io::Error> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn remove_lower_layers_entries(
+    root: &Path,
+    rel_dir: &Path,
+    layer_paths: &HashSet<PathBuf>,
+) -> Result<(), io::Error> {
+    for child in fs::read_dir(root.join(rel_dir))? {
+        let child = child?;
+        let rel_path = rel_dir.join(child.file_name());
+
+        if layer_paths.contains(&rel_path) {
+            if child.file_type()?.is_dir() {
+                remove_lower_layers_entries(root, &rel_path, layer_paths)?;
+            }
+
+            continue;
+        }
+
+        debug!("Removing {} from the lower layers", child.path().display());
+
+        remove_file_or_dir(&child.path())?;
+    }
+
+    Ok(())
+}
+
+fn unpack_hardlink(dir: &Path, entry_path: &Path, link_name: &Path) -> Result<(), io::Error> {
+    if link_name
+        .components()
+        .chain(entry_path.components())
+        .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Hardlink {} to {} escapes the root directory",
+                entry_path.display(),
+                link_name.display()
+            ),
+        ));
+    }
+
+    // A symlink of a lower layer could make a parent directory of either end point outside of
+    // the root. The ends themselves aren't resolved, so that a hardlink to a symlink links to the
+    // symlink.
+    let target = path_in_root(
+        dir,
+        &link_name
+            .components()
+            .filter(|c| matches!(c, std::path::Component::Normal(_)))
+            .collect::<PathBuf>(),
+    )?;
+    let link = path_in_root(dir, entry_path)?;
+
+    if link.symlink_metadata().is_ok() {
+        remove_file_or_dir(&link)?;
+    }
+
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if let Err(e) = fs::hard_link(&target, &link) {
+        // Copying would follow the target if it's a symlink
+        if !target.symlink_metadata()?.is_file() {
+            return Err(e);
+        }
+
+        debug!(
+            "Couldn't hardlink {} to {} ({e}), copying instead.",
+            link.display(),
+            target.display()
+        );
+
+        fs::copy(&target, &link)?;
+    }
+
+    Ok(())
+}
+
+const SETID_MODE_BITS: u32 = 0o6000;
+
+fn skip_rootless_entry(path: &Path, entry_type: EntryType) -> bool {
+    if matches!(
+        entry_type,
+        EntryType::Char | EntryType::Block | EntryType::Fifo
+    ) {
+        info!(
+            "Skipping special file {} ({entry_type:?}) in rootless mode",
+            path.display()
+        );
+
+        return true;
+    }
+
+    false
+}
+
+fn normalize_entry_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, std::path::Component::CurDir))
+        .collect()
+}
+
+/// Checks whether a regular file of a layer is already in the directory, with the same size and
+/// modification time
+fn is_entry_unchanged(dir: &Path, entry_path: &Path, header: &tar::Header) -> bool {
+    if !matches!(
+        header.entry_type(),
+        EntryType::Regular | EntryType::Continuous
+    ) {
+        return false;
+    }
+
+    let Ok(metadata) = dir.join(entry_path).symlink_metadata() else {
+        return false;
+    };
+
+    let (Ok(size), Ok(mtime)) = (header.size(), header.mtime()) else {
+        return false;
+    };
+
+    // tar sets the modification time to 1 when it's 0 in the archive
+    let mtime = mtime.max(1);
+
+    metadata.is_file()
+        && metadata.len() == size
+        && u64::try_from(metadata.mtime()).is_ok_and(|found| found == mtime)
+}
+
+/// Turns a path given on the command line, possibly absolute, into one relative to the root of
+/// the image
+fn image_relative_path(path: &Path) -> PathBuf {
+    normalize_entry_path(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Checks whether an entry is under one of the paths to extract
+///
+/// The directories leading to those paths are selected too, so that they keep their ownership,
+/// mode and modification time. An empty list of paths selects everything.
+fn is_path_selected(paths: &[PathBuf], entry_path: &Path, is_dir: bool) -> bool {
+    paths.is_empty()
+        || paths
+            .iter()
+            .any(|path| entry_path.starts_with(path) || (is_dir && path.starts_with(entry_path)))
+}
+
+/// Limits how much data the extraction of an image can write, so that a decompression bomb can't
+/// fill the disk
+#[derive(Clone, Copy, Debug, Default)]
+struct ExtractionBudget {
+    max_bytes: Option<u64>,
+    extracted_bytes: u64,
+    layer_size: Option<u64>,
+}
+
+impl ExtractionBudget {
+    fn new(max_bytes: Option<u64>) -> Self {
+        Self {
+            max_bytes,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the declared size of the layer being extracted, that none of its entries can exceed
+    fn start_layer(&mut self, layer_size: Option<u64>) {
+        self.layer_size = layer_size;
+    }
+
+    /// Accounts for an entry about to be extracted
+    ///
+    /// # Errors
+    ///
+    /// If the entry is larger than its layer, or if it would take the extraction over its maximum
+    /// size
+    fn add_entry(&mut self, entry_path: &Path, size: u64) -> Result<(), io::Error> {
+        if let Some(layer_size) = self.layer_size {
+            if size > layer_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "File {} is larger ({size} bytes) than its layer ({layer_size} bytes)",
+                        entry_path.display()
+                    ),
+                ));
+            }
+        }
+
+        self.extracted_bytes = self.extracted_bytes.saturating_add(size);
+
+        if let Some(max_bytes) = self.max_bytes {
+            if self.extracted_bytes > max_bytes {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Extracting {} takes the image over the maximum of {max_bytes} bytes",
+                        entry_path.display()
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// User and group IDs mappings applied to the owners of the extracted files
+#[derive(Clone, Copy, Debug, Default)]
+struct IdMappings<'a> {
+    uid: &'a [IdMapping],
+    gid: &'a [IdMapping],
+}
+
+/// Returns the host ID an image ID is mapped to, or `None` if there's no mapping
+fn map_id(
+    mappings: &[IdMapping],
+    id: u64,
+    kind: &str,
+    entry_path: &Path,
+) -> Result<Option<u32>, OciBootstrapError> {
+    if mappings.is_empty() {
+        return Ok(None);
+    }
+
+    u32::try_from(id)
+        .ok()
+        .and_then(|id| mappings.iter().find_map(|mapping| mapping.map(id)))
+        .map(Some)
+        .ok_or(OciBootstrapError::Custom(format!(
+            "{kind} {id} of {} isn't part of the {kind} mapping",
+            entry_path.display()
+        )))
+}
+
+impl<'a> IdMappings<'a> {
+    fn is_empty(&self) -> bool {
+        self.uid.is_empty() && self.gid.is_empty()
+    }
+
+    /// Returns the mappings to use for a layer
+    ///
+    /// The layer mappings take precedence, but only for the mappings the caller asked for.
+    fn for_layer(self, (uid, gid): (&'a [IdMapping], &'a [IdMapping])) -> Self {
+        Self {
+            uid: if uid.is_empty() || self.uid.is_empty() {
+                self.uid
+            } else {
+                uid
+            },
+            gid: if gid.is_empty() || self.gid.is_empty() {
+                self.gid
+            } else {
+                gid
+            },
+        }
+    }
+
+    /// Changes the owner of an extracted file to the IDs its archive entry owner are mapped to
+    ///
+    /// Changing the owner of a file clears its setuid and setgid bits, and its capabilities, so
+    /// they are restored afterwards.
+    fn chown(
+        &self,
+        dir: &Path,
+        entry_path: &Path,
+        header: &tar::Header,
+        mode_mask: u32,
+        capability: Option<&[u8]>,
+    ) -> Result<(), OciBootstrapError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let uid = map_id(self.uid, header.uid()?, "UID", entry_path)?;
+        let gid = map_id(self.gid, header.gid()?, "GID", entry_path)?;
+
+        trace!(
+            "Changing the owner of {} to {uid:?}:{gid:?}",
+            entry_path.display()
+        );
+
+        let path = path_in_root(dir, entry_path)?;
+        unix_fs::lchown(&path, uid, gid).map_err(|e| {
+            OciBootstrapError::Custom(format!(
+                "Couldn't change the owner of {}: {e}",
+                entry_path.display()
+            ))
+        })?;
+
+        if !matches!(
+            header.entry_type(),
+            EntryType::Regular | EntryType::Continuous
+        ) {
+            return Ok(());
+        }
+
+        let mode = header.mode()? & 0o7777 & !mode_mask;
+        if mode & SETID_MODE_BITS != 0 {
+            fs::set_permissions(&path, Permissions::from_mode(mode))?;
+        }
+
+        if let Some(capability) = capability {
+            xattr::set(&path, CAPABILITY_XATTR, capability)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Extended attribute holding the capabilities of a file
+const CAPABILITY_XATTR: &str = "security.capability";
+
+/// Returns the file capabilities of an archive entry, if it has any
+fn entry_capability<R>(entry: &mut tar::Entry<'_, R>) -> Result<Option<Vec<u8>>, io::Error>
+where
+    R: io::Read,
+{
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(None);
+    };
+
+    let key = format!("SCHILY.xattr.{CAPABILITY_XATTR}");
+    for extension in extensions {
+        let extension = extension?;
+
+        if extension.key_bytes() == key.as_bytes() {
+            return Ok(Some(extension.value_bytes().to_vec()));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+fn extract_layer<R>(
+    reader: R,
+    dir: &Path,
+    rootless: bool,
+    paths: &[PathBuf],
+) -> Result<(), OciBootstrapError>
+where
+    R: io::Read,
+{
+    unpack_layer(
+        reader,
+        dir,
+        rootless,
+        false,
+        paths,
+        IdMappings::default(),
+        &mut ExtractionBudget::default(),
+    )?;
+
+    Ok(())
+}
+
+/// Extracts a layer, skipping the files that are already in the directory and look unchanged, and
+/// returns the number of files skipped
+///
+/// Whiteouts and opaque directories are still processed.
+#[cfg(test)]
+fn extract_layer_incremental<R>(
+    reader: R,
+    dir: &Path,
+    rootless: bool,
+    paths: &[PathBuf],
+) -> Result<usize, OciBootstrapError>
+where
+    R: io::Read,
+{
+    unpack_layer(
+        reader,
+        dir,
+        rootless,
+        true,
+        paths,
+        IdMappings::default(),
+        &mut ExtractionBudget::default(),
+    )
+}
+
+/// Extracts a layer whose regular files are already extracted in another directory
+///
+/// These files are copied from that directory, and their content in the archive is skipped over
+/// without being read. The archive is still used for everything else.
+#[expect(clippy::too_many_arguments)]
+fn extract_layer_from_dir<R>(
+    reader: R,
+    files: &Path,
+    dir: &Path,
+    rootless: bool,
+    skip_unchanged: bool,
+    paths: &[PathBuf],
+    ids: IdMappings<'_>,
+    budget: &mut ExtractionBudget,
+) -> Result<usize, OciBootstrapError>
+where
+    R: io::Read + io::Seek,
+{
+    let mut archive = Archive::new(reader);
+    if rootless {
+        archive.set_preserve_ownerships(false);
+    }
+
+    unpack_entries(
+        archive.entries_with_seek()?,
+        dir,
+        rootless,
+        skip_unchanged,
+        paths,
+        Some(files),
+        ids,
+        budget,
+    )
+}
+
+/// Copies a regular file from the directory the layer is already extracted to, and returns
+/// whether the entry was handled
+///
+/// The entries that need more than their content, mode and modification time need to be
+/// unpacked from the archive.
+fn copy_extracted_file<R>(
+    files: &Path,
+    dir: &Path,
+    entry_path: &Path,
+    entry: &mut tar::Entry<'_, R>,
+    rootless: bool,
+) -> Result<bool, io::Error>
+where
+    R: io::Read,
+{
+    if !matches!(
+        entry.header().entry_type(),
+        EntryType::Regular | EntryType::Continuous
+    ) || !entry_path
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)))
+    {
+        return Ok(false);
+    }
+
+    if !rootless {
+        if let Some(extensions) = entry.pax_extensions()? {
+            for extension in extensions {
+                if extension?.key_bytes().starts_with(b"SCHILY.xattr.") {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    // A symlink of a lower layer could make a parent directory point outside of the root, in
+    // which case tar will check the entry and report the error.
+    let Ok(dest) = path_in_root(dir, entry_path) else {
+        debug!(
+            "File {} isn't within the root directory, unpacking it instead",
+            entry_path.display()
+        );
+
+        return Ok(false);
+    };
+
+    let source = files.join(entry_path);
+
+    trace!("Copying File {} to {}", source.display(), dest.display());
+
+    if dest.symlink_metadata().is_ok() {
+        remove_file_or_dir(&dest)?;
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut output = File::options()
+        .write(true)
+        .create_new(true)
+        .custom_flags(OFlag::O_NOFOLLOW.bits())
+        .open(&dest)?;
+    io::copy(&mut File::open(&source)?, &mut output)?;
+
+    // tar sets the modification time to 1 when it's 0 in the archive
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?.max(1));
+    output.set_times(FileTimes::new().set_accessed(mtime).set_modified(mtime))?;
+
+    let mask = if rootless { SETID_MODE_BITS } else { 0 };
+    output.set_permissions(Permissions::from_mode(
+        entry.header().mode()? & 0o7777 & !mask,
+    ))?;
+
+    Ok(true)
+}
+
+/// Processes an entry if it's a whiteout file or marks an opaque directory, and returns whether
+/// it was one
+fn apply_whiteout(
+    dir: &Path,
+    entry_path: &Path,
+    layer_paths: &HashSet<PathBuf>,
+    paths: &[PathBuf],
+) -> Result<bool, io::Error> {
+    if let Some(file_name) = entry_path.file_name() {
+        if let Some(file_name_str) = file_name.to_str() {
+            if file_name_str == ".wh..wh..opq" {
+                let parent_dir = entry_path.parent().unwrap_or(Path::new(""));
+
+                if !is_path_selected(paths, parent_dir, true) {
+                    trace!(
+                        "Directory {} isn't extracted, skipping",
+                        parent_dir.display()
+                    );
+                    return Ok(true);
+                }
+
+                let actual_dir = join_path(&dir.canonicalize()?, parent_dir)?;
+
+                debug!(
+                    "Directory {} is opaque. Removing lower layers content ({})",
+                    parent_dir.display(),
+                    actual_dir.display()
+                );
+
+                if actual_dir.is_dir() {
+                    remove_lower_layers_entries(dir, parent_dir, layer_paths)?;
+                }
+
+                return Ok(true);
+            }
+
+            if let Some(remove_file_name) = file_name_str.strip_prefix(".wh.") {
+                let parent_dir = entry_path.parent().unwrap_or(Path::new("/"));
+                let remove_path = parent_dir.join(remove_file_name);
+
+                if !is_path_selected(paths, &remove_path, true) {
+                    trace!("File {} isn't extracted, skipping", remove_path.display());
+                    return Ok(true);
+                }
+
+                let actual_file = path_in_root(dir, &remove_path)?;
+
+                debug!(
+                    "File {} is a whiteout file. Removing {} ({})",
+                    entry_path.display(),
+                    remove_path.display(),
+                    actual_file.display()
+                );
+
+                remove_file_or_dir(&actual_file)?;
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Extracts a layer on top of the previous ones
+///
+/// If paths are given, only the entries under them, whiteouts included, are processed.
+fn unpack_layer<R>(
+    reader: R,
+    dir: &Path,
+    rootless: bool,
+    skip_unchanged: bool,
+    paths: &[PathBuf],
+    ids: IdMappings<'_>,
+    budget: &mut ExtractionBudget,
+) -> Result<usize, OciBootstrapError>
+where
+    R: io::Read,
+{
+    let mut archive = Archive::new(reader);
+    if rootless {
+        archive.set_preserve_ownerships(false);
+    }
+
+    unpack_entries(
+        archive.entries()?,
+        dir,
+        rootless,
+        skip_unchanged,
+        paths,
+        None,
+        ids,
+        budget,
+    )
+}
+
+#[expect(clippy::too_many_arguments)]
+fn unpack_entries<R>(
+    entries: tar::Entries<'_, R>,
+    dir: &Path,
+    rootless: bool,
+    skip_unchanged: bool,
+    paths: &[PathBuf],
+    files: Option<&Path>,
+    ids: IdMappings<'_>,
+    budget: &mut ExtractionBudget,
+) -> Result<usize, OciBootstrapError>
+where
+    R: io::Read,
+{
+    let mut skipped = 0;
+
+    // Paths extracted from the current layer, so that opaque directories only remove the content
+    // of the lower layers.
+    let mut layer_paths = HashSet::new();
+
+    for entry in entries {
+        let mut entry = entry?;
+
+        let entry_path =
+            normalize_entry_path(&entry.path().expect("This call can only fail on Windows."));
+
+        if apply_whiteout(dir, &entry_path, &layer_paths, paths)? {
+            continue;
+        }
+
+        if !is_path_selected(
+            paths,
+            &entry_path,
+            entry.header().entry_type() == EntryType::Directory,
+        ) {
+            trace!("File {} isn't extracted, skipping", entry_path.display());
+            continue;
+        }
+
+        if entry.header().entry_type() == EntryType::Link {
+            let link_name = entry
+                .link_name()?
+                .ok_or(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Hardlink without a target",
+                ))?
+                .into_owned();
+
+            debug!(
+                "File {} is a hardlink to {}",
+                entry_path.display(),
+                link_name.display()
+            );
+
+            if !is_path_selected(paths, &normalize_entry_path(&link_name), false) {
+                return Err(OciBootstrapError::Custom(format!(
+                    "Hardlink {} target {} isn't part of the extracted paths",
+                    entry_path.display(),
+                    link_name.display()
+                )));
+            }
+
+            unpack_hardlink(dir, &entry_path, &link_name)?;
+            layer_paths.extend(entry_path.ancestors().map(Path::to_path_buf));
+            continue;
+        }
+
+        if rootless {
+            if skip_rootless_entry(&entry_path, entry.header().entry_type()) {
+                continue;
+            }
+
+            if entry.header().mode()? & SETID_MODE_BITS != 0 {
+                info!(
+                    "Dropping setuid and setgid bits of {} in rootless mode",
+                    entry_path.display()
+                );
+            }
+
+            entry.set_mask(SETID_MODE_BITS);
+        }
+
+        if skip_unchanged && is_entry_unchanged(dir, &entry_path, entry.header()) {
+            trace!("File {} is unchanged, skipping", entry_path.display());
+
+            skipped += 1;
+            layer_paths.extend(entry_path.ancestors().map(Path::to_path_buf));
+            continue;
+        }
+
+        budget.add_entry(&entry_path, entry.size())?;
+
+        let mode_mask = if rootless { SETID_MODE_BITS } else { 0 };
+
+        if let Some(files) = files {
+            if copy_extracted_file(files, dir, &entry_path, &mut entry, rootless)? {
+                ids.chown(dir, &entry_path, entry.header(), mode_mask, None)?;
+                layer_paths.extend(entry_path.ancestors().map(Path::to_path_buf));
+                continue;
+            }
+        }
+
+        debug!("Extracting File {}", entry_path.display());
+
+        // The extended attributes aren't unpacked in rootless mode
+        let capability = if ids.is_empty() || rootless {
+            None
+        } else {
+            entry_capability(&mut entry)?
+        };
+
+        entry.set_preserve_mtime(true);
+        entry.set_preserve_permissions(true);
+        entry.set_unpack_xattrs(!rootless);
+
+        if entry.unpack_in(dir)? {
+            ids.chown(
+                dir,
+                &entry_path,
+                entry.header(),
+                mode_mask,
+                capability.as_deref(),
+            )?;
+        }
+
+        layer_paths.extend(entry_path.ancestors().map(Path::to_path_buf));
+    }
+
+    Ok(skipped)
+}
+
+/// Copies the content of a file to a raw or squashfs partition
+///
+/// The capacity of the partition is the size of its device file, and the content must fit in it.
+///
+/// If the content is smaller than the partition, the space after it is left unchanged, and isn't
+/// zeroed: it would allocate the whole partition in a sparse image file, and the raw and squashfs
+/// contents carry their own size anyway.
+fn write_raw_partition(source: &Path, dest: &Path) -> Result<(), io::Error> {
+    let source_file = File::open(source)?;
+    let source_len = source_file.metadata()?.len();
+
+    let mut dest_file = File::options().write(true).open(dest)?;
+    let capacity = dest_file.seek(io::SeekFrom::End(0))?;
+    dest_file.rewind()?;
+
+    if source_len > capacity {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Partition Content {} ({source_len} bytes) doesn't fit in partition {} ({capacity} bytes)",
+                source.display(),
+                dest.display()
+            ),
+        ));
+    }
+
+    let mut reader = io::BufReader::new(source_file).take(capacity);
+    let mut writer = io::BufWriter::new(dest_file);
+
+    let written = io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+
+    debug!(
+        "Wrote {written} bytes out of {capacity} to {}",
+        dest.display()
+    );
+
+    Ok(())
+}
+
+/// Copies files of the root filesystem into a mounted partition, and returns the paths they have
+/// been copied to
+///
+/// The sources are looked up in the root filesystem only, so that an image can't copy files of
+/// the host.
+fn install_partition_files(
+    root: &Path,
+    mount_dir: &Path,
+    files: &[PartitionFile],
+) -> Result<Vec<PathBuf>, io::Error> {
+    let mut installed = Vec::with_capacity(files.len());
+
+    for file in files {
+        let source = join_path(root, &file.source)?;
+        if !source.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Partition Source File {} Not Found", file.source.display()),
+            ));
+        }
+
+        // The partition might already hold files of the image, so we can't follow a symlink
+        // there either
+        let (dest, mut dest_file) = create_file_in_root(mount_dir, &file.dest, false)?;
+
+        debug!("Copying file {} to {}", source.display(), dest.display());
+
+        let mut source_file = File::open(&source)?;
+        io::copy(&mut source_file, &mut dest_file)?;
+        dest_file.set_permissions(source_file.metadata()?.permissions())?;
+        installed.push(dest);
+    }
+
+    Ok(installed)
+}
+
+/// Copies an EFI binary from the root filesystem to the removable media default boot path of an
+/// EFI System Partition, and returns the path it has been copied to
+fn install_efi_default_boot(
+    root: &Path,
+    esp: &Path,
+    source: &Path,
+    arch: Architecture,
+) -> Result<PathBuf, io::Error> {
+    let file_name = arch.efi_default_boot_file().ok_or(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Architecture {} has no EFI default boot path",
+            arch.as_oci_str()
+        ),
+    ))?;
+
+    let source_path = join_path(root, source)?;
+    if !source_path.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("EFI Boot Source File {} Not Found", source.display()),
+        ));
+    }
+
+    let boot_dir = esp.join("EFI").join("BOOT");
+    fs::create_dir_all(&boot_dir)?;
+
+    let dest = boot_dir.join(file_name);
+
+    debug!(
+        "Installing EFI binary {} to {}",
+        source.display(),
+        dest.display()
+    );
+
+    fs::copy(&source_path, &dest)?;
+
+    Ok(dest)
+}
+
+#[expect(clippy::too_many_arguments)]
+fn write_manifest_to_dir(
+    manifest: &LocalManifest<'_>,
+    dir: &Path,
+    rootless: bool,
+    incremental: bool,
+    check_layers: bool,
+    paths: &[PathBuf],
+    max_extracted_bytes: Option<u64>,
+    id_mappings: IdMappings<'_>,
+) -> Result<(), OciBootstrapError> {
+    fs::create_dir_all(dir)?;
+
+    for path in paths {
+        info!("Only extracting {}", path.display());
+    }
+
+    let mut budget = ExtractionBudget::new(max_extracted_bytes);
+    for layer in manifest.layers()? {
+        info!("Found layer {}, extracting...", layer.digest());
+
+        budget.start_layer(layer.size());
+
+        let ids = id_mappings.for_layer(layer.id_mappings());
+
+        if !check_layers {
+            if let Some((files, reader)) = layer.extracted_archive()? {
+                debug!("Layer is already extracted in {}", files.display());
+
+                let skipped = extract_layer_from_dir(
+                    reader,
+                    &files,
+                    dir,
+                    rootless,
+                    incremental,
+                    paths,
+                    ids,
+                    &mut budget,
+                )?;
+                if incremental {
+                    info!("Done, {skipped} unchanged files skipped");
+                } else {
+                    info!("Done");
+                }
+
+                continue;
+            }
+        }
+
+        let reader = layer.archive()?;
+
+        debug!("Got the archive. Extracting...");
+
+        let skipped = unpack_layer(reader, dir, rootless, incremental, paths, ids, &mut budget)?;
+        if incremental {
+            info!("Done, {skipped} unchanged files skipped");
+        } else {
+            info!("Done");
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes all the layers of an image, merged, to a single archive
+fn export_manifest(
+    manifest: &LocalManifest<'_>,
+    output: &Path,
+    gzip: bool,
+) -> Result<(), OciBootstrapError> {
+    let layers = manifest.layers()?;
+    let file = File::create(output)?;
+
+    if gzip {
+        let encoder = squash_layers(
+            layers.len(),
+            |idx| layers[idx].archive(),
+            GzEncoder::new(file, flate2::Compression::default()),
+        )?;
+
+        encoder.finish()?.sync_all()?;
+    } else {
+        squash_layers(layers.len(), |idx| layers[idx].archive(), file)?.sync_all()?;
+    }
+
+    info!("Image exported to {}", output.display());
+
+    Ok(())
+}
+
+fn open_registry(
+    oci_layout: Option<&Path>,
+    docker_archive: Option<&Path>,
+    storage_root: Option<&Path>,
+) -> Result<LocalRegistry, OciBootstrapError> {
+    if let Some(path) = oci_layout {
+        info!("Using OCI Image Layout {}", path.display());
+        return LocalRegistry::from_oci_layout(path);
+    }
+
+    if let Some(path) = docker_archive {
+        info!("Using Docker Archive {}", path.display());
+        return LocalRegistry::from_docker_archive(path);
+    }
+
+    LocalRegistry::new(storage_root)
+}
+
+fn list_images(registry: &LocalRegistry, format: OutputFormat) -> Result<(), io::Error> {
+    let images = registry.images();
+    let mut stdout = io::stdout().lock();
+
+    if format == OutputFormat::Json {
+        serde_json::to_writer_pretty(&mut stdout, &images)?;
+        return writeln!(stdout);
+    }
+
+    for image in images {
+        let names = if image.names.is_empty() {
+            String::from("<none>")
+        } else {
+            image.names.join(",")
+        };
+
+        let created = image
+            .created
+            .map_or_else(|| String::from("-"), |created| created.to_string());
+
+        writeln!(
+            stdout,
+            "{names}\t{}\t{created}\t{}",
+            image.id,
+            image.platforms.join(",")
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Compares the files of an image against its manifest, and returns the description of the ones
+/// that don't match
+fn verify_image(
+    manifest: &LocalManifest<'_>,
+    image: &Path,
+) -> Result<Vec<String>, OciBootstrapError> {
+    let partition_table = manifest.configuration().try_into()?;
+    let partitions = partition_descriptions(&partition_table);
+
+    let file = File::open(image)?;
+    let loop_control = LoopControl::open()?;
+    let loop_device = LoopDevice::create(&loop_control, file)?;
+
+    // The kernel scans the partitions when the loop device is attached, so they are all already
+    // listed even if their device files don't exist yet. Waiting for a partition that isn't there
+    // would only time out.
+    let found = find_device_parts(&loop_device.path())?.len();
+    if found != partitions.len() {
+        return Err(OciBootstrapError::Custom(format!(
+            "Image has {found} partitions, but its manifest expects {}",
+            partitions.len()
+        )));
+    }
+
+    let device_parts = wait_for_device_parts(&loop_device.path(), partitions.len())?;
+
+    let device = mount_device_partitions(
+        &loop_device.path(),
+        Some(loop_device),
+        &device_parts,
+        &partitions,
+        true,
+    )?;
+
+    let mut expected = ExpectedTree::default();
+    for layer in manifest.layers()? {
+        debug!("Found layer {}, listing...", layer.digest());
+        expected.add_layer(layer.archive()?)?;
+    }
+
+    let discrepancies = expected.verify(device.dir.path());
+    drop(device);
+
+    Ok(discrepancies)
+}
+
+/// Where to look for an image, and how to extract it
+#[derive(Clone, Debug, Default)]
+pub struct ImageOptions {
+    /// OCI Image Layout directory to read the images from, instead of the local storage
+    pub oci_layout: Option<PathBuf>,
+
+    /// docker save archive to read the images from, instead of the local storage
+    pub docker_archive: Option<PathBuf>,
+
+    /// Root directory of the local containers storage, overriding the graphroot of
+    /// `CONTAINERS_STORAGE_CONF`
+    pub storage_root: Option<PathBuf>,
+
+    /// Platform of the image to use. Defaults to the host OS and architecture
+    pub platform: Option<Platform>,
+
+    /// Check the local storage layers files against their checksums, instead of copying them
+    /// directly
+    pub check_layers: bool,
+
+    /// Abort the extraction once the image files take more than this many bytes
+    pub max_extracted_bytes: Option<u64>,
+}
+
+impl ImageOptions {
+    fn registry(&self) -> Result<LocalRegistry, OciBootstrapError> {
+        open_registry(
+            self.oci_layout.as_deref(),
+            self.docker_archive.as_deref(),
+            self.storage_root.as_deref(),
+        )
+    }
+
+    fn platform(&self) -> Result<Platform, OciBootstrapError> {
+        Ok(match self.platform {
+            Some(platform) => platform,
+            None => Platform {
+                os: OperatingSystem::host()?,
+                arch: Architecture::host()?,
+                variant: None,
+            },
+        })
+    }
+}
+
+/// How to create a device from an image
+#[derive(Clone, Debug, Default)]
+#[expect(clippy::struct_excessive_bools)]
+pub struct BuildOptions {
+    /// Where to look for the image, and how to extract it
+    pub image: ImageOptions,
+
+    /// Only compute the partition layout, without modifying the output device file
+    pub dry_run: bool,
+
+    /// Write an /etc/fstab mounting the partitions in the root filesystem
+    pub fstab: bool,
+
+    /// Create the output device file with this size, in bytes, instead of using an existing one
+    pub create_size: Option<u64>,
+
+    /// Allocate all the blocks of the created output device file, instead of leaving it sparse
+    pub preallocate: bool,
+
+    /// Write the image SHA-256 and partitions identifiers to `<OUTPUT>.json`
+    pub sidecar: bool,
+
+    /// Derive the partitions and filesystems identifiers from the image, and their timestamps
+    /// from `SOURCE_DATE_EPOCH`. This makes the partition tables and the freshly created
+    /// filesystems reproducible, but not the files extracted into them.
+    pub reproducible: bool,
+
+    /// Leave the loop device attached and its partitions mounted once done
+    pub keep_mounted: bool,
+
+    /// Allow the output to be a block device, and overwrite its content
+    pub force: bool,
+
+    /// Label the files with the security contexts of this `file_contexts` file, a path in the
+    /// image
+    pub selinux_file_contexts: Option<PathBuf>,
+}
+
+/// What [`build_device`] did
+#[derive(Debug)]
+pub struct BuildResult {
+    /// The partitions created, or that would be in a dry run
+    pub report: Report,
+
+    /// The device and the directory its partitions are still mounted on, if they have been kept
+    /// mounted
+    pub mounted: Option<(PathBuf, PathBuf)>,
+}
+
+/// How to extract an image to a directory
+#[derive(Clone, Debug, Default)]
+pub struct ExtractOptions {
+    /// Where to look for the image, and how to extract it
+    pub image: ImageOptions,
+
+    /// Skip device nodes, setuid and setgid bits, and ownership changes
+    pub rootless: bool,
+
+    /// Write the image environment, entrypoint and command to /.ocibootstrap/config.json
+    pub runtime_config: bool,
+
+    /// Skip the files already in the output directory with the same size and modification time
+    pub incremental: bool,
+
+    /// Only extract the files under these paths in the image
+    pub paths: Vec<PathBuf>,
+
+    /// Hardlink the files with the same content and metadata to a store shared by all the
+    /// extractions using it
+    pub hardlink_store: Option<PathBuf>,
+
+    /// Change the owners of the files to the user IDs the image ones are mapped to. The layers
+    /// stored with their own mapping use it instead
+    pub uid_map: Vec<IdMapping>,
+
+    /// Change the groups of the files to the group IDs the image ones are mapped to. The layers
+    /// stored with their own mapping use it instead
+    pub gid_map: Vec<IdMapping>,
+
+    /// Script of the host to run in the extracted root filesystem, with /proc, /sys and /dev
+    /// mounted
+    pub post_extract: Option<PathBuf>,
+
+    /// Label the files with the security contexts of this `file_contexts` file, a path in the
+    /// image
+    pub selinux_file_contexts: Option<PathBuf>,
+}
+
+/// Finds the image matching a container name, trying each of the search registries for the names
+/// without a domain
+///
+/// # Errors
+///
+/// If the name is invalid, or if no image matches it.
+pub fn find_container(
+    name: &str,
+    options: &ImageOptions,
+) -> Result<ContainerSpec, OciBootstrapError> {
+    let container_specs = ContainerSpec::search_from_container_name(name)?;
+
+    let registry = options.registry()?;
+    let (container_spec, _) = registry
+        .image_by_specs(container_specs)
+        .ok_or(OciBootstrapError::ImageNotFound(String::from(name)))?;
+
+    debug!("Found Image {container_spec} in our local storage");
+
+    Ok(container_spec)
+}
+
+fn image_not_found(container: &ContainerSpec) -> OciBootstrapError {
+    OciBootstrapError::ImageNotFound(container.to_oci_string())
+}
+
+fn manifest_not_found(platform: Platform) -> OciBootstrapError {
+    OciBootstrapError::NoManifestForPlatform {
+        arch: platform.arch,
+        os: platform.os,
+        variant: platform.variant,
+    }
+}
+
+/// Creates the partitions and filesystems described by an image on a device file or block
+/// device, and extracts the image into them
+///
+/// # Errors
+///
+/// If the image or its manifest can't be found, if the output isn't a suitable device, or if
+/// anything fails while creating the partitions or extracting the image.
+#[expect(clippy::too_many_lines)]
+pub fn build_device(
+    container: &ContainerSpec,
+    output: &Path,
+    opts: &BuildOptions,
+) -> Result<BuildResult, OciBootstrapError> {
+    info!(
+        "Using container {container} with output device {}",
+        output.display()
+    );
+
+    let mut block_device = false;
+    if opts.create_size.is_some() {
+        if output.exists() {
+            return Err(OciBootstrapError::Custom(String::from(
+                "Output file already exists.",
+            )));
+        }
+    } else {
+        if !output.exists() {
+            return Err(OciBootstrapError::Custom(String::from(
+                "Output file doesn't exist.",
+            )));
+        }
+
+        let metadata = output.metadata()?;
+        let file_type = metadata.file_type();
+        if file_type.is_block_device() {
+            if !opts.force && !opts.dry_run {
+                return Err(OciBootstrapError::Custom(String::from(
+                    "Output argument is a block device, use --force to overwrite it",
+                )));
+            }
+
+            block_device = true;
+        } else if !file_type.is_file() {
+            return Err(OciBootstrapError::Custom(String::from(
+                "Output argument isn't a file or a block device",
+            )));
+        }
+    }
+
+    let platform = opts.image.platform()?;
+    let registry = opts.image.registry()?;
+    let image = registry
+        .image_by_spec(container)
+        .ok_or_else(|| image_not_found(container))?;
+
+    let manifest = image
+        .manifest_for_platform(platform.arch, platform.variant, platform.os)?
+        .ok_or_else(|| manifest_not_found(platform))?;
+    log_platform(&manifest)?;
+
+    let partition_table = manifest.configuration().try_into()?;
+
+    let device_size = match opts.create_size {
+        Some(size) => size,
+        None => gpt::device_size(&File::open(output)?)?,
+    };
+    check_layout(&partition_table, device_size)?;
+
+    if opts.dry_run {
+        let file = File::open(output)?;
+        let partitions = partition_reports(partition_plan(&file, &partition_table)?, None);
+
+        return Ok(BuildResult {
+            report: report(container, &manifest, output, partitions),
+            mounted: None,
+        });
+    }
+
+    let mut tools = required_tools(
+        partition_descriptions(&partition_table)
+            .iter()
+            .map(|(fs, _, _)| fs),
+    );
+    if block_device {
+        tools.push("blockdev");
+    }
+    if opts.selinux_file_contexts.is_some() {
+        tools.push("setfiles");
+    }
+    if opts.sidecar {
+        tools.push("blkid");
+    }
+    check_device_requirements(&tools)?;
+
+    let file = if let Some(size) = opts.create_size {
+        create_output_file(output, size, opts.preallocate, &partition_table)?
+    } else {
+        File::options().read(true).write(true).open(output)?
+    };
+
+    let plan = partition_plan(&file, &partition_table)?;
+
+    let reproducible = opts
+        .reproducible
+        .then(|| {
+            Reproducible::from_configuration(manifest.config_digest(), manifest.configuration())
+        })
+        .transpose()?;
+
+    let (device, part_uuids) = if block_device {
+        create_and_mount_block_device(file, output, &partition_table, reproducible.as_ref())?
+    } else {
+        create_and_mount_loop_device(file, &partition_table, reproducible.as_ref())?
+    };
+    write_manifest_to_dir(
+        &manifest,
+        device.dir.path(),
+        false,
+        false,
+        opts.image.check_layers,
+        &[],
+        opts.image.max_extracted_bytes,
+        IdMappings::default(),
+    )?;
+
+    if opts.fstab {
+        let content = fstab(
+            &partition_descriptions(&partition_table),
+            &part_uuids,
+            &partition_grow(&partition_table),
+        );
+        write_fstab(device.dir.path(), &content)?;
+    }
+
+    for part in &device.parts {
+        if let Some(content) = part.fs.content() {
+            let source = join_path(device.dir.path(), content)?;
+
+            if !source.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Partition Source File {} Not Found", content.display()),
+                )
+                .into());
+            }
+
+            debug!(
+                "Writing content of file {} to {}",
+                content.display(),
+                part.dev.display()
+            );
+
+            write_raw_partition(&source, &part.dev)?;
+        }
+
+        if let Filesystem::Fat32(FatParameters {
+            efi_boot: Some(source),
+            ..
+        }) = &part.fs
+        {
+            let esp = part.host_mnt.as_ref().ok_or(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Partition {} needs a mount point to install an EFI binary",
+                    part.dev.display()
+                ),
+            ))?;
+
+            install_efi_default_boot(device.dir.path(), esp.target_path(), source, platform.arch)?;
+        }
+    }
+
+    for (mnt, files) in partition_files(&partition_table) {
+        if files.is_empty() {
+            continue;
+        }
+
+        let mnt = mnt.ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Partitions need a mount point to copy files into",
+        ))?;
+
+        install_partition_files(
+            device.dir.path(),
+            &join_path(device.dir.path(), mnt)?,
+            files,
+        )?;
+    }
+
+    install_firmware(
+        device.dir.path(),
+        &partition_table,
+        &mut File::options().read(true).write(true).open(output)?,
+        reproducible.as_ref(),
+    )?;
+
+    if let Some(file_contexts) = &opts.selinux_file_contexts {
+        relabel(device.dir.path(), file_contexts)?;
+    }
+
+    let sidecar_partitions = opts
+        .sidecar
+        .then(|| sidecar_partitions(&device, &partition_table, &part_uuids))
+        .transpose()?;
+
+    let mounted = if opts.keep_mounted {
+        Some(device.keep())
+    } else {
+        drop(device);
+        None
+    };
+
+    if let Some(partitions) = sidecar_partitions {
+        let path =
+            ImageSidecar::new(output, partition_table.to_string(), partitions)?.write(output)?;
+
+        info!("Image sidecar written to {}", path.display());
+    }
+
+    let partitions = partition_reports(plan, Some(&part_uuids));
+
+    Ok(BuildResult {
+        report: report(container, &manifest, output, partitions),
+        mounted,
+    })
+}
+
+/// Extracts an image to a directory, created if needed
+///
+/// # Errors
+///
+/// If the image or its manifest can't be found, if the output isn't a directory, or if anything
+/// fails while extracting the image.
+pub fn extract_to_dir(
+    container: &ContainerSpec,
+    output: &Path,
+    opts: &ExtractOptions,
+) -> Result<Report, OciBootstrapError> {
+    info!(
+        "Using container {container} with output directory {}",
+        output.display()
+    );
+
+    if !output.exists() {
+        debug!("Output directory doesn't exist, creating.");
+        fs::create_dir_all(output)?;
+    }
+
+    if !output.is_dir() {
+        return Err(OciBootstrapError::Custom(String::from(
+            "Output isn't a directory",
+        )));
+    }
+
+    let mut tools = Vec::new();
+    // The script runs through chroot only if systemd-nspawn isn't there
+    if opts.post_extract.is_some() && !is_in_path("systemd-nspawn") {
+        tools.push("chroot");
+    }
+    if opts.selinux_file_contexts.is_some() {
+        tools.push("setfiles");
+    }
+    check_dir_requirements(&tools)?;
+
+    let platform = opts.image.platform()?;
+    let registry = opts.image.registry()?;
+    let image = registry
+        .image_by_spec(container)
+        .ok_or_else(|| image_not_found(container))?;
+
+    let manifest = image
+        .manifest_for_platform(platform.arch, platform.variant, platform.os)?
+        .ok_or_else(|| manifest_not_found(platform))?;
+    log_platform(&manifest)?;
+
+    let paths = opts
+        .paths
+        .iter()
+        .map(|path| image_relative_path(path))
+        .collect::<Vec<_>>();
+
+    write_manifest_to_dir(
+        &manifest,
+        output,
+        opts.rootless,
+        opts.incremental,
+        opts.image.check_layers,
+        &paths,
+        opts.image.max_extracted_bytes,
+        IdMappings {
+            uid: &opts.uid_map,
+            gid: &opts.gid_map,
+        },
+    )?;
+
+    if let Some(script) = &opts.post_extract {
+        run_post_extract(output, script)?;
+    }
+
+    if let Some(file_contexts) = &opts.selinux_file_contexts {
+        relabel(output, file_contexts)?;
+    }
+
+    if let Some(store) = &opts.hardlink_store {
+        ContentStore::new(store)?.link_tree(output)?;
+    }
+
+    if opts.runtime_config {
+        RuntimeConfig::from(manifest.configuration()).write(output)?;
+    }
+
+    Ok(report(container, &manifest, output, Vec::new()))
+}
+
+/// Squashes the layers of an image into a single tar archive, optionally compressed with gzip
+///
+/// # Errors
+///
+/// If the image or its manifest can't be found, or if the archive can't be written.
+pub fn export_to_archive(
+    container: &ContainerSpec,
+    output: &Path,
+    gzip: bool,
+    opts: &ImageOptions,
+) -> Result<(), OciBootstrapError> {
+    info!("Exporting container {container} to {}", output.display());
+
+    let platform = opts.platform()?;
+    let registry = opts.registry()?;
+    let image = registry
+        .image_by_spec(container)
+        .ok_or_else(|| image_not_found(container))?;
+
+    let manifest = image
+        .manifest_for_platform(platform.arch, platform.variant, platform.os)?
+        .ok_or_else(|| manifest_not_found(platform))?;
+    log_platform(&manifest)?;
+
+    export_manifest(&manifest, output, gzip)
+}
+
+/// Compares the files of a device image against the manifest of a container, and returns the
+/// description of the files that don't match
+///
+/// # Errors
+///
+/// If the container or its manifest can't be found, or if the partitions of the device image
+/// can't be mounted.
+pub fn verify_device(
+    container: &ContainerSpec,
+    image: &Path,
+    opts: &ImageOptions,
+) -> Result<Vec<String>, OciBootstrapError> {
+    info!(
+        "Verifying image {} against container {container}",
+        image.display(),
+    );
+
+    if !image.is_file() {
+        return Err(OciBootstrapError::Custom(String::from(
+            "Image argument isn't a file",
+        )));
+    }
+
+    let platform = opts.platform()?;
+    let registry = opts.registry()?;
+    let oci_image = registry
+        .image_by_spec(container)
+        .ok_or_else(|| image_not_found(container))?;
+
+    let manifest = oci_image
+        .manifest_for_platform(platform.arch, platform.variant, platform.os)?
+        .ok_or_else(|| manifest_not_found(platform))?;
+
+    verify_image(&manifest, image)
+}
+
+/// Prints the images available in the registry
+///
+/// # Errors
+///
+/// If the registry can't be opened, or if the standard output can't be written to.
+pub fn print_images(opts: &ImageOptions, format: OutputFormat) -> Result<(), OciBootstrapError> {
+    let registry = opts.registry()?;
+
+    Ok(list_images(&registry, format)?)
+}
+
+#[cfg(test)]
+mod raw_partition_test {
+    use std::{fs, process::Command};
+
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::write_raw_partition;
+
+    const PARTITION_SIZE: usize = 4096;
+
+    fn setup(content_len: usize) -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        fs::write(dir.path().join("content.bin"), vec![0xaa; content_len]).unwrap();
+        fs::write(dir.path().join("partition"), vec![0x55; PARTITION_SIZE]).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_raw_content_fits() {
+        let dir = setup(1024);
+
+        write_raw_partition(
+            &dir.path().join("content.bin"),
+            &dir.path().join("partition"),
+        )
+        .unwrap();
+
+        let partition = fs::read(dir.path().join("partition")).unwrap();
+        assert_eq!(partition.len(), PARTITION_SIZE);
+
+        let (content, remaining) = partition.split_at(1024);
+        assert!(content.iter().all(|b| *b == 0xaa));
+        assert!(remaining.iter().all(|b| *b == 0x55));
+    }
+
+    #[test]
+    fn test_raw_content_oversized() {
+        let dir = setup(PARTITION_SIZE + 1);
+
+        let err = write_raw_partition(
+            &dir.path().join("content.bin"),
+            &dir.path().join("partition"),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        let partition = fs::read(dir.path().join("partition")).unwrap();
+        assert!(partition.iter().all(|b| *b == 0x55));
+    }
+
+    #[test]
+    #[ignore = "requires mksquashfs"]
+    fn test_squashfs_content() {
+        let dir = TempDir::new().unwrap();
+
+        let rootfs = dir.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("etc/hostname"), "ocibootstrap").unwrap();
+
+        let image = dir.path().join("rootfs.squashfs");
+        let status = Command::new("mksquashfs")
+            .arg(&rootfs)
+            .arg(&image)
+            .args(["-quiet", "-noappend"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let image_content = fs::read(&image).unwrap();
+        let partition_size = image_content.len().next_multiple_of(PARTITION_SIZE) + PARTITION_SIZE;
+        fs::write(dir.path().join("partition"), vec![0x55; partition_size]).unwrap();
+
+        write_raw_partition(&image, &dir.path().join("partition")).unwrap();
+
+        let partition = fs::read(dir.path().join("partition")).unwrap();
+        assert_eq!(partition.len(), partition_size);
+
+        let (content, remaining) = partition.split_at(image_content.len());
+        assert!(content.starts_with(b"hsqs"));
+        assert_eq!(content, image_content.as_slice());
+        assert!(remaining.iter().all(|b| *b == 0x55));
+    }
+}
+
+#[cfg(test)]
+mod chroot_test {
+    use std::{
+        fs::{self, File},
+        os,
+        path::PathBuf,
+    };
+
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::join_path;
+
+    const ROOT_CANARY_DIR: &str = "canary";
+
+    const ROOT_TEST_DIR: &str = "test";
+    const TEST_CANARY_SUBDIR: &str = "canary";
+    const TEST_TEST_SUBDIR: &str = "testdir";
+
+    fn create_directories() -> TempDir {
+        let root_dir = TempDir::new().unwrap();
+        let root = root_dir.path();
+
+        fs::create_dir(root.join(ROOT_CANARY_DIR)).unwrap();
+        let canary = root.join(ROOT_CANARY_DIR);
+        File::create(canary.join("canary-test-file.txt")).unwrap();
+
+        fs::create_dir(root.join(ROOT_TEST_DIR)).unwrap();
+        let test = root_dir.path().join(ROOT_TEST_DIR);
+
+        File::create(root.join("root-test-file.txt")).unwrap();
+
+        os::unix::fs::symlink(root.join(ROOT_CANARY_DIR), test.join(TEST_CANARY_SUBDIR)).unwrap();
+        File::create(test.join("test.txt")).unwrap();
+        fs::create_dir(test.join(TEST_TEST_SUBDIR)).unwrap();
+
+        let test_dir = test.join(TEST_TEST_SUBDIR);
+        File::create(test_dir.join("test.txt")).unwrap();
+
+        root_dir
+    }
+
+    #[test]
+    fn test_absolute_file() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join(ROOT_TEST_DIR);
+
+        assert_eq!(
+            join_path(&root, &PathBuf::from("/test.txt")).unwrap(),
+            root.join("test.txt")
+        );
+    }
+
+    #[test]
+    fn test_absolute_file_missing() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        assert_eq!(
+            join_path(&root, &PathBuf::from("/not-there.txt")).unwrap(),
+            root.join("not-there.txt")
+        );
+    }
+
+    #[test]
+    fn test_absolute_file_dir_missing() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        assert_eq!(
+            join_path(&root, &PathBuf::from("/invalid/not-there.txt")).unwrap(),
+            root.join("invalid/not-there.txt")
+        );
+    }
+
+    #[test]
+    fn test_absolute_file_outside_missing() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        join_path(&root, &PathBuf::from("/testdir/../../../not-there.txt")).unwrap_err();
+    }
+
+    #[test]
+    fn test_absolute_file_outside_symlink() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        join_path(&root, &PathBuf::from("/canary/not-there.txt")).unwrap_err();
+    }
+
+    #[test]
+    fn test_relative_file() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        assert_eq!(
+            join_path(&root, &PathBuf::from("test.txt")).unwrap(),
+            root.join("test.txt")
+        );
+    }
+
+    #[test]
+    fn test_relative_dir_file() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        assert_eq!(
+            join_path(&root, &PathBuf::from("testdir/test.txt")).unwrap(),
+            root.join("testdir/test.txt")
+        );
+    }
+
+    #[test]
+    fn test_relative_file_outside() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        join_path(&root, &PathBuf::from("../root-test-file.txt")).unwrap_err();
+    }
+
+    #[test]
+    fn test_relative_file_symlink() {
+        let root_dir = create_directories();
+        let root = root_dir.path().join("test");
+
+        join_path(&root, &PathBuf::from("canary/canary-test-file.txt")).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod lsblk_tests {
+    use std::path::{Path, PathBuf};
+
+    use test_log::test;
+
+    use crate::{match_device_parts, parse_lsblk_partitions, parse_lsblk_parts};
+
+    const LOOP_DEVICE: &str = "/dev/loop42";
+
+    #[test]
+    fn test_lsblk_parts() {
+        let output = serde_json::json!({
+            "blockdevices": [{
+                "path": LOOP_DEVICE,
+                "children": [
+                    { "path": "/dev/loop42p1" },
+                    { "path": "/dev/loop42p2" },
+                ],
+            }],
+        });
+
+        assert_eq!(
+            parse_lsblk_parts(Path::new(LOOP_DEVICE), output.to_string().as_bytes()).unwrap(),
+            vec![
+                PathBuf::from("/dev/loop42p1"),
+                PathBuf::from("/dev/loop42p2")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lsblk_parts_numeric_order() {
+        // lsblk sorts the partitions by name, and only recent versions report their number
+        let mut children = (1..=12)
+            .map(|idx| format!("/dev/loop42p{idx}"))
+            .collect::<Vec<_>>();
+        children.sort();
+
+        let output = serde_json::json!({
+            "blockdevices": [{
+                "path": LOOP_DEVICE,
+                "children": children
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, path)| if idx % 2 == 0 {
+                        serde_json::json!({ "path": path })
+                    } else {
+                        let number: u32 = path.rsplit_once('p').unwrap().1.parse().unwrap();
+                        serde_json::json!({ "path": path, "partn": number })
+                    })
+                    .collect::<Vec<_>>(),
+            }],
+        });
+
+        assert_eq!(
+            parse_lsblk_parts(Path::new(LOOP_DEVICE), output.to_string().as_bytes()).unwrap(),
+            (1..=12)
+                .map(|idx| PathBuf::from(format!("/dev/loop42p{idx}")))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_lsblk_match_partitions() {
+        // The device enumerates the partitions in a different order than the layout
+        let output = serde_json::json!({
+            "blockdevices": [{
+                "path": LOOP_DEVICE,
+                "children": [
+                    {
+                        "path": "/dev/loop42p1",
+                        "partlabel": "rootfs",
+                        "partuuid": "0fc63daf-8483-4772-8e79-3d69d8477de4",
+                    },
+                    {
+                        "path": "/dev/loop42p2",
+                        "partlabel": "esp",
+                        "partuuid": null,
+                    },
+                    {
+                        "path": "/dev/loop42p3",
+                        "partlabel": "data",
+                        "partuuid": "3b8f8425-20e0-4f3b-907f-1a25a76f98e8",
+                    },
+                ],
+            }],
+        });
+
+        let parts =
+            parse_lsblk_partitions(Path::new(LOOP_DEVICE), output.to_string().as_bytes()).unwrap();
+        assert_eq!(parts[1].partlabel.as_deref(), Some("esp"));
+
+        assert_eq!(
+            match_device_parts(
+                &parts,
+                &[
+                    String::from("c12a7328-f81f-11d2-ba4b-00a0c93ec93b"),
+                    String::from("0FC63DAF-8483-4772-8E79-3D69D8477DE4"),
+                    String::from("3b8f8425-20e0-4f3b-907f-1a25a76f98e8"),
+                ],
+                &[Some(String::from("esp")), Some(String::from("data")), None,],
+            )
+            .unwrap(),
+            vec![
+                PathBuf::from("/dev/loop42p2"),
+                PathBuf::from("/dev/loop42p1"),
+                PathBuf::from("/dev/loop42p3"),
+            ]
+        );
+
+        let err = match_device_parts(
+            &parts,
+            &[String::from("c12a7328-f81f-11d2-ba4b-00a0c93ec93b")],
+            &[Some(String::from("boot"))],
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("c12a7328-f81f-11d2-ba4b-00a0c93ec93b"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_lsblk_no_device() {
+        let output = serde_json::json!({ "blockdevices": [] });
+
+        let err =
+            parse_lsblk_parts(Path::new(LOOP_DEVICE), output.to_string().as_bytes()).unwrap_err();
+        assert!(err.to_string().contains(LOOP_DEVICE), "{err}");
+    }
+
+    #[test]
+    fn test_lsblk_no_partitions() {
+        let output = serde_json::json!({ "blockdevices": [{ "path": LOOP_DEVICE }] });
+
+        let err =
+            parse_lsblk_parts(Path::new(LOOP_DEVICE), output.to_string().as_bytes()).unwrap_err();
+        assert!(err.to_string().contains(LOOP_DEVICE), "{err}");
+    }
+}
+
+#[cfg(test)]
+mod mount_test {
+    use std::{
+        collections::HashSet,
+        fs::{self, File},
+        path::{Path, PathBuf},
+        process::Command,
+        thread,
+    };
+
+    use loopdev::LoopControl;
+    use tempfile::{NamedTempFile, TempDir};
+    use test_log::test;
+
+    use gpt::{
+        GuidPartitionBuilder, GuidPartitionTableBuilder, PartitionBuilder as _,
+        EFI_SYSTEM_PART_GUID, LINUX_DATA_PART_GUID,
+    };
+
+    use crate::{
+        create_and_mount_block_device, install_partition_files,
+        layout::{ExtParameters, FatParameters, Filesystem, PartitionFile, PartitionTable},
+        wait_for_device_parts, Device, DevicePartition, LoopDevice,
+    };
+
+    #[test]
+    #[ignore = "requires root privileges and loop devices"]
+    fn test_mount_read_only() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(16 << 20).unwrap();
+
+        let status = Command::new("mkfs.ext4")
+            .args(["-q", "-F"])
+            .arg(image.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let loop_control = LoopControl::open().unwrap();
+        let loop_device = LoopDevice::create(&loop_control, image.reopen().unwrap()).unwrap();
+
+        let mnt = TempDir::new().unwrap();
+        let _part = DevicePartition::new(
+            &loop_device.path(),
+            Filesystem::Ext4(ExtParameters::default()),
+            Some(mnt.path()),
+            Some("ro"),
+        )
+        .unwrap();
+
+        File::create(mnt.path().join("test-file.txt")).unwrap_err();
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and loop devices"]
+    fn test_keep_mounted() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(16 << 20).unwrap();
+
+        let status = Command::new("mkfs.ext4")
+            .args(["-q", "-F"])
+            .arg(image.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let loop_control = LoopControl::open().unwrap();
+        let loop_device = LoopDevice::create(&loop_control, image.reopen().unwrap()).unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let part = DevicePartition::new(
+            &loop_device.path(),
+            Filesystem::Ext4(ExtParameters::default()),
+            Some(dir.path()),
+            None,
+        )
+        .unwrap();
+
+        let device = Device {
+            parts: vec![part],
+            dir,
+            path: loop_device.path(),
+            loopdev: Some(loop_device),
+        };
+
+        let (loop_path, dir) = device.keep();
+
+        let mounts = fs::read_to_string("/proc/self/mounts").unwrap();
+        assert!(
+            mounts.lines().any(|line| line
+                .split(' ')
+                .nth(1)
+                .is_some_and(|target| Path::new(target) == dir)),
+            "{mounts}"
+        );
+        assert!(Path::new("/sys/block")
+            .join(loop_path.file_name().unwrap())
+            .join("loop/backing_file")
+            .exists());
+
+        assert!(Command::new("umount").arg(&dir).status().unwrap().success());
+        loopdev::LoopDevice::open(&loop_path)
+            .unwrap()
+            .detach()
+            .unwrap();
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and loop devices"]
+    fn test_partition_files_fat() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 << 20).unwrap();
+
+        let status = Command::new("mkfs.vfat")
+            .arg(image.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let root = TempDir::new().unwrap();
+        fs::write(root.path().join("config.txt"), "arm_64bit=1").unwrap();
+
+        let fat = Filesystem::Fat32(FatParameters {
+            volume_id: None,
+            label: None,
+            fat_bits: None,
+            heads: None,
+            sectors_per_track: None,
+            efi_boot: None,
+        });
+
+        let loop_control = LoopControl::open().unwrap();
+        let loop_device = LoopDevice::create(&loop_control, image.reopen().unwrap()).unwrap();
+
+        let mnt = TempDir::new().unwrap();
+        let part =
+            DevicePartition::new(&loop_device.path(), fat.clone(), Some(mnt.path()), None).unwrap();
+
+        install_partition_files(
+            &root.path().canonicalize().unwrap(),
+            mnt.path(),
+            &[PartitionFile {
+                source: PathBuf::from("/config.txt"),
+                dest: PathBuf::from("/firmware/config.txt"),
+            }],
+        )
+        .unwrap();
+        drop(part);
+
+        assert!(!mnt.path().join("firmware/config.txt").exists());
+
+        let _part =
+            DevicePartition::new(&loop_device.path(), fat, Some(mnt.path()), Some("ro")).unwrap();
+        assert_eq!(
+            fs::read_to_string(mnt.path().join("firmware/config.txt")).unwrap(),
+            "arm_64bit=1"
+        );
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and loop devices"]
+    fn test_block_device_output() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 << 20).unwrap();
+
+        // The loop device stands in for a real block device
+        let loop_control = LoopControl::open().unwrap();
+        let target = LoopDevice::create(&loop_control, image.reopen().unwrap()).unwrap();
+        let target_path = target.path();
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(&target_path)
+            .unwrap();
+        assert_eq!(gpt::device_size(&file).unwrap(), 64 << 20);
+
+        let config = serde_json::from_value(serde_json::json!({
+            "architecture": "arm64",
+            "os": "linux",
+            "config": {
+                "Labels": {
+                    "com.github.mripard.ocibootstrap.table.type": "gpt",
+                    "com.github.mripard.ocibootstrap.table.partitions": "[\"boot\", \"root\"]",
+                    "com.github.mripard.ocibootstrap.partition.boot.partition_uuid":
+                        "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+                    "com.github.mripard.ocibootstrap.partition.boot.fs": "fat",
+                    "com.github.mripard.ocibootstrap.partition.boot.size_mb": "16",
+                    "com.github.mripard.ocibootstrap.partition.boot.mount_point": "/boot",
+                    "com.github.mripard.ocibootstrap.partition.root.partition_uuid":
+                        "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                    "com.github.mripard.ocibootstrap.partition.root.fs": "ext4",
+                    "com.github.mripard.ocibootstrap.partition.root.mount_point": "/",
+                },
+            },
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        }))
+        .unwrap();
+        let table = PartitionTable::try_from(&config).unwrap();
+
+        let (device, part_uuids) =
+            create_and_mount_block_device(file, &target_path, &table, None).unwrap();
+        assert!(device.loopdev.is_none());
+        assert_eq!(device.path, target_path);
+        assert_eq!(part_uuids.len(), 2);
+        assert!(device.dir.path().join("boot").is_dir());
+        assert_eq!(
+            device
+                .parts
+                .iter()
+                .filter(|part| part.host_mnt.is_some())
+                .count(),
+            2
+        );
+
+        // The partitions are backed by the target, not by another loop device
+        let prefix = target_path.to_string_lossy();
+        assert!(device
+            .parts
+            .iter()
+            .all(|part| part.dev.to_string_lossy().starts_with(&*prefix)));
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and loop devices"]
+    fn test_wait_for_partitions() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(64 << 20).unwrap();
+
+        GuidPartitionTableBuilder::new()
+            .add_partition(
+                GuidPartitionBuilder::new(EFI_SYSTEM_PART_GUID)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .add_partition(
+                GuidPartitionBuilder::new(LINUX_DATA_PART_GUID)
+                    .size(16 << 20)
+                    .build(),
+            )
+            .add_partition(GuidPartitionBuilder::new(LINUX_DATA_PART_GUID).build())
+            .build()
+            .write(image.as_file())
+            .unwrap();
+
+        let loop_control = LoopControl::open().unwrap();
+        let loop_device = LoopDevice::create(&loop_control, image.reopen().unwrap()).unwrap();
+
+        let parts = wait_for_device_parts(&loop_device.path(), 3).unwrap();
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| part.exists()));
+    }
+
+    #[test]
+    #[ignore = "requires root privileges and loop devices"]
+    fn test_concurrent_loop_devices() {
+        let threads = (0..16)
+            .map(|_| {
+                thread::spawn(|| {
+                    let image = NamedTempFile::new().unwrap();
+                    image.as_file().set_len(16 << 20).unwrap();
+
+                    let loop_control = LoopControl::open().unwrap();
+                    let loop_device =
+                        LoopDevice::create(&loop_control, image.reopen().unwrap()).unwrap();
+
+                    (image, loop_device)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let devices = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+
+        let paths = devices
+            .iter()
+            .map(|(_, loop_device)| loop_device.path())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(paths.len(), devices.len());
+    }
+}
+
+#[cfg(test)]
+mod mkfs_test {
+    use std::{fs, os::unix::fs::MetadataExt as _, path::Path, process::Command};
+
+    use loopdev::LoopControl;
+    use sha2::{Digest as _, Sha256};
+    use tempfile::{NamedTempFile, TempDir};
+    use test_log::test;
+
+    use crate::{
+        create_btrfs, create_ext4,
+        layout::{BtrfsParameters, BtrfsSubvolume, ExtParameters},
+        reproducible::Reproducible,
+        LoopDevice,
+    };
+
+    /// Inode number of the root directory of all btrfs subvolumes
+    const BTRFS_SUBVOLUME_INO: u64 = 256;
+
+    fn blkid_tag(path: &Path, tag: &str) -> String {
+        let output = Command::new("blkid")
+            .args(["-o", "value", "-s", tag])
+            .arg(path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    #[test]
+    #[ignore = "requires mkfs.ext4 and blkid"]
+    fn test_create_ext4() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(32 << 20).unwrap();
+
+        create_ext4(
+            image.path(),
+            &ExtParameters {
+                label: Some(String::from("rootfs")),
+                block_size: Some(1024),
+                reserved_percent: Some(0),
+                ..ExtParameters::default()
+            },
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(blkid_tag(image.path(), "LABEL"), "rootfs");
+        assert_eq!(blkid_tag(image.path(), "BLOCK_SIZE"), "1024");
+    }
+
+    #[test]
+    #[ignore = "requires mkfs.ext4 and blkid"]
+    fn test_create_ext4_reproducible() {
+        let reproducible = Reproducible::new(b"config", 1_700_000_000);
+        let ids = reproducible.filesystem(1);
+
+        let images = [(); 2].map(|()| {
+            let image = NamedTempFile::new().unwrap();
+            image.as_file().set_len(32 << 20).unwrap();
+
+            create_ext4(image.path(), &ExtParameters::default(), Some(&ids)).unwrap();
+
+            image
+        });
+
+        assert_eq!(blkid_tag(images[0].path(), "UUID"), ids.uuid.to_string());
+        assert_eq!(
+            Sha256::digest(fs::read(images[0].path()).unwrap()),
+            Sha256::digest(fs::read(images[1].path()).unwrap())
+        );
+    }
+
+    #[test]
+    #[ignore = "requires root privileges, loop devices and btrfs-progs"]
+    fn test_create_btrfs_subvolumes() {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(256 << 20).unwrap();
+
+        let loop_control = LoopControl::open().unwrap();
+        let loop_device = LoopDevice::create(&loop_control, image.reopen().unwrap()).unwrap();
+
+        create_btrfs(
+            &loop_device.path(),
+            &BtrfsParameters {
+                label: Some(String::from("rootfs")),
+                uuid: None,
+                subvolumes: vec![
+                    BtrfsSubvolume {
+                        name: String::from("@"),
+                        mnt: Some("/".into()),
+                    },
+                    BtrfsSubvolume {
+                        name: String::from("@home"),
+                        mnt: Some("/home".into()),
+                    },
+                ],
+            },
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(blkid_tag(&loop_device.path(), "LABEL"), "rootfs");
+        assert_eq!(blkid_tag(&loop_device.path(), "TYPE"), "btrfs");
+
+        let mnt = TempDir::new().unwrap();
+        let status = Command::new("mount")
+            .args(["-t", "btrfs"])
+            .arg(loop_device.path())
+            .arg(mnt.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let inos = ["@", "@home"].map(|name| mnt.path().join(name).metadata().map(|m| m.ino()));
+
+        let status = Command::new("umount").arg(mnt.path()).status().unwrap();
+        assert!(status.success());
+
+        for ino in inos {
+            assert_eq!(ino.unwrap(), BTRFS_SUBVOLUME_INO);
+        }
+
+        let status = Command::new("mount")
+            .args(["-t", "btrfs", "-o", "subvol=@home"])
+            .arg(loop_device.path())
+            .arg(mnt.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let ino = mnt.path().metadata().map(|m| m.ino());
+
+        let status = Command::new("umount").arg(mnt.path()).status().unwrap();
+        assert!(status.success());
+
+        assert_eq!(ino.unwrap(), BTRFS_SUBVOLUME_INO);
+    }
+}
+
+#[cfg(test)]
+mod fstab_test {
+    use std::{fs, os::unix::fs as unix_fs};
+
+    use oci_spec::image::ImageConfiguration;
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::{
+        fstab, layout::PartitionTable, partition_descriptions, partition_grow, write_fstab,
+    };
+
+    fn partition_table() -> PartitionTable {
+        partition_table_with(&[], &[])
+    }
+
+    /// Returns the test partition table, with some labels removed and others added
+    fn partition_table_with(removed: &[&str], added: &[(&str, &str)]) -> PartitionTable {
+        let mut config = serde_json::json!({
+            "architecture": "arm64",
+            "os": "linux",
+            "config": {
+                "Labels": {
+                    "com.github.mripard.ocibootstrap.table.type": "gpt",
+                    "com.github.mripard.ocibootstrap.table.partitions":
+                        "[\"boot\", \"swap\", \"root\", \"firmware\"]",
+                    "com.github.mripard.ocibootstrap.partition.boot.partition_uuid":
+                        "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+                    "com.github.mripard.ocibootstrap.partition.boot.fs": "fat",
+                    "com.github.mripard.ocibootstrap.partition.boot.size_mb": "16",
+                    "com.github.mripard.ocibootstrap.partition.boot.mount_point": "/boot",
+                    "com.github.mripard.ocibootstrap.partition.swap.partition_uuid":
+                        "0657fd6d-a4ab-43c4-84e5-0933c84b4f4f",
+                    "com.github.mripard.ocibootstrap.partition.swap.fs": "swap",
+                    "com.github.mripard.ocibootstrap.partition.swap.size_mb": "16",
+                    "com.github.mripard.ocibootstrap.partition.root.partition_uuid":
+                        "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                    "com.github.mripard.ocibootstrap.partition.root.fs": "btrfs",
+                    "com.github.mripard.ocibootstrap.partition.root.size_mb": "64",
+                    "com.github.mripard.ocibootstrap.partition.root.mount_point": "/",
+                    "com.github.mripard.ocibootstrap.partition.root.mount_options": "noatime",
+                    "com.github.mripard.ocibootstrap.partition.root.btrfs.subvolumes":
+                        "[\"home\"]",
+                    "com.github.mripard.ocibootstrap.partition.root.btrfs.subvolume.home.mount_point":
+                        "/home",
+                    "com.github.mripard.ocibootstrap.partition.firmware.partition_uuid":
+                        "0fc63daf-8483-4772-8e79-3d69d8477de4",
+                    "com.github.mripard.ocibootstrap.partition.firmware.fs": "raw",
+                    "com.github.mripard.ocibootstrap.partition.firmware.raw.content":
+                        "/usr/lib/firmware.bin",
+                },
+            },
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        });
+
+        let labels = config["config"]["Labels"].as_object_mut().unwrap();
+        for label in removed {
+            labels
+                .remove(&format!(
+                    "com.github.mripard.ocibootstrap.partition.{label}"
+                ))
+                .unwrap();
+        }
+
+        for (label, value) in added {
+            labels.insert(
+                format!("com.github.mripard.ocibootstrap.partition.{label}"),
+                serde_json::Value::from(*value),
+            );
+        }
+
+        let config: ImageConfiguration = serde_json::from_value(config).unwrap();
+        (&config).try_into().unwrap()
+    }
+
+    #[test]
+    fn test_fstab() {
+        let partitions = partition_descriptions(&partition_table());
+        let part_uuids = ["boot-uuid", "swap-uuid", "root-uuid", "firmware-uuid"]
+            .map(String::from)
+            .to_vec();
+
+        let root = TempDir::new().unwrap();
+        write_fstab(root.path(), &fstab(&partitions, &part_uuids, &[])).unwrap();
+
+        let content = fs::read_to_string(root.path().join("etc/fstab")).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            lines,
+            [
+                "PARTUUID=root-uuid\t/\tbtrfs\tnoatime\t0\t0",
+                "PARTUUID=boot-uuid\t/boot\tvfat\tdefaults\t0\t2",
+                "PARTUUID=root-uuid\t/home\tbtrfs\tsubvol=home,noatime\t0\t0",
+                "PARTUUID=swap-uuid\tnone\tswap\tsw\t0\t0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fstab_symlink() {
+        let root = TempDir::new().unwrap();
+        let host = TempDir::new().unwrap();
+        let host_fstab = host.path().join("fstab");
+        fs::write(&host_fstab, "host").unwrap();
+
+        // The image fstab points to a file of the host
+        fs::create_dir(root.path().join("etc")).unwrap();
+        unix_fs::symlink(&host_fstab, root.path().join("etc/fstab")).unwrap();
+        write_fstab(root.path(), "").unwrap_err();
+
+        // The image etc points to a directory of the host
+        fs::remove_dir_all(root.path().join("etc")).unwrap();
+        unix_fs::symlink(host.path(), root.path().join("etc")).unwrap();
+        write_fstab(root.path(), "").unwrap_err();
+
+        assert_eq!(fs::read_to_string(&host_fstab).unwrap(), "host");
+    }
+
+    #[test]
+    fn test_fstab_grow() {
+        let partition_table =
+            partition_table_with(&["root.size_mb"], &[("root.flags.grow", "true")]);
+        let part_uuids = ["boot-uuid", "swap-uuid", "root-uuid", "firmware-uuid"]
+            .map(String::from)
+            .to_vec();
+
+        let content = fstab(
+            &partition_descriptions(&partition_table),
+            &part_uuids,
+            &partition_grow(&partition_table),
+        );
+        assert_eq!(
+            content.lines().collect::<Vec<_>>(),
+            [
+                "PARTUUID=root-uuid\t/\tbtrfs\tnoatime,x-systemd.growfs\t0\t0",
+                "PARTUUID=boot-uuid\t/boot\tvfat\tdefaults\t0\t2",
+                "PARTUUID=root-uuid\t/home\tbtrfs\tsubvol=home,noatime\t0\t0",
+                "PARTUUID=swap-uuid\tnone\tswap\tsw\t0\t0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fstab_grow_vfat() {
+        // systemd-growfs can't grow a FAT filesystem, only its partition is grown
+        let partition_table =
+            partition_table_with(&["boot.size_mb"], &[("boot.flags.grow", "true")]);
+        let part_uuids = ["boot-uuid", "swap-uuid", "root-uuid", "firmware-uuid"]
+            .map(String::from)
+            .to_vec();
+
+        let grow = partition_grow(&partition_table);
+        assert_eq!(grow, [true, false, false, false]);
+
+        let content = fstab(
+            &partition_descriptions(&partition_table),
+            &part_uuids,
+            &grow,
+        );
+        assert_eq!(
+            content.lines().collect::<Vec<_>>(),
+            [
+                "PARTUUID=root-uuid\t/\tbtrfs\tnoatime\t0\t0",
+                "PARTUUID=boot-uuid\t/boot\tvfat\tdefaults\t0\t2",
+                "PARTUUID=root-uuid\t/home\tbtrfs\tsubvol=home,noatime\t0\t0",
+                "PARTUUID=swap-uuid\tnone\tswap\tsw\t0\t0",
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod mount_order_test {
+    use std::path::{Path, PathBuf};
+
+    use test_log::test;
+
+    use crate::{layout::Filesystem, sort_partition_mounts, PartitionMount};
+
+    const MOUNT_POINTS: [Option<&str>; 4] = [Some("/boot/efi"), None, Some("/"), Some("/boot")];
+
+    fn mounts(mount_points: &[Option<&str>]) -> Vec<PartitionMount> {
+        mount_points
+            .iter()
+            .enumerate()
+            .map(|(idx, mnt)| {
+                (
+                    PathBuf::from(format!("/dev/loop42p{}", idx + 1)),
+                    Filesystem::Swap,
+                    mnt.map(PathBuf::from),
+                    None,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mount_order() {
+        for rotation in 0..MOUNT_POINTS.len() {
+            let mut rotated = MOUNT_POINTS;
+            rotated.rotate_left(rotation);
+
+            let mut reversed = rotated;
+            reversed.reverse();
+
+            for mount_points in [rotated, reversed] {
+                let mut mounts = mounts(&mount_points);
+                sort_partition_mounts(&mut mounts);
+
+                assert_eq!(
+                    mounts
+                        .iter()
+                        .map(|(_, _, mnt, _)| mnt.as_deref())
+                        .collect::<Vec<_>>(),
+                    [
+                        Some(Path::new("/")),
+                        Some(Path::new("/boot")),
+                        Some(Path::new("/boot/efi")),
+                        None
+                    ],
+                    "{mount_points:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_mount_order_same_depth() {
+        let mut mounts = mounts(&[Some("/var"), Some("/home"), Some("/")]);
+        sort_partition_mounts(&mut mounts);
+
+        assert_eq!(
+            mounts
+                .iter()
+                .map(|(dev, _, _, _)| dev.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["/dev/loop42p3", "/dev/loop42p1", "/dev/loop42p2"]
+        );
+    }
+}
+
+#[cfg(test)]
+mod dry_run_test {
+    use std::{
+        fs,
+        io::Write as _,
+        os::unix::fs::{self as unix_fs, MetadataExt as _},
+    };
+
+    use oci_spec::image::ImageConfiguration;
+    use serde_json::Value;
+    use tempfile::{NamedTempFile, TempDir};
+    use test_log::test;
+
+    use mbr::MasterBootRecordPartitionTable;
+
+    use crate::{
+        create_output_file, create_partition_table, install_firmware, layout::PartitionTable,
+        partition_plan, partition_reports, report::Report,
+    };
+
+    const TEMP_FILE_SIZE: u64 = 64 << 20;
+
+    fn partition_table(table_type: &str) -> PartitionTable {
+        let config: ImageConfiguration = serde_json::from_value(serde_json::json!({
+            "architecture": "arm64",
+            "os": "linux",
+            "config": {
+                "Labels": {
+                    "com.github.mripard.ocibootstrap.table.type": table_type,
+                    "com.github.mripard.ocibootstrap.table.partitions": "[\"boot\", \"root\"]",
+                    "com.github.mripard.ocibootstrap.partition.boot.partition_uuid":
+                        "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+                    "com.github.mripard.ocibootstrap.partition.boot.type": "0x0c",
+                    "com.github.mripard.ocibootstrap.partition.boot.fs": "fat",
+                    "com.github.mripard.ocibootstrap.partition.boot.size_mb": "16",
+                    "com.github.mripard.ocibootstrap.partition.boot.mount_point": "/boot",
+                    "com.github.mripard.ocibootstrap.partition.root.partition_uuid":
+                        "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                    "com.github.mripard.ocibootstrap.partition.root.type": "0x83",
+                    "com.github.mripard.ocibootstrap.partition.root.fs": "ext4",
+                    "com.github.mripard.ocibootstrap.partition.root.mount_point": "/",
+                },
+            },
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        }))
+        .unwrap();
+
+        PartitionTable::try_from(&config).unwrap()
+    }
+
+    fn test_dry_run(table_type: &str) {
+        let mut file = NamedTempFile::new().unwrap();
+        file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+        file.flush().unwrap();
+
+        partition_plan(file.as_file(), &partition_table(table_type)).unwrap();
+
+        let content = fs::read(file.path()).unwrap();
+        assert_eq!(content.len() as u64, TEMP_FILE_SIZE);
+        assert!(content.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_dry_run_gpt() {
+        test_dry_run("gpt");
+    }
+
+    #[test]
+    fn test_dry_run_mbr() {
+        test_dry_run("mbr");
+    }
+
+    #[test]
+    fn test_mbr_offset() {
+        let config: ImageConfiguration = serde_json::from_value(serde_json::json!({
+            "architecture": "arm64",
+            "os": "linux",
+            "config": {
+                "Labels": {
+                    "com.github.mripard.ocibootstrap.table.type": "mbr",
+                    "com.github.mripard.ocibootstrap.table.partitions": "[\"boot\", \"root\"]",
+                    "com.github.mripard.ocibootstrap.partition.boot.type": "0x0c",
+                    "com.github.mripard.ocibootstrap.partition.boot.fs": "fat",
+                    "com.github.mripard.ocibootstrap.partition.boot.offset_lba": "2048",
+                    "com.github.mripard.ocibootstrap.partition.boot.size_mb": "16",
+                    "com.github.mripard.ocibootstrap.partition.root.type": "0x83",
+                    "com.github.mripard.ocibootstrap.partition.root.fs": "ext4",
+                },
+            },
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        }))
+        .unwrap();
+
+        let file = NamedTempFile::new().unwrap();
+        file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let mut output = file.reopen().unwrap();
+        create_partition_table(
+            &PartitionTable::try_from(&config).unwrap(),
+            &mut output,
+            None,
+        )
+        .unwrap();
+
+        let info = MasterBootRecordPartitionTable::read(file.as_file()).unwrap();
+        assert_eq!(info.partitions[0].start_lba, 2048);
+        assert_eq!(info.partitions[0].size_lba, (16 << 20) / 512);
+        assert!(info.partitions[1].start_lba >= 2048 + info.partitions[0].size_lba);
+    }
+
+    fn firmware_table(table_type: &str, firmware: &Value) -> PartitionTable {
+        let config: ImageConfiguration = serde_json::from_value(serde_json::json!({
+            "architecture": "arm64",
+            "os": "linux",
+            "config": {
+                "Labels": {
+                    "com.github.mripard.ocibootstrap.table.type": table_type,
+                    "com.github.mripard.ocibootstrap.table.partitions": "[\"root\"]",
+                    "com.github.mripard.ocibootstrap.table.reserved_start_bytes": "0x100000",
+                    "com.github.mripard.ocibootstrap.table.firmware": firmware.to_string(),
+                    "com.github.mripard.ocibootstrap.partition.root.partition_uuid":
+                        "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                    "com.github.mripard.ocibootstrap.partition.root.type": "0x83",
+                    "com.github.mripard.ocibootstrap.partition.root.fs": "ext4",
+                },
+            },
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        }))
+        .unwrap();
+
+        PartitionTable::try_from(&config).unwrap()
+    }
+
+    #[test]
+    fn test_firmware() {
+        let root = TempDir::new().unwrap();
+        let root_dir = root.path().canonicalize().unwrap();
+        fs::write(root_dir.join("idbloader.img"), [0xaa; 4096]).unwrap();
+        fs::write(root_dir.join("u-boot.itb"), [0x55; 8192]).unwrap();
+
+        for table_type in ["gpt", "mbr"] {
+            let table = firmware_table(
+                table_type,
+                &serde_json::json!([
+                    { "source": "/idbloader.img", "offset_bytes": 0x8000 },
+                    { "source": "/u-boot.itb", "offset_bytes": 0x80000 },
+                ]),
+            );
+
+            let file = NamedTempFile::new().unwrap();
+            file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+            let mut output = file.reopen().unwrap();
+            create_partition_table(&table, &mut output, None).unwrap();
+            install_firmware(&root_dir, &table, &mut output, None).unwrap();
+
+            let content = fs::read(file.path()).unwrap();
+            assert_eq!(&content[0x8000..0x9000], [0xaa; 4096]);
+            assert_eq!(&content[0x80000..0x82000], [0x55; 8192]);
+        }
+    }
+
+    #[test]
+    fn test_firmware_overlap() {
+        let root = TempDir::new().unwrap();
+        let root_dir = root.path().canonicalize().unwrap();
+        fs::write(root_dir.join("u-boot.itb"), [0x55; 8192]).unwrap();
+        let firmware = "/u-boot.itb";
+
+        let overlaps = [
+            // Over the partition table
+            serde_json::json!([{ "source": firmware, "offset_bytes": 0 }]),
+            // Over the root partition
+            serde_json::json!([{ "source": firmware, "offset_bytes": 0xff000 }]),
+            // Over another firmware
+            serde_json::json!([
+                { "source": firmware, "offset_bytes": 0x8000 },
+                { "source": firmware, "offset_bytes": 0x9000 },
+            ]),
+        ];
+
+        for overlap in overlaps {
+            let table = firmware_table("gpt", &overlap);
+
+            let file = NamedTempFile::new().unwrap();
+            file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+            let mut output = file.reopen().unwrap();
+            create_partition_table(&table, &mut output, None).unwrap();
+            let err = install_firmware(&root_dir, &table, &mut output, None).unwrap_err();
+            assert!(err.to_string().contains("overlaps"), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_firmware_outside_root() {
+        let root = TempDir::new().unwrap();
+        let root_dir = root.path().canonicalize().unwrap();
+
+        let host = TempDir::new().unwrap();
+        let host_file = host.path().join("shadow");
+        fs::write(&host_file, "secret").unwrap();
+        unix_fs::symlink(&host_file, root_dir.join("shadow")).unwrap();
+
+        for source in [host_file.to_str().unwrap(), "/shadow", "/../shadow"] {
+            let table = firmware_table(
+                "gpt",
+                &serde_json::json!([{ "source": source, "offset_bytes": 0x8000 }]),
+            );
+
+            let file = NamedTempFile::new().unwrap();
+            file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+            let mut output = file.reopen().unwrap();
+            create_partition_table(&table, &mut output, None).unwrap();
+            install_firmware(&root_dir, &table, &mut output, None).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_create_output_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("disk.img");
+
+        let file = create_output_file(&path, 2 << 30, false, &partition_table("gpt")).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 2 << 30);
+
+        let plan = partition_plan(&file, &partition_table("gpt")).unwrap();
+        assert_eq!(plan.len(), 2);
+    }
+
+    #[test]
+    fn test_create_output_file_too_small() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("disk.img");
+
+        create_output_file(&path, 8 << 20, false, &partition_table("gpt")).unwrap_err();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_create_output_file_exists() {
+        let file = NamedTempFile::new().unwrap();
+
+        create_output_file(file.path(), 2 << 30, false, &partition_table("gpt")).unwrap_err();
+        assert!(file.path().exists());
+    }
+
+    #[test]
+    fn test_create_output_file_preallocate() {
+        let size = 64 << 20;
+
+        let dir = TempDir::new().unwrap();
+
+        let sparse = dir.path().join("sparse.img");
+        let file = create_output_file(&sparse, size, false, &partition_table("gpt")).unwrap();
+        assert!(file.metadata().unwrap().blocks() * 512 < size);
+
+        let allocated = dir.path().join("allocated.img");
+        let file = create_output_file(&allocated, size, true, &partition_table("gpt")).unwrap();
+        let metadata = file.metadata().unwrap();
+        assert_eq!(metadata.len(), size);
+        assert!(metadata.blocks() * 512 >= size);
+    }
+
+    #[test]
+    fn test_json_report() {
+        let file = NamedTempFile::new().unwrap();
+        file.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let plan = partition_plan(file.as_file(), &partition_table("gpt")).unwrap();
+        let part_uuids = ["boot-uuid", "root-uuid"].map(String::from);
+        let report = Report {
+            container: String::from("docker.io/library/test:latest"),
+            manifest_digest: None,
+            image_created: Some(String::from("2024-09-01T00:00:00Z")),
+            platform: String::from("linux/arm64/v8"),
+            output: file.path().display().to_string(),
+            partitions: partition_reports(plan, Some(&part_uuids)),
+        };
+
+        let json: Value = serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["container"], "docker.io/library/test:latest");
+        assert_eq!(json["manifest_digest"], Value::Null);
+        assert_eq!(json["image_created"], "2024-09-01T00:00:00Z");
+        assert_eq!(report.manifest_digest(), None);
+        assert_eq!(report.image_created(), Some("2024-09-01T00:00:00Z"));
+        assert_eq!(json["platform"], "linux/arm64/v8");
+        assert_eq!(json["output"], file.path().display().to_string());
+
+        let partitions = json["partitions"].as_array().unwrap();
+        assert_eq!(partitions.len(), 2);
+
+        assert_eq!(partitions[0]["guid"], "boot-uuid");
+        assert_eq!(
+            partitions[0]["type"],
+            "c12a7328-f81f-11d2-ba4b-00a0c93ec93b"
+        );
+        assert_eq!(partitions[0]["size_bytes"], 16 << 20);
+        assert_eq!(partitions[0]["filesystem"], "fat");
+        assert_eq!(partitions[0]["mount_point"], "/boot");
+
+        assert_eq!(partitions[1]["guid"], "root-uuid");
+        assert_eq!(partitions[1]["filesystem"], "ext4");
+        assert_eq!(partitions[1]["mount_point"], "/");
+        assert!(
+            partitions[1]["offset_lba"].as_u64().unwrap()
+                > partitions[0]["offset_lba"].as_u64().unwrap()
+        );
+    }
+}
+
+#[cfg(test)]
+mod reproducible_test {
+    use std::fs::{self, File};
+
+    use oci_spec::image::ImageConfiguration;
+    use sha2::{Digest as _, Sha256};
+    use tempfile::NamedTempFile;
+    use test_log::test;
+    use types::{Digest, DigestAlgorithm};
+
+    use crate::{
+        create_partition_table,
+        layout::PartitionTable,
+        reproducible::{Reproducible, SOURCE_DATE_EPOCH},
+    };
+
+    const TEMP_FILE_SIZE: u64 = 64 << 20;
+    const CONFIG_DIGEST: &str = "bcbd60a3e3e8d3c7a1a7bc39fd8cdbb4b8e2cbbde7d2e0d3fd5bd1c3c6c3d1a0";
+
+    fn configuration(table_type: &str) -> ImageConfiguration {
+        serde_json::from_value(serde_json::json!({
+            "created": "2024-09-01T00:00:00Z",
+            "architecture": "arm64",
+            "os": "linux",
+            "config": {
+                "Labels": {
+                    "com.github.mripard.ocibootstrap.table.type": table_type,
+                    "com.github.mripard.ocibootstrap.table.partitions": "[\"boot\", \"root\"]",
+                    "com.github.mripard.ocibootstrap.partition.boot.partition_uuid":
+                        "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+                    "com.github.mripard.ocibootstrap.partition.boot.type": "0x0c",
+                    "com.github.mripard.ocibootstrap.partition.boot.fs": "fat",
+                    "com.github.mripard.ocibootstrap.partition.boot.size_mb": "16",
+                    "com.github.mripard.ocibootstrap.partition.root.partition_uuid":
+                        "b921b045-1df0-41c3-af44-4c6f280d3fae",
+                    "com.github.mripard.ocibootstrap.partition.root.type": "0x83",
+                    "com.github.mripard.ocibootstrap.partition.root.fs": "ext4",
+                },
+            },
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        }))
+        .unwrap()
+    }
+
+    fn create_image(
+        table: &PartitionTable,
+        reproducible: Option<&Reproducible>,
+    ) -> (NamedTempFile, Vec<String>) {
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(TEMP_FILE_SIZE).unwrap();
+
+        let mut file = File::options().write(true).open(image.path()).unwrap();
+        let part_uuids = create_partition_table(table, &mut file, reproducible).unwrap();
+
+        (image, part_uuids)
+    }
+
+    fn test_reproducible_table(table_type: &str) {
+        let config = configuration(table_type);
+        let table = PartitionTable::try_from(&config).unwrap();
+
+        // The timestamp comes from the image when SOURCE_DATE_EPOCH isn't set
+        assert!(std::env::var(SOURCE_DATE_EPOCH).is_err());
+        let digest = Digest::new(DigestAlgorithm::Sha256, CONFIG_DIGEST).unwrap();
+        let reproducible = Reproducible::from_configuration(&digest, &config).unwrap();
+
+        let (first, first_uuids) = create_image(&table, Some(&reproducible));
+        let (second, second_uuids) = create_image(&table, Some(&reproducible));
+        assert_eq!(first_uuids, second_uuids);
+        assert_eq!(
+            Sha256::digest(fs::read(first.path()).unwrap()),
+            Sha256::digest(fs::read(second.path()).unwrap())
+        );
+
+        let (random, random_uuids) = create_image(&table, None);
+        assert_ne!(first_uuids, random_uuids);
+        assert_ne!(
+            Sha256::digest(fs::read(first.path()).unwrap()),
+            Sha256::digest(fs::read(random.path()).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_reproducible_gpt() {
+        test_reproducible_table("gpt");
+    }
+
+    #[test]
+    fn test_reproducible_mbr() {
+        test_reproducible_table("mbr");
+    }
+}
+
+#[cfg(test)]
+mod extract_test {
+    use core::error::Error as _;
+    use std::{
+        fs,
+        io::{self, Read as _, Write as _},
+        os::unix::fs::{self as unix_fs, MetadataExt as _},
+        path::{Path, PathBuf},
+    };
+
+    use base64::Engine as _;
+    use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+    use log::trace;
+    use nix::unistd::{getgid, getuid};
+    use tar::{Archive, Builder, EntryType, Header};
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use types::{Architecture, IdMapping};
+
+    use crate::{
+        extract_layer, extract_layer_from_dir, extract_layer_incremental, image_relative_path,
+        install_efi_default_boot, install_partition_files, layout::PartitionFile, unpack_layer,
+        ExtractionBudget, IdMappings,
+    };
+
+    fn layer(entries: &[&str]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for path in entries {
+            let mut header = Header::new_gnu();
+
+            let content = if path.ends_with('/') {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o755);
+                &[][..]
+            } else {
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(0o644);
+                path.as_bytes()
+            };
+
+            header.set_size(content.len() as u64);
+            builder.append_data(&mut header, path, content).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_opaque_directory() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        extract_layer(
+            layer(&[
+                "etc/",
+                "etc/lower-file",
+                "etc/sub/",
+                "etc/sub/lower-file",
+                "usr/",
+                "usr/lower-file",
+            ])
+            .as_slice(),
+            dir,
+            false,
+            &[],
+        )
+        .unwrap();
+
+        extract_layer(
+            layer(&["etc/", "etc/.wh..wh..opq", "etc/upper-file"]).as_slice(),
+            dir,
+            false,
+            &[],
+        )
+        .unwrap();
+
+        assert!(dir.join("etc").is_dir());
+        assert!(dir.join("etc/upper-file").exists());
+        assert!(!dir.join("etc/lower-file").exists());
+        assert!(!dir.join("etc/sub").exists());
+        assert!(!dir.join("etc/.wh..wh..opq").exists());
+        assert!(dir.join("usr/lower-file").exists());
+    }
+
+    #[test]
+    fn test_opaque_directory_keeps_current_layer() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        extract_layer(
+            layer(&["etc/", "etc/lower-file", "etc/sub/", "etc/sub/lower-file"]).as_slice(),
+            dir,
+            false,
+            &[],
+        )
+        .unwrap();
+
+        extract_layer(
+            layer(&["etc/sub/upper-file", "etc/.wh..wh..opq"]).as_slice(),
+            dir,
+            false,
+            &[],
+        )
+        .unwrap();
+
+        assert!(!dir.join("etc/lower-file").exists());
+        assert!(!dir.join("etc/sub/lower-file").exists());
+        assert!(dir.join("etc/sub/upper-file").exists());
+    }
+
+    #[test]
+    fn test_hardlink() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let mut builder = Builder::new(Vec::new());
+
+        let content = b"Hardlinked Content";
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(content.len() as u64);
+        builder
+            .append_data(&mut header, "usr/bin/file", &content[..])
+            .unwrap();
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "usr/sbin/link", "usr/bin/file")
+            .unwrap();
+
+        extract_layer(builder.into_inner().unwrap().as_slice(), dir, false, &[]).unwrap();
+
+        let file = dir.join("usr/bin/file").metadata().unwrap();
+        let link = dir.join("usr/sbin/link").metadata().unwrap();
+        assert_eq!(file.ino(), link.ino());
+        assert_eq!(file.nlink(), 2);
+        assert_eq!(fs::read(dir.join("usr/sbin/link")).unwrap(), content);
+    }
+
+    #[test]
+    fn test_hardlink_escaping_root() {
+        let root = TempDir::new().unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "link", "../outside")
+            .unwrap();
+
+        extract_layer(
+            builder.into_inner().unwrap().as_slice(),
+            root.path(),
+            false,
+            &[],
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_hardlink_symlinked_parent() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path().join("rootfs");
+        let outside = root.path().join("outside");
+        fs::create_dir(&dir).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("shadow"), "secret").unwrap();
+
+        // A lower layer turned etc into a symlink to a directory of the host
+        unix_fs::symlink(&outside, dir.join("etc")).unwrap();
+
+        for (path, link_name) in [("usr/shadow", "etc/shadow"), ("etc/link", "usr/file")] {
+            let mut builder = Builder::new(Vec::new());
+
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(4);
+            builder
+                .append_data(&mut header, "usr/file", &b"file"[..])
+                .unwrap();
+
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Link);
+            header.set_size(0);
+            builder.append_link(&mut header, path, link_name).unwrap();
+
+            extract_layer(builder.into_inner().unwrap().as_slice(), &dir, false, &[]).unwrap_err();
+        }
+
+        assert!(!dir.join("usr/shadow").exists());
+        assert_eq!(
+            fs::read_dir(&outside)
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect::<Vec<_>>(),
+            ["shadow"]
+        );
+    }
+
+    #[test]
+    fn test_rootless() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let mut builder = Builder::new(Vec::new());
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Char);
+        header.set_mode(0o666);
+        header.set_device_major(1).unwrap();
+        header.set_device_minor(3).unwrap();
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "dev/null", &[][..])
+            .unwrap();
+
+        let content = b"setuid binary";
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o4755);
+        header.set_size(content.len() as u64);
+        builder
+            .append_data(&mut header, "usr/bin/su", &content[..])
+            .unwrap();
+
+        extract_layer(builder.into_inner().unwrap().as_slice(), dir, true, &[]).unwrap();
+
+        dir.join("dev/null").symlink_metadata().unwrap_err();
+        assert_eq!(
+            dir.join("usr/bin/su").metadata().unwrap().mode() & 0o7777,
+            0o755
+        );
+    }
+
+    fn owned_layer(path: &str, uid: u64, gid: u64) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_uid(uid);
+        header.set_gid(gid);
+        header.set_size(path.len() as u64);
+        builder
+            .append_data(&mut header, path, path.as_bytes())
+            .unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_id_mapping() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let uid = getuid().as_raw();
+        let gid = getgid().as_raw();
+        let uid_map = [IdMapping {
+            container_id: 0,
+            host_id: uid,
+            size: 1,
+        }];
+        let gid_map = [IdMapping {
+            container_id: 0,
+            host_id: gid,
+            size: 1,
+        }];
+        let ids = IdMappings {
+            uid: &uid_map,
+            gid: &gid_map,
+        };
+
+        unpack_layer(
+            owned_layer("etc/hostname", 0, 0).as_slice(),
+            dir,
+            true,
+            false,
+            &[],
+            ids,
+            &mut ExtractionBudget::default(),
+        )
+        .unwrap();
+
+        let metadata = dir.join("etc/hostname").metadata().unwrap();
+        assert_eq!(metadata.uid(), uid);
+        assert_eq!(metadata.gid(), gid);
+
+        let err = unpack_layer(
+            owned_layer("etc/shadow", 42, 0).as_slice(),
+            dir,
+            true,
+            false,
+            &[],
+            ids,
+            &mut ExtractionBudget::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("UID 42"), "{err}");
+    }
+
+    /// Maps the IDs 0 of the image to the IDs of the current user
+    fn current_user_mappings() -> [IdMapping; 2] {
+        [getuid().as_raw(), getgid().as_raw()].map(|host_id| IdMapping {
+            container_id: 0,
+            host_id,
+            size: 1,
+        })
+    }
+
+    #[test]
+    fn test_id_mapping_setuid() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o4755);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(4);
+        builder
+            .append_data(&mut header, "usr/bin/su", &b"su\n\n"[..])
+            .unwrap();
+
+        let [uid_map, gid_map] = current_user_mappings();
+        unpack_layer(
+            builder.into_inner().unwrap().as_slice(),
+            dir,
+            false,
+            false,
+            &[],
+            IdMappings {
+                uid: &[uid_map],
+                gid: &[gid_map],
+            },
+            &mut ExtractionBudget::default(),
+        )
+        .unwrap();
+
+        let metadata = dir.join("usr/bin/su").metadata().unwrap();
+        assert_eq!(metadata.uid(), uid_map.host_id);
+        assert_eq!(metadata.mode() & 0o7777, 0o4755);
+    }
+
+    #[test]
+    #[ignore = "requires root privileges"]
+    fn test_id_mapping_capability() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        // CAP_NET_RAW, permitted and effective
+        let capability = [
+            0x01, 0x00, 0x00, 0x02, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let mut builder = Builder::new(Vec::new());
+        builder
+            .append_pax_extensions([("SCHILY.xattr.security.capability", &capability[..])])
+            .unwrap();
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o755);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(5);
+        builder
+            .append_data(&mut header, "usr/bin/ping", &b"ping\n"[..])
+            .unwrap();
+
+        let [uid_map, gid_map] = current_user_mappings();
+        unpack_layer(
+            builder.into_inner().unwrap().as_slice(),
+            dir,
+            false,
+            false,
+            &[],
+            IdMappings {
+                uid: &[uid_map],
+                gid: &[gid_map],
+            },
+            &mut ExtractionBudget::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            xattr::get(dir.join("usr/bin/ping"), "security.capability").unwrap(),
+            Some(capability.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_id_mapping_for_layer() {
+        let [uid_map, gid_map] = current_user_mappings();
+        let (uid_map, gid_map) = ([uid_map], [gid_map]);
+        let layer_map = [IdMapping {
+            container_id: 0,
+            host_id: 100_000,
+            size: 0x0001_0000,
+        }];
+
+        // The layer mappings are only used if the caller asked for a mapping
+        let ids = IdMappings::default().for_layer((&layer_map, &layer_map));
+        assert!(ids.is_empty());
+
+        let ids = IdMappings {
+            uid: &uid_map,
+            gid: &[],
+        }
+        .for_layer((&layer_map, &layer_map));
+        assert_eq!(ids.uid, layer_map);
+        assert!(ids.gid.is_empty());
+
+        let ids = IdMappings {
+            uid: &uid_map,
+            gid: &gid_map,
+        }
+        .for_layer((&[], &[]));
+        assert_eq!(ids.uid, uid_map);
+        assert_eq!(ids.gid, gid_map);
+    }
+
+    #[test]
+    fn test_whiteout_file() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        extract_layer(
+            layer(&["etc/", "etc/file", "etc/other-file"]).as_slice(),
+            dir,
+            false,
+            &[],
+        )
+        .unwrap();
+
+        extract_layer(layer(&["etc/.wh.file"]).as_slice(), dir, false, &[]).unwrap();
+
+        assert!(!dir.join("etc/file").exists());
+        assert!(dir.join("etc/other-file").exists());
+    }
+
+    /// Creates a layer with an entry whose path isn't checked, like a malicious image could
+    fn raw_path_layer(path: &str) -> Vec<u8> {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(0);
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+
+        let mut builder = Builder::new(Vec::new());
+        builder.append(&header, io::empty()).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_whiteout_escaping_root() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path().join("rootfs");
+        let victim = root.path().join("victim");
+        fs::create_dir(&dir).unwrap();
+        fs::create_dir(&victim).unwrap();
+        fs::write(victim.join("file"), "victim").unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "escape", "../victim")
+            .unwrap();
+        extract_layer(builder.into_inner().unwrap().as_slice(), &dir, false, &[]).unwrap();
+
+        for path in [
+            "../victim/.wh.file",
+            "escape/.wh.file",
+            "escape/.wh..wh..opq",
+            ".wh...",
+        ] {
+            extract_layer(raw_path_layer(path).as_slice(), &dir, false, &[]).unwrap_err();
+            assert!(victim.join("file").exists(), "{path}");
+        }
+
+        // Removing the symlink itself is fine
+        extract_layer(raw_path_layer(".wh.escape").as_slice(), &dir, false, &[]).unwrap();
+        assert!(dir.join("escape").symlink_metadata().is_err());
+        assert!(victim.join("file").exists());
+    }
+
+    #[test]
+    fn test_efi_default_boot() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        extract_layer(
+            layer(&[
+                "boot/",
+                "boot/efi/",
+                "usr/",
+                "usr/lib/",
+                "usr/lib/systemd/",
+                "usr/lib/systemd/boot/",
+                "usr/lib/systemd/boot/efi/",
+                "usr/lib/systemd/boot/efi/systemd-bootaa64.efi",
+            ])
+            .as_slice(),
+            dir,
+            false,
+            &[],
+        )
+        .unwrap();
+
+        let dest = install_efi_default_boot(
+            dir,
+            &dir.join("boot/efi"),
+            "/usr/lib/systemd/boot/efi/systemd-bootaa64.efi".as_ref(),
+            Architecture::Arm64,
+        )
+        .unwrap();
+
+        assert_eq!(dest, dir.join("boot/efi/EFI/BOOT/BOOTAA64.EFI"));
+        assert_eq!(
+            fs::read(&dest).unwrap(),
+            b"usr/lib/systemd/boot/efi/systemd-bootaa64.efi"
+        );
+    }
+
+    #[test]
+    fn test_efi_default_boot_missing() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        extract_layer(layer(&["boot/", "boot/efi/"]).as_slice(), dir, false, &[]).unwrap();
+
+        install_efi_default_boot(
+            dir,
+            &dir.join("boot/efi"),
+            "/usr/lib/systemd/boot/efi/systemd-bootaa64.efi".as_ref(),
+            Architecture::Arm64,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_partition_files() {
+        let root = TempDir::new().unwrap();
+        let root_dir = root.path().canonicalize().unwrap();
+        fs::create_dir(root_dir.join("boot")).unwrap();
+        fs::write(root_dir.join("boot/config.txt"), "arm_64bit=1").unwrap();
+
+        let host = TempDir::new().unwrap();
+        let host_file = host.path().join("shadow");
+        fs::write(&host_file, "secret").unwrap();
+        unix_fs::symlink(&host_file, root_dir.join("boot/shadow")).unwrap();
+
+        let mnt = TempDir::new().unwrap();
+        let mnt_dir = mnt.path().canonicalize().unwrap();
+
+        let installed = install_partition_files(
+            &root_dir,
+            &mnt_dir,
+            &[PartitionFile {
+                source: PathBuf::from("/boot/config.txt"),
+                dest: PathBuf::from("/firmware/config.txt"),
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(installed, vec![mnt_dir.join("firmware/config.txt")]);
+        assert_eq!(fs::read_to_string(&installed[0]).unwrap(), "arm_64bit=1");
+
+        install_partition_files(
+            &root_dir,
+            &mnt_dir,
+            &[PartitionFile {
+                source: PathBuf::from("/boot/config.txt"),
+                dest: PathBuf::from("../config.txt"),
+            }],
+        )
+        .unwrap_err();
+
+        install_partition_files(
+            &root_dir,
+            &mnt_dir,
+            &[PartitionFile {
+                source: PathBuf::from("/boot/not-there.txt"),
+                dest: PathBuf::from("/not-there.txt"),
+            }],
+        )
+        .unwrap_err();
+
+        // Host files can't be copied, directly or through a symlink
+        for source in [host_file, PathBuf::from("/boot/shadow")] {
+            install_partition_files(
+                &root_dir,
+                &mnt_dir,
+                &[PartitionFile {
+                    source,
+                    dest: PathBuf::from("/shadow"),
+                }],
+            )
+            .unwrap_err();
+        }
+        assert!(!mnt_dir.join("shadow").exists());
+
+        // Nor can a dangling symlink of the partition be used to create a file on the host
+        let host_dest = host.path().join("evil");
+        unix_fs::symlink(&host_dest, mnt_dir.join("evil")).unwrap();
+        install_partition_files(
+            &root_dir,
+            &mnt_dir,
+            &[PartitionFile {
+                source: PathBuf::from("/boot/config.txt"),
+                dest: PathBuf::from("/evil"),
+            }],
+        )
+        .unwrap_err();
+        assert!(!host_dest.exists());
+    }
+
+    #[test]
+    fn test_extract_incremental() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let lower = layer(&[
+            "etc/",
+            "etc/hostname",
+            "etc/passwd",
+            "usr/",
+            "usr/bin/",
+            "usr/bin/sh",
+        ]);
+
+        assert_eq!(
+            extract_layer_incremental(lower.as_slice(), dir, false, &[]).unwrap(),
+            0
+        );
+        assert_eq!(
+            extract_layer_incremental(lower.as_slice(), dir, false, &[]).unwrap(),
+            3
+        );
+
+        fs::write(dir.join("etc/hostname"), "modified").unwrap();
+        assert_eq!(
+            extract_layer_incremental(lower.as_slice(), dir, false, &[]).unwrap(),
+            2
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("etc/hostname")).unwrap(),
+            "etc/hostname"
+        );
+
+        let upper = layer(&["etc/.wh.passwd", "usr/bin/sh"]);
+        assert_eq!(
+            extract_layer_incremental(upper.as_slice(), dir, false, &[]).unwrap(),
+            1
+        );
+        assert!(!dir.join("etc/passwd").exists());
+    }
+
+    fn file_layer(path: &str, size: u64) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(size);
+        builder
+            .append_data(&mut header, path, io::repeat(0).take(size))
+            .unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_extract_decompression_bomb() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&file_layer("bomb", 16 << 20)).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < 64 << 10);
+
+        let mut budget = ExtractionBudget::new(Some(1 << 20));
+        let err = unpack_layer(
+            GzDecoder::new(compressed.as_slice()),
+            dir,
+            false,
+            false,
+            &[],
+            IdMappings::default(),
+            &mut budget,
+        )
+        .unwrap_err();
+
+        let msg = err.source().unwrap().to_string();
+        assert!(msg.contains("maximum"), "{msg}");
+        assert!(!dir.join("bomb").exists());
+    }
+
+    #[test]
+    fn test_extract_budget_across_layers() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let mut budget = ExtractionBudget::new(Some(1 << 20));
+        for path in ["lower", "upper"] {
+            budget.start_layer(None);
+            unpack_layer(
+                file_layer(path, 512 << 10).as_slice(),
+                dir,
+                false,
+                false,
+                &[],
+                IdMappings::default(),
+                &mut budget,
+            )
+            .unwrap();
+        }
+
+        budget.start_layer(None);
+        unpack_layer(
+            file_layer("extra", 1).as_slice(),
+            dir,
+            false,
+            false,
+            &[],
+            IdMappings::default(),
+            &mut budget,
+        )
+        .unwrap_err();
+        assert!(!dir.join("extra").exists());
+
+        // Entries can't be larger than the size declared for their layer
+        let mut budget = ExtractionBudget::new(None);
+        budget.start_layer(Some(4096));
+        let err = unpack_layer(
+            file_layer("large", 8192).as_slice(),
+            dir,
+            false,
+            false,
+            &[],
+            IdMappings::default(),
+            &mut budget,
+        )
+        .unwrap_err();
+        let msg = err.source().unwrap().to_string();
+        assert!(msg.contains("larger"), "{msg}");
+    }
+
+    #[test]
+    fn test_extract_paths() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+        let paths = [PathBuf::from("etc")];
+
+        let lower = layer(&[
+            "etc/",
+            "etc/hostname",
+            "etc/passwd",
+            "etc/ssh/",
+            "etc/ssh/sshd_config",
+            "usr/",
+            "usr/bin/",
+            "usr/bin/sh",
+            "etcetera",
+        ]);
+        extract_layer(lower.as_slice(), dir, false, &paths).unwrap();
+
+        let upper = layer(&[
+            "etc/.wh.passwd",
+            "etc/ssh/",
+            "etc/ssh/.wh..wh..opq",
+            "usr/.wh.bin",
+        ]);
+        extract_layer(upper.as_slice(), dir, false, &paths).unwrap();
+
+        assert!(dir.join("etc/hostname").is_file());
+        assert!(!dir.join("etc/passwd").exists());
+        assert!(dir.join("etc/ssh").is_dir());
+        assert!(!dir.join("etc/ssh/sshd_config").exists());
+        assert!(!dir.join("usr").exists());
+        assert!(!dir.join("etcetera").exists());
+    }
+
+    #[test]
+    fn test_extract_paths_parent_dirs() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+
+        let lower = layer(&[
+            "etc/",
+            "etc/hostname",
+            "usr/",
+            "usr/bin/",
+            "usr/bin/sh",
+            "usr/lib/",
+        ]);
+        extract_layer(
+            lower.as_slice(),
+            dir,
+            false,
+            &[image_relative_path(Path::new("/usr/bin"))],
+        )
+        .unwrap();
+
+        assert!(dir.join("usr/bin/sh").is_file());
+        assert!(!dir.join("usr/lib").exists());
+        assert!(!dir.join("etc").exists());
+    }
+
+    /// Counts the bytes read from a layer archive
+    struct CountingReader<R> {
+        inner: R,
+        count: u64,
+    }
+
+    impl<R: io::Read> io::Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let used = self.inner.read(buf)?;
+            self.count += used as u64;
+            Ok(used)
+        }
+    }
+
+    impl<R: io::Seek> io::Seek for CountingReader<R> {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    /// Creates the tar-split metadata of a layer, as the containers storage would
+    fn split_layer(layer: &[u8]) -> Vec<u8> {
+        let mut split = Vec::new();
+        let mut position = 0;
+        let mut cursor = 0;
+
+        let segment = |split: &mut Vec<u8>, position: &mut usize, payload: &[u8]| {
+            serde_json::to_writer(
+                &mut *split,
+                &serde_json::json!({
+                    "type": 2,
+                    "payload": base64::engine::general_purpose::STANDARD.encode(payload),
+                    "position": *position,
+                }),
+            )
+            .unwrap();
+            split.push(b'\n');
+            *position += 1;
+        };
+
+        let mut archive = Archive::new(layer);
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let file_position = usize::try_from(entry.raw_file_position()).unwrap();
+            let size = usize::try_from(entry.size()).unwrap();
+
+            segment(&mut split, &mut position, &layer[cursor..file_position]);
+
+            let mut file = serde_json::json!({
+                "type": 1,
+                "name": entry.path().unwrap(),
+                "position": position,
+            });
+            if size > 0 {
+                file["size"] = size.into();
+            }
+
+            serde_json::to_writer(&mut split, &file).unwrap();
+            split.push(b'\n');
+            position += 1;
+
+            cursor = file_position + size;
+        }
+
+        segment(&mut split, &mut position, &layer[cursor..]);
+
+        split
+    }
+
+    #[test]
+    fn test_extract_from_dir() {
+        let root = TempDir::new().unwrap();
+        let files = root.path().join("diff");
+        let streamed = root.path().join("streamed");
+        let copied = root.path().join("copied");
+
+        let content = vec![0x42; 4 << 20];
+        let mut builder = Builder::new(Vec::new());
+        for (path, entry_type, mode, data) in [
+            ("usr/", EntryType::Directory, 0o755, &[][..]),
+            ("usr/bin/", EntryType::Directory, 0o755, &[][..]),
+            ("usr/bin/tool", EntryType::Regular, 0o4755, &content[..]),
+            ("usr/bin/empty", EntryType::Regular, 0o600, &[][..]),
+            ("usr/bin/script", EntryType::Regular, 0o755, b"#!/bin/sh\n"),
+        ] {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_mtime(1_700_000_000);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let layer = builder.into_inner().unwrap();
+
+        Archive::new(layer.as_slice()).unpack(&files).unwrap();
+        let split = split_layer(&layer);
+
+        fs::create_dir(&streamed).unwrap();
+        fs::create_dir(&copied).unwrap();
+
+        let mut reader = CountingReader {
+            inner: tar_split::from_reader(&files, split.as_slice()),
+            count: 0,
+        };
+        extract_layer(&mut reader, &streamed, true, &[]).unwrap();
+
+        let mut fast_reader = CountingReader {
+            inner: tar_split::from_reader(&files, split.as_slice()),
+            count: 0,
+        };
+        assert_eq!(
+            extract_layer_from_dir(
+                &mut fast_reader,
+                &files,
+                &copied,
+                true,
+                false,
+                &[],
+                IdMappings::default(),
+                &mut ExtractionBudget::default()
+            )
+            .unwrap(),
+            0
+        );
+
+        // Only the headers are read when the files are copied
+        trace!(
+            "Read {} bytes from tar-split, {} with the files copied",
+            reader.count,
+            fast_reader.count
+        );
+        assert!(reader.count > content.len() as u64);
+        assert!(fast_reader.count < 16 << 10);
+
+        for path in ["usr/bin/tool", "usr/bin/empty", "usr/bin/script"] {
+            let expected = streamed.join(path);
+            let found = copied.join(path);
+
+            assert_eq!(fs::read(&found).unwrap(), fs::read(&expected).unwrap());
+
+            let expected = expected.metadata().unwrap();
+            let found = found.metadata().unwrap();
+            assert_eq!(found.mode(), expected.mode(), "{path}");
+            assert_eq!(found.mtime(), expected.mtime(), "{path}");
+        }
+
+        assert_eq!(
+            copied.join("usr/bin/tool").metadata().unwrap().mode() & 0o7777,
+            0o755
+        );
+    }
+
+    #[test]
+    fn test_extract_from_dir_symlinked_parent() {
+        let root = TempDir::new().unwrap();
+        let files = root.path().join("diff");
+        let copied = root.path().join("copied");
+        let outside = root.path().join("outside");
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(4);
+        builder
+            .append_data(&mut header, "etc/passwd", &b"root"[..])
+            .unwrap();
+        let layer = builder.into_inner().unwrap();
+
+        Archive::new(layer.as_slice()).unpack(&files).unwrap();
+        let split = split_layer(&layer);
+
+        // A lower layer turned etc into a symlink to a directory of the host
+        fs::create_dir(&copied).unwrap();
+        fs::create_dir(&outside).unwrap();
+        unix_fs::symlink(&outside, copied.join("etc")).unwrap();
+
+        extract_layer_from_dir(
+            tar_split::from_reader(&files, split.as_slice()),
+            &files,
+            &copied,
+            true,
+            false,
+            &[],
+            IdMappings::default(),
+            &mut ExtractionBudget::default(),
+        )
+        .unwrap_err();
+
+        assert!(!outside.join("passwd").exists());
+    }
+}
@@ -1,21 +1,33 @@
+use core::iter::zip;
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{self, Read},
-    path::PathBuf,
+    env,
+    fs::{self, File},
+    io::{self, Read, Seek as _},
+    path::{Component, Path, PathBuf},
 };
 
 use base64::Engine as _;
 use jiff::Timestamp;
-use log::{debug, trace};
+use log::{debug, trace, warn};
 use nix::unistd::Uid;
-use oci_spec::image::{ImageConfiguration, ImageManifest};
-use serde::{de, Deserialize};
+use oci_spec::image::{
+    Descriptor, ImageConfiguration, ImageIndex, ImageManifest, MediaType, OciLayout,
+    ANNOTATION_REF_NAME,
+};
+use serde::{de, Deserialize, Serialize};
 use serde_json::Value;
+use tar::{Archive, EntryType};
 use tar_split::TarSplitReader;
-use types::{Architecture, Digest, DigestAlgorithm, OciBootstrapError, OperatingSystem};
+use types::{
+    Architecture, Compression, Digest, DigestAlgorithm, IdMapping, OciBootstrapError,
+    OperatingSystem, Variant,
+};
 
-use crate::container::ContainerSpec;
+use crate::{
+    container::{ContainerReference, ContainerSpec},
+    normalize_entry_path,
+};
 
 fn digest_to_oci_base64(digest: &Digest) -> String {
     // For some reason, it appears the blobs when stored on the FS are regular base64 encoding
@@ -41,14 +53,12 @@ struct LocalContainerImage {
     #[serde(deserialize_with = "deserialize_sha256_digest")]
     id: Digest,
 
-    #[serde(rename = "digest")]
-    _digest: Digest,
+    digest: Digest,
 
     #[serde(default)]
     names: Vec<String>,
 
-    #[serde(rename = "created")]
-    _created: Timestamp,
+    created: Timestamp,
 
     #[serde(rename = "names-history")]
     _names_history: Vec<String>,
@@ -71,17 +81,26 @@ struct LocalContainerImage {
     _big_data_digests: HashMap<String, Digest>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
-struct IdMap {
-    #[serde(rename = "container_id")]
-    _container_id: u32,
-
-    #[serde(rename = "host_id")]
-    _host_id: u32,
-
-    #[serde(rename = "size")]
-    _size: usize,
+impl LocalContainerImage {
+    fn matches(&self, spec: &ContainerSpec) -> bool {
+        match &spec.reference {
+            ContainerReference::Tag(_) => self.names.contains(&spec.to_oci_string()),
+            ContainerReference::Digest(digest) => {
+                if self.id != *digest && self.digest != *digest {
+                    return false;
+                }
+
+                // Images pulled by digest might not have any name, but if they do, they must
+                // belong to the repository we've been asked for.
+                let repository = spec.to_string();
+                self.names.is_empty()
+                    || self.names.iter().any(|name| {
+                        name.strip_prefix(&repository)
+                            .is_some_and(|rest| rest.starts_with([':', '@']))
+                    })
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -111,10 +130,9 @@ struct LocalContainerLayer {
     _diff_digest: Option<Digest>,
 
     #[serde(rename = "diff-size")]
-    _diff_size: Option<usize>,
+    diff_size: Option<u64>,
 
-    #[serde(rename = "compression")]
-    _compression: Option<u8>,
+    compression: Option<u8>,
 
     #[serde(default, rename = "uidset")]
     _uidset: Vec<u32>,
@@ -122,11 +140,143 @@ struct LocalContainerLayer {
     #[serde(default, rename = "gidset")]
     _gidset: Vec<u32>,
 
-    #[serde(default, rename = "uidmap")]
-    _uidmap: Vec<IdMap>,
+    #[serde(default)]
+    uidmap: Vec<IdMapping>,
+
+    #[serde(default)]
+    gidmap: Vec<IdMapping>,
+}
+
+/// Media type of the Docker manifest lists, the Docker equivalent of the OCI image indexes
+const DOCKER_MANIFEST_LIST_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+
+const DOCKER_CONFIG_MEDIA_TYPE: &str = "application/vnd.docker.container.image.v1+json";
+
+/// Returns whether a manifest describes an artifact, such as a signature or an SBOM, rather than
+/// an image
+///
+/// Images can refer to another image through their subject too, so we can only rely on the
+/// artifact type or on the configuration not being an image configuration.
+fn is_artifact(manifest: &ImageManifest) -> bool {
+    if manifest.artifact_type().is_some() {
+        return true;
+    }
+
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match manifest.config().media_type() {
+        MediaType::ImageConfig => false,
+        MediaType::Other(media_type) => media_type != DOCKER_CONFIG_MEDIA_TYPE,
+        _ => true,
+    }
+}
+
+/// Returns whether a descriptor points to an index of manifests, rather than to a manifest
+fn is_image_index(desc: &Descriptor) -> bool {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match desc.media_type() {
+        MediaType::ImageIndex => true,
+        MediaType::Other(media_type) => media_type == DOCKER_MANIFEST_LIST_MEDIA_TYPE,
+        _ => false,
+    }
+}
+
+/// Parses the variant of an image
+///
+/// Variants we don't know about are ignored, so that the image is considered as compatible with
+/// all the variants of its architecture.
+fn image_variant(variant: Option<&str>) -> Option<Variant> {
+    let variant = variant?;
+
+    Variant::from_oci_str(variant)
+        .inspect_err(|_e| warn!("Unknown variant {variant}, ignoring it"))
+        .ok()
+}
+
+/// Returns the platform of an index entry, or `None` if it doesn't have one or if it isn't a
+/// platform we know about, like the `unknown/unknown` of the attestation manifests
+fn descriptor_platform(
+    desc: &Descriptor,
+) -> Option<(Architecture, OperatingSystem, Option<Variant>)> {
+    let platform = desc.platform().as_ref()?;
 
-    #[serde(default, rename = "gidmap")]
-    _gidmap: Vec<IdMap>,
+    let arch = Architecture::from_oci_str(&platform.architecture().to_string()).ok()?;
+    let os = OperatingSystem::from_oci_str(&platform.os().to_string()).ok()?;
+    let variant = image_variant(platform.variant().as_deref());
+
+    Some((arch, os, variant))
+}
+
+/// Returns the entries of an index that can match a platform, the most suitable first
+///
+/// Entries without a platform can't be ruled out without looking at their configuration, and are
+/// kept at the end.
+fn index_candidates(
+    index: &ImageIndex,
+    arch: Architecture,
+    variant: Option<Variant>,
+    os: OperatingSystem,
+) -> Vec<&Descriptor> {
+    let mut candidates = Vec::new();
+    let mut unknown = Vec::new();
+
+    for desc in index.manifests() {
+        if desc.platform().is_none() {
+            unknown.push(desc);
+            continue;
+        }
+
+        let Some((desc_arch, desc_os, desc_variant)) = descriptor_platform(desc) else {
+            debug!("Index entry {} has an unknown platform", desc.digest());
+            continue;
+        };
+
+        if desc_arch != arch || desc_os != os || Variant::select(variant, [desc_variant]).is_none()
+        {
+            debug!("Index entry {} doesn't match our platform", desc.digest());
+            continue;
+        }
+
+        candidates.push((desc_variant, desc));
+    }
+
+    // The highest compatible variant is the most suitable, and the sort is stable so the index
+    // order is kept otherwise.
+    candidates.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    candidates
+        .into_iter()
+        .map(|(_, desc)| desc)
+        .chain(unknown)
+        .collect()
+}
+
+/// Parses an image manifest, with an actionable error for deprecated Docker schema 1 manifests
+fn image_manifest_from_value(value: Value) -> Result<ImageManifest, OciBootstrapError> {
+    if value.get("schemaVersion").and_then(Value::as_u64) == Some(1) {
+        return Err(OciBootstrapError::Custom(String::from(
+            "Image uses the deprecated Docker Image Manifest V2 Schema 1, which isn't supported. Pull it again from a registry serving Schema 2 or OCI manifests, or convert it with skopeo copy --format oci",
+        )));
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Environment variable pointing to a containers-storage.conf(5) file to use instead of the
+/// default one
+const CONTAINERS_STORAGE_CONF: &str = "CONTAINERS_STORAGE_CONF";
+
+/// Returns the storage root (graphroot) set in a containers-storage.conf(5) file, if any
+fn storage_root_from_conf(path: &Path) -> Result<Option<PathBuf>, OciBootstrapError> {
+    debug!("Reading storage configuration {}", path.display());
+
+    let cfg: toml::Table = toml::from_str(&fs::read_to_string(path)?)?;
+
+    Ok(cfg
+        .get("storage")
+        .and_then(|storage| storage.get("graphroot"))
+        .and_then(toml::Value::as_str)
+        .map(PathBuf::from))
 }
 
 fn get_containers_dir() -> Result<PathBuf, io::Error> {
@@ -139,33 +289,94 @@ fn get_containers_dir() -> Result<PathBuf, io::Error> {
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct LocalRegistry {
-    base_dir: PathBuf,
-    images: Vec<LocalContainerImage>,
-    layers: Vec<LocalContainerLayer>,
+fn config_matches_platform(
+    cfg: &ImageConfiguration,
+    arch: Architecture,
+    variant: Option<Variant>,
+    os: OperatingSystem,
+) -> bool {
+    let Ok(cfg_arch) = Architecture::try_from(cfg.architecture().clone()) else {
+        debug!(
+            "Configuration uses an unknown architecture {}",
+            cfg.architecture()
+        );
+        return false;
+    };
+    let Ok(cfg_os) = OperatingSystem::try_from(cfg.os().clone()) else {
+        debug!("Configuration uses an unknown OS {}", cfg.os());
+        return false;
+    };
+
+    if cfg_arch != arch || cfg_os != os {
+        return false;
+    }
+
+    let cfg_variant = image_variant(cfg.variant().as_deref());
+    if Variant::select(variant, [cfg_variant]).is_none() {
+        debug!("Image variant isn't compatible with the requested one");
+        return false;
+    }
+
+    true
 }
 
-impl LocalRegistry {
-    pub(crate) fn new() -> Result<Self, OciBootstrapError> {
-        let base_dir = get_containers_dir()?;
-        let storage_dir = base_dir.join("storage");
-        let images_file = File::open(storage_dir.join("overlay-images").join("images.json"))?;
-        let images: Vec<LocalContainerImage> = serde_json::from_reader(&images_file)?;
+fn config_platform(cfg: &ImageConfiguration) -> String {
+    let mut platform = format!("{}/{}", cfg.os(), cfg.architecture());
 
-        let layers_dir = storage_dir.join("overlay-layers");
-        let layer_file = File::open(layers_dir.join("layers.json"))?;
-        let layers: Vec<LocalContainerLayer> = serde_json::from_reader(&layer_file)?;
+    if let Some(variant) = cfg.variant() {
+        platform.push('/');
+        platform.push_str(variant);
+    }
 
-        Ok(Self {
-            base_dir,
-            images,
-            layers,
-        })
+    platform
+}
+
+/// An image found in a [`LocalRegistry`]
+#[derive(Debug, Serialize)]
+pub(crate) struct ImageSummary {
+    pub(crate) names: Vec<String>,
+    pub(crate) id: String,
+    pub(crate) created: Option<Timestamp>,
+    pub(crate) platforms: Vec<String>,
+}
+
+impl ImageSummary {
+    fn new(
+        names: Vec<String>,
+        id: &Digest,
+        created: Option<Timestamp>,
+        configs: Result<Vec<ImageConfiguration>, OciBootstrapError>,
+    ) -> Self {
+        let configs = configs.unwrap_or_else(|e| {
+            warn!("Couldn't read image {id} configuration: {e}");
+            Vec::new()
+        });
+
+        let created = created.or_else(|| {
+            configs
+                .iter()
+                .find_map(|cfg| cfg.created().as_ref()?.parse().ok())
+        });
+
+        Self {
+            names,
+            id: id.to_oci_string(),
+            created,
+            platforms: configs.iter().map(config_platform).collect(),
+        }
     }
+}
+
+#[derive(Debug)]
+struct ContainersStorage {
+    storage_dir: PathBuf,
+    images: Vec<LocalContainerImage>,
+    layers: Vec<LocalContainerLayer>,
+}
 
-    fn storage_dir(&self) -> PathBuf {
-        self.base_dir.join("storage")
+impl ContainersStorage {
+    fn storage_dir(&self) -> &Path {
+        &self.storage_dir
     }
 
     fn overlay_images_dir(&self) -> PathBuf {
@@ -190,65 +401,591 @@ impl LocalRegistry {
         })
     }
 
+    fn image_manifest(
+        &self,
+        image: &LocalContainerImage,
+    ) -> Result<(ImageManifest, ImageConfiguration), OciBootstrapError> {
+        let path = self.overlay_images_dir().join(image.id.to_raw_string());
+        debug!("Path to image dir {}", path.display());
+
+        let manifest_path = path.join("manifest");
+        let manifest_file = File::open(manifest_path)?;
+        let manifest = image_manifest_from_value(serde_json::from_reader(&manifest_file)?)?;
+
+        let cfg_desc = manifest.config();
+        let cfg_digest = Digest::from_oci_str(cfg_desc.digest())?;
+        let cfg_path = path.join(digest_to_oci_base64(&cfg_digest));
+        debug!("Config Path {}", cfg_path.display());
+
+        let cfg_file = File::open(&cfg_path)?;
+        let cfg: ImageConfiguration = serde_json::from_reader(&cfg_file)?;
+
+        Ok((manifest, cfg))
+    }
+
+    fn image_layers(&self, image: &LocalContainerImage) -> Result<Vec<LocalLayer<'_>>, io::Error> {
+        let mut layer = self
+            .find_layer_by_id(&image.layer)
+            .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+
+        let mut image_layers = vec![LocalLayer(LayerSource::Containers(self, layer))];
+        while let Some(parent) = &layer.parent {
+            debug!("Layer has a parent: {}", parent);
+
+            layer = self
+                .find_layer_by_id(parent)
+                .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+
+            image_layers.push(LocalLayer(LayerSource::Containers(self, layer)));
+        }
+
+        image_layers.reverse();
+
+        Ok(image_layers)
+    }
+}
+
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+
+#[derive(Debug)]
+struct OciImageLayout {
+    dir: PathBuf,
+    index: ImageIndex,
+}
+
+impl OciImageLayout {
+    fn blob_path(&self, digest: &Digest) -> PathBuf {
+        self.dir
+            .join("blobs")
+            .join(digest.algorithm().to_string())
+            .join(digest.to_raw_string())
+    }
+
+    /// Checks that a blob file has the size its descriptor expects
+    fn check_blob_size(desc: &Descriptor, path: &Path) -> Result<(), OciBootstrapError> {
+        let expected = u64::try_from(desc.size()).map_err(|_err| {
+            OciBootstrapError::Custom(format!("Invalid size for blob {}", desc.digest()))
+        })?;
+
+        let actual = fs::metadata(path)?.len();
+        if actual != expected {
+            return Err(OciBootstrapError::BlobSizeMismatch { expected, actual });
+        }
+
+        Ok(())
+    }
+
+    fn blob<T>(&self, desc: &Descriptor) -> Result<T, OciBootstrapError>
+    where
+        T: de::DeserializeOwned,
+    {
+        let path = self.blob_path(&Digest::from_oci_str(desc.digest())?);
+        debug!("Opening blob {}", path.display());
+
+        Self::check_blob_size(desc, &path)?;
+
+        let file = File::open(&path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    fn descriptor_matches(desc: &Descriptor, spec: &ContainerSpec) -> bool {
+        let ref_name = desc
+            .annotations()
+            .as_ref()
+            .and_then(|annotations| annotations.get(ANNOTATION_REF_NAME));
+
+        match &spec.reference {
+            ContainerReference::Tag(tag) => {
+                ref_name.is_some_and(|name| *name == spec.to_oci_string() || name == tag)
+            }
+            ContainerReference::Digest(digest) => {
+                Digest::from_oci_str(desc.digest()).is_ok_and(|d| d == *digest)
+            }
+        }
+    }
+
+    /// Returns the configurations of all the platforms a descriptor provides
+    fn configurations(
+        &self,
+        desc: &Descriptor,
+    ) -> Result<Vec<ImageConfiguration>, OciBootstrapError> {
+        if is_image_index(desc) {
+            let index: ImageIndex = self.blob(desc)?;
+
+            // Attestations are listed with an unknown/unknown platform, and aren't images
+            return index
+                .manifests()
+                .iter()
+                .filter(|manifest_desc| {
+                    manifest_desc.platform().is_none()
+                        || descriptor_platform(manifest_desc).is_some()
+                })
+                .map(|manifest_desc| self.configurations(manifest_desc))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|configs| configs.into_iter().flatten().collect());
+        }
+
+        let manifest = image_manifest_from_value(self.blob(desc)?)?;
+        if is_artifact(&manifest) {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![self.blob(manifest.config())?])
+    }
+
+    fn image_manifest(
+        &self,
+        desc: &Descriptor,
+        arch: Architecture,
+        variant: Option<Variant>,
+        os: OperatingSystem,
+    ) -> Result<Option<(Digest, ImageManifest, ImageConfiguration)>, OciBootstrapError> {
+        if is_image_index(desc) {
+            debug!("Descriptor {} is an image index", desc.digest());
+
+            let index: ImageIndex = self.blob(desc)?;
+            for manifest_desc in index_candidates(&index, arch, variant, os) {
+                if let Some(found) = self.image_manifest(manifest_desc, arch, variant, os)? {
+                    return Ok(Some(found));
+                }
+            }
+
+            return Ok(None);
+        }
+
+        let manifest = image_manifest_from_value(self.blob(desc)?)?;
+
+        // Signatures, SBOMs and other artifacts attached to an image through the referrers API
+        // are stored next to it, but don't have an image configuration.
+        if is_artifact(&manifest) {
+            debug!("Manifest {} is an artifact, skipping", desc.digest());
+            return Ok(None);
+        }
+
+        let cfg: ImageConfiguration = self.blob(manifest.config())?;
+
+        if !config_matches_platform(&cfg, arch, variant, os) {
+            debug!("Manifest {} doesn't match our platform", desc.digest());
+            return Ok(None);
+        }
+
+        Ok(Some((Digest::from_oci_str(desc.digest())?, manifest, cfg)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerArchiveImage {
+    config: String,
+
+    #[serde(default)]
+    repo_tags: Vec<String>,
+
+    layers: Vec<String>,
+}
+
+impl DockerArchiveImage {
+    fn matches(&self, spec: &ContainerSpec) -> bool {
+        match &spec.reference {
+            ContainerReference::Tag(tag) => {
+                let mut names = vec![spec.to_oci_string()];
+
+                // docker save stores the images hosted on the Docker Hub with their short name
+                if spec.domain == "docker.io" {
+                    names.push(format!("{}:{tag}", spec.name));
+
+                    if let Some(name) = spec.name.strip_prefix("library/") {
+                        names.push(format!("{name}:{tag}"));
+                    }
+                }
+
+                self.repo_tags
+                    .iter()
+                    .any(|repo_tag| names.contains(repo_tag))
+            }
+            ContainerReference::Digest(digest) => Path::new(&self.config)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| *stem == digest.to_raw_string()),
+        }
+    }
+}
+
+const DOCKER_ARCHIVE_MAX_LINKS: usize = 16;
+
+/// Resolves the target of a symlink found in an archive, relative to the archive root
+fn resolve_archive_link(entry: &Path, target: &Path) -> PathBuf {
+    let mut resolved = entry.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    for component in target.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+
+    resolved
+}
+
+#[derive(Debug)]
+struct DockerArchive {
+    path: PathBuf,
+    images: Vec<DockerArchiveImage>,
+
+    // Offset and size of each regular file in the archive
+    entries: HashMap<PathBuf, (u64, u64)>,
+    links: HashMap<PathBuf, PathBuf>,
+}
+
+impl DockerArchive {
+    fn entry(&self, name: &str) -> Result<(u64, u64), io::Error> {
+        let mut path = normalize_entry_path(Path::new(name));
+
+        for _ in 0..DOCKER_ARCHIVE_MAX_LINKS {
+            let Some(target) = self.links.get(&path) else {
+                break;
+            };
+
+            trace!("Entry {} links to {}", path.display(), target.display());
+            path.clone_from(target);
+        }
+
+        self.entries.get(&path).copied().ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Entry {name} Not Found in {}", self.path.display()),
+        ))
+    }
+
+    fn entry_reader(&self, name: &str) -> Result<io::BufReader<io::Take<File>>, io::Error> {
+        let (offset, size) = self.entry(name)?;
+
+        let mut file = File::open(&self.path)?;
+        file.seek(io::SeekFrom::Start(offset))?;
+
+        Ok(io::BufReader::new(file.take(size)))
+    }
+}
+
+#[derive(Debug)]
+enum RegistryStorage {
+    Containers(ContainersStorage),
+    DockerArchive(DockerArchive),
+    OciLayout(Box<OciImageLayout>),
+}
+
+#[derive(Debug)]
+pub(crate) struct LocalRegistry {
+    storage: RegistryStorage,
+}
+
+impl LocalRegistry {
+    /// Opens the local containers storage
+    ///
+    /// The storage root is, by order of precedence, the one given as an argument, the graphroot
+    /// of the file `CONTAINERS_STORAGE_CONF` points to, or the default one for the current user.
+    pub(crate) fn new(storage_root: Option<&Path>) -> Result<Self, OciBootstrapError> {
+        if let Some(root) = storage_root {
+            return Self::from_storage_dir(root.to_path_buf());
+        }
+
+        if let Some(conf) = env::var_os(CONTAINERS_STORAGE_CONF) {
+            if let Some(root) = storage_root_from_conf(Path::new(&conf))? {
+                return Self::from_storage_dir(root);
+            }
+        }
+
+        Self::with_base_dir(&get_containers_dir()?)
+    }
+
+    /// Opens the containers storage of a containers directory, such as `/var/lib/containers`
+    pub(crate) fn with_base_dir(base_dir: &Path) -> Result<Self, OciBootstrapError> {
+        Self::from_storage_dir(base_dir.join("storage"))
+    }
+
+    fn from_storage_dir(storage_dir: PathBuf) -> Result<Self, OciBootstrapError> {
+        debug!("Opening containers storage {}", storage_dir.display());
+
+        let images_file = File::open(storage_dir.join("overlay-images").join("images.json"))?;
+        let images: Vec<LocalContainerImage> = serde_json::from_reader(&images_file)?;
+
+        let layers_dir = storage_dir.join("overlay-layers");
+        let layer_file = File::open(layers_dir.join("layers.json"))?;
+        let layers: Vec<LocalContainerLayer> = serde_json::from_reader(&layer_file)?;
+
+        Ok(Self {
+            storage: RegistryStorage::Containers(ContainersStorage {
+                storage_dir,
+                images,
+                layers,
+            }),
+        })
+    }
+
+    /// Opens an OCI Image Layout directory, as created by `skopeo copy` or `podman save
+    /// --format oci-dir`
+    pub(crate) fn from_oci_layout(path: &Path) -> Result<Self, OciBootstrapError> {
+        debug!("Opening OCI Image Layout {}", path.display());
+
+        let layout = OciLayout::from_file(path.join("oci-layout"))
+            .map_err(|e| OciBootstrapError::Custom(e.to_string()))?;
+        if layout.image_layout_version() != OCI_LAYOUT_VERSION {
+            return Err(OciBootstrapError::Custom(format!(
+                "Unsupported OCI Image Layout Version {}",
+                layout.image_layout_version()
+            )));
+        }
+
+        let index_file = File::open(path.join("index.json"))?;
+        let index: ImageIndex = serde_json::from_reader(io::BufReader::new(index_file))?;
+
+        Ok(Self {
+            storage: RegistryStorage::OciLayout(Box::new(OciImageLayout {
+                dir: path.to_path_buf(),
+                index,
+            })),
+        })
+    }
+
+    /// Opens an archive created by `docker save`
+    pub(crate) fn from_docker_archive(path: &Path) -> Result<Self, OciBootstrapError> {
+        debug!("Opening Docker Archive {}", path.display());
+
+        let mut archive = Archive::new(File::open(path)?);
+        let mut entries = HashMap::new();
+        let mut links = HashMap::new();
+        let mut images = None;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = normalize_entry_path(&entry.path()?);
+
+            #[allow(clippy::wildcard_enum_match_arm)]
+            match entry.header().entry_type() {
+                EntryType::Regular => {
+                    trace!("Found file {}", entry_path.display());
+
+                    entries.insert(
+                        entry_path.clone(),
+                        (entry.raw_file_position(), entry.size()),
+                    );
+
+                    if entry_path == Path::new("manifest.json") {
+                        images = Some(serde_json::from_reader(&mut entry)?);
+                    }
+                }
+                EntryType::Symlink | EntryType::Link => {
+                    let target = entry.link_name()?.ok_or(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Link without a target",
+                    ))?;
+
+                    // Hardlink targets are relative to the archive root, symlinks to the link
+                    // directory.
+                    let target = if entry.header().entry_type() == EntryType::Link {
+                        normalize_entry_path(&target)
+                    } else {
+                        resolve_archive_link(&entry_path, &target)
+                    };
+
+                    trace!(
+                        "Found link {} to {}",
+                        entry_path.display(),
+                        target.display()
+                    );
+
+                    links.insert(entry_path, target);
+                }
+                _ => {}
+            }
+        }
+
+        let images: Vec<DockerArchiveImage> = images.ok_or(OciBootstrapError::Custom(
+            String::from("Docker Archive without a manifest.json"),
+        ))?;
+
+        debug!("Found {} images in the archive", images.len());
+
+        Ok(Self {
+            storage: RegistryStorage::DockerArchive(DockerArchive {
+                path: path.to_path_buf(),
+                images,
+                entries,
+                links,
+            }),
+        })
+    }
+
     pub(crate) fn image_by_spec(&self, spec: &ContainerSpec) -> Option<LocalImage<'_>> {
         let container_name = spec.to_oci_string();
 
         debug!("Looking for image {container_name}");
 
-        self.images
-            .iter()
-            .find(|i| i.names.contains(&container_name))
-            .map(|image| LocalImage {
-                registry: self,
-                name: container_name.clone(),
-                image,
-            })
+        let source = match &self.storage {
+            RegistryStorage::Containers(storage) => storage
+                .images
+                .iter()
+                .find(|i| i.matches(spec))
+                .map(|image| ImageSource::Containers(storage, image)),
+            RegistryStorage::DockerArchive(archive) => archive
+                .images
+                .iter()
+                .find(|image| image.matches(spec))
+                .map(|image| ImageSource::DockerArchive(archive, image)),
+            RegistryStorage::OciLayout(layout) => layout
+                .index
+                .manifests()
+                .iter()
+                .find(|desc| OciImageLayout::descriptor_matches(desc, spec))
+                .map(|desc| ImageSource::OciLayout(layout, desc)),
+        }?;
+
+        Some(LocalImage {
+            name: container_name,
+            source,
+        })
     }
+
+    /// Lists the images available in the registry
+    pub(crate) fn images(&self) -> Vec<ImageSummary> {
+        match &self.storage {
+            RegistryStorage::Containers(storage) => storage
+                .images
+                .iter()
+                .map(|image| {
+                    ImageSummary::new(
+                        image.names.clone(),
+                        &image.id,
+                        Some(image.created),
+                        storage.image_manifest(image).map(|(_, cfg)| vec![cfg]),
+                    )
+                })
+                .collect(),
+            RegistryStorage::DockerArchive(archive) => archive
+                .images
+                .iter()
+                .filter_map(|image| {
+                    let stem = Path::new(&image.config).file_stem()?.to_str()?;
+                    let id = Digest::new(DigestAlgorithm::Sha256, stem).ok()?;
+
+                    let cfg = archive
+                        .entry_reader(&image.config)
+                        .map_err(OciBootstrapError::from)
+                        .and_then(|reader| Ok(vec![serde_json::from_reader(reader)?]));
+
+                    Some(ImageSummary::new(image.repo_tags.clone(), &id, None, cfg))
+                })
+                .collect(),
+            RegistryStorage::OciLayout(layout) => layout
+                .index
+                .manifests()
+                .iter()
+                .filter_map(|desc| {
+                    let id = Digest::from_oci_str(desc.digest()).ok()?;
+                    let names = desc
+                        .annotations()
+                        .as_ref()
+                        .and_then(|annotations| annotations.get(ANNOTATION_REF_NAME))
+                        .cloned()
+                        .into_iter()
+                        .collect();
+
+                    Some(ImageSummary::new(
+                        names,
+                        &id,
+                        None,
+                        layout.configurations(desc),
+                    ))
+                })
+                .collect(),
+        }
+    }
+
+    /// Looks for each of the candidate containers in turn, and returns the first one found
+    pub(crate) fn image_by_specs(
+        &self,
+        specs: Vec<ContainerSpec>,
+    ) -> Option<(ContainerSpec, LocalImage<'_>)> {
+        specs
+            .into_iter()
+            .find_map(|spec| self.image_by_spec(&spec).map(|image| (spec, image)))
+    }
+}
+
+#[derive(Debug)]
+enum ImageSource<'a> {
+    Containers(&'a ContainersStorage, &'a LocalContainerImage),
+    DockerArchive(&'a DockerArchive, &'a DockerArchiveImage),
+    OciLayout(&'a OciImageLayout, &'a Descriptor),
 }
 
 #[derive(Debug)]
 pub(crate) struct LocalImage<'a> {
-    registry: &'a LocalRegistry,
     name: String,
-    image: &'a LocalContainerImage,
+    source: ImageSource<'a>,
 }
 
 impl LocalImage<'_> {
     pub(crate) fn manifest_for_platform(
         &self,
         arch: Architecture,
+        variant: Option<Variant>,
         os: OperatingSystem,
     ) -> Result<Option<LocalManifest<'_>>, OciBootstrapError> {
         debug!("Looking for image {} manifest", self.name);
 
-        let path = self
-            .registry
-            .overlay_images_dir()
-            .join(self.image.id.to_raw_string());
-        debug!("Path to image dir {}", path.display());
-
-        let manifest_path = path.join("manifest");
-        let manifest_file = File::open(manifest_path)?;
-        let manifest: ImageManifest = serde_json::from_reader(&manifest_file)?;
-
-        let cfg_desc = manifest.config();
-        let cfg_digest = Digest::from_oci_str(cfg_desc.digest())?;
-        let cfg_path = path.join(digest_to_oci_base64(&cfg_digest));
-        debug!("Config Path {}", cfg_path.display());
-
-        let cfg_file = File::open(&cfg_path)?;
-        let cfg: ImageConfiguration = serde_json::from_reader(&cfg_file)?;
+        let (digest, manifest, cfg) = match &self.source {
+            ImageSource::Containers(storage, image) => {
+                let (manifest, cfg) = storage.image_manifest(image)?;
+                if !config_matches_platform(&cfg, arch, variant, os) {
+                    return Ok(None);
+                }
 
-        let cfg_arch: Architecture = cfg.architecture().clone().into();
-        let cfg_os: OperatingSystem = cfg.os().clone().into();
-        if cfg_arch != arch || cfg_os != os {
-            return Ok(None);
-        }
+                (Some(image.digest.clone()), Some(manifest), cfg)
+            }
+            ImageSource::DockerArchive(archive, image) => {
+                let cfg: ImageConfiguration =
+                    serde_json::from_reader(archive.entry_reader(&image.config)?)?;
+                if !config_matches_platform(&cfg, arch, variant, os) {
+                    return Ok(None);
+                }
+
+                (None, None, cfg)
+            }
+            ImageSource::OciLayout(layout, desc) => {
+                let Some((digest, manifest, cfg)) =
+                    layout.image_manifest(desc, arch, variant, os)?
+                else {
+                    return Ok(None);
+                };
+
+                (Some(digest), Some(manifest), cfg)
+            }
+        };
+
+        let config_digest = match (&manifest, &self.source) {
+            (Some(manifest), _) => Digest::from_oci_str(manifest.config().digest())?,
+            // The configuration of docker archives is named after its digest
+            (None, ImageSource::DockerArchive(_, image)) => Path::new(&image.config)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or(OciBootstrapError::Custom(format!(
+                    "Invalid docker archive configuration {}",
+                    image.config
+                )))
+                .and_then(|stem| Digest::new(DigestAlgorithm::Sha256, stem))?,
+            (None, ImageSource::Containers(..) | ImageSource::OciLayout(..)) => {
+                unreachable!("Only docker archives don't have a manifest")
+            }
+        };
 
         Ok(Some(LocalManifest {
-            registry: self.registry,
             img: self,
-            _json: manifest,
+            digest,
+            config_digest,
+            json: manifest,
             config: cfg,
         }))
     }
@@ -256,65 +993,1472 @@ impl LocalImage<'_> {
 
 #[derive(Debug)]
 pub(crate) struct LocalManifest<'a> {
-    registry: &'a LocalRegistry,
     img: &'a LocalImage<'a>,
-    _json: ImageManifest,
+
+    // Docker archives don't have an OCI manifest
+    digest: Option<Digest>,
+    config_digest: Digest,
+    json: Option<ImageManifest>,
     config: ImageConfiguration,
 }
 
 impl LocalManifest<'_> {
-    pub(crate) fn layers(&self) -> Result<Vec<LocalLayer<'_>>, io::Error> {
-        let mut layer = self
-            .registry
-            .find_layer_by_id(&self.img.image.layer)
-            .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
-
-        let mut image_layers = vec![LocalLayer(self.registry, layer)];
-        while let Some(parent) = &layer.parent {
-            debug!("Layer has a parent: {}", parent);
+    pub(crate) fn layers(&self) -> Result<Vec<LocalLayer<'_>>, OciBootstrapError> {
+        Ok(match &self.img.source {
+            ImageSource::Containers(storage, image) => storage.image_layers(image)?,
+            ImageSource::DockerArchive(archive, image) => {
+                let diff_ids = self.config.rootfs().diff_ids();
+                if diff_ids.len() != image.layers.len() {
+                    return Err(OciBootstrapError::Custom(format!(
+                        "Image has {} layers but {} diff IDs",
+                        image.layers.len(),
+                        diff_ids.len()
+                    )));
+                }
+
+                zip(&image.layers, diff_ids)
+                    .map(|(layer, diff_id)| {
+                        Ok(LocalLayer(LayerSource::ArchiveEntry(
+                            Digest::from_oci_str(diff_id)?,
+                            archive,
+                            layer,
+                        )))
+                    })
+                    .collect::<Result<_, OciBootstrapError>>()?
+            }
+            ImageSource::OciLayout(layout, _) => self
+                .json
+                .as_ref()
+                .ok_or(OciBootstrapError::Custom(String::from(
+                    "OCI Image without a manifest",
+                )))?
+                .layers()
+                .iter()
+                .map(|desc| {
+                    let digest = Digest::from_oci_str(desc.digest())?;
+                    let path = layout.blob_path(&digest);
+                    OciImageLayout::check_blob_size(desc, &path)?;
+
+                    Ok(LocalLayer(LayerSource::Blob(digest, path)))
+                })
+                .collect::<Result<_, OciBootstrapError>>()?,
+        })
+    }
 
-            layer = self
-                .registry
-                .find_layer_by_id(parent)
-                .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+    /// Returns the digest of the image manifest, as recorded by the storage
+    pub(crate) fn digest(&self) -> Option<&Digest> {
+        self.digest.as_ref()
+    }
 
-            image_layers.push(LocalLayer(self.registry, layer));
-        }
+    /// Returns the digest of the image configuration, which also identifies all its layers
+    pub(crate) fn config_digest(&self) -> &Digest {
+        &self.config_digest
+    }
 
-        image_layers.reverse();
+    /// Returns when the image was created, if its configuration records it
+    pub(crate) fn created(&self) -> Option<Timestamp> {
+        let created = self.config.created().as_ref()?;
 
-        Ok(image_layers)
+        created
+            .parse()
+            .inspect_err(|e| warn!("Invalid image creation date {created}: {e}"))
+            .ok()
     }
 
     pub(crate) fn configuration(&self) -> &ImageConfiguration {
         &self.config
     }
+
+    /// Returns the platform of the selected image, which can differ from the requested one if
+    /// no variant was requested
+    pub(crate) fn platform(
+        &self,
+    ) -> Result<(Architecture, OperatingSystem, Option<Variant>), OciBootstrapError> {
+        let arch = Architecture::try_from(self.config.architecture().clone())?;
+        let os = OperatingSystem::try_from(self.config.os().clone())?;
+        let variant = image_variant(self.config.variant().as_deref());
+
+        Ok((arch, os, variant))
+    }
+
+    /// Returns the platform of the selected image, formatted as `os/arch[/variant]`
+    pub(crate) fn platform_string(&self) -> String {
+        config_platform(&self.config)
+    }
 }
 
 #[derive(Debug)]
-pub(crate) struct LocalLayer<'a>(&'a LocalRegistry, &'a LocalContainerLayer);
+enum LayerSource<'a> {
+    Containers(&'a ContainersStorage, &'a LocalContainerLayer),
+    ArchiveEntry(Digest, &'a DockerArchive, &'a str),
+    Blob(Digest, PathBuf),
+}
+
+/// Tar stream of a local containers storage layer
+pub(crate) type SplitArchive = TarSplitReader<'static, Box<dyn Read>>;
+
+#[derive(Debug)]
+pub(crate) struct LocalLayer<'a>(LayerSource<'a>);
 
 impl LocalLayer<'_> {
     pub(crate) fn digest(&self) -> Digest {
-        self.1.id.clone()
+        match &self.0 {
+            LayerSource::Containers(_, layer) => layer.id.clone(),
+            LayerSource::ArchiveEntry(digest, _, _) | LayerSource::Blob(digest, _) => {
+                digest.clone()
+            }
+        }
+    }
+
+    /// Returns the user and group IDs mappings the layer is stored with, if any
+    pub(crate) fn id_mappings(&self) -> (&[IdMapping], &[IdMapping]) {
+        match &self.0 {
+            LayerSource::Containers(_, layer) => (&layer.uidmap, &layer.gidmap),
+            LayerSource::ArchiveEntry(..) | LayerSource::Blob(..) => (&[], &[]),
+        }
+    }
+
+    /// Returns the size of the uncompressed tar stream of the layer, if it's known
+    pub(crate) fn size(&self) -> Option<u64> {
+        match &self.0 {
+            LayerSource::Containers(_, layer) => layer.diff_size,
+            LayerSource::ArchiveEntry(..) | LayerSource::Blob(..) => None,
+        }
+    }
+
+    /// Returns the uncompressed tar stream of the layer
+    ///
+    /// Nothing is unpacked: callers can process the tar entries as they see fit.
+    pub(crate) fn archive(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.0 {
+            LayerSource::Containers(storage, layer) => Self::storage_archive(storage, layer),
+            LayerSource::ArchiveEntry(_, archive, name) => {
+                debug!("Opening Layer {name} in {}", archive.path.display());
+
+                Self::decompress(archive.entry_reader(name)?)
+            }
+            LayerSource::Blob(_, path) => {
+                debug!("Opening Layer Blob {}", path.display());
+
+                Self::decompress(io::BufReader::new(File::open(path)?))
+            }
+        }
+    }
+
+    /// Returns the directory the layer files are already extracted to, along with the tar stream
+    /// of the layer, if the layer comes from the local containers storage
+    ///
+    /// The tar stream can seek over the files content without reading it, so that the files can
+    /// be copied from that directory instead. Their checksum isn't verified then.
+    pub(crate) fn extracted_archive(&self) -> io::Result<Option<(PathBuf, SplitArchive)>> {
+        match &self.0 {
+            LayerSource::Containers(storage, layer) => {
+                Ok(Some(Self::storage_split_archive(storage, layer)?))
+            }
+            LayerSource::ArchiveEntry(..) | LayerSource::Blob(..) => Ok(None),
+        }
+    }
+
+    fn storage_archive(
+        storage: &ContainersStorage,
+        layer: &LocalContainerLayer,
+    ) -> io::Result<Box<dyn Read>> {
+        let (_, archive) = Self::storage_split_archive(storage, layer)?;
+
+        Ok(Box::new(archive))
     }
 
-    pub(crate) fn archive(&self) -> io::Result<TarSplitReader<'_, Box<dyn Read>>> {
-        let split_path = self
-            .0
+    fn storage_split_archive(
+        storage: &ContainersStorage,
+        layer: &LocalContainerLayer,
+    ) -> io::Result<(PathBuf, SplitArchive)> {
+        // The layer content is stored uncompressed in the overlay diff directory, and the
+        // tar-split metadata is always gzip-compressed, whatever the original blob compression
+        // was. We still want to reject layers we don't know anything about.
+        let compression = layer
+            .compression
+            .map(Compression::try_from)
+            .transpose()?
+            .unwrap_or(Compression::Uncompressed);
+
+        debug!("Layer was created from a {compression:?} blob");
+
+        let split_path = storage
             .overlay_layers_dir()
-            .join(format!("{}.tar-split.gz", self.1.id.to_raw_string()));
+            .join(format!("{}.tar-split.gz", layer.id.to_raw_string()));
 
         debug!("Opening Tar Split Archive {}", split_path.display());
 
-        tar_split::from_path(
-            &self
-                .0
-                .storage_dir()
-                .join("overlay")
-                .join(self.1.id.to_raw_string())
-                .join("diff"),
-            &split_path,
+        let diff_path = storage
+            .storage_dir()
+            .join("overlay")
+            .join(layer.id.to_raw_string())
+            .join("diff");
+
+        let archive = tar_split::from_path(&diff_path, &split_path)?;
+
+        Ok((diff_path, archive))
+    }
+
+    fn decompress<R>(mut reader: R) -> io::Result<Box<dyn Read>>
+    where
+        R: io::BufRead + 'static,
+    {
+        let compression = Compression::from_magic(reader.fill_buf()?);
+
+        debug!("Layer blob is {compression:?}");
+
+        types::decoder(compression, reader)
+    }
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use test_log::test;
+
+    use types::Compression;
+
+    #[test]
+    fn test_layer_compression() {
+        assert_eq!(Compression::try_from(0).unwrap(), Compression::Uncompressed);
+        assert_eq!(Compression::try_from(2).unwrap(), Compression::Gzip);
+        assert_eq!(Compression::try_from(4).unwrap(), Compression::Zstd);
+    }
+
+    #[test]
+    fn test_layer_compression_unknown() {
+        Compression::try_from(42).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use serde_json::json;
+    use test_log::test;
+
+    use crate::local::{image_manifest_from_value, is_artifact};
+
+    #[test]
+    fn test_manifest_schema_2() {
+        let manifest = image_manifest_from_value(json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2,
+            },
+            "layers": [],
+        }))
+        .unwrap();
+
+        assert!(manifest.layers().is_empty());
+    }
+
+    #[test]
+    fn test_manifest_subject() {
+        let manifest = image_manifest_from_value(json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "artifactType": "application/vnd.dev.cosign.artifact.sig.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2,
+            },
+            "layers": [],
+            "subject": {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+                "size": 7143,
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            manifest.subject().as_ref().unwrap().digest(),
+            "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270"
+        );
+    }
+
+    #[test]
+    fn test_manifest_is_artifact() {
+        let manifest = |artifact_type: Option<&str>, config_media_type: &str| {
+            let mut manifest = json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": {
+                    "mediaType": config_media_type,
+                    "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                    "size": 2,
+                },
+                "layers": [],
+            });
+            if let Some(artifact_type) = artifact_type {
+                manifest["artifactType"] = json!(artifact_type);
+            }
+
+            image_manifest_from_value(manifest).unwrap()
+        };
+
+        assert!(!is_artifact(&manifest(
+            None,
+            "application/vnd.oci.image.config.v1+json"
+        )));
+        assert!(!is_artifact(&manifest(
+            None,
+            "application/vnd.docker.container.image.v1+json"
+        )));
+        assert!(is_artifact(&manifest(
+            Some("application/vnd.dev.cosign.artifact.sig.v1+json"),
+            "application/vnd.oci.empty.v1+json"
+        )));
+        assert!(is_artifact(&manifest(
+            None,
+            "application/vnd.dev.cosign.artifact.sig.v1+json"
+        )));
+    }
+
+    #[test]
+    fn test_manifest_schema_1() {
+        let err = image_manifest_from_value(json!({
+            "schemaVersion": 1,
+            "name": "library/debian",
+            "tag": "latest",
+            "architecture": "amd64",
+            "fsLayers": [
+                {
+                    "blobSum": "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46d4",
+                },
+            ],
+            "history": [
+                {
+                    "v1Compatibility": "{\"id\":\"e45a5af57b00862e5ef5782a9925979a02ba2b12dff832fd0991335f4a11e5c5\"}",
+                },
+            ],
+        }))
+        .unwrap_err();
+
+        assert!(err.to_string().contains("Schema 1"), "{err}");
+    }
+}
+
+#[cfg(test)]
+mod oci_layout_tests {
+    use std::{
+        fs,
+        io::{Read as _, Write as _},
+        path::Path,
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+    use serde_json::json;
+    use sha2::{Digest as _, Sha256};
+    use tar::{Builder, EntryType, Header};
+    use tempfile::TempDir;
+    use test_log::test;
+    use types::{Architecture, OciBootstrapError, OperatingSystem, Platform, Variant};
+
+    use crate::{
+        container::ContainerSpec, extract_layer, extract_to_dir, local::LocalRegistry,
+        ExtractOptions, ImageOptions,
+    };
+
+    fn write_blob(dir: &Path, content: &[u8]) -> String {
+        let digest = format!("{:x}", Sha256::digest(content));
+
+        let blobs_dir = dir.join("blobs").join("sha256");
+        fs::create_dir_all(&blobs_dir).unwrap();
+        fs::write(blobs_dir.join(&digest), content).unwrap();
+
+        format!("sha256:{digest}")
+    }
+
+    fn layer(path: &str, content: &str) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(content.len() as u64);
+        builder
+            .append_data(&mut header, path, content.as_bytes())
+            .unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn create_layout() -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        fs::write(
+            dir.path().join("oci-layout"),
+            json!({ "imageLayoutVersion": "1.0.0" }).to_string(),
+        )
+        .unwrap();
+
+        let lower = layer("hostname", "ocibootstrap");
+        let upper = layer("os-release", "ID=test");
+
+        let lower_blob = gzip(&lower);
+        let lower_digest = write_blob(dir.path(), &lower_blob);
+        let upper_digest = write_blob(dir.path(), &upper);
+
+        let config = json!({
+            "created": "2024-09-01T00:00:00Z",
+            "architecture": Architecture::host().unwrap().as_oci_str(),
+            "os": OperatingSystem::host().unwrap().as_oci_str(),
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [
+                    format!("sha256:{:x}", Sha256::digest(&lower)),
+                    format!("sha256:{:x}", Sha256::digest(&upper)),
+                ],
+            },
+            "history": [],
+        })
+        .to_string();
+        let config_digest = write_blob(dir.path(), config.as_bytes());
+
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": config_digest,
+                "size": config.len(),
+            },
+            "layers": [
+                {
+                    "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                    "digest": lower_digest,
+                    "size": lower_blob.len(),
+                },
+                {
+                    "mediaType": "application/vnd.oci.image.layer.v1.tar",
+                    "digest": upper_digest,
+                    "size": upper.len(),
+                },
+            ],
+        })
+        .to_string();
+        let manifest_digest = write_blob(dir.path(), manifest.as_bytes());
+
+        fs::write(
+            dir.path().join("index.json"),
+            json!({
+                "schemaVersion": 2,
+                "manifests": [
+                    {
+                        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                        "digest": manifest_digest,
+                        "size": manifest.len(),
+                        "annotations": {
+                            "org.opencontainers.image.ref.name": "latest",
+                        },
+                    },
+                ],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_oci_layout() {
+        let layout = create_layout();
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+        let manifest = image
+            .manifest_for_platform(
+                Architecture::host().unwrap(),
+                None,
+                OperatingSystem::host().unwrap(),
+            )
+            .unwrap()
+            .unwrap();
+
+        let root = TempDir::new().unwrap();
+        let layers = manifest.layers().unwrap();
+        assert_eq!(layers.len(), 2);
+
+        for layer in layers {
+            extract_layer(layer.archive().unwrap(), root.path(), false, &[]).unwrap();
+        }
+
+        assert_eq!(
+            fs::read_to_string(root.path().join("hostname")).unwrap(),
+            "ocibootstrap"
+        );
+        assert_eq!(
+            fs::read_to_string(root.path().join("os-release")).unwrap(),
+            "ID=test"
+        );
+    }
+
+    #[test]
+    fn test_oci_layout_digest_and_created() {
+        let layout = create_layout();
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+        let manifest = image
+            .manifest_for_platform(
+                Architecture::host().unwrap(),
+                None,
+                OperatingSystem::host().unwrap(),
+            )
+            .unwrap()
+            .unwrap();
+
+        let digest = manifest.digest().unwrap();
+        let manifest_path = layout
+            .path()
+            .join("blobs")
+            .join("sha256")
+            .join(digest.to_raw_string());
+        assert_eq!(
+            digest.to_oci_string(),
+            format!(
+                "sha256:{:x}",
+                Sha256::digest(fs::read(manifest_path).unwrap())
+            )
+        );
+
+        assert_eq!(
+            manifest.created(),
+            Some("2024-09-01T00:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_extract_to_dir() {
+        let layout = create_layout();
+        let root = TempDir::new().unwrap();
+        let output = root.path().join("rootfs");
+
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let report = extract_to_dir(
+            &spec,
+            &output,
+            &ExtractOptions {
+                image: ImageOptions {
+                    oci_layout: Some(layout.path().to_path_buf()),
+                    ..ImageOptions::default()
+                },
+                rootless: true,
+                paths: vec!["/os-release".into()],
+                ..ExtractOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(report.container, "docker.io/library/test:latest");
+        assert!(report.partitions.is_empty());
+        assert_eq!(
+            fs::read_to_string(output.join("os-release")).unwrap(),
+            "ID=test"
+        );
+        assert!(!output.join("hostname").exists());
+
+        let missing = ContainerSpec::from_container_name("docker.io/library/test:missing").unwrap();
+        let err = extract_to_dir(
+            &missing,
+            &output,
+            &ExtractOptions {
+                image: ImageOptions {
+                    oci_layout: Some(layout.path().to_path_buf()),
+                    ..ImageOptions::default()
+                },
+                ..ExtractOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&err, OciBootstrapError::ImageNotFound(name) if name == "docker.io/library/test:missing"),
+            "{err}"
+        );
+
+        let err = extract_to_dir(
+            &spec,
+            &output,
+            &ExtractOptions {
+                image: ImageOptions {
+                    oci_layout: Some(layout.path().to_path_buf()),
+                    platform: Some(Platform {
+                        os: OperatingSystem::Windows,
+                        arch: Architecture::host().unwrap(),
+                        variant: None,
+                    }),
+                    ..ImageOptions::default()
+                },
+                ..ExtractOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                OciBootstrapError::NoManifestForPlatform {
+                    os: OperatingSystem::Windows,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_oci_layout_blob_size_mismatch() {
+        let layout = create_layout();
+
+        let index_path = layout.path().join("index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&index_path).unwrap()).unwrap();
+        let size = index["manifests"][0]["size"].as_u64().unwrap();
+        index["manifests"][0]["size"] = json!(size + 1);
+        fs::write(&index_path, index.to_string()).unwrap();
+
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+        let err = image
+            .manifest_for_platform(
+                Architecture::host().unwrap(),
+                None,
+                OperatingSystem::host().unwrap(),
+            )
+            .unwrap_err();
+
+        assert!(
+            matches!(
+                err,
+                OciBootstrapError::BlobSizeMismatch { expected, actual }
+                    if expected == size + 1 && actual == size
+            ),
+            "{err}"
+        );
+    }
+
+    /// Moves the image of a layout into a nested index of the given media type, after some other
+    /// entries
+    fn nest_index(
+        layout: &Path,
+        media_type: &str,
+        entries: &[serde_json::Value],
+    ) -> serde_json::Value {
+        let index_path = layout.join("index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&index_path).unwrap()).unwrap();
+        let image_desc = index["manifests"][0].clone();
+
+        let mut manifests = entries.to_vec();
+        manifests.push(json!({
+            "mediaType": image_desc["mediaType"],
+            "digest": image_desc["digest"],
+            "size": image_desc["size"],
+        }));
+
+        let nested = json!({
+            "schemaVersion": 2,
+            "mediaType": media_type,
+            "manifests": manifests,
+        })
+        .to_string();
+        let nested_digest = write_blob(layout, nested.as_bytes());
+
+        index["manifests"] = json!([
+            {
+                "mediaType": media_type,
+                "digest": nested_digest,
+                "size": nested.len(),
+                "annotations": {
+                    "org.opencontainers.image.ref.name": "latest",
+                },
+            },
+        ]);
+        fs::write(&index_path, index.to_string()).unwrap();
+
+        image_desc
+    }
+
+    fn host_manifest_digest(registry: &LocalRegistry) -> String {
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+        let manifest = image
+            .manifest_for_platform(
+                Architecture::host().unwrap(),
+                None,
+                OperatingSystem::host().unwrap(),
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(manifest.layers().unwrap().len(), 2);
+
+        manifest.digest().unwrap().to_oci_string()
+    }
+
+    #[test]
+    fn test_oci_layout_nested_index() {
+        for media_type in [
+            "application/vnd.oci.image.index.v1+json",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        ] {
+            let layout = create_layout();
+            let image_desc = nest_index(layout.path(), media_type, &[]);
+
+            let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+            assert_eq!(host_manifest_digest(&registry), image_desc["digest"]);
+            assert_eq!(registry.images()[0].platforms.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_oci_layout_referrer() {
+        let layout = create_layout();
+
+        let index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(layout.path().join("index.json")).unwrap())
+                .unwrap();
+        let image_desc = &index["manifests"][0];
+
+        // A signature attached to the image, listed before it in the index
+        let empty_digest = write_blob(layout.path(), b"{}");
+        let signature = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "artifactType": "application/vnd.dev.cosign.artifact.sig.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": empty_digest,
+                "size": 2,
+            },
+            "layers": [],
+            "subject": {
+                "mediaType": image_desc["mediaType"],
+                "digest": image_desc["digest"],
+                "size": image_desc["size"],
+            },
+        })
+        .to_string();
+        let signature_digest = write_blob(layout.path(), signature.as_bytes());
+
+        let image_desc = nest_index(
+            layout.path(),
+            "application/vnd.oci.image.index.v1+json",
+            &[json!({
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": signature_digest,
+                "size": signature.len(),
+            })],
+        );
+
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+        assert_eq!(host_manifest_digest(&registry), image_desc["digest"]);
+
+        let images = registry.images();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].platforms.len(), 1);
+    }
+
+    #[test]
+    fn test_oci_layout_image_with_subject() {
+        let layout = create_layout();
+
+        let index_path = layout.path().join("index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&index_path).unwrap()).unwrap();
+        let image_desc = index["manifests"][0].clone();
+        let image_path = layout.path().join("blobs/sha256").join(
+            image_desc["digest"]
+                .as_str()
+                .unwrap()
+                .trim_start_matches("sha256:"),
+        );
+
+        // An image built on top of another one can refer to it through its subject
+        let mut manifest: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(image_path).unwrap()).unwrap();
+        manifest["subject"] = json!({
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+            "size": 7143,
+        });
+        let manifest = manifest.to_string();
+        let manifest_digest = write_blob(layout.path(), manifest.as_bytes());
+
+        index["manifests"][0]["digest"] = json!(manifest_digest);
+        index["manifests"][0]["size"] = json!(manifest.len());
+        fs::write(&index_path, index.to_string()).unwrap();
+
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+        assert_eq!(host_manifest_digest(&registry), manifest_digest);
+        assert_eq!(registry.images()[0].platforms.len(), 1);
+    }
+
+    /// Writes an image with a single layer for the given platform, and returns its descriptor
+    fn platform_image(
+        layout: &Path,
+        os: &str,
+        arch: &str,
+        variant: Option<&str>,
+    ) -> serde_json::Value {
+        let layer = layer("arch", arch);
+        let layer_digest = write_blob(layout, &layer);
+
+        let mut config = json!({
+            "architecture": arch,
+            "os": os,
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [format!("sha256:{:x}", Sha256::digest(&layer))],
+            },
+            "history": [],
+        });
+        let mut platform = json!({
+            "architecture": arch,
+            "os": os,
+        });
+        if let Some(variant) = variant {
+            config["variant"] = json!(variant);
+            platform["variant"] = json!(variant);
+        }
+        let config = config.to_string();
+        let config_digest = write_blob(layout, config.as_bytes());
+
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {
+                "mediaType": "application/vnd.docker.container.image.v1+json",
+                "digest": config_digest,
+                "size": config.len(),
+            },
+            "layers": [
+                {
+                    "mediaType": "application/vnd.docker.image.rootfs.diff.tar",
+                    "digest": layer_digest,
+                    "size": layer.len(),
+                },
+            ],
+        })
+        .to_string();
+
+        json!({
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "digest": write_blob(layout, manifest.as_bytes()),
+            "size": manifest.len(),
+            "platform": platform,
+        })
+    }
+
+    #[test]
+    fn test_oci_layout_docker_manifest_list() {
+        let layout = TempDir::new().unwrap();
+        fs::write(
+            layout.path().join("oci-layout"),
+            json!({ "imageLayoutVersion": "1.0.0" }).to_string(),
+        )
+        .unwrap();
+
+        let x86_64 = platform_image(layout.path(), "linux", "amd64", None);
+        let aarch64 = platform_image(layout.path(), "linux", "arm64", Some("v8"));
+        let armel = platform_image(layout.path(), "linux", "arm", Some("v6"));
+        let armhf = platform_image(layout.path(), "linux", "arm", Some("v7"));
+
+        // Build attestations are listed next to the images, with an unknown platform
+        let mut attestation = platform_image(layout.path(), "unknown", "unknown", None);
+        attestation["annotations"] = json!({
+            "vnd.docker.reference.type": "attestation-manifest",
+            "vnd.docker.reference.digest": x86_64["digest"],
+        });
+
+        let list = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+            "manifests": [attestation, armel, x86_64, armhf, aarch64],
+        })
+        .to_string();
+
+        fs::write(
+            layout.path().join("index.json"),
+            json!({
+                "schemaVersion": 2,
+                "manifests": [
+                    {
+                        "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+                        "digest": write_blob(layout.path(), list.as_bytes()),
+                        "size": list.len(),
+                        "annotations": {
+                            "org.opencontainers.image.ref.name": "latest",
+                        },
+                    },
+                ],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+
+        for (arch, variant, expected) in [
+            (Architecture::X86_64, None, &x86_64),
+            (Architecture::Arm64, None, &aarch64),
+            (Architecture::Arm, None, &armhf),
+            (Architecture::Arm, Some(Variant::V7), &armhf),
+            (Architecture::Arm, Some(Variant::V6), &armel),
+        ] {
+            let manifest = image
+                .manifest_for_platform(arch, variant, OperatingSystem::Linux)
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(
+                manifest.digest().unwrap().to_oci_string(),
+                expected["digest"],
+                "{arch} {variant:?}"
+            );
+        }
+
+        assert!(image
+            .manifest_for_platform(Architecture::Riscv64, None, OperatingSystem::Linux)
+            .unwrap()
+            .is_none());
+        assert!(image
+            .manifest_for_platform(Architecture::Arm, Some(Variant::V5), OperatingSystem::Linux)
+            .unwrap()
+            .is_none());
+
+        let images = registry.images();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].platforms.len(), 4);
+    }
+
+    #[test]
+    fn test_oci_layout_unknown_variant() {
+        let layout = TempDir::new().unwrap();
+        fs::write(
+            layout.path().join("oci-layout"),
+            json!({ "imageLayoutVersion": "1.0.0" }).to_string(),
+        )
+        .unwrap();
+
+        let image_desc = platform_image(layout.path(), "linux", "arm", Some("v9"));
+        let index = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [image_desc],
+        })
+        .to_string();
+
+        fs::write(
+            layout.path().join("index.json"),
+            json!({
+                "schemaVersion": 2,
+                "manifests": [
+                    {
+                        "mediaType": "application/vnd.oci.image.index.v1+json",
+                        "digest": write_blob(layout.path(), index.as_bytes()),
+                        "size": index.len(),
+                        "annotations": {
+                            "org.opencontainers.image.ref.name": "latest",
+                        },
+                    },
+                ],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+
+        // An unknown variant is treated like an image without a variant
+        for variant in [None, Some(Variant::V5), Some(Variant::V7)] {
+            let manifest = image
+                .manifest_for_platform(Architecture::Arm, variant, OperatingSystem::Linux)
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(
+                manifest.digest().unwrap().to_oci_string(),
+                image_desc["digest"],
+                "{variant:?}"
+            );
+            assert_eq!(
+                manifest.platform().unwrap(),
+                (Architecture::Arm, OperatingSystem::Linux, None)
+            );
+        }
+    }
+
+    #[test]
+    fn test_oci_layout_layer_stream() {
+        let layout = create_layout();
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+        let manifest = image
+            .manifest_for_platform(
+                Architecture::host().unwrap(),
+                None,
+                OperatingSystem::host().unwrap(),
+            )
+            .unwrap()
+            .unwrap();
+
+        // The first layer is gzip-compressed, the second one isn't.
+        let paths = manifest
+            .layers()
+            .unwrap()
+            .iter()
+            .map(|layer| {
+                let mut header = [0u8; 512];
+                layer.archive().unwrap().read_exact(&mut header).unwrap();
+
+                Header::from_byte_slice(&header)
+                    .path()
+                    .unwrap()
+                    .display()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(paths, vec!["hostname", "os-release"]);
+    }
+
+    #[test]
+    fn test_oci_layout_unknown_tag() {
+        let layout = create_layout();
+        let registry = LocalRegistry::from_oci_layout(layout.path()).unwrap();
+
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:other").unwrap();
+        assert!(registry.image_by_spec(&spec).is_none());
+    }
+}
+
+#[cfg(test)]
+mod docker_archive_tests {
+    use std::fs;
+
+    use serde_json::json;
+    use sha2::{Digest as _, Sha256};
+    use tar::{Builder, EntryType, Header};
+    use tempfile::TempDir;
+    use test_log::test;
+    use types::{Architecture, OperatingSystem};
+
+    use crate::{container::ContainerSpec, extract_layer, local::LocalRegistry};
+
+    fn append_file(builder: &mut Builder<Vec<u8>>, path: &str, content: &[u8]) {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(content.len() as u64);
+
+        builder.append_data(&mut header, path, content).unwrap();
+    }
+
+    fn layer(path: &str, content: &str) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, path, content.as_bytes());
+        builder.into_inner().unwrap()
+    }
+
+    fn create_archive() -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        let lower = layer("hostname", "ocibootstrap");
+        let upper = layer("os-release", "ID=test");
+
+        let config = json!({
+            "architecture": Architecture::host().unwrap().as_oci_str(),
+            "os": OperatingSystem::host().unwrap().as_oci_str(),
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [
+                    format!("sha256:{:x}", Sha256::digest(&lower)),
+                    format!("sha256:{:x}", Sha256::digest(&upper)),
+                ],
+            },
+            "history": [],
+        })
+        .to_string();
+        let config_name = format!("{:x}.json", Sha256::digest(&config));
+
+        let manifest = json!([
+            {
+                "Config": config_name,
+                "RepoTags": ["test:latest"],
+                "Layers": ["lower/layer.tar", "upper/layer.tar"],
+            },
+        ])
+        .to_string();
+
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, &config_name, config.as_bytes());
+        append_file(&mut builder, "lower/layer.tar", &lower);
+
+        // docker save deduplicates identical layers through symlinks
+        append_file(&mut builder, "layers/upper.tar", &upper);
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "upper/layer.tar", "../layers/upper.tar")
+            .unwrap();
+
+        append_file(&mut builder, "manifest.json", manifest.as_bytes());
+
+        fs::write(
+            dir.path().join("archive.tar"),
+            builder.into_inner().unwrap(),
+        )
+        .unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_docker_archive() {
+        let dir = create_archive();
+        let registry = LocalRegistry::from_docker_archive(&dir.path().join("archive.tar")).unwrap();
+
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:latest").unwrap();
+        let image = registry.image_by_spec(&spec).unwrap();
+        let manifest = image
+            .manifest_for_platform(
+                Architecture::host().unwrap(),
+                None,
+                OperatingSystem::host().unwrap(),
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            manifest.platform().unwrap(),
+            (
+                Architecture::host().unwrap(),
+                OperatingSystem::host().unwrap(),
+                None
+            )
+        );
+
+        let root = TempDir::new().unwrap();
+        let layers = manifest.layers().unwrap();
+        assert_eq!(layers.len(), 2);
+
+        for layer in layers {
+            extract_layer(layer.archive().unwrap(), root.path(), false, &[]).unwrap();
+        }
+
+        assert_eq!(
+            fs::read_to_string(root.path().join("hostname")).unwrap(),
+            "ocibootstrap"
+        );
+        assert_eq!(
+            fs::read_to_string(root.path().join("os-release")).unwrap(),
+            "ID=test"
+        );
+    }
+
+    #[test]
+    fn test_docker_archive_unknown_tag() {
+        let dir = create_archive();
+        let registry = LocalRegistry::from_docker_archive(&dir.path().join("archive.tar")).unwrap();
+
+        let spec = ContainerSpec::from_container_name("docker.io/library/test:other").unwrap();
+        assert!(registry.image_by_spec(&spec).is_none());
+    }
+}
+
+#[cfg(test)]
+mod image_selection_tests {
+    use serde_json::json;
+    use test_log::test;
+
+    use crate::{
+        container::ContainerSpec,
+        local::{
+            ContainersStorage, ImageSource, LocalContainerImage, LocalRegistry, RegistryStorage,
+        },
+    };
+
+    const STABLE_ID: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const STABLE_DIGEST: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const TESTING_ID: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+    const TESTING_DIGEST: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    fn image(id: &str, digest: &str, name: &str) -> LocalContainerImage {
+        serde_json::from_value(json!({
+            "id": id,
+            "digest": format!("sha256:{digest}"),
+            "names": [name],
+            "created": "2024-09-01T00:00:00Z",
+            "names-history": [],
+            "layer": id,
+            "metadata": "{}",
+            "big-data-names": [],
+            "big-data-sizes": {},
+            "big-data-digests": {},
+        }))
+        .unwrap()
+    }
+
+    fn registry() -> LocalRegistry {
+        LocalRegistry {
+            storage: RegistryStorage::Containers(ContainersStorage {
+                storage_dir: "/nonexistent".into(),
+                images: vec![
+                    image(STABLE_ID, STABLE_DIGEST, "docker.io/library/debian:stable"),
+                    image(
+                        TESTING_ID,
+                        TESTING_DIGEST,
+                        "docker.io/library/debian:testing",
+                    ),
+                ],
+                layers: Vec::new(),
+            }),
+        }
+    }
+
+    fn selected_id(registry: &LocalRegistry, name: &str) -> Option<String> {
+        let spec = ContainerSpec::from_container_name(name).unwrap();
+
+        registry
+            .image_by_spec(&spec)
+            .map(|image| match image.source {
+                ImageSource::Containers(_, image) => image.id.to_raw_string(),
+                ImageSource::DockerArchive(..) | ImageSource::OciLayout(..) => unreachable!(),
+            })
+    }
+
+    #[test]
+    fn test_select_by_tag() {
+        let registry = registry();
+
+        assert_eq!(
+            selected_id(&registry, "docker.io/library/debian:stable").as_deref(),
+            Some(STABLE_ID)
+        );
+        assert_eq!(
+            selected_id(&registry, "docker.io/library/debian:testing").as_deref(),
+            Some(TESTING_ID)
+        );
+        assert!(selected_id(&registry, "docker.io/library/debian:unstable").is_none());
+    }
+
+    #[test]
+    fn test_select_by_digest() {
+        let registry = registry();
+
+        assert_eq!(
+            selected_id(
+                &registry,
+                &format!("docker.io/library/debian@sha256:{TESTING_DIGEST}")
+            )
+            .as_deref(),
+            Some(TESTING_ID)
+        );
+        assert_eq!(
+            selected_id(
+                &registry,
+                &format!("docker.io/library/debian@sha256:{STABLE_ID}")
+            )
+            .as_deref(),
+            Some(STABLE_ID)
+        );
+    }
+
+    #[test]
+    fn test_select_by_search_registries() {
+        let registry = registry();
+        let cfg = toml::from_str(
+            r#"unqualified-search-registries = ["registry.fedoraproject.org", "docker.io"]"#,
+        )
+        .unwrap();
+
+        let specs = ContainerSpec::from_search_registries("debian:testing", Some(&cfg)).unwrap();
+        let (spec, image) = registry.image_by_specs(specs).unwrap();
+
+        assert_eq!(spec.to_oci_string(), "docker.io/library/debian:testing");
+        assert!(matches!(
+            image.source,
+            ImageSource::Containers(_, image) if image.id.to_raw_string() == TESTING_ID
+        ));
+
+        let specs = ContainerSpec::from_search_registries("debian:unstable", Some(&cfg)).unwrap();
+        assert!(registry.image_by_specs(specs).is_none());
+    }
+
+    #[test]
+    fn test_select_by_digest_wrong_repository() {
+        let registry = registry();
+
+        assert!(selected_id(
+            &registry,
+            &format!("docker.io/library/ubuntu@sha256:{STABLE_DIGEST}")
+        )
+        .is_none());
+    }
+}
+
+#[cfg(test)]
+mod image_list_tests {
+    use std::{fs, path::Path};
+
+    use serde_json::json;
+    use tempfile::TempDir;
+    use test_log::test;
+    use types::Digest;
+
+    use crate::local::{digest_to_oci_base64, storage_root_from_conf, LocalRegistry};
+
+    const DEBIAN_ID: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const DEBIAN_CONFIG: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const FEDORA_ID: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+    const FEDORA_CONFIG: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    fn write_image(images_dir: &Path, id: &str, config_digest: &str, arch: &str) {
+        let dir = images_dir.join(id);
+        fs::create_dir_all(&dir).unwrap();
+
+        let config_digest = Digest::from_oci_str(&format!("sha256:{config_digest}")).unwrap();
+        fs::write(
+            dir.join("manifest"),
+            json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": {
+                    "mediaType": "application/vnd.oci.image.config.v1+json",
+                    "digest": config_digest.to_oci_string(),
+                    "size": 0,
+                },
+                "layers": [],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        fs::write(
+            dir.join(digest_to_oci_base64(&config_digest)),
+            json!({
+                "architecture": arch,
+                "os": "linux",
+                "rootfs": {
+                    "type": "layers",
+                    "diff_ids": [],
+                },
+                "history": [],
+            })
+            .to_string(),
+        )
+        .unwrap();
+    }
+
+    fn image_entry(id: &str, name: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "digest": format!("sha256:{id}"),
+            "names": [name],
+            "created": "2024-09-01T00:00:00Z",
+            "names-history": [],
+            "layer": id,
+            "metadata": "{}",
+            "big-data-names": [],
+            "big-data-sizes": {},
+            "big-data-digests": {},
+        })
+    }
+
+    fn create_storage() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let images_dir = dir.path().join("storage/overlay-images");
+        let layers_dir = dir.path().join("storage/overlay-layers");
+        fs::create_dir_all(&images_dir).unwrap();
+        fs::create_dir_all(&layers_dir).unwrap();
+
+        write_image(&images_dir, DEBIAN_ID, DEBIAN_CONFIG, "arm64");
+        write_image(&images_dir, FEDORA_ID, FEDORA_CONFIG, "amd64");
+
+        fs::write(
+            images_dir.join("images.json"),
+            json!([
+                image_entry(DEBIAN_ID, "docker.io/library/debian:stable"),
+                image_entry(FEDORA_ID, "registry.fedoraproject.org/fedora:40"),
+            ])
+            .to_string(),
         )
+        .unwrap();
+        fs::write(layers_dir.join("layers.json"), "[]").unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_list_images() {
+        let dir = create_storage();
+        let registry = LocalRegistry::with_base_dir(dir.path()).unwrap();
+
+        let images = registry.images();
+        assert_eq!(images.len(), 2);
+
+        assert_eq!(images[0].names, ["docker.io/library/debian:stable"]);
+        assert_eq!(images[0].id, format!("sha256:{DEBIAN_ID}"));
+        assert_eq!(images[0].platforms, ["linux/arm64"]);
+        assert!(images[0].created.is_some());
+
+        assert_eq!(images[1].names, ["registry.fedoraproject.org/fedora:40"]);
+        assert_eq!(images[1].id, format!("sha256:{FEDORA_ID}"));
+        assert_eq!(images[1].platforms, ["linux/amd64"]);
+    }
+
+    #[test]
+    fn test_storage_root() {
+        let dir = create_storage();
+        let registry = LocalRegistry::new(Some(&dir.path().join("storage"))).unwrap();
+
+        let images = registry.images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].names, ["docker.io/library/debian:stable"]);
+    }
+
+    #[test]
+    fn test_storage_conf() {
+        let dir = create_storage();
+        let conf = dir.path().join("storage.conf");
+        fs::write(
+            &conf,
+            format!(
+                "[storage]\ndriver = \"overlay\"\ngraphroot = \"{}\"\n",
+                dir.path().join("storage").display()
+            ),
+        )
+        .unwrap();
+
+        let root = storage_root_from_conf(&conf).unwrap().unwrap();
+        assert_eq!(root, dir.path().join("storage"));
+
+        let registry = LocalRegistry::new(Some(&root)).unwrap();
+        assert_eq!(registry.images().len(), 2);
+
+        fs::write(&conf, "[storage]\ndriver = \"overlay\"\n").unwrap();
+        assert_eq!(storage_root_from_conf(&conf).unwrap(), None);
     }
 }
@@ -0,0 +1,200 @@
+use std::{env, ffi::OsStr, os::unix::fs::PermissionsExt as _};
+
+use log::debug;
+use loopdev::LoopControl;
+use types::OciBootstrapError;
+
+use crate::layout::Filesystem;
+
+/// Returns the external tools needed to create a device with the given filesystems
+pub(crate) fn required_tools<'a, I>(filesystems: I) -> Vec<&'static str>
+where
+    I: IntoIterator<Item = &'a Filesystem>,
+{
+    let mut tools = vec!["lsblk"];
+
+    for fs in filesystems {
+        let fs_tools: &[&str] = match fs {
+            // FAT12 and FAT16 are always created by mkfs.vfat
+            #[cfg(feature = "native-fat")]
+            Filesystem::Fat32(params) if params.fat_bits.is_none_or(|bits| bits == 32) => &[],
+            Filesystem::Fat32(_) => &["mkfs.vfat"],
+            Filesystem::Ext4(_) => &["mkfs.ext4"],
+            Filesystem::Btrfs(params) if params.subvolumes.is_empty() => &["mkfs.btrfs"],
+            Filesystem::Btrfs(_) => &["mkfs.btrfs", "btrfs"],
+            Filesystem::Swap => &["mkswap"],
+            Filesystem::Raw(_) | Filesystem::Squashfs(_) => &[],
+        };
+
+        for tool in fs_tools {
+            if !tools.contains(tool) {
+                tools.push(tool);
+            }
+        }
+    }
+
+    tools
+}
+
+/// Returns the tools that can't be found in a PATH-like list of directories
+fn missing_tools<'a>(tools: &[&'a str], path: &OsStr) -> Vec<&'a str> {
+    tools
+        .iter()
+        .copied()
+        .filter(|tool| {
+            !env::split_paths(path).any(|dir| {
+                dir.join(tool).metadata().is_ok_and(|metadata| {
+                    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+                })
+            })
+        })
+        .collect()
+}
+
+/// Returns whether a tool can be found in the PATH
+pub(crate) fn is_in_path(tool: &str) -> bool {
+    missing_tools(&[tool], &env::var_os("PATH").unwrap_or_default()).is_empty()
+}
+
+/// Checks that everything needed to create a device with the given filesystems is there, so that
+/// we don't fail halfway through
+///
+/// # Errors
+///
+/// If any tool is missing from the PATH, or if we can't create loop devices. The error lists all
+/// the missing requirements at once.
+pub(crate) fn check_device_requirements(tools: &[&str]) -> Result<(), OciBootstrapError> {
+    check_requirements(tools, &env::var_os("PATH").unwrap_or_default(), true)
+}
+
+/// Checks that the tools needed to extract an image to a directory are there, so that we don't
+/// fail after the extraction
+///
+/// # Errors
+///
+/// If any tool is missing from the PATH. The error lists all the missing tools at once.
+pub(crate) fn check_dir_requirements(tools: &[&str]) -> Result<(), OciBootstrapError> {
+    check_requirements(tools, &env::var_os("PATH").unwrap_or_default(), false)
+}
+
+fn check_requirements(
+    tools: &[&str],
+    path: &OsStr,
+    loop_devices: bool,
+) -> Result<(), OciBootstrapError> {
+    debug!("Checking for {}", tools.join(", "));
+
+    let mut missing = missing_tools(tools, path)
+        .into_iter()
+        .map(|tool| format!("{tool} isn't in PATH"))
+        .collect::<Vec<_>>();
+
+    if loop_devices {
+        if let Err(e) = LoopControl::open() {
+            missing.push(format!(
+                "Loop devices can't be created ({e}), ocibootstrap needs to run as root"
+            ));
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(OciBootstrapError::Custom(format!(
+            "Missing requirements: {}",
+            missing.join("; ")
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod preflight_tests {
+    use std::{
+        fs::{self, Permissions},
+        os::unix::fs::PermissionsExt as _,
+    };
+
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::{
+        layout::{ExtParameters, FatParameters, Filesystem},
+        preflight::{check_requirements, missing_tools, required_tools},
+    };
+
+    #[test]
+    fn test_missing_tools() {
+        let bin = TempDir::new().unwrap();
+        for (tool, mode) in [("lsblk", 0o755), ("mkfs.ext4", 0o755), ("mkfs.vfat", 0o644)] {
+            let path = bin.path().join(tool);
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, Permissions::from_mode(mode)).unwrap();
+        }
+
+        let filesystems = [
+            Filesystem::Fat32(FatParameters {
+                volume_id: None,
+                label: None,
+                fat_bits: None,
+                heads: None,
+                sectors_per_track: None,
+                efi_boot: None,
+            }),
+            Filesystem::Ext4(ExtParameters::default()),
+            Filesystem::Swap,
+        ];
+        let tools = required_tools(&filesystems);
+
+        let expected: &[&str] = if cfg!(feature = "native-fat") {
+            &["mkswap"]
+        } else {
+            // mkfs.vfat isn't executable
+            &["mkfs.vfat", "mkswap"]
+        };
+
+        assert_eq!(missing_tools(&tools, bin.path().as_os_str()), expected);
+        assert_eq!(
+            missing_tools(&tools, "".as_ref()),
+            tools.as_slice(),
+            "{tools:?}"
+        );
+
+        let msg = check_requirements(&tools, bin.path().as_os_str(), true)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("mkswap isn't in PATH"), "{msg}");
+        assert!(!msg.contains("lsblk"), "{msg}");
+    }
+
+    #[test]
+    fn test_check_dir_requirements() {
+        let bin = TempDir::new().unwrap();
+        let path = bin.path().join("chroot");
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o755)).unwrap();
+
+        check_requirements(&["chroot"], bin.path().as_os_str(), false).unwrap();
+
+        let msg = check_requirements(&["chroot", "setfiles"], bin.path().as_os_str(), false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.ends_with("Missing requirements: setfiles isn't in PATH"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn test_required_tools_fat16() {
+        let filesystems = [Filesystem::Fat32(FatParameters {
+            volume_id: None,
+            label: None,
+            fat_bits: Some(16),
+            heads: None,
+            sectors_per_track: None,
+            efi_boot: None,
+        })];
+
+        assert_eq!(required_tools(&filesystems), ["lsblk", "mkfs.vfat"]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    fs::File,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use log::debug;
+use serde::Serialize;
+use sha2::{Digest as _, Sha256};
+
+/// How to output what a subcommand did
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Only log what is being done
+    #[default]
+    Text,
+
+    /// Print a JSON report once done
+    Json,
+}
+
+/// A partition, as laid out on the output device
+#[derive(Debug, Serialize)]
+pub(crate) struct PartitionReport {
+    /// Partition PARTUUID, if the partition has been created
+    pub(crate) guid: Option<String>,
+
+    /// GPT Partition Type GUID, or MBR Partition Type
+    #[serde(rename = "type")]
+    pub(crate) kind: String,
+
+    pub(crate) offset_lba: usize,
+    pub(crate) size_bytes: usize,
+    pub(crate) filesystem: String,
+    pub(crate) mount_point: Option<String>,
+}
+
+/// What a subcommand did, for scripts driving ocibootstrap
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub(crate) container: String,
+    pub(crate) manifest_digest: Option<String>,
+    pub(crate) image_created: Option<String>,
+    pub(crate) platform: String,
+    pub(crate) output: String,
+    pub(crate) partitions: Vec<PartitionReport>,
+}
+
+impl Report {
+    /// Returns the digest of the image manifest, if the image storage records it
+    ///
+    /// Along with [`Report::image_created`], this tells whether a device built previously is
+    /// older than the image. A dry run of [`crate::build_device`] is enough to get them.
+    #[must_use]
+    pub fn manifest_digest(&self) -> Option<&str> {
+        self.manifest_digest.as_deref()
+    }
+
+    /// Returns when the image was created, in RFC 3339 format, if its configuration records it
+    #[must_use]
+    pub fn image_created(&self) -> Option<&str> {
+        self.image_created.as_deref()
+    }
+
+    /// Prints the report as JSON on the standard output
+    ///
+    /// # Errors
+    ///
+    /// If the standard output can't be written to.
+    pub fn print(&self) -> Result<(), io::Error> {
+        let mut stdout = io::stdout().lock();
+
+        serde_json::to_writer_pretty(&mut stdout, self)?;
+        writeln!(stdout)
+    }
+
+    /// Prints the layout of the partitions, one per line, on the standard output
+    ///
+    /// # Errors
+    ///
+    /// If the standard output can't be written to.
+    pub fn print_partitions(&self) -> Result<(), io::Error> {
+        let mut stdout = io::stdout().lock();
+
+        for (idx, part) in self.partitions.iter().enumerate() {
+            writeln!(
+                stdout,
+                "Partition {idx}: Type {}, Offset LBA {}, Size {} bytes, Filesystem {}, Mount Point {}",
+                part.kind,
+                part.offset_lba,
+                part.size_bytes,
+                part.filesystem,
+                part.mount_point.as_deref().unwrap_or("none")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A partition, as recorded in the image sidecar
+#[derive(Debug, Serialize)]
+pub(crate) struct SidecarPartition {
+    pub(crate) partuuid: Option<String>,
+    pub(crate) filesystem: String,
+    pub(crate) filesystem_uuid: Option<String>,
+}
+
+/// Description of a produced image, written next to it for distribution
+#[derive(Debug, Serialize)]
+pub(crate) struct ImageSidecar {
+    pub(crate) sha256: String,
+    pub(crate) table: String,
+    pub(crate) partitions: Vec<SidecarPartition>,
+}
+
+impl ImageSidecar {
+    /// Hashes the image, which must have been synced and released beforehand
+    pub(crate) fn new(
+        image: &Path,
+        table: String,
+        partitions: Vec<SidecarPartition>,
+    ) -> Result<Self, io::Error> {
+        debug!("Computing the SHA-256 of {}", image.display());
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(image)?, &mut hasher)?;
+
+        Ok(Self {
+            sha256: format!("{:x}", hasher.finalize()),
+            table,
+            partitions,
+        })
+    }
+
+    /// Writes the sidecar as `<image>.json`, and returns its path
+    pub(crate) fn write(&self, image: &Path) -> Result<PathBuf, io::Error> {
+        let mut path = image.as_os_str().to_owned();
+        path.push(".json");
+        let path = PathBuf::from(path);
+
+        debug!("Writing image sidecar to {}", path.display());
+
+        let mut file = File::create(&path)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        writeln!(file)?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod sidecar_tests {
+    use std::fs;
+
+    use serde_json::Value;
+    use sha2::{Digest as _, Sha256};
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::report::{ImageSidecar, SidecarPartition};
+
+    #[test]
+    fn test_sidecar_sha256() {
+        let dir = TempDir::new().unwrap();
+        let image = dir.path().join("image.img");
+
+        let content: Vec<u8> = (0..(4 << 20))
+            .map(|idx: u32| idx.to_le_bytes()[1])
+            .collect();
+        fs::write(&image, &content).unwrap();
+
+        let sidecar = ImageSidecar::new(
+            &image,
+            String::from("gpt"),
+            vec![SidecarPartition {
+                partuuid: Some(String::from("b921b045-1df0-41c3-af44-4c6f280d3fae")),
+                filesystem: String::from("ext4"),
+                filesystem_uuid: None,
+            }],
+        )
+        .unwrap();
+
+        let path = sidecar.write(&image).unwrap();
+        assert_eq!(path, dir.path().join("image.img.json"));
+
+        let written: Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(
+            written["sha256"],
+            Value::String(format!(
+                "{:x}",
+                Sha256::digest(fs::read(image.as_path()).unwrap())
+            ))
+        );
+        assert_eq!(written["table"], "gpt");
+        assert_eq!(written["partitions"][0]["filesystem"], "ext4");
+    }
+}
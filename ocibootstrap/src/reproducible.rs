@@ -0,0 +1,189 @@
+use std::env;
+
+use jiff::Timestamp;
+use log::debug;
+use oci_spec::image::ImageConfiguration;
+use sha2::{Digest as _, Sha256};
+use types::{Digest, OciBootstrapError};
+use uuid::{Builder, Uuid};
+
+/// Environment variable holding the timestamp to use instead of the current time, as defined by
+/// <https://reproducible-builds.org/specs/source-date-epoch/>
+pub(crate) const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Identifiers and timestamp to create a filesystem with, instead of random ones and the current
+/// time
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FilesystemIds {
+    pub(crate) uuid: Uuid,
+    pub(crate) hash_seed: Uuid,
+    pub(crate) timestamp: u64,
+}
+
+/// Derives all the identifiers that are usually random from a seed, so that building an image
+/// twice from the same inputs gives the same partition tables and freshly created filesystems.
+///
+/// The files extracted into the filesystems still get their change times and some metadata from
+/// the kernel, so a populated device isn't byte-identical from one build to the next.
+#[derive(Clone, Debug)]
+pub(crate) struct Reproducible {
+    seed: [u8; 32],
+    timestamp: u64,
+}
+
+impl Reproducible {
+    pub(crate) fn new(seed: &[u8], timestamp: u64) -> Self {
+        Self {
+            seed: Sha256::digest(seed).into(),
+            timestamp,
+        }
+    }
+
+    /// Seeds the identifiers from the digest of the image configuration, which also identifies
+    /// all its layers.
+    ///
+    /// The timestamp is taken from `SOURCE_DATE_EPOCH` if set, or from the image creation date.
+    pub(crate) fn from_configuration(
+        digest: &Digest,
+        cfg: &ImageConfiguration,
+    ) -> Result<Self, OciBootstrapError> {
+        let timestamp = if let Ok(epoch) = env::var(SOURCE_DATE_EPOCH) {
+            epoch.parse().map_err(|_err| {
+                OciBootstrapError::Custom(format!("Invalid {SOURCE_DATE_EPOCH} value {epoch}"))
+            })?
+        } else {
+            let created = cfg
+                .created()
+                .as_ref()
+                .and_then(|created| created.parse::<Timestamp>().ok())
+                .ok_or(OciBootstrapError::Custom(format!(
+                    "Reproducible images need either {SOURCE_DATE_EPOCH} or an image creation date"
+                )))?;
+
+            u64::try_from(created.as_second()).map_err(|_err| {
+                OciBootstrapError::Custom(format!("Invalid image creation date {created}"))
+            })?
+        };
+
+        debug!("Reproducible image timestamp is {timestamp}");
+
+        Ok(Self::new(digest.to_oci_string().as_bytes(), timestamp))
+    }
+
+    fn hash(&self, name: &str) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(self.seed)
+            .chain_update(name.as_bytes())
+            .finalize()
+            .into()
+    }
+
+    fn uuid(&self, name: &str) -> Uuid {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&self.hash(name)[..16]);
+
+        Builder::from_random_bytes(bytes).into_uuid()
+    }
+
+    /// Returns the GPT Disk GUID
+    pub(crate) fn disk_guid(&self) -> Uuid {
+        self.uuid("disk")
+    }
+
+    /// Returns the MBR Disk Identifier
+    pub(crate) fn disk_id(&self) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.hash("disk")[..4]);
+
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Returns the GPT Unique Partition GUID of the partition at a given index
+    pub(crate) fn partition_guid(&self, idx: usize) -> Uuid {
+        self.uuid(&format!("partition.{idx}"))
+    }
+
+    /// Returns the identifiers of the filesystem in the partition at a given index
+    pub(crate) fn filesystem(&self, idx: usize) -> FilesystemIds {
+        FilesystemIds {
+            uuid: self.uuid(&format!("partition.{idx}.fs")),
+            hash_seed: self.uuid(&format!("partition.{idx}.fs.hash_seed")),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod reproducible_tests {
+    use oci_spec::image::ImageConfiguration;
+    use test_log::test;
+    use types::{Digest, DigestAlgorithm};
+
+    use crate::reproducible::{Reproducible, SOURCE_DATE_EPOCH};
+
+    #[test]
+    fn test_reproducible_ids() {
+        let first = Reproducible::new(b"config", 1_700_000_000);
+        let second = Reproducible::new(b"config", 1_700_000_000);
+
+        assert_eq!(first.disk_guid(), second.disk_guid());
+        assert_eq!(first.disk_id(), second.disk_id());
+        assert_eq!(first.partition_guid(1), second.partition_guid(1));
+        assert_eq!(first.filesystem(1).uuid, second.filesystem(1).uuid);
+
+        assert_ne!(first.partition_guid(0), first.partition_guid(1));
+        assert_ne!(first.filesystem(0).uuid, first.filesystem(1).uuid);
+        assert_ne!(first.filesystem(0).uuid, first.filesystem(0).hash_seed);
+
+        let other = Reproducible::new(b"other config", 1_700_000_000);
+        assert_ne!(first.disk_guid(), other.disk_guid());
+    }
+
+    #[test]
+    fn test_reproducible_configuration_seed() {
+        // Labels are stored in a HashMap, so the configuration doesn't serialize the same way
+        // from one run to the next. Only the digest must be used as a seed.
+        let config = |labels: serde_json::Value| -> ImageConfiguration {
+            serde_json::from_value(serde_json::json!({
+                "created": "2024-09-01T00:00:00Z",
+                "architecture": "arm64",
+                "os": "linux",
+                "config": { "Labels": labels },
+                "rootfs": { "type": "layers", "diff_ids": [] },
+                "history": [],
+            }))
+            .unwrap()
+        };
+
+        assert!(std::env::var(SOURCE_DATE_EPOCH).is_err());
+
+        let digest = Digest::new(
+            DigestAlgorithm::Sha256,
+            "bcbd60a3e3e8d3c7a1a7bc39fd8cdbb4b8e2cbbde7d2e0d3fd5bd1c3c6c3d1a0",
+        )
+        .unwrap();
+        let first = Reproducible::from_configuration(
+            &digest,
+            &config(serde_json::json!({ "a": "1", "b": "2", "c": "3" })),
+        )
+        .unwrap();
+        let second = Reproducible::from_configuration(
+            &digest,
+            &config(serde_json::json!({ "c": "3", "b": "2", "a": "1" })),
+        )
+        .unwrap();
+        assert_eq!(first.disk_guid(), second.disk_guid());
+        assert_eq!(first.filesystem(0).uuid, second.filesystem(0).uuid);
+
+        let other = Reproducible::from_configuration(
+            &Digest::new(
+                DigestAlgorithm::Sha256,
+                "0cbd60a3e3e8d3c7a1a7bc39fd8cdbb4b8e2cbbde7d2e0d3fd5bd1c3c6c3d1a0",
+            )
+            .unwrap(),
+            &config(serde_json::json!({ "a": "1", "b": "2", "c": "3" })),
+        )
+        .unwrap();
+        assert_ne!(first.disk_guid(), other.disk_guid());
+    }
+}
@@ -0,0 +1,150 @@
+use alloc::collections::BTreeMap;
+use std::{
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use oci_spec::image::ImageConfiguration;
+use serde::Serialize;
+
+use crate::create_file_in_root;
+
+/// Where the runtime configuration is stored, relative to the root filesystem
+pub(crate) const RUNTIME_CONFIG_PATH: &str = ".ocibootstrap/config.json";
+
+/// The parts of an image configuration needed to run it the way the container would
+#[derive(Debug, Default, Eq, PartialEq, Serialize)]
+pub(crate) struct RuntimeConfig {
+    pub(crate) env: Vec<String>,
+    pub(crate) entrypoint: Vec<String>,
+    pub(crate) cmd: Vec<String>,
+    pub(crate) working_dir: Option<String>,
+    pub(crate) user: Option<String>,
+    pub(crate) labels: BTreeMap<String, String>,
+}
+
+impl From<&ImageConfiguration> for RuntimeConfig {
+    fn from(config: &ImageConfiguration) -> Self {
+        let Some(config) = config.config() else {
+            return Self::default();
+        };
+
+        Self {
+            env: config.env().clone().unwrap_or_default(),
+            entrypoint: config.entrypoint().clone().unwrap_or_default(),
+            cmd: config.cmd().clone().unwrap_or_default(),
+            working_dir: config.working_dir().clone(),
+            user: config.user().clone(),
+            labels: config
+                .labels()
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// Writes the configuration as JSON in the root filesystem, and returns its path
+    ///
+    /// The image symlinks aren't followed, so that the configuration is always written within
+    /// the root filesystem.
+    pub(crate) fn write(&self, root: &Path) -> Result<PathBuf, io::Error> {
+        let (path, mut file) = create_file_in_root(root, Path::new(RUNTIME_CONFIG_PATH), false)?;
+
+        debug!("Writing runtime configuration to {}", path.display());
+
+        serde_json::to_writer_pretty(&mut file, self)?;
+        writeln!(file)?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod runtime_config_tests {
+    use std::{fs, os::unix::fs as unix_fs};
+
+    use oci_spec::image::ImageConfiguration;
+    use serde_json::Value;
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::runtime::{RuntimeConfig, RUNTIME_CONFIG_PATH};
+
+    fn configuration(config: &Value) -> ImageConfiguration {
+        serde_json::from_value(serde_json::json!({
+            "architecture": "arm64",
+            "os": "linux",
+            "config": config,
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": [],
+            },
+            "history": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_write_runtime_config() {
+        let config = RuntimeConfig::from(&configuration(&serde_json::json!({
+            "Env": ["PATH=/usr/sbin:/usr/bin", "LANG=C.UTF-8"],
+            "Entrypoint": ["/usr/bin/init"],
+            "Cmd": ["--verbose"],
+            "WorkingDir": "/root",
+            "User": "root",
+            "Labels": {
+                "com.github.mripard.ocibootstrap.table.type": "gpt",
+            },
+        })));
+
+        let root = TempDir::new().unwrap();
+        let path = config.write(root.path()).unwrap();
+        assert_eq!(path, root.path().join(RUNTIME_CONFIG_PATH));
+
+        let written: Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({
+                "env": ["PATH=/usr/sbin:/usr/bin", "LANG=C.UTF-8"],
+                "entrypoint": ["/usr/bin/init"],
+                "cmd": ["--verbose"],
+                "working_dir": "/root",
+                "user": "root",
+                "labels": {
+                    "com.github.mripard.ocibootstrap.table.type": "gpt",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_runtime_config_symlink() {
+        let root = TempDir::new().unwrap();
+        let host = TempDir::new().unwrap();
+
+        unix_fs::symlink(host.path(), root.path().join(".ocibootstrap")).unwrap();
+        RuntimeConfig::default().write(root.path()).unwrap_err();
+
+        fs::remove_file(root.path().join(".ocibootstrap")).unwrap();
+        fs::create_dir(root.path().join(".ocibootstrap")).unwrap();
+        unix_fs::symlink(
+            host.path().join("config.json"),
+            root.path().join(RUNTIME_CONFIG_PATH),
+        )
+        .unwrap();
+        RuntimeConfig::default().write(root.path()).unwrap_err();
+
+        assert_eq!(fs::read_dir(host.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_runtime_config_empty() {
+        let config = RuntimeConfig::from(&configuration(&serde_json::json!({})));
+
+        assert_eq!(config, RuntimeConfig::default());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{path::Path, process::Command};
+
+use log::info;
+use types::OciBootstrapError;
+
+use crate::{command::run_command, join_path};
+
+/// Sets the `security.selinux` extended attribute of the files of a root filesystem, from a
+/// `file_contexts` file found in it
+///
+/// # Errors
+///
+/// If the `file_contexts` file isn't in the root filesystem, or if setfiles fails.
+pub(crate) fn relabel(root: &Path, file_contexts: &Path) -> Result<(), OciBootstrapError> {
+    let spec = join_path(root, file_contexts)?;
+    if !spec.is_file() {
+        return Err(OciBootstrapError::Custom(format!(
+            "SELinux file contexts {} not found in the image",
+            file_contexts.display()
+        )));
+    }
+
+    info!(
+        "Labeling files with the SELinux contexts of {}",
+        file_contexts.display()
+    );
+
+    run_command(
+        Command::new("setfiles")
+            .arg("-F")
+            .arg("-r")
+            .arg(root)
+            .arg(&spec)
+            .arg(root),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod selinux_tests {
+    use std::{fs, process::Command};
+
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::selinux::relabel;
+
+    #[test]
+    fn test_relabel_missing_file_contexts() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("etc/selinux")).unwrap();
+
+        relabel(
+            root.path(),
+            "/etc/selinux/targeted/contexts/files/file_contexts".as_ref(),
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    #[ignore = "requires an SELinux enabled host, root privileges and setfiles"]
+    fn test_relabel() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(root.path().join("etc/hostname"), "ocibootstrap").unwrap();
+        fs::write(
+            root.path().join("file_contexts"),
+            "/.*\tsystem_u:object_r:etc_t:s0\n",
+        )
+        .unwrap();
+
+        relabel(root.path(), "/file_contexts".as_ref()).unwrap();
+
+        let output = Command::new("stat")
+            .args(["-c", "%C"])
+            .arg(root.path().join("etc/hostname"))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "system_u:object_r:etc_t:s0"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata},
+    io,
+    os::unix::{ffi::OsStrExt as _, fs::MetadataExt as _},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, trace};
+use sha2::{Digest as _, Sha256};
+
+/// A directory holding a single copy of each file, that the extracted trees are hardlinked to
+///
+/// Files are identified by their content and by their metadata, since hardlinks share them: two
+/// files with the same content but a different owner, mode or extended attributes aren't merged.
+#[derive(Debug)]
+pub(crate) struct ContentStore {
+    path: PathBuf,
+}
+
+/// Hashes the content and the metadata shared by the hardlinks to a file
+fn content_key(path: &Path, metadata: &Metadata) -> Result<String, io::Error> {
+    let mut hasher = Sha256::new()
+        .chain_update(metadata.mode().to_le_bytes())
+        .chain_update(metadata.uid().to_le_bytes())
+        .chain_update(metadata.gid().to_le_bytes())
+        .chain_update(metadata.mtime().to_le_bytes())
+        .chain_update(metadata.mtime_nsec().to_le_bytes());
+
+    // Extended attributes, like SELinux labels or capabilities, are shared by the hardlinks too
+    let mut names = xattr::list(path)?.collect::<Vec<_>>();
+    names.sort();
+
+    for name in names {
+        let value = xattr::get(path, &name)?.unwrap_or_default();
+
+        hasher.update(name.len().to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(value.len().to_le_bytes());
+        hasher.update(value);
+    }
+
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl ContentStore {
+    pub(crate) fn new(path: &Path) -> Result<Self, io::Error> {
+        fs::create_dir_all(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Replaces the regular files of a directory by hardlinks to the store, and returns how many
+    /// of them were already stored
+    ///
+    /// The store and the directory must be on the same filesystem.
+    pub(crate) fn link_tree(&self, root: &Path) -> Result<usize, io::Error> {
+        debug!(
+            "Linking {} to the content store {}",
+            root.display(),
+            self.path.display()
+        );
+
+        let mut shared = 0;
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+
+                if file_type.is_dir() {
+                    dirs.push(entry.path());
+                } else if file_type.is_file() && self.link_file(&entry.path())? {
+                    shared += 1;
+                }
+            }
+        }
+
+        debug!("{shared} files were already in the content store");
+
+        Ok(shared)
+    }
+
+    /// Links a file to the store, and returns whether its content was already stored
+    fn link_file(&self, path: &Path) -> Result<bool, io::Error> {
+        let metadata = path.symlink_metadata()?;
+        let stored_path = self.path.join(content_key(path, &metadata)?);
+
+        let stored = match stored_path.symlink_metadata() {
+            Ok(stored) => stored,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                trace!("Adding {} to the content store", path.display());
+
+                fs::hard_link(path, &stored_path)?;
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if stored.dev() == metadata.dev() && stored.ino() == metadata.ino() {
+            return Ok(true);
+        }
+
+        trace!(
+            "Replacing {} by a link to {}",
+            path.display(),
+            stored_path.display()
+        );
+
+        // Link next to the file first and then rename, so that an error never leaves the tree
+        // without the file.
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(path.file_name().unwrap_or_default());
+        tmp_name.push(".ocibootstrap-link");
+        let tmp_path = path.with_file_name(tmp_name);
+
+        fs::hard_link(&stored_path, &tmp_path)?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod store_tests {
+    use std::{
+        fs::{self, Permissions},
+        os::unix::fs::{MetadataExt as _, PermissionsExt as _},
+        path::Path,
+    };
+
+    use tar::{Builder, EntryType, Header};
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::{extract_layer, store::ContentStore};
+
+    fn layer(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for (path, content) in entries {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(content.len() as u64);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn extract(layers: &[&[u8]], root: &Path) {
+        fs::create_dir(root).unwrap();
+
+        for layer in layers {
+            extract_layer(*layer, root, false, &[]).unwrap();
+        }
+    }
+
+    fn ino(path: &Path) -> u64 {
+        path.symlink_metadata().unwrap().ino()
+    }
+
+    #[test]
+    fn test_content_store() {
+        let shared = layer(&[
+            ("etc/hostname", "ocibootstrap"),
+            ("usr/bin/tool", "#!/bin/sh"),
+        ]);
+        let first = layer(&[("etc/os-release", "ID=first")]);
+        let second = layer(&[("etc/os-release", "ID=second")]);
+
+        let dir = TempDir::new().unwrap();
+        let store = ContentStore::new(&dir.path().join("store")).unwrap();
+
+        let first_root = dir.path().join("first");
+        extract(&[&shared, &first], &first_root);
+        assert_eq!(store.link_tree(&first_root).unwrap(), 0);
+
+        let second_root = dir.path().join("second");
+        extract(&[&shared, &second], &second_root);
+        assert_eq!(store.link_tree(&second_root).unwrap(), 2);
+
+        for path in ["etc/hostname", "usr/bin/tool"] {
+            assert_eq!(ino(&first_root.join(path)), ino(&second_root.join(path)));
+        }
+
+        assert_ne!(
+            ino(&first_root.join("etc/os-release")),
+            ino(&second_root.join("etc/os-release"))
+        );
+        assert_eq!(
+            fs::read_to_string(second_root.join("etc/os-release")).unwrap(),
+            "ID=second"
+        );
+
+        // Running it again on an already linked tree doesn't change anything
+        assert_eq!(store.link_tree(&first_root).unwrap(), 3);
+        assert_eq!(fs::read_dir(dir.path().join("store")).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn test_content_store_different_mode() {
+        let dir = TempDir::new().unwrap();
+        let store = ContentStore::new(&dir.path().join("store")).unwrap();
+
+        let first_root = dir.path().join("first");
+        extract(&[&layer(&[("tool", "#!/bin/sh")])], &first_root);
+        store.link_tree(&first_root).unwrap();
+
+        let second_root = dir.path().join("second");
+        extract(&[&layer(&[("tool", "#!/bin/sh")])], &second_root);
+        fs::set_permissions(second_root.join("tool"), Permissions::from_mode(0o755)).unwrap();
+
+        assert_eq!(store.link_tree(&second_root).unwrap(), 0);
+        assert_ne!(
+            ino(&first_root.join("tool")),
+            ino(&second_root.join("tool"))
+        );
+    }
+
+    #[test]
+    fn test_content_store_different_xattrs() {
+        let dir = TempDir::new().unwrap();
+        let store = ContentStore::new(&dir.path().join("store")).unwrap();
+
+        let first_root = dir.path().join("first");
+        extract(&[&layer(&[("tool", "#!/bin/sh")])], &first_root);
+        xattr::set(first_root.join("tool"), "user.ocibootstrap", b"first").unwrap();
+        store.link_tree(&first_root).unwrap();
+
+        let second_root = dir.path().join("second");
+        extract(&[&layer(&[("tool", "#!/bin/sh")])], &second_root);
+        xattr::set(second_root.join("tool"), "user.ocibootstrap", b"second").unwrap();
+
+        assert_eq!(store.link_tree(&second_root).unwrap(), 0);
+        assert_ne!(
+            ino(&first_root.join("tool")),
+            ino(&second_root.join("tool"))
+        );
+        assert_eq!(
+            xattr::get(second_root.join("tool"), "user.ocibootstrap").unwrap(),
+            Some(b"second".to_vec())
+        );
+
+        let third_root = dir.path().join("third");
+        extract(&[&layer(&[("tool", "#!/bin/sh")])], &third_root);
+        xattr::set(third_root.join("tool"), "user.ocibootstrap", b"first").unwrap();
+
+        assert_eq!(store.link_tree(&third_root).unwrap(), 1);
+        assert_eq!(ino(&first_root.join("tool")), ino(&third_root.join("tool")));
+    }
+}
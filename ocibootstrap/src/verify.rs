@@ -0,0 +1,249 @@
+use alloc::collections::BTreeMap;
+use std::{
+    io,
+    os::unix::fs::FileTypeExt as _,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use tar::{Archive, EntryType};
+use types::OciBootstrapError;
+
+use crate::normalize_entry_path;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum EntryKind {
+    File(u64),
+    Directory,
+    Symlink,
+    Special,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ExpectedEntry {
+    kind: EntryKind,
+    layer: usize,
+}
+
+/// Files an image would produce once all its layers are extracted on top of each other
+#[derive(Debug, Default)]
+pub(crate) struct ExpectedTree {
+    entries: BTreeMap<PathBuf, ExpectedEntry>,
+    layers: usize,
+}
+
+impl ExpectedTree {
+    /// Applies a layer on top of the ones previously added
+    pub(crate) fn add_layer<R>(&mut self, reader: R) -> Result<(), OciBootstrapError>
+    where
+        R: io::Read,
+    {
+        let layer = self.layers;
+        self.layers += 1;
+
+        let mut archive = Archive::new(reader);
+        for entry in archive.entries()? {
+            let entry = entry?;
+
+            let entry_path = normalize_entry_path(&entry.path()?);
+
+            let Some(file_name) = entry_path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            let parent_dir = entry_path.parent().unwrap_or(Path::new(""));
+
+            if file_name == ".wh..wh..opq" {
+                debug!("Directory {} is opaque", parent_dir.display());
+
+                self.entries.retain(|path, expected| {
+                    path == parent_dir || !path.starts_with(parent_dir) || expected.layer == layer
+                });
+                continue;
+            }
+
+            if let Some(remove_file_name) = file_name.strip_prefix(".wh.") {
+                let removed = parent_dir.join(remove_file_name);
+
+                debug!("File {} has been removed", removed.display());
+
+                self.entries.retain(|path, _| !path.starts_with(&removed));
+                continue;
+            }
+
+            #[allow(clippy::wildcard_enum_match_arm)]
+            let kind = match entry.header().entry_type() {
+                EntryType::Directory => EntryKind::Directory,
+                EntryType::Symlink => EntryKind::Symlink,
+                EntryType::Char | EntryType::Block | EntryType::Fifo => EntryKind::Special,
+                EntryType::Link => {
+                    let target = entry.link_name()?.ok_or(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Hardlink without a target",
+                    ))?;
+
+                    self.entries
+                        .get(&normalize_entry_path(&target))
+                        .map_or(EntryKind::File(0), |target| target.kind)
+                }
+                _ => EntryKind::File(entry.header().size()?),
+            };
+
+            self.entries
+                .insert(entry_path, ExpectedEntry { kind, layer });
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a directory matches the layers, and returns the list of discrepancies
+    pub(crate) fn verify(&self, root: &Path) -> Vec<String> {
+        let mut discrepancies = Vec::new();
+
+        for (path, expected) in &self.entries {
+            let metadata = match root.join(path).symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    discrepancies.push(format!("{}: Missing ({e})", path.display()));
+                    continue;
+                }
+            };
+
+            let file_type = metadata.file_type();
+            let found = if file_type.is_file() {
+                EntryKind::File(metadata.len())
+            } else if file_type.is_dir() {
+                EntryKind::Directory
+            } else if file_type.is_symlink() {
+                EntryKind::Symlink
+            } else if file_type.is_char_device()
+                || file_type.is_block_device()
+                || file_type.is_fifo()
+            {
+                EntryKind::Special
+            } else {
+                discrepancies.push(format!("{}: Unknown file type", path.display()));
+                continue;
+            };
+
+            if found != expected.kind {
+                debug!(
+                    "File {} mismatch: Expected {:?}, found {found:?}",
+                    path.display(),
+                    expected.kind
+                );
+
+                discrepancies.push(match (expected.kind, found) {
+                    (EntryKind::File(expected_size), EntryKind::File(found_size)) => format!(
+                        "{}: Size mismatch (expected {expected_size} bytes, found {found_size} bytes)",
+                        path.display()
+                    ),
+                    _ => format!(
+                        "{}: Type mismatch (expected {:?}, found {found:?})",
+                        path.display(),
+                        expected.kind
+                    ),
+                });
+            }
+        }
+
+        discrepancies
+    }
+}
+
+#[cfg(test)]
+mod verify_tests {
+    use std::fs;
+
+    use tar::{Builder, EntryType, Header};
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use crate::{extract_layer, verify::ExpectedTree};
+
+    fn layer(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for (path, content) in entries {
+            let mut header = Header::new_gnu();
+
+            if path.ends_with('/') {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o755);
+            } else {
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(0o644);
+            }
+
+            header.set_size(content.len() as u64);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn layers() -> Vec<Vec<u8>> {
+        vec![
+            layer(&[
+                ("etc/", ""),
+                ("etc/hostname", "ocibootstrap"),
+                ("etc/removed", "removed"),
+                ("usr/", ""),
+                ("usr/bin/", ""),
+                ("usr/bin/tool", "#!/bin/sh"),
+            ]),
+            layer(&[("etc/.wh.removed", ""), ("etc/added", "added")]),
+        ]
+    }
+
+    fn extract_and_verify() -> (TempDir, ExpectedTree) {
+        let root = TempDir::new().unwrap();
+        let mut expected = ExpectedTree::default();
+
+        for layer in layers() {
+            extract_layer(layer.as_slice(), root.path(), false, &[]).unwrap();
+            expected.add_layer(layer.as_slice()).unwrap();
+        }
+
+        (root, expected)
+    }
+
+    #[test]
+    fn test_verify_matching_tree() {
+        let (root, expected) = extract_and_verify();
+
+        assert!(expected.verify(root.path()).is_empty());
+    }
+
+    #[test]
+    fn test_verify_corrupted_file() {
+        let (root, expected) = extract_and_verify();
+
+        fs::write(root.path().join("usr/bin/tool"), "corrupted content").unwrap();
+
+        let discrepancies = expected.verify(root.path());
+        assert_eq!(discrepancies.len(), 1);
+        assert!(
+            discrepancies[0].starts_with("usr/bin/tool: Size mismatch"),
+            "{}",
+            discrepancies[0]
+        );
+    }
+
+    #[test]
+    fn test_verify_missing_file() {
+        let (root, expected) = extract_and_verify();
+
+        fs::remove_file(root.path().join("etc/added")).unwrap();
+
+        let discrepancies = expected.verify(root.path());
+        assert_eq!(discrepancies.len(), 1);
+        assert!(
+            discrepancies[0].starts_with("etc/added: Missing"),
+            "{}",
+            discrepancies[0]
+        );
+    }
+}